@@ -0,0 +1,58 @@
+//! Times `transliterate_batch` on a corpus of short sentences with 1 to 8 threads, against the
+//! synthetic dictionary of the lattice bench, and prints the speedup over one thread.
+//!
+//! Run with `cargo bench --bench batch`. The speedup can only approach the thread count on a
+//! machine with that many free cores.
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{transliterate_batch, Dictionary};
+use std::time::Instant;
+
+fn main() {
+    let kanji: Vec<char> = (0..200u32)
+        .map(|i| char::from_u32('一' as u32 + i * 7).unwrap())
+        .collect();
+    let mut builder = DictionaryBuilder::new();
+    for (i, &a) in kanji.iter().enumerate() {
+        builder.add_entry(&a.to_string(), "ア", 0, 500);
+        for &b in kanji.iter().skip(i % 5).step_by(5).take(20) {
+            builder.add_entry(&format!("{}{}", a, b), "イウ", 0, 300);
+        }
+    }
+    builder.set_matrix(vec![0], 1);
+    let path = std::env::temp_dir().join(format!("mucab-batch-{}.bin", std::process::id()));
+    builder
+        .write_to_file(&path)
+        .expect("Failed to write dictionary");
+    let mut dict = Dictionary::load(&path).expect("Failed to load dictionary");
+    // Fill the shared cache so only conversion is timed.
+    dict.preload_all().expect("Failed to preload dictionary");
+
+    let sentences: Vec<String> = (0..20_000)
+        .map(|n| {
+            (0..10 + n % 30)
+                .map(|i| kanji[(n * 13 + i * 31 + i / 3) % kanji.len()])
+                .collect()
+        })
+        .collect();
+    let texts: Vec<&str> = sentences.iter().map(String::as_str).collect();
+
+    let mut single = 0.0;
+    for parallelism in [1, 2, 4, 8] {
+        let start = Instant::now();
+        let readings = transliterate_batch(&texts, dict.data(), parallelism);
+        let ms = start.elapsed().as_secs_f64() * 1000.0;
+        assert!(readings.iter().all(Result::is_ok));
+        if parallelism == 1 {
+            single = ms;
+        }
+        println!(
+            "{} threads: {:>8.1} ms for {} sentences, {:>4.1}x",
+            parallelism,
+            ms,
+            texts.len(),
+            single / ms
+        );
+    }
+    std::fs::remove_file(&path).ok();
+}
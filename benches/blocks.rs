@@ -0,0 +1,79 @@
+//! Times decoding and searching large entry blocks, and counts the allocations decoding makes,
+//! on a text made of the three first chars with the most entries.
+//!
+//! Run with `cargo bench --bench blocks`.
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{transliterate, CacheLimit, Dictionary};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+struct Counting;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Counting = Counting;
+
+fn main() {
+    let heads = ['大', '人', '日'];
+    let tails: Vec<char> = (0..3000u32)
+        .map(|i| char::from_u32('一' as u32 + i).unwrap())
+        .collect();
+    let mut builder = DictionaryBuilder::new();
+    for &head in &heads {
+        builder.add_entry(&head.to_string(), "ア", 0, 500);
+        for &tail in &tails {
+            builder.add_entry(&format!("{}{}", head, tail), "イウ", 0, 300);
+        }
+    }
+    builder.set_matrix(vec![0], 1);
+    let path = std::env::temp_dir().join(format!("mucab-blocks-{}.bin", std::process::id()));
+    builder
+        .write_to_file(&path)
+        .expect("Failed to write dictionary");
+    let text: String = (0..600)
+        .map(|i| heads[(i * 7 + i / 5) % heads.len()])
+        .collect();
+
+    // With one block cached, every change of first char decodes its block again.
+    let mut dict = Dictionary::load(&path).expect("Failed to load dictionary");
+    dict.set_cache_limit(CacheLimit::Blocks(1));
+    transliterate(&text, &mut dict);
+    let runs = 5;
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..runs {
+        transliterate(&text, &mut dict);
+    }
+    println!(
+        "decoding: {:>8.2} ms, {:>9} allocations per transliterate",
+        start.elapsed().as_secs_f64() * 1000.0 / runs as f64,
+        (ALLOCATIONS.load(Ordering::Relaxed) - allocations) / runs
+    );
+
+    let mut dict = Dictionary::load(&path).expect("Failed to load dictionary");
+    transliterate(&text, &mut dict);
+    let runs = 50;
+    let start = Instant::now();
+    for _ in 0..runs {
+        transliterate(&text, &mut dict);
+    }
+    println!(
+        "cached:   {:>8.2} ms per transliterate",
+        start.elapsed().as_secs_f64() * 1000.0 / runs as f64
+    );
+    std::fs::remove_file(&path).ok();
+}
@@ -0,0 +1,71 @@
+//! Times converting the same sentences over and over, lazily loaded, with and without the
+//! per-handle frame cache. Readings are read from the strings region on every conversion, so
+//! without the cache each read decompresses its frame up to the reading again.
+//!
+//! Run with `cargo bench --bench frames`.
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{transliterate, Dictionary, LoadOptions, DEFAULT_FRAME_CACHE};
+use std::time::Instant;
+
+fn main() {
+    let kanji: Vec<char> = (0..2000u32)
+        .map(|i| char::from_u32('一' as u32 + i).unwrap())
+        .collect();
+    let kana: Vec<char> = ('ァ'..='ヶ').collect();
+    let reading = |i: usize| -> String {
+        (0..6 + i % 5)
+            .map(|j| kana[(i * 13 + j * 7) % kana.len()])
+            .collect()
+    };
+    let mut builder = DictionaryBuilder::new();
+    for (i, &a) in kanji.iter().enumerate() {
+        builder.add_entry(&a.to_string(), &reading(i), 0, 500);
+        for (j, &b) in kanji.iter().skip(i % 7).step_by(7).take(10).enumerate() {
+            builder.add_entry(&format!("{}{}", a, b), &reading(i * 10 + j), 0, 300);
+        }
+    }
+    builder.set_matrix(vec![0], 1);
+    let path = std::env::temp_dir().join(format!("mucab-frames-{}.bin", std::process::id()));
+    let stats = builder
+        .write_to_file(&path)
+        .expect("Failed to write dictionary");
+    println!(
+        "{} bytes of entries and strings",
+        stats.entries_bytes + stats.strings_bytes
+    );
+
+    let sentences: Vec<String> = (0..20)
+        .map(|s| {
+            (0..30)
+                .map(|i| kanji[(s * 97 + i * 31 + i / 3) % kanji.len()])
+                .collect()
+        })
+        .collect();
+    for frame_cache in [0, DEFAULT_FRAME_CACHE] {
+        let options = LoadOptions {
+            frame_cache,
+            ..LoadOptions::default()
+        };
+        let mut dict =
+            Dictionary::load_with_options(&path, options).expect("Failed to load dictionary");
+        // Decode the blocks first so only readings are timed.
+        for sentence in &sentences {
+            transliterate(sentence, &mut dict);
+        }
+        let runs = 50;
+        let start = Instant::now();
+        for _ in 0..runs {
+            for sentence in &sentences {
+                transliterate(sentence, &mut dict);
+            }
+        }
+        let ms = start.elapsed().as_secs_f64() * 1000.0 / (runs * sentences.len()) as f64;
+        let stats = dict.cache_stats();
+        println!(
+            "{:>2} frames: {:>8.3} ms per sentence, {:>6} frame hits, {:>6} misses",
+            frame_cache, ms, stats.frame_hits, stats.frame_misses
+        );
+    }
+    std::fs::remove_file(&path).ok();
+}
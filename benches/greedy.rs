@@ -0,0 +1,58 @@
+//! Times the greedy longest-match mode against the lattice search on identical inputs, with the
+//! synthetic dictionary of the lattice bench.
+//!
+//! Run with `cargo bench --bench greedy`.
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{Dictionary, Mode, Options, Tokenizer};
+use std::time::Instant;
+
+fn main() {
+    let kanji: Vec<char> = (0..200u32)
+        .map(|i| char::from_u32('一' as u32 + i * 7).unwrap())
+        .collect();
+    let mut builder = DictionaryBuilder::new();
+    for (i, &a) in kanji.iter().enumerate() {
+        builder.add_entry(&a.to_string(), "ア", 0, 500);
+        for &b in kanji.iter().skip(i % 5).step_by(5).take(20) {
+            builder.add_entry(&format!("{}{}", a, b), "イウ", 0, 300);
+        }
+    }
+    builder.set_matrix(vec![0], 1);
+    let path = std::env::temp_dir().join(format!("mucab-greedy-{}.bin", std::process::id()));
+    builder
+        .write_to_file(&path)
+        .expect("Failed to write dictionary");
+    let mut dict = Dictionary::load(&path).expect("Failed to load dictionary");
+    dict.preload_all().expect("Failed to preload dictionary");
+
+    let mut tokenizer = Tokenizer::new(dict);
+    for len in [20, 1000, 4000] {
+        let text: String = (0..len)
+            .map(|i| kanji[(i * 31 + i / 3) % kanji.len()])
+            .collect();
+        let mut times = Vec::new();
+        for mode in [Mode::Viterbi, Mode::Greedy] {
+            tokenizer = Tokenizer::new(tokenizer.into_dictionary()).with_options(Options {
+                mode,
+                ..Options::default()
+            });
+            // Warm the entry cache so only segmentation is timed.
+            tokenizer.transliterate(&text);
+            let runs = 4000 / len.min(1000) * 5;
+            let start = Instant::now();
+            for _ in 0..runs {
+                tokenizer.transliterate(&text);
+            }
+            times.push(start.elapsed().as_secs_f64() * 1000.0 / runs as f64);
+        }
+        println!(
+            "{:>5} chars: {:>8.3} ms viterbi, {:>8.3} ms greedy, {:>5.1}x",
+            len,
+            times[0],
+            times[1],
+            times[0] / times[1]
+        );
+    }
+    std::fs::remove_file(&path).ok();
+}
@@ -0,0 +1,68 @@
+//! Times lattice construction on long inputs against a synthetic dictionary.
+//!
+//! Run with `cargo bench --bench lattice`.
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{transliterate, Dictionary};
+use std::time::Instant;
+
+fn main() {
+    let kanji: Vec<char> = (0..200u32)
+        .map(|i| char::from_u32('一' as u32 + i * 7).unwrap())
+        .collect();
+    let mut builder = DictionaryBuilder::new();
+    for (i, &a) in kanji.iter().enumerate() {
+        builder.add_entry(&a.to_string(), "ア", 0, 500);
+        for &b in kanji.iter().skip(i % 5).step_by(5).take(20) {
+            builder.add_entry(&format!("{}{}", a, b), "イウ", 0, 300);
+        }
+    }
+    builder.set_matrix(vec![0], 1);
+    let path = std::env::temp_dir().join(format!("mucab-bench-{}.bin", std::process::id()));
+    builder
+        .write_to_file(&path)
+        .expect("Failed to write dictionary");
+    let mut dict = Dictionary::load(path.to_str().unwrap()).expect("Failed to load dictionary");
+
+    for len in [1000, 2000, 4000] {
+        let text: String = (0..len)
+            .map(|i| kanji[(i * 31 + i / 3) % kanji.len()])
+            .collect();
+        // Warm the entry cache so only lattice work is timed.
+        transliterate(&text, &mut dict);
+        let runs = 5;
+        let start = Instant::now();
+        for _ in 0..runs {
+            transliterate(&text, &mut dict);
+        }
+        println!(
+            "{:>5} chars: {:>8.2} ms per transliterate",
+            len,
+            start.elapsed().as_secs_f64() * 1000.0 / runs as f64
+        );
+    }
+
+    // Readings go through the decoder unless the dictionary is preloaded.
+    let text: String = (0..2000)
+        .map(|i| kanji[(i * 31 + i / 3) % kanji.len()])
+        .collect();
+    for preload in [false, true] {
+        let mut dict = Dictionary::load(path.to_str().unwrap()).expect("Failed to load dictionary");
+        if preload {
+            dict.preload_all().expect("Failed to preload dictionary");
+        }
+        transliterate(&text, &mut dict);
+        let runs = 5;
+        let start = Instant::now();
+        for _ in 0..runs {
+            transliterate(&text, &mut dict);
+        }
+        println!(
+            "{:>9}: {:>8.2} ms per transliterate",
+            if preload { "preloaded" } else { "lazy" },
+            start.elapsed().as_secs_f64() * 1000.0 / runs as f64
+        );
+    }
+
+    std::fs::remove_file(&path).ok();
+}
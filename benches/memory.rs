@@ -0,0 +1,77 @@
+//! Measures the heap one transliteration of a long input needs at its peak, and how many
+//! allocations it makes, against the synthetic dictionary of the lattice bench.
+//!
+//! Run with `cargo bench --bench memory`.
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{transliterate, Dictionary, DEFAULT_MAX_LATTICE_CHARS};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct Tracking;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Tracking {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        let live = LIVE.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+        PEAK.fetch_max(live, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::Relaxed);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: Tracking = Tracking;
+
+fn main() {
+    let kanji: Vec<char> = (0..200u32)
+        .map(|i| char::from_u32('一' as u32 + i * 7).unwrap())
+        .collect();
+    let mut builder = DictionaryBuilder::new();
+    for (i, &a) in kanji.iter().enumerate() {
+        builder.add_entry(&a.to_string(), "ア", 0, 500);
+        for &b in kanji.iter().skip(i % 5).step_by(5).take(20) {
+            builder.add_entry(&format!("{}{}", a, b), "イウ", 0, 300);
+        }
+    }
+    builder.set_matrix(vec![0], 1);
+    let path = std::env::temp_dir().join(format!("mucab-memory-{}.bin", std::process::id()));
+    builder
+        .write_to_file(&path)
+        .expect("Failed to write dictionary");
+    let mut dict = Dictionary::load(&path).expect("Failed to load dictionary");
+    dict.preload_all().expect("Failed to preload dictionary");
+
+    for len in [1000, 10000] {
+        let text: String = (0..len)
+            .map(|i| kanji[(i * 31 + i / 3) % kanji.len()])
+            .collect();
+        for max_chars in [DEFAULT_MAX_LATTICE_CHARS, usize::MAX] {
+            dict.set_max_lattice_chars(max_chars);
+            transliterate(&text, &mut dict);
+            let (live, allocations) = (
+                LIVE.load(Ordering::Relaxed),
+                ALLOCATIONS.load(Ordering::Relaxed),
+            );
+            PEAK.store(live, Ordering::Relaxed);
+            let out = transliterate(&text, &mut dict);
+            println!(
+                "{:>5} chars, {:>5} per lattice: {:>9} bytes at peak, {:>7} allocations",
+                len,
+                max_chars.min(len),
+                PEAK.load(Ordering::Relaxed) - live,
+                ALLOCATIONS.load(Ordering::Relaxed) - allocations,
+            );
+            drop(out);
+        }
+    }
+    std::fs::remove_file(&path).ok();
+}
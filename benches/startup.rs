@@ -0,0 +1,38 @@
+//! Times loading a dictionary whose connection matrix is the size of IPADIC's.
+//!
+//! Run with `cargo bench --bench startup`.
+
+use mucab::builder::DictionaryBuilder;
+use mucab::DictionaryData;
+use std::time::Instant;
+
+fn main() {
+    for size in [400u16, 1316, 2000] {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("東京", "トーキョー", 0, 100);
+        let matrix = (0..size as usize * size as usize)
+            .map(|i| (i % 2001) as i16 - 1000)
+            .collect();
+        builder.set_matrix(matrix, size);
+        let path =
+            std::env::temp_dir().join(format!("mucab-startup-{}-{}.bin", std::process::id(), size));
+        builder
+            .write_to_file(&path)
+            .expect("Failed to write dictionary");
+
+        // The first load also warms the page cache.
+        DictionaryData::load(&path).expect("Failed to load dictionary");
+        let runs = 10;
+        let start = Instant::now();
+        for _ in 0..runs {
+            DictionaryData::load(&path).expect("Failed to load dictionary");
+        }
+        println!(
+            "{:>4}x{:<4} matrix: {:>8.2} ms per load",
+            size,
+            size,
+            start.elapsed().as_secs_f64() * 1000.0 / runs as f64
+        );
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -0,0 +1,35 @@
+//! A single binary with its dictionary compiled in: `cargo run --example embedded --features
+//! embed -- 東京都`. The dictionary is parsed the first time it is needed and shared from then
+//! on; each conversion takes a handle of its own.
+
+use std::sync::{Arc, LazyLock};
+
+use mucab::{transliterate, Dictionary, DictionaryData};
+
+/// Aligned to 2 so that the connection matrix can be read in place.
+#[repr(C, align(2))]
+struct Aligned<B: ?Sized>(B);
+
+static DICTIONARY_FILE: &Aligned<[u8]> =
+    &Aligned(*include_bytes!("../tests/fixtures/embedded.bin"));
+
+static DICTIONARY: LazyLock<Arc<DictionaryData>> = LazyLock::new(|| {
+    Arc::new(DictionaryData::from_static(&DICTIONARY_FILE.0).expect("embedded dictionary"))
+});
+
+fn main() {
+    let text = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "東京都".to_string());
+    let mut dict = Dictionary::with_data(DICTIONARY.clone()).expect("embedded dictionary");
+    println!("{}", transliterate(&text, &mut dict));
+    eprintln!(
+        "{} entries, matrix {}",
+        dict.num_entries(),
+        if DICTIONARY.matrix_is_borrowed() {
+            "read in place"
+        } else {
+            "copied"
+        }
+    );
+}
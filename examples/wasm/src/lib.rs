@@ -0,0 +1,33 @@
+//! mucab for JavaScript: load a dictionary from its bytes once, then transliterate.
+//!
+//! Without zstd the dictionary has to be written uncompressed, e.g.
+//! `converter --no-compress ...`. Build with `wasm-pack build --target web`.
+
+use std::cell::RefCell;
+
+use mucab::Dictionary;
+use wasm_bindgen::prelude::*;
+
+thread_local! {
+    static DICTIONARY: RefCell<Option<Dictionary<'static>>> = const { RefCell::new(None) };
+}
+
+/// Loads the dictionary every later [`transliterate`] call uses, replacing any earlier one.
+#[wasm_bindgen(js_name = loadDictionary)]
+pub fn load_dictionary(bytes: Vec<u8>) -> Result<(), JsError> {
+    let dict = Dictionary::from_bytes(bytes)?;
+    DICTIONARY.with(|cell| *cell.borrow_mut() = Some(dict));
+    Ok(())
+}
+
+/// Rewrites `text` with kanji replaced by their katakana readings. Fails if no dictionary has
+/// been loaded.
+#[wasm_bindgen]
+pub fn transliterate(text: &str) -> Result<String, JsError> {
+    DICTIONARY.with(|cell| match cell.borrow_mut().as_mut() {
+        Some(dict) => Ok(mucab::transliterate(text, dict)),
+        None => Err(JsError::new(
+            "no dictionary loaded; call loadDictionary first",
+        )),
+    })
+}
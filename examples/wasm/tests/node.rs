@@ -0,0 +1,19 @@
+//! Smoke test: `wasm-pack test --node examples/wasm`.
+
+use mucab::builder::DictionaryBuilder;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn transliterates_with_a_dictionary_from_bytes() {
+    assert!(mucab_wasm::transliterate("東京").is_err());
+
+    let mut builder = DictionaryBuilder::new();
+    builder.add_entry("東京", "トーキョー", 0, 100);
+    builder.add_entry("都", "ト", 0, 100);
+    builder.set_matrix(vec![0], 1);
+    let mut bytes = Vec::new();
+    builder.write(&mut bytes).unwrap();
+
+    mucab_wasm::load_dictionary(bytes).unwrap();
+    assert_eq!(mucab_wasm::transliterate("東京都").unwrap(), "トーキョート");
+}
@@ -0,0 +1,44 @@
+#ifndef MUCAB_H
+#define MUCAB_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+// A loaded dictionary, opaque to C.
+typedef struct MucabDict MucabDict;
+
+// Loads the dictionary at `path`, or returns `NULL` on failure. Free it with
+// [`mucab_dict_free`].
+//
+// # Safety
+//
+// `path` must be `NULL` or a NUL-terminated string.
+struct MucabDict *mucab_dict_load(const char *path);
+
+// Frees a dictionary from [`mucab_dict_load`]; `NULL` is ignored.
+//
+// # Safety
+//
+// `dict` must be `NULL` or a pointer from [`mucab_dict_load`] that has not been freed.
+void mucab_dict_free(struct MucabDict *dict);
+
+// Transliterates `text` as [`crate::transliterate`] does. The result is owned by the caller
+// and freed with [`mucab_string_free`]; `NULL` means failure.
+//
+// # Safety
+//
+// `dict` must be `NULL` or a live pointer from [`mucab_dict_load`], not in use on another
+// thread, and `text` `NULL` or a NUL-terminated string.
+char *mucab_transliterate(struct MucabDict *dict, const char *text);
+
+// Frees a string from [`mucab_transliterate`]; `NULL` is ignored.
+//
+// # Safety
+//
+// `s` must be `NULL` or a pointer from [`mucab_transliterate`] that has not been freed.
+void mucab_string_free(char *s);
+
+// The message of the last failure on this thread, or `NULL` if nothing has failed yet. The
+// string belongs to mucab and stays valid until the next failure on the same thread.
+const char *mucab_last_error(void);
+
+#endif  /* MUCAB_H */
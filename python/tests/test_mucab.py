@@ -0,0 +1,67 @@
+"""Smoke test of the Python module: `maturin develop && pytest`.
+
+The dictionary is converted from the CSV fixture the converter tests use, with `cargo run`.
+"""
+
+import subprocess
+import threading
+from pathlib import Path
+
+import pytest
+
+import mucab
+
+ROOT = Path(__file__).resolve().parents[2]
+
+
+@pytest.fixture(scope="module")
+def dict_path(tmp_path_factory):
+    out = tmp_path_factory.mktemp("dict")
+    subprocess.run(
+        [
+            "cargo", "run", "--quiet", "--bin", "converter", "--",
+            "--ipadic", "--encoding", "utf-8", "--no-matrix",
+            str(ROOT / "tests/fixtures/encoding/lex.utf8.csv"), str(out),
+        ],
+        cwd=ROOT,
+        check=True,
+    )
+    return out / "mucab.bin"
+
+
+def test_transliterate(dict_path):
+    m = mucab.Mucab(str(dict_path))
+    assert m.transliterate("東京に行く") == "トーキョーにイク"
+
+
+def test_tokenize(dict_path):
+    tokens = mucab.Mucab(dict_path).tokenize("東京に行く")
+    assert [t["surface"] for t in tokens] == ["東京", "に", "行く"]
+    assert tokens[0]["reading"] == "トーキョー"
+    assert not tokens[0]["is_unknown"]
+    assert tokens[1]["is_unknown"]
+    assert [(t["start_byte"], t["end_byte"]) for t in tokens] == [(0, 6), (6, 9), (9, 15)]
+    assert tokens[0]["lemma"] is None
+
+
+def test_shared_between_threads(dict_path):
+    m = mucab.Mucab(dict_path)
+    results = []
+    threads = [
+        threading.Thread(target=lambda: results.append(m.transliterate("行く東京")))
+        for _ in range(8)
+    ]
+    for t in threads:
+        t.start()
+    for t in threads:
+        t.join()
+    assert results == ["イクトーキョー"] * 8
+
+
+def test_errors(tmp_path):
+    with pytest.raises(OSError):
+        mucab.Mucab(tmp_path / "missing.bin")
+    damaged = tmp_path / "damaged.bin"
+    damaged.write_bytes(b"not a dictionary")
+    with pytest.raises(ValueError):
+        mucab.Mucab(damaged)
@@ -0,0 +1,399 @@
+//! Full analysis results and their snapshot encoding.
+//!
+//! Snapshots are meant to be compared across crate versions, so the encoding is fixed and
+//! versioned independently of the dictionary format: magic `MUAN`, a u16 version, then
+//! little-endian integers and u32-length-prefixed UTF-8 strings. Version 2 added the POS name,
+//! written after a token's flags when bit 2 is set. Version 3 added the pronunciation (bit 3)
+//! the accent type (bit 4, one byte) and the lemma (bit 5), written after the POS name in that
+//! order. Version 1 and 2 snapshots still load.
+//!
+//! With the `serde` feature both types also serialize, under field names that are kept stable:
+//! an [`Analysis`] is `text`, `tokens` and `total_cost`, and each token carries the fields of
+//! [`OwnedToken`] (`surface`, `reading`, `pos_id`, `pos` when known, `word_cost`, `is_unknown`,
+//! `is_punctuation`, `start_byte`, `end_byte`, and `pronunciation`, `accent` and `lemma` when
+//! known) alongside `start`, `end` and `path_cost`.
+
+use std::io::{Error, ErrorKind};
+
+use crate::OwnedToken;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"MUAN";
+pub const SNAPSHOT_VERSION: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnalysisToken {
+    #[cfg_attr(feature = "serde", serde(flatten))]
+    pub token: OwnedToken,
+    /// Char offsets of the token in [`Analysis::text`].
+    pub start: usize,
+    pub end: usize,
+    /// Cost of the best path from the start of the text up to and including this token.
+    pub path_cost: i32,
+}
+
+/// The chosen path through one text, as returned by [`crate::analyze`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Analysis {
+    pub text: String,
+    pub tokens: Vec<AnalysisToken>,
+    /// Cost of the whole path, including the EOS connection where the compat level has one.
+    pub total_cost: i32,
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u32).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+struct SnapshotReader<'b> {
+    bytes: &'b [u8],
+}
+
+impl<'b> SnapshotReader<'b> {
+    fn take(&mut self, n: usize) -> std::io::Result<&'b [u8]> {
+        if self.bytes.len() < n {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "truncated snapshot"));
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> std::io::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn string(&mut self) -> std::io::Result<String> {
+        let len = u32::from_le_bytes(self.array()?) as usize;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "snapshot string not UTF-8"))
+    }
+}
+
+impl Analysis {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(SNAPSHOT_MAGIC);
+        out.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        put_str(&mut out, &self.text);
+        out.extend_from_slice(&self.total_cost.to_le_bytes());
+        out.extend_from_slice(&(self.tokens.len() as u32).to_le_bytes());
+        for t in &self.tokens {
+            put_str(&mut out, &t.token.surface);
+            put_str(&mut out, &t.token.reading);
+            out.extend_from_slice(&(t.start as u32).to_le_bytes());
+            out.extend_from_slice(&(t.end as u32).to_le_bytes());
+            out.extend_from_slice(&t.token.pos_id.to_le_bytes());
+            out.extend_from_slice(&t.token.word_cost.to_le_bytes());
+            out.extend_from_slice(&t.path_cost.to_le_bytes());
+            out.push(
+                t.token.is_unknown as u8
+                    | (t.token.is_punctuation as u8) << 1
+                    | (t.token.pos.is_some() as u8) << 2
+                    | (t.token.pronunciation.is_some() as u8) << 3
+                    | (t.token.accent.is_some() as u8) << 4
+                    | (t.token.lemma.is_some() as u8) << 5,
+            );
+            if let Some(pos) = &t.token.pos {
+                put_str(&mut out, pos);
+            }
+            if let Some(pronunciation) = &t.token.pronunciation {
+                put_str(&mut out, pronunciation);
+            }
+            if let Some(accent) = t.token.accent {
+                out.push(accent);
+            }
+            if let Some(lemma) = &t.token.lemma {
+                put_str(&mut out, lemma);
+            }
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<Self> {
+        let mut r = SnapshotReader { bytes };
+        if r.take(4)? != SNAPSHOT_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid snapshot magic"));
+        }
+        let version = u16::from_le_bytes(r.array()?);
+        if version == 0 || version > SNAPSHOT_VERSION {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported snapshot version {}", version),
+            ));
+        }
+        let text = r.string()?;
+        let byte_offsets: Vec<usize> = text
+            .char_indices()
+            .map(|(i, _)| i)
+            .chain(std::iter::once(text.len()))
+            .collect();
+        let byte_offset = |pos: usize| {
+            byte_offsets.get(pos).copied().ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    "token span outside the snapshot text",
+                )
+            })
+        };
+        let total_cost = i32::from_le_bytes(r.array()?);
+        let count = u32::from_le_bytes(r.array()?) as usize;
+        let mut tokens = Vec::with_capacity(count.min(r.bytes.len()));
+        for _ in 0..count {
+            let surface = r.string()?;
+            let reading = r.string()?;
+            let start = u32::from_le_bytes(r.array()?) as usize;
+            let end = u32::from_le_bytes(r.array()?) as usize;
+            let pos_id = u16::from_le_bytes(r.array()?);
+            let word_cost = i16::from_le_bytes(r.array()?);
+            let path_cost = i32::from_le_bytes(r.array()?);
+            let [flags] = r.array()?;
+            let pos = if flags & 4 != 0 {
+                Some(r.string()?)
+            } else {
+                None
+            };
+            let pronunciation = if flags & 8 != 0 {
+                Some(r.string()?)
+            } else {
+                None
+            };
+            let accent = if flags & 16 != 0 {
+                let [accent] = r.array()?;
+                Some(accent)
+            } else {
+                None
+            };
+            let lemma = if flags & 32 != 0 {
+                Some(r.string()?)
+            } else {
+                None
+            };
+            tokens.push(AnalysisToken {
+                token: OwnedToken {
+                    surface,
+                    reading,
+                    pos_id,
+                    pos,
+                    word_cost,
+                    is_unknown: flags & 1 != 0,
+                    is_punctuation: flags & 2 != 0,
+                    start_byte: byte_offset(start)?,
+                    end_byte: byte_offset(end)?,
+                    pronunciation,
+                    accent,
+                    lemma,
+                },
+                start,
+                end,
+                path_cost,
+            });
+        }
+        if !r.bytes.is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "trailing bytes after snapshot",
+            ));
+        }
+        Ok(Analysis {
+            text,
+            tokens,
+            total_cost,
+        })
+    }
+
+    /// Human-readable differences from `other`, taken as the earlier result. Empty when equal.
+    pub fn differences(&self, other: &Analysis) -> Vec<String> {
+        let mut diffs = Vec::new();
+        if self.text != other.text {
+            diffs.push(format!("text: {:?} -> {:?}", other.text, self.text));
+            return diffs;
+        }
+        if self.total_cost != other.total_cost {
+            diffs.push(format!(
+                "total cost: {} -> {}",
+                other.total_cost, self.total_cost
+            ));
+        }
+        let describe = |t: &AnalysisToken| {
+            let mut extras = String::new();
+            if let Some(pronunciation) = &t.token.pronunciation {
+                extras.push_str(&format!(" pronunciation={}", pronunciation));
+            }
+            if let Some(accent) = t.token.accent {
+                extras.push_str(&format!(" accent={}", accent));
+            }
+            if let Some(lemma) = &t.token.lemma {
+                extras.push_str(&format!(" lemma={}", lemma));
+            }
+            format!(
+                "{}..{} {}/{} pos={} cost={} path={}{}{}",
+                t.start,
+                t.end,
+                t.token.surface,
+                t.token.reading,
+                t.token.pos_id,
+                t.token.word_cost,
+                t.path_cost,
+                extras,
+                if t.token.is_punctuation {
+                    " punctuation"
+                } else if t.token.is_unknown {
+                    " unknown"
+                } else {
+                    ""
+                }
+            )
+        };
+        for i in 0..self.tokens.len().max(other.tokens.len()) {
+            match (other.tokens.get(i), self.tokens.get(i)) {
+                (Some(a), Some(b)) if a == b => {}
+                (Some(a), Some(b)) => {
+                    diffs.push(format!("token {}: {} -> {}", i, describe(a), describe(b)))
+                }
+                (Some(a), None) => diffs.push(format!("token {}: removed {}", i, describe(a))),
+                (None, Some(b)) => diffs.push(format!("token {}: added {}", i, describe(b))),
+                (None, None) => unreachable!(),
+            }
+        }
+        diffs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Analysis {
+        Analysis {
+            text: "東京X".to_string(),
+            tokens: vec![
+                AnalysisToken {
+                    token: OwnedToken {
+                        surface: "東京".to_string(),
+                        reading: "トーキョー".to_string(),
+                        pos_id: 3,
+                        pos: Some("名詞".to_string()),
+                        word_cost: -40,
+                        is_unknown: false,
+                        is_punctuation: false,
+                        start_byte: 0,
+                        end_byte: "東京".len(),
+                        pronunciation: Some("トーキョー".to_string()),
+                        accent: Some(0),
+                        lemma: Some("東京".to_string()),
+                    },
+                    start: 0,
+                    end: 2,
+                    path_cost: -40,
+                },
+                AnalysisToken {
+                    token: OwnedToken {
+                        surface: "X".to_string(),
+                        reading: "X".to_string(),
+                        pos_id: 0,
+                        pos: None,
+                        word_cost: 0,
+                        is_unknown: true,
+                        is_punctuation: false,
+                        start_byte: "東京".len(),
+                        end_byte: "東京X".len(),
+                        pronunciation: None,
+                        accent: None,
+                        lemma: None,
+                    },
+                    start: 2,
+                    end: 3,
+                    path_cost: 9960,
+                },
+            ],
+            total_cost: 9960,
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let analysis = sample();
+        let bytes = analysis.to_bytes();
+        assert_eq!(Analysis::from_bytes(&bytes).unwrap(), analysis);
+        assert!(analysis.differences(&analysis).is_empty());
+    }
+
+    #[test]
+    fn test_encoding_is_stable() {
+        // Pins the v3 layout: changing it must bump SNAPSHOT_VERSION.
+        let bytes = sample().to_bytes();
+        assert_eq!(&bytes[..6], b"MUAN\x03\x00");
+        assert_eq!(
+            bytes.len(),
+            98 + 4 + "名詞".len() + 4 + "トーキョー".len() + 1 + 4 + "東京".len()
+        );
+        assert_eq!(&bytes[6..10], &7u32.to_le_bytes());
+        assert_eq!(bytes[bytes.len() - 1], 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_json_is_stable() {
+        // Pins the field names; renaming one breaks every consumer of the JSON.
+        let json = serde_json::to_string(&sample()).unwrap();
+        assert_eq!(
+            json,
+            concat!(
+                r#"{"text":"東京X","tokens":["#,
+                r#"{"surface":"東京","reading":"トーキョー","pos_id":3,"pos":"名詞","word_cost":-40,"#,
+                r#""is_unknown":false,"is_punctuation":false,"start_byte":0,"end_byte":6,"#,
+                r#""pronunciation":"トーキョー","accent":0,"lemma":"東京","start":0,"end":2,"path_cost":-40},"#,
+                r#"{"surface":"X","reading":"X","pos_id":0,"word_cost":0,"is_unknown":true,"#,
+                r#""is_punctuation":false,"start_byte":6,"end_byte":7,"start":2,"end":3,"#,
+                r#""path_cost":9960}"#,
+                r#"],"total_cost":9960}"#
+            )
+        );
+        let back: Analysis = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, sample());
+    }
+
+    #[test]
+    fn test_reads_earlier_versions() {
+        let mut analysis = sample();
+        analysis.tokens[0].token.pronunciation = None;
+        analysis.tokens[0].token.accent = None;
+        analysis.tokens[0].token.lemma = None;
+        let mut bytes = analysis.to_bytes();
+        bytes[4] = 2;
+        assert_eq!(Analysis::from_bytes(&bytes).unwrap(), analysis);
+
+        analysis.tokens[0].token.pos = None;
+        let mut bytes = analysis.to_bytes();
+        bytes[4] = 1;
+        assert_eq!(Analysis::from_bytes(&bytes).unwrap(), analysis);
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let bytes = sample().to_bytes();
+        assert!(Analysis::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Analysis::from_bytes(b"NOPE\x01\x00").is_err());
+        let mut future = bytes.clone();
+        future[4] = 4;
+        assert!(Analysis::from_bytes(&future).is_err());
+        let mut trailing = bytes;
+        trailing.push(0);
+        assert!(Analysis::from_bytes(&trailing).is_err());
+    }
+
+    #[test]
+    fn test_differences() {
+        let before = sample();
+        let mut after = sample();
+        after.tokens[0].token.reading = "ヒガシキョー".to_string();
+        after.tokens.pop();
+        let diffs = after.differences(&before);
+        assert_eq!(diffs.len(), 2);
+        assert!(diffs[0].starts_with("token 0: 0..2 東京/トーキョー"));
+        assert!(diffs[1].starts_with("token 1: removed"));
+    }
+}
@@ -0,0 +1,107 @@
+//! Converting many texts at once on a rayon thread pool.
+
+use std::sync::Arc;
+
+use rayon::prelude::*;
+
+use crate::{transliterate, Dictionary, DictionaryData, MucabError};
+
+/// [`transliterate`]s every text of `texts` on `parallelism` threads, or on rayon's global pool
+/// if it is 0, returning the readings in input order.
+///
+/// A [`Dictionary`] is opened on `data` for each split of `texts` rayon hands a worker, and kept
+/// for every text of that split. A thread can take many splits, so more handles are opened than
+/// there are threads, but far fewer than there are texts. The handles share the block cache of
+/// `data`: a block one of them decodes is there for the others, and warming the cache, with
+/// [`Dictionary::preload_all`] for instance, pays off for all of them.
+///
+/// A text whose conversion met an error, as [`Dictionary::take_error`] reports it, gets that
+/// error instead of its reading; so does every text of a split whose handle could not be
+/// opened, and every text if the pool could not be built.
+pub fn transliterate_batch(
+    texts: &[&str],
+    data: &Arc<DictionaryData>,
+    parallelism: usize,
+) -> Vec<Result<String, MucabError>> {
+    let convert = || {
+        texts
+            .par_iter()
+            .map_init(
+                || Dictionary::with_data(data.clone()),
+                |dict, text| match dict {
+                    Ok(dict) => {
+                        let reading = transliterate(text, dict);
+                        match dict.take_error() {
+                            Some(error) => Err(error),
+                            None => Ok(reading),
+                        }
+                    }
+                    Err(e) => Err(MucabError::Io(std::io::Error::new(e.kind(), e.to_string()))),
+                },
+            )
+            .collect()
+    };
+    if parallelism == 0 {
+        return convert();
+    }
+    match rayon::ThreadPoolBuilder::new()
+        .num_threads(parallelism)
+        .build()
+    {
+        Ok(pool) => pool.install(convert),
+        Err(e) => texts
+            .iter()
+            .map(|_| Err(MucabError::Io(std::io::Error::other(e.to_string()))))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DictionaryBuilder;
+    #[cfg(feature = "fs")]
+    use crate::testutil::fixture;
+
+    const ENTRIES: [(&str, &str, u16, i16); 3] = [
+        ("東京", "トーキョー", 0, 100),
+        ("京都", "キョート", 0, 100),
+        ("都", "ト", 0, 100),
+    ];
+
+    #[test]
+    fn test_batch_keeps_input_order() {
+        let mut builder = DictionaryBuilder::new();
+        for (surface, reading, pos_id, cost) in ENTRIES {
+            builder.add_entry(surface, reading, pos_id, cost);
+        }
+        builder.set_matrix(vec![0], 1);
+        builder.compress(false);
+        let mut bytes = Vec::new();
+        builder.write(&mut bytes).unwrap();
+        let mut dict = Dictionary::from_bytes(bytes).unwrap();
+
+        let texts: Vec<String> = (0..500)
+            .map(|i| ["東京", "京都", "東京都", "X"][i % 4].repeat(i % 3 + 1))
+            .collect();
+        let texts: Vec<&str> = texts.iter().map(String::as_str).collect();
+        let expected: Vec<String> = texts.iter().map(|t| transliterate(t, &mut dict)).collect();
+        for parallelism in [0, 1, 4] {
+            let readings: Vec<String> = transliterate_batch(&texts, dict.data(), parallelism)
+                .into_iter()
+                .map(Result::unwrap)
+                .collect();
+            assert_eq!(readings, expected);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_batch_reads_a_file_removed_since_loading() {
+        // Handles share the data's open file rather than opening the path again.
+        let gone = fixture(&ENTRIES, 1);
+        let results = transliterate_batch(&["東京", "京都", "都"], gone.data(), 2);
+        let readings: Vec<String> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(readings, ["トーキョー", "キョート", "ト"]);
+    }
+}
@@ -1,360 +1,2922 @@
-use encoding_rs::{EUC_JP, UTF_8};
+use encoding_rs::EUC_JP;
 use glob::glob;
+use mucab::builder::{BuildStats, DictionaryBuilder, Entry};
+use mucab::overrides::CostOverride;
+use mucab::unknown::{CharDefinitions, UnknownTemplate};
+use mucab::{user, KanaForm};
+use rayon::prelude::*;
 use regex::Regex;
-use std::collections::HashMap;
+use std::collections::{hash_map, BTreeMap, HashMap, HashSet};
 use std::env;
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Read, Write};
-use zeekstd::{EncodeOptions, Encoder, FrameSizePolicy};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-const HEADER_SIZE: u32 = 16;
-const ENTRY_METADATA_SIZE: u32 = 9;
-const INDEX_ENTRY_SIZE: u32 = 10;
+/// Removes `flag` and the value after it from `args`, returning the value.
+fn take_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == flag)?;
+    let value = args.get(i + 1).cloned().unwrap_or_default();
+    args.drain(i..(i + 2).min(args.len()));
+    Some(value)
+}
+
+/// Removes every `flag` and the value after it from `args`, returning the values in order.
+fn take_values(args: &mut Vec<String>, flag: &str) -> Vec<String> {
+    std::iter::from_fn(|| take_value(args, flag)).collect()
+}
+
+/// Removes `flag` from `args`, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|a| a == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Exit code for bad arguments. 1 means the run worked but found something, such as
+/// `--inspect --verify` finding problems.
+const EXIT_USAGE: i32 = 2;
+/// Exit code for malformed sources, or a dictionary file that can't be used.
+const EXIT_DICTIONARY: i32 = 3;
+/// Exit code for a file that can't be read or written.
+const EXIT_IO: i32 = 4;
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_USAGE);
+}
+
+/// Why a conversion, inspection or export failed.
+#[derive(Debug)]
+enum Failure {
+    /// Malformed sources, or a file that isn't a usable dictionary.
+    Dictionary(String),
+    /// A file that couldn't be read or written.
+    Io(String),
+}
+
+impl Failure {
+    /// Sorts `e` by its kind: bad data is a dictionary failure, anything else an I/O one.
+    fn from_io(context: impl std::fmt::Display, e: std::io::Error) -> Self {
+        let message = format!("{}: {}", context, e);
+        match e.kind() {
+            std::io::ErrorKind::InvalidData
+            | std::io::ErrorKind::InvalidInput
+            | std::io::ErrorKind::UnexpectedEof => Failure::Dictionary(message),
+            _ => Failure::Io(message),
+        }
+    }
+
+    /// Prefixes the message with what was being done.
+    fn context(self, context: &str) -> Self {
+        match self {
+            Failure::Dictionary(message) => {
+                Failure::Dictionary(format!("{}: {}", context, message))
+            }
+            Failure::Io(message) => Failure::Io(format!("{}: {}", context, message)),
+        }
+    }
+
+    fn exit(&self) -> ! {
+        eprintln!("{}", self);
+        std::process::exit(match self {
+            Failure::Dictionary(_) => EXIT_DICTIONARY,
+            Failure::Io(_) => EXIT_IO,
+        });
+    }
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Dictionary(message) | Failure::Io(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<String> for Failure {
+    fn from(message: String) -> Self {
+        Failure::Dictionary(message)
+    }
+}
+
+/// How mucab.bin is written, from `--format-version`, `--level`, `--frame-size`,
+/// `--no-compress` and `--reading-kana`.
+#[derive(Debug, Clone, Copy, Default)]
+struct OutputOptions {
+    /// The newest format unless given, for readers built against an older mucab.
+    format_version: Option<u16>,
+    level: Option<i32>,
+    frame_size: Option<u32>,
+    disabled: bool,
+    /// The form readings are stored in; katakana, as the sources have them, unless given.
+    reading_kana: KanaForm,
+}
+
+impl OutputOptions {
+    fn apply(&self, builder: &mut DictionaryBuilder) {
+        if let Some(version) = self.format_version {
+            builder.format_version(version);
+        }
+        if let Some(level) = self.level {
+            builder.compression_level(level);
+        }
+        if let Some(bytes) = self.frame_size {
+            builder.frame_size(bytes);
+        }
+        builder.compress(!self.disabled);
+        builder.reading_kana(self.reading_kana);
+    }
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 4 {
+    let mut args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("--inspect") {
+        let first_char = take_value(&mut args, "--char").map(|value| {
+            let mut chars = value.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => c,
+                _ => usage_error("--char takes a single character"),
+            }
+        });
+        let verify = take_flag(&mut args, "--verify");
+        if args.len() != 3 {
+            usage_error(&format!(
+                "Usage: {} --inspect [--char C] [--verify] <mucab.bin>",
+                args[0]
+            ));
+        }
+        match inspect(&args[2], first_char, verify) {
+            Ok(true) => {}
+            Ok(false) => std::process::exit(1),
+            Err(e) => Failure::from_io(&args[2], e).exit(),
+        }
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("--export") {
+        if args.len() != 4 {
+            usage_error(&format!(
+                "Usage: {} --export <mucab.bin> <output_dir>",
+                args[0]
+            ));
+        }
+        if let Err(e) = export(&args[2], &args[3]) {
+            Failure::from_io(&args[2], e).exit();
+        }
+        return;
+    }
+    let encoding = take_value(&mut args, "--encoding").map(|value| {
+        InputEncoding::parse(&value)
+            .unwrap_or_else(|| usage_error("encoding must be one of euc-jp, utf-8 or auto"))
+    });
+    let kanji_only = take_flag(&mut args, "--kanji-only");
+    let strict = take_flag(&mut args, "--strict");
+    let report = take_value(&mut args, "--report");
+    let reading_column =
+        take_value(&mut args, "--reading-column").map(|value| match value.parse() {
+            Ok(column) if column >= FIRST_READING_COLUMN => column,
+            _ => usage_error(&format!(
+                "reading column must be a number from {} up, the surface being column 0",
+                FIRST_READING_COLUMN
+            )),
+        });
+    let pos_filter = PosFilter::new(
+        &take_values(&mut args, "--include-pos"),
+        &take_values(&mut args, "--exclude-pos"),
+    );
+    let compiled = take_flag(&mut args, "--compiled");
+    let extra_fields = take_flag(&mut args, "--extra-fields");
+    let with_lemma = take_flag(&mut args, "--with-lemma");
+    let overrides = take_values(&mut args, "--overrides")
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+    let use_matrix = !take_flag(&mut args, "--no-matrix");
+    let print_stats = take_flag(&mut args, "--stats");
+    let stats_json = take_value(&mut args, "--stats-json");
+    if stats_json.is_some() && !cfg!(feature = "serde") {
+        usage_error("--stats-json needs mucab built with the serde feature");
+    }
+    let output_options = OutputOptions {
+        format_version: take_value(&mut args, "--format-version").map(|value| {
+            match value.parse() {
+                Ok(version) if mucab::SUPPORTED_FORMAT_VERSIONS.contains(&version) => version,
+                _ => usage_error(&format!(
+                    "format version must be a number from {} to {}",
+                    mucab::SUPPORTED_FORMAT_VERSIONS.start(),
+                    mucab::SUPPORTED_FORMAT_VERSIONS.end()
+                )),
+            }
+        }),
+        level: take_value(&mut args, "--level").map(|value| match value.parse() {
+            Ok(level) if (1..=22).contains(&level) => level,
+            _ => usage_error("level must be a number from 1 to 22"),
+        }),
+        frame_size: take_value(&mut args, "--frame-size").map(|value| match value.parse() {
+            Ok(bytes) if bytes > 0 => bytes,
+            _ => usage_error("frame size must be a positive number of bytes"),
+        }),
+        disabled: take_flag(&mut args, "--no-compress"),
+        reading_kana: take_value(&mut args, "--reading-kana").map_or(KanaForm::Katakana, |value| {
+            match value.as_str() {
+                "katakana" => KanaForm::Katakana,
+                "hiragana" => KanaForm::Hiragana,
+                _ => usage_error("reading kana must be either of katakana or hiragana"),
+            }
+        }),
+    };
+    let on_duplicate = take_value(&mut args, "--on-duplicate").map(|value| {
+        OnDuplicate::parse(&value)
+            .unwrap_or_else(|| usage_error("on-duplicate must be one of min-cost, first or error"))
+    });
+    if args.len() < 4 || args[1] == "--user" && args.len() != 4 {
         eprintln!(
-            "Usage: {} --ipadic|--unidic <input_dir> <output_dir>",
+            "Usage: {} --ipadic|--unidic [--encoding euc-jp|utf-8|auto] [--kanji-only] \
+             [--reading-column N] [--format-version N] [--level N] [--frame-size BYTES] [--no-compress] \
+             [--reading-kana katakana|hiragana] [--on-duplicate min-cost|first|error] [--no-matrix] [--include-pos POS]... \
+             [--exclude-pos POS]... [--strict] [--report FILE] [--stats] [--stats-json FILE] \
+             [--compiled] [--extra-fields] [--with-lemma] [--overrides FILE]... <input>... \
+             <output_dir>",
             args[0]
         );
-        std::process::exit(1);
+        eprintln!(
+            "       {} --user [--format-version N] [--level N] [--frame-size BYTES] [--no-compress] \
+             [--reading-kana katakana|hiragana] <user.csv> <output_dir>",
+            args[0]
+        );
+        eprintln!(
+            "       {} --inspect [--char C] [--verify] <mucab.bin>",
+            args[0]
+        );
+        eprintln!("       {} --export <mucab.bin> <output_dir>", args[0]);
+        std::process::exit(EXIT_USAGE);
+    }
+    if args[1] == "--user" {
+        if let Err(e) = convert_user_dictionary(&args[2], &args[3], output_options) {
+            e.exit();
+        }
+        return;
     }
 
     let mode = match args[1].as_str() {
         "--ipadic" => Mode::Ipadic,
         "--unidic" => Mode::Unidic,
-        _ => {
-            eprintln!("mode must be either of --ipadic or --unidic");
-            std::process::exit(1);
-        }
+        _ => usage_error("mode must be either of --ipadic or --unidic"),
+    };
+    // The encodings the distributions ship in unless told otherwise.
+    let encoding = encoding.unwrap_or(match mode {
+        Mode::Ipadic => InputEncoding::EucJp,
+        Mode::Unidic => InputEncoding::Utf8,
+    });
+    let (output_dir, inputs) = args[2..].split_last().unwrap();
+    let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+
+    let options = CsvOptions {
+        mode,
+        reading_column,
+        encoding,
+        compiled,
+        extra_fields,
+        with_lemma,
+        kanji_only,
+        on_duplicate: on_duplicate.unwrap_or_default(),
+        pos_filter,
+        strict,
+        report: report.map(PathBuf::from),
+        overrides,
+    };
+    let stats = match convert_dictionary(&inputs, output_dir, options, output_options, use_matrix) {
+        Ok(stats) => stats,
+        Err(e) => e.exit(),
     };
-    let input_dir = &args[2];
-    let output_dir = &args[3];
+    println!("Conversion complete!");
+    if print_stats {
+        stats.print();
+    }
+    if let Some(path) = stats_json {
+        if let Err(e) = write_stats_json(&stats, &path) {
+            Failure::from_io(&path, e).exit();
+        }
+    }
+}
 
-    std::fs::create_dir_all(output_dir).expect("Failed to create output directory");
+/// Converts the MeCab dictionary sources `inputs` into `output_dir/mucab.bin`. Each input is a
+/// directory of CSVs or a single CSV; matrix.def, char.def and unk.def come from the first
+/// directory that has a matrix.def, or char.def and unk.def from the first directory if none
+/// does. With `options.compiled`, each input is instead a directory of a compiled dictionary,
+/// whose sys.dic holds the entries and matrix.bin takes the place of matrix.def; char.bin and
+/// unk.dic are not read, so unknown words only get character definitions from a char.def and
+/// unk.def shipped alongside. The same inputs always produce the same bytes: files are read in name order and compact
+/// ids follow the raw context ids.
+///
+/// Entries `options.pos_filter` drops take no part in assigning compact ids, so the context ids
+/// only they used leave the matrix too.
+///
+/// Without a matrix.def, or when `use_matrix` is false, every connection cost is zero: the
+/// dictionary still loads and segments, but only by word costs, so it can't tell which words
+/// follow each other well.
+///
+/// Returns the statistics `--stats` reports.
+fn convert_dictionary(
+    inputs: &[&str],
+    output_dir: &str,
+    options: CsvOptions,
+    output_options: OutputOptions,
+    use_matrix: bool,
+) -> Result<DictionaryStats, Failure> {
+    let matrix_file = if options.compiled {
+        "matrix.bin"
+    } else {
+        "matrix.def"
+    };
+    let matrix_dir = inputs
+        .iter()
+        .find(|input| Path::new(input).join(matrix_file).is_file());
+    let base_dir = matrix_dir.or_else(|| inputs.iter().find(|input| Path::new(input).is_dir()));
+    std::fs::create_dir_all(output_dir)
+        .map_err(|e| Failure::from_io("Failed to create output directory", e))?;
 
-    println!("Processing CSV files from {}...", input_dir);
-    let (pos_id_map, entries) = process_csv_files(input_dir, mode);
-    println!("Found {} unique pos_ids", pos_id_map.len());
+    println!("Processing dictionary files from {}...", inputs.join(", "));
+    let (mut id_maps, mut entries) = process_csv_files(inputs, &options)?;
+    let mut char_defs = match base_dir {
+        Some(dir) => load_char_definitions(dir, options.encoding, &mut id_maps)
+            .map_err(|e| e.context("Failed to load char.def/unk.def"))?,
+        None => None,
+    };
+    id_maps.renumber(&mut entries, char_defs.as_mut());
+    for path in &options.overrides {
+        let text =
+            std::fs::read_to_string(path).map_err(|e| Failure::from_io(path.display(), e))?;
+        let overrides = mucab::overrides::parse_overrides(&text)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let unmatched = apply_overrides(&mut entries, &overrides, output_options.reading_kana);
+        println!(
+            "Applied {} of {} overrides from {}",
+            overrides.len() - unmatched.len(),
+            overrides.len(),
+            path.display()
+        );
+        if let Some(o) = unmatched.first() {
+            if options.strict {
+                return Err(Failure::Dictionary(format!(
+                    "{}: no entry is {},{}",
+                    path.display(),
+                    o.surface,
+                    o.reading
+                )));
+            }
+        }
+    }
+    if options.pos_filter.is_active() {
+        let (left, right) = id_maps.dropped_ids();
+        println!(
+            "Dropped {} entries by POS, and with them {} left ids and {} right ids",
+            id_maps.dropped_entries, left, right
+        );
+    }
+    match &char_defs {
+        Some(defs) => println!("Loaded {} character categories", defs.categories.len()),
+        None => println!("No char.def/unk.def found, unknown words use a flat cost"),
+    }
+    println!(
+        "Found {} unique left ids and {} unique right ids",
+        id_maps.left.len(),
+        id_maps.right.len()
+    );
     println!("Processed {} entries", entries.len());
 
-    let matrix_path = format!("{}/matrix.def", input_dir);
-    let (matrix_data, matrix_size) =
-        load_matrix(&matrix_path, &pos_id_map).expect("Failed to load matrix");
+    let (matrix_data, right_size, left_size) = match matrix_dir.filter(|_| use_matrix) {
+        Some(dir) => {
+            let matrix_path = format!("{}/{}", dir, matrix_file);
+            let matrix = if options.compiled {
+                load_matrix_bin(Path::new(&matrix_path), &id_maps)
+            } else {
+                load_matrix(&matrix_path, options.encoding, &id_maps)
+            };
+            matrix.map_err(|e| e.context("Failed to load matrix"))?
+        }
+        None => {
+            println!(
+                "No {} used: all connection costs are zero, so words are chosen by \
+                 their own cost alone and segmentation will be worse",
+                matrix_file
+            );
+            let (right_size, left_size) = (id_maps.right.len(), id_maps.left.len());
+            (vec![0; right_size * left_size], right_size, left_size)
+        }
+    };
 
     println!(
         "Matrix: {}x{} = {} entries ({} bytes)",
-        matrix_size,
-        matrix_size,
+        right_size,
+        left_size,
         matrix_data.len(),
         matrix_data.len() * 2
     );
-    println!("{:?}", matrix_data[0]);
+    println!("{:?}", matrix_data.first());
 
+    let mut stats = DictionaryStats::collect(&entries, &matrix_data, left_size, &id_maps);
     let output_path = format!("{}/mucab.bin", output_dir);
-    write_binary(&output_path, &entries, &matrix_data, matrix_size as u16)
-        .expect("Failed to write binary");
+    let mut builder = DictionaryBuilder::new();
+    output_options.apply(&mut builder);
+    builder.extend_entries(entries);
+    let build_stats = write_binary(
+        &output_path,
+        builder,
+        matrix_data,
+        right_size as u16,
+        left_size as u16,
+        char_defs,
+        &id_maps.pos_names,
+    )
+    .map_err(|e| Failure::from_io("Failed to write binary", e))?;
     println!("Wrote {}", output_path);
+    stats.add_sections(&build_stats);
+    Ok(stats)
+}
 
-    println!("Conversion complete!");
+/// Prints the header and section sizes of the dictionary at `path`, then the entries under
+/// `first_char` and the result of verifying every entry if asked. Returns whether verification
+/// passed (or was not asked for).
+fn inspect(path: &str, first_char: Option<char>, verify: bool) -> std::io::Result<bool> {
+    let mut dict = mucab::Dictionary::load(path)?;
+    let info = dict.info()?;
+    println!("Format version: {}", info.format_version);
+    match info.checksum {
+        Some(checksum) => println!("Checksum: {:016x} (xxh64)", checksum),
+        None => println!("Checksum: none"),
+    }
+    println!(
+        "Matrix: {} right ids x {} left ids",
+        info.right_size, info.left_size
+    );
+    println!("Entries: {}", info.num_entries);
+    println!("Index keys: {}", info.index_keys);
+    println!("Character categories: {}", info.char_categories);
+    println!(
+        "Entries and strings: {} bytes, {} bytes in the file ({})",
+        info.uncompressed_bytes,
+        info.region_bytes,
+        if info.compressed {
+            "compressed"
+        } else {
+            "uncompressed"
+        }
+    );
+    println!(
+        "Strings: {} bytes at offset {}",
+        info.uncompressed_bytes.saturating_sub(info.strings_offset),
+        info.strings_offset
+    );
+    println!("Readings: {:?}", info.reading_kana);
+
+    if let Some(c) = first_char {
+        let entries = dict.entries_starting_with(c);
+        println!("{} entries start with {:?}:", entries.len(), c);
+        for (entry, reading) in entries {
+            println!(
+                "  {}\t{}\tcost {}\tleft {}\tright {}{}",
+                entry.surface,
+                reading,
+                entry.word_cost,
+                entry.pos_id,
+                entry.right_id,
+                dict.pos_name(entry.pos_id)
+                    .map(|name| format!("\t{}", name))
+                    .unwrap_or_default()
+            );
+        }
+    }
+
+    if !verify {
+        return Ok(true);
+    }
+    let report = dict.verify()?;
+    for problem in &report.problems {
+        eprintln!("{}", problem);
+    }
+    if report.is_sound() {
+        println!(
+            "Verified {} entries in {} blocks, {} stored readings",
+            report.entries, report.blocks, report.readings
+        );
+    } else {
+        if report.problem_count > report.problems.len() {
+            eprintln!(
+                "... and {} more",
+                report.problem_count - report.problems.len()
+            );
+        }
+        eprintln!("{} problems found", report.problem_count);
+    }
+    Ok(report.is_sound())
 }
 
-struct Entry {
-    surface: String,
-    pos_id: u16,
-    cost: i16,
-    reading: String,
+/// Quotes `field` for a dictionary CSV if it holds a comma or a quote.
+fn csv_field(field: &str) -> std::borrow::Cow<'_, str> {
+    if field.contains([',', '"']) {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+/// Writes the dictionary at `path` back out as IPADIC-style sources in `output_dir`: lex.csv,
+/// matrix.def, and char.def and unk.def if it has character definitions. The ids are the
+/// dictionary's compact ids and the files are UTF-8, so that `--ipadic --encoding utf-8` on
+/// `output_dir` rebuilds the same binary from a converter-built one.
+fn export(path: &str, output_dir: &str) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut dict = mucab::Dictionary::load(path)?;
+    let info = dict.info()?;
+    std::fs::create_dir_all(output_dir)?;
+    let output = Path::new(output_dir);
+
+    // Every reading is needed, so decompress once rather than seeking per entry.
+    dict.preload_all()?;
+    let mut lex = std::io::BufWriter::new(std::fs::File::create(output.join("lex.csv"))?);
+    let mut num_entries = 0;
+    let first_chars: Vec<char> = dict.index_keys().into_keys().collect();
+    for c in first_chars {
+        for (entry, reading) in dict.entries_starting_with(c) {
+            num_entries += 1;
+            let features = dict.pos_name(entry.pos_id).unwrap_or("*,*,*,*,*,*");
+            let surface = csv_field(&entry.surface);
+            let reading = csv_field(&reading);
+            writeln!(
+                lex,
+                "{},{},{},{},{},{},{},{}",
+                surface,
+                entry.pos_id,
+                entry.right_id,
+                entry.word_cost,
+                features,
+                surface,
+                reading,
+                reading
+            )?;
+        }
+    }
+    lex.flush()?;
+
+    let data = dict.data();
+    let mut matrix = std::io::BufWriter::new(std::fs::File::create(output.join("matrix.def"))?);
+    writeln!(matrix, "{} {}", info.right_size, info.left_size)?;
+    for (prev_id, curr_id, cost) in data.connection_costs() {
+        writeln!(matrix, "{} {} {}", prev_id, curr_id, cost)?;
+    }
+    matrix.flush()?;
+
+    if let Some(defs) = data.char_definitions() {
+        std::fs::write(output.join("char.def"), defs.to_char_def())?;
+        std::fs::write(output.join("unk.def"), defs.to_unk_def())?;
+    }
+    println!(
+        "Exported {} entries and a {}x{} matrix to {}",
+        num_entries, info.right_size, info.left_size, output_dir
+    );
+    Ok(())
+}
+
+/// Builds a user dictionary from `surface,reading[,cost[,left_id[,right_id]]]` lines.
+fn convert_user_dictionary(
+    input_path: &str,
+    output_dir: &str,
+    output_options: OutputOptions,
+) -> Result<(), Failure> {
+    let text = std::fs::read_to_string(input_path).map_err(|e| Failure::from_io(input_path, e))?;
+    let entries = user::parse_user_entries(&text).map_err(|e| format!("{}: {}", input_path, e))?;
+    println!("Read {} user entries", entries.len());
+
+    std::fs::create_dir_all(output_dir)
+        .map_err(|e| Failure::from_io("Failed to create output directory", e))?;
+    let output_path = format!("{}/mucab.bin", output_dir);
+    let mut builder = user::user_dictionary_builder(entries);
+    output_options.apply(&mut builder);
+    let stats = builder
+        .write_to_file(&output_path)
+        .map_err(|e| Failure::from_io("Failed to write binary", e))?;
+    println!(
+        "Wrote {} ({} index keys, {} compressed bytes)",
+        output_path, stats.index_keys, stats.compressed_bytes
+    );
+    Ok(())
 }
 
+/// Column layout of the dictionary CSVs. Both start with the surface, left id, right id and
+/// cost, followed by the POS columns.
+#[derive(Clone, Copy)]
 enum Mode {
+    /// The reading is column 12.
     Ipadic,
+    /// The reading is column 13, `pron`: the pronunciation, as IPADIC readings are (トーキョー
+    /// for 東京). `kana`, column 21 of unidic-mecab 2.1.2, spells the word as written instead
+    /// (トウキョウ); `--reading-column` picks it.
     Unidic,
 }
 
-fn process_csv_files(input_dir: &str, mode: Mode) -> (HashMap<i16, u16>, Vec<Entry>) {
-    let pattern = format!("{}/*.csv", input_dir);
-    let han_regex = Regex::new(r"^\p{Han}+").unwrap();
+impl Mode {
+    fn reading_column(self) -> usize {
+        match self {
+            Mode::Ipadic => 12,
+            Mode::Unidic => 13,
+        }
+    }
 
-    let mut pos_id_map: HashMap<i16, u16> = HashMap::new();
-    let mut entries = Vec::new();
+    /// The pronunciation, which `--extra-fields` stores where it differs from the reading: the
+    /// reading column by default, so only a `--reading-column` such as IPADIC's 11 or UniDic's
+    /// 21, which spell the particle は as ハ where it is pronounced ワ, gives entries one.
+    fn pronunciation_column(self) -> usize {
+        match self {
+            Mode::Ipadic => 12,
+            Mode::Unidic => 13,
+        }
+    }
+
+    /// The base form `--with-lemma` stores: IPADIC's 原形, 食べる for 食べ, and UniDic's
+    /// `orthBase`, the base form as the entry spells it.
+    fn lemma_column(self) -> usize {
+        match self {
+            Mode::Ipadic => 10,
+            Mode::Unidic => 14,
+        }
+    }
 
-    let (surface_idx, left_idx, right_idx, cost_idx, reading_idx, encoding) = match mode {
-        Mode::Ipadic => (0, 1, 2, 3, 12, EUC_JP),
-        Mode::Unidic => (0, 1, 2, 3, 13, UTF_8),
+    /// `aType`, the accent type, which only UniDic has.
+    fn accent_column(self) -> Option<usize> {
+        match self {
+            Mode::Ipadic => None,
+            Mode::Unidic => Some(27),
+        }
+    }
+}
+
+/// Columns before the first one a reading can be in: surface, context ids and cost.
+const FIRST_READING_COLUMN: usize = 4;
+
+/// Charset of the dictionary sources.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum InputEncoding {
+    EucJp,
+    Utf8,
+    /// Per file: UTF-8 if it has a BOM or is valid UTF-8, EUC-JP otherwise.
+    Auto,
+}
+
+impl InputEncoding {
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "euc-jp" | "eucjp" => Some(InputEncoding::EucJp),
+            "utf-8" | "utf8" => Some(InputEncoding::Utf8),
+            "auto" => Some(InputEncoding::Auto),
+            _ => None,
+        }
+    }
+}
+
+/// Decodes the contents of `path`, failing on any byte sequence invalid in the charset rather
+/// than replacing it.
+fn decode(path: &Path, bytes: &[u8], encoding: InputEncoding) -> Result<String, String> {
+    let utf8 = |bytes: &[u8]| std::str::from_utf8(bytes).ok().map(str::to_string);
+    let euc_jp = |bytes: &[u8]| {
+        EUC_JP
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|text| text.into_owned())
     };
+    let decoded = match encoding {
+        InputEncoding::Utf8 => utf8(bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes)),
+        InputEncoding::EucJp => euc_jp(bytes),
+        InputEncoding::Auto => match bytes.strip_prefix(b"\xEF\xBB\xBF") {
+            Some(rest) => utf8(rest),
+            None => utf8(bytes).or_else(|| euc_jp(bytes)),
+        },
+    };
+    decoded.ok_or_else(|| {
+        let expected = match encoding {
+            InputEncoding::Utf8 => "UTF-8",
+            InputEncoding::EucJp => "EUC-JP",
+            InputEncoding::Auto => "UTF-8 or EUC-JP",
+        };
+        format!("{}: not valid {}", path.display(), expected)
+    })
+}
+
+fn read_decoded(path: &Path, encoding: InputEncoding) -> Result<String, Failure> {
+    let bytes = std::fs::read(path).map_err(|e| Failure::from_io(path.display(), e))?;
+    Ok(decode(path, &bytes, encoding)?)
+}
+
+/// Compact ids assigned to the raw matrix.def context ids that survive filtering.
+#[derive(Default)]
+struct IdMaps {
+    left: HashMap<i16, u16>,
+    right: HashMap<i16, u16>,
+    /// POS feature columns of the first entry seen with each compact left id, and right id.
+    pos_names: HashMap<u16, String>,
+    right_pos_names: HashMap<u16, String>,
+    /// Entries left out by [`PosFilter`], and the raw ids they had.
+    dropped_entries: usize,
+    dropped_left: HashSet<i16>,
+    dropped_right: HashSet<i16>,
+}
+
+impl IdMaps {
+    /// Renumbers the compact ids in ascending order of the raw ids, so that they don't depend on
+    /// the order entries were read in, and moves `entries` and `char_defs` to the new ids.
+    fn renumber(&mut self, entries: &mut [Entry], char_defs: Option<&mut CharDefinitions>) {
+        let left = renumber_ids(&mut self.left);
+        let right = renumber_ids(&mut self.right);
+        for entry in entries {
+            entry.pos_id = left[entry.pos_id as usize];
+            entry.right_id = right[entry.right_id as usize];
+        }
+        let templates = char_defs
+            .into_iter()
+            .flat_map(|defs| &mut defs.categories)
+            .flat_map(|category| &mut category.templates);
+        for template in templates {
+            template.left_id = left[template.left_id as usize];
+            template.right_id = right[template.right_id as usize];
+        }
+        self.pos_names = std::mem::take(&mut self.pos_names)
+            .into_iter()
+            .map(|(pos_id, name)| (left[pos_id as usize], name))
+            .collect();
+        self.right_pos_names = std::mem::take(&mut self.right_pos_names)
+            .into_iter()
+            .map(|(right_id, name)| (right[right_id as usize], name))
+            .collect();
+    }
+
+    /// How many left and right ids were only used by entries [`PosFilter`] left out.
+    fn dropped_ids(&self) -> (usize, usize) {
+        let count = |dropped: &HashSet<i16>, kept: &HashMap<i16, u16>| {
+            dropped.iter().filter(|raw| !kept.contains_key(raw)).count()
+        };
+        (
+            count(&self.dropped_left, &self.left),
+            count(&self.dropped_right, &self.right),
+        )
+    }
+}
+
+/// Reassigns the compact ids of `map` by raw id order. Returns the new id of each old one.
+fn renumber_ids(map: &mut HashMap<i16, u16>) -> Vec<u16> {
+    let mut raw_ids: Vec<i16> = map.keys().copied().collect();
+    raw_ids.sort_unstable();
+    let mut new_ids = vec![0; raw_ids.len()];
+    for (new_id, raw) in raw_ids.into_iter().enumerate() {
+        let old_id = map.insert(raw, new_id as u16).unwrap();
+        new_ids[old_id as usize] = new_id as u16;
+    }
+    new_ids
+}
+
+fn compact_id(map: &mut HashMap<i16, u16>, raw: i16) -> u16 {
+    let len = map.len();
+    *map.entry(raw).or_insert_with(|| {
+        let id = len as u16;
+        if id == 65535 {
+            panic!("Too many unique pos_ids! Maximum is 65535.");
+        }
+        id
+    })
+}
 
+/// The POS, its subcategories and the conjugation type and form, in both IPADIC and UniDic.
+const POS_COLUMNS: std::ops::RangeInclusive<usize> = 4..=9;
+
+/// What to do with an entry that has the surface, context ids and reading of an earlier one but
+/// a different cost. Exact duplicates are always dropped.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum OnDuplicate {
+    /// Keep the lower cost.
+    #[default]
+    MinCost,
+    /// Keep the entry read first.
+    First,
+    Error,
+}
+
+impl OnDuplicate {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "min-cost" => Some(OnDuplicate::MinCost),
+            "first" => Some(OnDuplicate::First),
+            "error" => Some(OnDuplicate::Error),
+            _ => None,
+        }
+    }
+}
+
+/// The CSV files of `input`: the file itself, or every `*.csv` in it if it is a directory. They
+/// are sorted by name, so that ties between equal surfaces and the first POS name seen per id
+/// don't depend on the file system.
+fn csv_paths(input: &str) -> Result<Vec<PathBuf>, Failure> {
+    let path = Path::new(input);
+    if path.is_file() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    if !path.is_dir() {
+        return Err(Failure::Io(format!("{}: no such file or directory", input)));
+    }
+    let pattern = format!("{}/*.csv", input);
+    let mut paths = Vec::new();
     for entry in glob(&pattern).expect("Failed to read glob pattern") {
         match entry {
-            Ok(path) => {
-                println!("Processing {:?}...", path);
-                let file = File::open(&path).expect("Failed to open file");
-                let mut reader = BufReader::new(file);
-
-                let mut buffer = Vec::new();
-                reader
-                    .read_to_end(&mut buffer)
-                    .expect("Failed to read file");
-
-                let (decoded, _, had_errors) = encoding.decode(&buffer);
-                if had_errors {
-                    eprintln!("Warning: encoding errors in {:?}", path);
+            Ok(path) => paths.push(path),
+            Err(e) => eprintln!("Error reading glob entry: {}", e),
+        }
+    }
+    paths.sort();
+    Ok(paths)
+}
+
+/// Which entries to keep by their POS feature columns, from `--include-pos` and `--exclude-pos`.
+/// A pattern is one or more comma-separated columns and matches the entries whose POS columns
+/// start with them: `名詞` matches every noun, `名詞,固有名詞` only proper nouns.
+#[derive(Clone, Debug, Default)]
+struct PosFilter {
+    include: Vec<Vec<String>>,
+    exclude: Vec<Vec<String>>,
+}
+
+impl PosFilter {
+    fn new(include: &[String], exclude: &[String]) -> Self {
+        let patterns = |patterns: &[String]| {
+            patterns
+                .iter()
+                .map(|pattern| pattern.split(',').map(str::to_string).collect())
+                .collect()
+        };
+        PosFilter {
+            include: patterns(include),
+            exclude: patterns(exclude),
+        }
+    }
+
+    fn is_active(&self) -> bool {
+        !self.include.is_empty() || !self.exclude.is_empty()
+    }
+
+    /// Whether an entry with the POS columns `features` is kept: it matches no exclude pattern,
+    /// and one of the include patterns if there are any.
+    fn keeps(&self, features: &[String]) -> bool {
+        let matches = |pattern: &Vec<String>| {
+            pattern.len() <= features.len() && pattern.iter().zip(features).all(|(p, f)| p == f)
+        };
+        (self.include.is_empty() || self.include.iter().any(matches))
+            && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Adds the delta of each of `overrides` to the cost of every entry with its surface and reading,
+/// as [`mucab::Dictionary::load_overrides`] does at runtime, so the CSV the `tune` tool writes
+/// can be built into the dictionary. The reading matches as in the sources or in `reading_kana`,
+/// the form a dictionary tuned before might have stored. Returns the overrides that matched no
+/// entry.
+fn apply_overrides<'o>(
+    entries: &mut [Entry],
+    overrides: &'o [CostOverride],
+    reading_kana: KanaForm,
+) -> Vec<&'o CostOverride> {
+    let mut deltas: HashMap<&str, HashMap<&str, i16>> = HashMap::new();
+    for o in overrides {
+        let delta = deltas
+            .entry(&o.surface)
+            .or_default()
+            .entry(&o.reading)
+            .or_default();
+        *delta = delta.saturating_add(o.delta);
+    }
+    let mut matched: HashSet<(&str, &str)> = HashSet::new();
+    for entry in entries.iter_mut() {
+        let Some((&surface, by_reading)) = deltas.get_key_value(entry.surface.as_str()) else {
+            continue;
+        };
+        let stored = reading_kana.apply(&entry.reading);
+        for reading in [entry.reading.as_str(), &stored] {
+            if let Some((&reading, &delta)) = by_reading.get_key_value(reading) {
+                entry.cost = entry.cost.saturating_add(delta);
+                matched.insert((surface, reading));
+                break;
+            }
+        }
+    }
+    overrides
+        .iter()
+        .filter(|o| !matched.contains(&(o.surface.as_str(), o.reading.as_str())))
+        .collect()
+}
+
+/// How to read the dictionary CSVs.
+#[derive(Clone)]
+struct CsvOptions {
+    mode: Mode,
+    /// Column of the reading, counting the surface as column 0, instead of the mode's. Rows
+    /// need as many columns as it takes to reach it.
+    reading_column: Option<usize>,
+    encoding: InputEncoding,
+    /// The inputs are compiled dictionaries, read with [`read_sys_dic`].
+    compiled: bool,
+    /// Store pronunciations and accents, from `--extra-fields`; see [`Mode::pronunciation_column`].
+    extra_fields: bool,
+    /// Store base forms, from `--with-lemma`; see [`Mode::lemma_column`].
+    with_lemma: bool,
+    /// See [`process_csv_files`].
+    kanji_only: bool,
+    on_duplicate: OnDuplicate,
+    pos_filter: PosFilter,
+    /// Fail on the first row that can't be used; see [`parse_csv_file`].
+    strict: bool,
+    /// Where to list the rows left out, from `--report`.
+    report: Option<PathBuf>,
+    /// Cost adjustments to build in, from `--overrides`; see [`apply_overrides`].
+    overrides: Vec<PathBuf>,
+}
+
+/// A row of a CSV file that passed filtering, still with the raw matrix.def context ids.
+struct Row {
+    line_no: usize,
+    surface: String,
+    left_id: i16,
+    right_id: i16,
+    cost: i16,
+    reading: String,
+    /// The POS feature columns joined with `,`, if the row has them.
+    features: Option<String>,
+    pronunciation: Option<String>,
+    accent: Option<u8>,
+    lemma: Option<String>,
+}
+
+/// Why a row was left out of the dictionary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SkipReason {
+    /// Too few columns, bad quoting, or context ids and a cost that aren't integers.
+    Malformed,
+    SurfaceTooLong,
+    CostOutOfRange,
+    ReadingTooLong,
+    /// `--kanji-only` leaves out words read as they are written; this is the only reason
+    /// `--strict` lets pass.
+    ReadingIsSurface,
+}
+
+impl SkipReason {
+    fn label(self) -> &'static str {
+        match self {
+            SkipReason::Malformed => "malformed",
+            SkipReason::SurfaceTooLong => "surface too long",
+            SkipReason::CostOutOfRange => "cost out of range",
+            SkipReason::ReadingTooLong => "reading too long",
+            SkipReason::ReadingIsSurface => "reading is the surface",
+        }
+    }
+}
+
+/// A row left out, for the summary and `--report`.
+struct Skipped {
+    line_no: usize,
+    reason: SkipReason,
+    message: String,
+    line: String,
+}
+
+/// The rows of one CSV file that passed filtering, and what was left out.
+struct ParsedCsv {
+    rows: Vec<Row>,
+    skipped: Vec<Skipped>,
+    /// Raw left and right ids of the rows [`PosFilter`] dropped.
+    dropped: Vec<(i16, i16)>,
+}
+
+/// Parses the rows of one CSV file. With `options.strict`, the first row left out for anything
+/// but `--kanji-only` fails the file.
+fn parse_csv_file(
+    path: &Path,
+    options: &CsvOptions,
+    han_regex: &Regex,
+) -> Result<ParsedCsv, Failure> {
+    let CsvOptions {
+        encoding,
+        kanji_only,
+        strict,
+        ..
+    } = *options;
+    let (surface_idx, left_idx, right_idx, cost_idx) = (0, 1, 2, 3);
+    let reading_idx = options
+        .reading_column
+        .unwrap_or(options.mode.reading_column());
+    let decoded = if options.compiled {
+        read_sys_dic(path)?
+    } else {
+        read_decoded(path, encoding)?
+    };
+    let mut rows = Vec::new();
+    let mut skipped = Vec::new();
+    let mut dropped = Vec::new();
+
+    for (line_no, line) in decoded.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let mut skip = |reason, message: String| {
+            if strict && reason != SkipReason::ReadingIsSurface {
+                return Err(Failure::Dictionary(format!(
+                    "{}:{}: {}: {}",
+                    path.display(),
+                    line_no + 1,
+                    message,
+                    line
+                )));
+            }
+            eprintln!("{}:{}: {}, skipping", path.display(), line_no + 1, message);
+            skipped.push(Skipped {
+                line_no: line_no + 1,
+                reason,
+                message,
+                line: line.to_string(),
+            });
+            Ok(())
+        };
+        let mut parts = match split_csv_line(line) {
+            Ok(parts) if parts.len() > reading_idx => parts,
+            Ok(parts) => {
+                skip(
+                    SkipReason::Malformed,
+                    format!(
+                        "expected at least {} columns, found {}",
+                        reading_idx + 1,
+                        parts.len()
+                    ),
+                )?;
+                continue;
+            }
+            Err(e) => {
+                skip(SkipReason::Malformed, e)?;
+                continue;
+            }
+        };
+        let surface = parts[surface_idx].as_str();
+        if surface.is_empty() || kanji_only && !han_regex.is_match(surface) {
+            continue;
+        }
+
+        if surface.len() > u16::MAX as usize {
+            skip(
+                SkipReason::SurfaceTooLong,
+                format!("surface too long ({} bytes)", surface.len()),
+            )?;
+            continue;
+        }
+
+        let (Ok(left_id), Ok(right_id), Ok(cost)) = (
+            parts[left_idx].parse::<i16>(),
+            parts[right_idx].parse::<i16>(),
+            parts[cost_idx].parse::<i32>(),
+        ) else {
+            skip(
+                SkipReason::Malformed,
+                "context ids and cost must be integers".to_string(),
+            )?;
+            continue;
+        };
+        if cost < i16::MIN as i32 || cost > i16::MAX as i32 {
+            skip(
+                SkipReason::CostOutOfRange,
+                format!("cost {} out of range", cost),
+            )?;
+            continue;
+        }
+        let cost = cost as i16;
+        let features = parts.get(POS_COLUMNS).unwrap_or_default();
+        if !options.pos_filter.keeps(features) {
+            dropped.push((left_id, right_id));
+            continue;
+        }
+
+        let mut reading = std::mem::take(&mut parts[reading_idx]);
+        let surface = parts[surface_idx].as_str();
+        // IPADIC marks entries without a reading with `*`; store those with an empty one, which
+        // the analysis replaces with the surface.
+        if is_missing(&reading) {
+            eprintln!("Warning: no reading, storing it empty: {}", surface);
+            reading.clear();
+        }
+        if reading.len() > u16::MAX as usize {
+            skip(
+                SkipReason::ReadingTooLong,
+                format!("reading too long ({} bytes)", reading.len()),
+            )?;
+            continue;
+        }
+        if kanji_only && reading == surface {
+            skip(
+                SkipReason::ReadingIsSurface,
+                format!("reading is the surface ({})", reading),
+            )?;
+            continue;
+        }
+
+        let (pronunciation, accent) = if options.extra_fields {
+            extra_fields(&parts, options.mode, &reading)
+        } else {
+            (None, None)
+        };
+        let lemma = options
+            .with_lemma
+            .then(|| lemma(&parts, options.mode))
+            .flatten();
+        rows.push(Row {
+            line_no: line_no + 1,
+            surface: surface.to_string(),
+            left_id,
+            right_id,
+            cost,
+            reading,
+            features: parts.get(POS_COLUMNS).map(|features| features.join(",")),
+            pronunciation,
+            accent,
+            lemma,
+        });
+    }
+    Ok(ParsedCsv {
+        rows,
+        skipped,
+        dropped,
+    })
+}
+
+/// The pronunciation of the row `parts` if it has one other than `reading`, and its accent type:
+/// the first of the accent types UniDic lists for some words, such as `0,2`.
+fn extra_fields(parts: &[String], mode: Mode, reading: &str) -> (Option<String>, Option<u8>) {
+    let pronunciation = parts
+        .get(mode.pronunciation_column())
+        .filter(|p| !is_missing(p) && *p != reading && p.len() <= MAX_PRONUNCIATION_BYTES)
+        .cloned();
+    let accent = mode
+        .accent_column()
+        .and_then(|column| parts.get(column))
+        .and_then(|accent| accent.split(',').next()?.parse().ok());
+    (pronunciation, accent)
+}
+
+/// The base form of the row `parts`, if it has one short enough to store.
+fn lemma(parts: &[String], mode: Mode) -> Option<String> {
+    parts
+        .get(mode.lemma_column())
+        .filter(|lemma| !is_missing(lemma) && lemma.len() <= u16::MAX as usize)
+        .cloned()
+}
+
+/// Longest pronunciation a record's extra fields hold.
+const MAX_PRONUNCIATION_BYTES: usize = u16::MAX as usize - 7;
+
+/// Whether a column is empty or the `*` MeCab dictionaries put in place of a value.
+fn is_missing(column: &str) -> bool {
+    column.is_empty() || column == "*"
+}
+
+/// Writes every row in `skipped` to `path`, with its file, line and reason, tab-separated.
+fn write_report(path: &Path, skipped: &[(&Path, Skipped)]) -> std::io::Result<()> {
+    use std::io::Write;
+    let mut out = std::io::BufWriter::new(std::fs::File::create(path)?);
+    writeln!(out, "file\tline\treason\trow")?;
+    for (file, row) in skipped {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}",
+            file.display(),
+            row.line_no,
+            row.message,
+            row.line
+        )?;
+    }
+    out.flush()
+}
+
+/// Reads the entries of every input, in order; each is a CSV file or a directory of them. All
+/// inputs must use the context ids of the same matrix.def. Entries repeating the surface, context
+/// ids and reading of an earlier one are merged according to `options.on_duplicate`.
+///
+/// With `options.kanji_only`, only entries starting with a Han character are kept, and words whose
+/// reading is their surface are dropped as well; otherwise kana-initial words such as お茶 and
+/// particles stay, so they take part in segmentation instead of going down the unknown-word path.
+///
+/// The files are parsed in parallel, then merged one after the other in input order, which is
+/// where compact ids are assigned and duplicates resolved, so the result is the same however the
+/// parsing was scheduled.
+fn process_csv_files(
+    inputs: &[&str],
+    options: &CsvOptions,
+) -> Result<(IdMaps, Vec<Entry>), Failure> {
+    let han_regex = Regex::new(r"^\p{Han}+").unwrap();
+
+    let mut files = Vec::new();
+    for (input_idx, &input) in inputs.iter().enumerate() {
+        let paths = if options.compiled {
+            vec![Path::new(input).join("sys.dic")]
+        } else {
+            csv_paths(input)?
+        };
+        files.extend(paths.into_iter().map(|path| (input_idx, path)));
+    }
+    let (files_done, rows_done) = (AtomicUsize::new(0), AtomicUsize::new(0));
+    let parsed: Vec<Result<ParsedCsv, Failure>> = files
+        .par_iter()
+        .map(|(_, path)| {
+            let parsed = parse_csv_file(path, options, &han_regex)?;
+            let done = files_done.fetch_add(1, Ordering::Relaxed) + 1;
+            let count = parsed.rows.len();
+            let rows = rows_done.fetch_add(count, Ordering::Relaxed) + count;
+            println!(
+                "[{}/{} files, {} entries] {}",
+                done,
+                files.len(),
+                rows,
+                path.display()
+            );
+            Ok(parsed)
+        })
+        .collect();
+
+    let mut id_maps = IdMaps::default();
+    let mut entries: Vec<Entry> = Vec::new();
+    let mut skipped = Vec::new();
+    // Index into `entries` by surface, context ids and reading.
+    let mut seen: HashMap<(String, u16, u16, String), usize> = HashMap::new();
+    let mut parsed = files.iter().zip(parsed).peekable();
+
+    for (input_idx, &input) in inputs.iter().enumerate() {
+        let (entries_before, mut num_duplicates) = (entries.len(), 0);
+        while let Some(((_, path), file)) = parsed.next_if(|((idx, _), _)| *idx == input_idx) {
+            let file = file?;
+            skipped.extend(file.skipped.into_iter().map(|row| (path.as_path(), row)));
+            id_maps.dropped_entries += file.dropped.len();
+            for (left_id, right_id) in file.dropped {
+                id_maps.dropped_left.insert(left_id);
+                id_maps.dropped_right.insert(right_id);
+            }
+
+            for row in file.rows {
+                let pos_id = compact_id(&mut id_maps.left, row.left_id);
+                let right_id = compact_id(&mut id_maps.right, row.right_id);
+                if let Some(features) = row.features {
+                    id_maps
+                        .right_pos_names
+                        .entry(right_id)
+                        .or_insert_with(|| features.clone());
+                    id_maps.pos_names.entry(pos_id).or_insert(features);
                 }
 
-                for line in decoded.lines() {
-                    let parts: Vec<&str> = line.split(',').collect();
-                    let surface = parts[surface_idx];
-                    if !han_regex.is_match(surface) {
+                let key = (row.surface.clone(), pos_id, right_id, row.reading.clone());
+                match seen.entry(key) {
+                    hash_map::Entry::Occupied(kept) => {
+                        num_duplicates += 1;
+                        let kept = &mut entries[*kept.get()];
+                        if kept.cost == row.cost {
+                            continue;
+                        }
+                        match options.on_duplicate {
+                            OnDuplicate::MinCost => kept.cost = kept.cost.min(row.cost),
+                            OnDuplicate::First => {}
+                            OnDuplicate::Error => {
+                                return Err(Failure::Dictionary(format!(
+                                    "{}:{}: {} ({}) has cost {}, an earlier entry has {}",
+                                    path.display(),
+                                    row.line_no,
+                                    row.surface,
+                                    row.reading,
+                                    row.cost,
+                                    kept.cost
+                                )));
+                            }
+                        }
                         continue;
                     }
-
-                    if surface.len() > 255 {
-                        eprintln!("Warning: surface too long ({}), skipping", surface.len());
-                        continue;
+                    hash_map::Entry::Vacant(slot) => {
+                        slot.insert(entries.len());
                     }
+                }
 
-                    let left_id_str = parts[left_idx];
-                    let right_id_str = parts[right_idx];
-                    assert_eq!(
-                        left_id_str, right_id_str,
-                        "left_id and right_id differ for surface: {}",
-                        surface
-                    );
-
-                    let cost: i32 = parts[cost_idx].parse().unwrap();
-                    if cost < i16::MIN as i32 || cost > i16::MAX as i32 {
-                        eprintln!("Warning: cost out of range ({}), skipping", cost);
-                        continue;
-                    }
-                    let cost = cost as i16;
+                entries.push(Entry {
+                    surface: row.surface,
+                    pos_id,
+                    right_id,
+                    cost: row.cost,
+                    reading: row.reading,
+                    lemma: row.lemma,
+                    pronunciation: row.pronunciation,
+                    accent: row.accent,
+                });
+            }
+        }
+        println!(
+            "{}: {} entries, {} duplicates dropped",
+            input,
+            entries.len() - entries_before,
+            num_duplicates
+        );
+    }
 
-                    let reading = parts[reading_idx].to_string();
-                    if reading.len() == 0 {
-                        eprintln!("Warning: reading empty, skipping: {}", surface);
-                    }
-                    if reading.len() > 255 {
-                        eprintln!("Warning: reading too long ({}), skipping", reading.len());
-                        continue;
+    if !skipped.is_empty() {
+        let mut counts: BTreeMap<SkipReason, usize> = BTreeMap::new();
+        for (_, row) in &skipped {
+            *counts.entry(row.reason).or_default() += 1;
+        }
+        eprintln!("Skipped {} rows:", skipped.len());
+        for (reason, count) in counts {
+            eprintln!("{:>10}  {}", count, reason.label());
+        }
+    }
+    if let Some(report) = &options.report {
+        write_report(report, &skipped).map_err(|e| Failure::from_io(report.display(), e))?;
+    }
+    Ok((id_maps, entries))
+}
+
+/// Splits a MeCab dictionary CSV line. Fields may be quoted, in which case they can contain
+/// commas, and a doubled quote stands for a literal one.
+fn split_csv_line(line: &str) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
                     }
-                    if reading == surface {
-                        eprintln!("Warning: reading == surface ({}), skipping", reading);
-                        continue;
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err("unterminated quoted field".to_string()),
+                }
+            }
+            match chars.next() {
+                None => {
+                    fields.push(field);
+                    return Ok(fields);
+                }
+                Some(',') => fields.push(field),
+                Some(c) => return Err(format!("unexpected {:?} after quoted field", c)),
+            }
+        } else {
+            loop {
+                match chars.next() {
+                    Some(',') => break,
+                    Some(c) => field.push(c),
+                    None => {
+                        fields.push(field);
+                        return Ok(fields);
                     }
-
-                    let pos_id_len = pos_id_map.len();
-                    let pos_id = *pos_id_map
-                        .entry(left_id_str.parse().unwrap())
-                        .or_insert_with(|| {
-                            let id = pos_id_len as u16;
-                            if id == 65535 {
-                                panic!("Too many unique pos_ids! Maximum is 65535.");
-                            }
-                            id
-                        });
-
-                    entries.push(Entry {
-                        surface: surface.to_string(),
-                        pos_id,
-                        cost,
-                        reading,
-                    });
                 }
             }
-            Err(e) => eprintln!("Error reading glob entry: {}", e),
+            fields.push(field);
         }
     }
+}
 
-    // Sort by first character, then by surface
-    entries.sort_by(|a, b| {
-        let a_first = a.surface.chars().next();
-        let b_first = b.surface.chars().next();
-        match (a_first, b_first) {
-            (Some(ac), Some(bc)) => ac.cmp(&bc).then_with(|| a.surface.cmp(&b.surface)),
-            _ => a.surface.cmp(&b.surface),
-        }
-    });
+/// Reads char.def and unk.def from `input_dir` if both are present, registering the templates'
+/// context ids so the matrix keeps their rows and columns.
+fn load_char_definitions(
+    input_dir: &str,
+    encoding: InputEncoding,
+    id_maps: &mut IdMaps,
+) -> Result<Option<CharDefinitions>, Failure> {
+    let (char_def, unk_def) = (
+        Path::new(input_dir).join("char.def"),
+        Path::new(input_dir).join("unk.def"),
+    );
+    if !char_def.exists() || !unk_def.exists() {
+        return Ok(None);
+    }
+    let char_def = read_decoded(&char_def, encoding)?;
+    let unk_def = read_decoded(&unk_def, encoding)?;
 
-    (pos_id_map, entries)
+    let mut defs = CharDefinitions::parse_char_def(&char_def)?;
+    for (category, left_id, right_id, cost) in CharDefinitions::parse_unk_def(&unk_def)? {
+        let template = UnknownTemplate {
+            left_id: compact_id(&mut id_maps.left, left_id),
+            right_id: compact_id(&mut id_maps.right, right_id),
+            cost,
+        };
+        defs.add_template(&category, template)?;
+    }
+    Ok(Some(defs))
 }
 
+/// Loads matrix.def, keeping only rows for known right ids (of the previous token) and columns
+/// for known left ids (of the current token). Returns the matrix with its row and column counts.
 fn load_matrix(
     input_path: &str,
-    pos_id_map: &HashMap<i16, u16>,
-) -> std::io::Result<(Vec<i16>, usize)> {
-    let mut data = String::with_capacity(23 * 1024 * 1024);
-    let mut file = File::open(input_path)?;
-    file.read_to_string(&mut data).unwrap();
+    encoding: InputEncoding,
+    id_maps: &IdMaps,
+) -> Result<(Vec<i16>, usize, usize), Failure> {
+    let data = read_decoded(Path::new(input_path), encoding)?;
     let mut lines = data.lines();
 
     lines.next();
 
-    let matrix_size = pos_id_map.len();
-    let mut matrix = vec![0i16; matrix_size * matrix_size];
+    let right_size = id_maps.right.len();
+    let left_size = id_maps.left.len();
+    let mut matrix = vec![0i16; right_size * left_size];
 
-    for line in lines {
+    for (line_no, line) in lines.enumerate() {
         let parts: Vec<&str> = line.split_whitespace().collect();
 
         if parts.len() >= 3 {
-            let left: i16 = parts[0].parse().unwrap();
-            let right: i16 = parts[1].parse().unwrap();
-            let cost: i16 = parts[2].parse().unwrap();
+            let (Ok(prev_right), Ok(curr_left), Ok(cost)) = (
+                parts[0].parse::<i16>(),
+                parts[1].parse::<i16>(),
+                parts[2].parse::<i16>(),
+            ) else {
+                return Err(Failure::Dictionary(format!(
+                    "{}:{}: context ids and cost must be 16-bit integers",
+                    input_path,
+                    line_no + 2
+                )));
+            };
 
             if let (Some(&prev_id), Some(&curr_id)) =
-                (pos_id_map.get(&left), pos_id_map.get(&right))
+                (id_maps.right.get(&prev_right), id_maps.left.get(&curr_left))
             {
-                let idx = (prev_id as usize) * matrix_size + (curr_id as usize);
+                let idx = (prev_id as usize) * left_size + (curr_id as usize);
                 matrix[idx] = cost;
             }
         }
     }
 
-    Ok((matrix, matrix_size))
+    Ok((matrix, right_size, left_size))
 }
 
-fn write_binary(
-    path: &str,
-    entries: &[Entry],
-    matrix: &[i16],
-    matrix_size: u16,
-) -> std::io::Result<()> {
-    let file = File::create(path)?;
-    let mut writer = BufWriter::new(file);
-
-    // First, build entry_records with compressed strings
-    let mut strings_data = Vec::new(); // Compressed supersequence
-    let mut entry_records = Vec::new();
-
-    for entry in entries.iter() {
-        let reading_bytes = entry.reading.as_bytes();
-
-        // Find longest suffix of strings_data that matches a prefix of reading
-        let mut best_overlap = 0;
-        let search_start = strings_data.len().saturating_sub(reading_bytes.len());
-
-        for start in search_start..strings_data.len() {
-            let suffix_len = strings_data.len() - start;
-            if suffix_len > reading_bytes.len() {
+/// The magic number of a compiled MeCab dictionary, stored XORed with the file's size.
+const SYS_DIC_MAGIC: u32 = 0xef71_8f77;
+/// The only version of the compiled dictionary format MeCab has written since 0.99.
+const SYS_DIC_VERSION: u32 = 102;
+/// Ten `u32` fields and the charset name, NUL-padded to 32 bytes.
+const SYS_DIC_HEADER_BYTES: usize = 10 * 4 + 32;
+/// A token: left and right ids, an unused POS id, the cost, the offset of its feature string
+/// and an unused compound field.
+const SYS_DIC_TOKEN_BYTES: usize = 16;
+
+/// Reads sys.dic, a dictionary compiled by mecab-dict-index, back into the CSV it was compiled
+/// from: a line per token, in the order they are stored, which is by surface. The surface is the
+/// trie key leading to the token, the context ids and cost are the token's and the rest of the
+/// columns its feature string, all decoded with the charset the header names rather than
+/// `--encoding`. Line numbers in messages about the rows are token numbers.
+fn read_sys_dic(path: &Path) -> Result<String, Failure> {
+    let bytes = std::fs::read(path).map_err(|e| Failure::from_io(path.display(), e))?;
+    let malformed =
+        |message: String| Failure::Dictionary(format!("{}: {}", path.display(), message));
+    if bytes.len() < SYS_DIC_HEADER_BYTES {
+        return Err(malformed("too short for a sys.dic header".to_string()));
+    }
+    let u32_at =
+        |bytes: &[u8], at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    let field = |i: usize| u32_at(&bytes, i * 4);
+    if field(0) ^ SYS_DIC_MAGIC != bytes.len() as u32 {
+        return Err(malformed("not a compiled MeCab dictionary".to_string()));
+    }
+    if field(1) != SYS_DIC_VERSION {
+        return Err(malformed(format!("unsupported version {}", field(1))));
+    }
+    let charset = &bytes[40..SYS_DIC_HEADER_BYTES];
+    let charset = &charset[..charset
+        .iter()
+        .position(|&b| b == 0)
+        .unwrap_or(charset.len())];
+    let charset = String::from_utf8_lossy(charset);
+    let encoding = InputEncoding::parse(&charset)
+        .filter(|&encoding| encoding != InputEncoding::Auto)
+        .ok_or_else(|| malformed(format!("unsupported charset {:?}", charset)))?;
+
+    let (array_len, tokens_len, features_len) =
+        (field(6) as usize, field(7) as usize, field(8) as usize);
+    let sections = &bytes[SYS_DIC_HEADER_BYTES..];
+    if sections.len() < array_len + tokens_len + features_len {
+        return Err(malformed("truncated".to_string()));
+    }
+    let (array, rest) = sections.split_at(array_len);
+    let (tokens, rest) = rest.split_at(tokens_len);
+    let features = &rest[..features_len];
+    let num_tokens = tokens_len / SYS_DIC_TOKEN_BYTES;
+
+    // The double array: a node's child on byte `c` is the unit at its base plus `c + 1`, and the
+    // end of a key the unit at the base itself, each with the node's base as its check. The value
+    // at a key's end, `-base - 1`, holds its first token and, in the low byte, how many there are.
+    let unit = |at: usize| {
+        let at = at * 8;
+        (
+            i32::from_le_bytes(array[at..at + 4].try_into().unwrap()),
+            u32_at(array, at + 4),
+        )
+    };
+    let num_units = array_len / 8;
+    let mut surfaces: Vec<Option<Vec<u8>>> = vec![None; num_tokens];
+    let mut nodes = vec![(Vec::new(), if num_units > 0 { unit(0).0 } else { -1 })];
+    while let Some((key, base)) = nodes.pop() {
+        if base < 0 {
+            continue;
+        }
+        if key.len() > u16::MAX as usize {
+            return Err(malformed("trie has a cycle".to_string()));
+        }
+        let base = base as usize;
+        for code in 0..=256 {
+            if base + code >= num_units {
+                break;
+            }
+            let (child_base, check) = unit(base + code);
+            if check as usize != base {
                 continue;
             }
-            if strings_data[start..] == reading_bytes[..suffix_len] {
-                best_overlap = suffix_len;
-                break;
+            if code > 0 {
+                let mut child = key.clone();
+                child.push((code - 1) as u8);
+                nodes.push((child, child_base));
+                continue;
+            }
+            if child_base >= 0 {
+                continue;
+            }
+            let value = (-(child_base as i64) - 1) as usize;
+            let (first, count) = (value >> 8, value & 0xff);
+            for surface in surfaces
+                .get_mut(first..first + count)
+                .ok_or_else(|| malformed(format!("key points past the {} tokens", num_tokens)))?
+            {
+                *surface = Some(key.clone());
             }
         }
+    }
 
-        let reading_offset = (strings_data.len() - best_overlap) as u32;
-        strings_data.extend_from_slice(&reading_bytes[best_overlap..]);
-        let reading_len = entry.reading.len() as u8;
-
-        entry_records.push((
-            entry.surface.as_bytes().to_vec(),
-            reading_offset,
-            reading_len,
-            entry.pos_id,
-            entry.cost,
+    let mut csv = String::new();
+    for (idx, surface) in surfaces.into_iter().enumerate() {
+        let token = &tokens[idx * SYS_DIC_TOKEN_BYTES..(idx + 1) * SYS_DIC_TOKEN_BYTES];
+        let u16_at = |at: usize| u16::from_le_bytes([token[at], token[at + 1]]);
+        let (left_id, right_id, cost) = (u16_at(0), u16_at(2), u16_at(6) as i16);
+        let surface = surface.ok_or_else(|| malformed(format!("token {} has no surface", idx)))?;
+        let feature = features
+            .get(u32_at(token, 8) as usize..)
+            .and_then(|rest| rest.split(|&b| b == 0).next())
+            .ok_or_else(|| malformed(format!("token {} has no feature string", idx)))?;
+        csv.push_str(&format!(
+            "{},{},{},{},{}\n",
+            csv_field(&decode(path, &surface, encoding)?),
+            left_id,
+            right_id,
+            cost,
+            decode(path, feature, encoding)?
         ));
     }
+    Ok(csv)
+}
 
-    // Now build index with byte offsets
-    let mut index: Vec<(char, u32, u16)> = Vec::new();
-    let mut current_char: Option<char> = None;
-    let mut current_byte_offset = 0u32;
-    let mut current_count = 0u16;
-    let mut byte_offset = 0u32;
+/// Loads matrix.bin, the compiled matrix.def, keeping what [`load_matrix`] keeps. It holds the
+/// number of right ids (of the previous token) and left ids (of the current one) as `u16`s, then
+/// an `i16` cost for every pair, the right id varying fastest.
+fn load_matrix_bin(path: &Path, id_maps: &IdMaps) -> Result<(Vec<i16>, usize, usize), Failure> {
+    let bytes = std::fs::read(path).map_err(|e| Failure::from_io(path.display(), e))?;
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let (rows, columns) = match bytes.len() {
+        4.. => (u16_at(0) as usize, u16_at(2) as usize),
+        _ => (0, 0),
+    };
+    if bytes.len() < 4 || bytes.len() != 4 + 2 * rows * columns {
+        return Err(Failure::Dictionary(format!(
+            "{}: not a compiled matrix",
+            path.display()
+        )));
+    }
 
-    for (i, entry) in entries.iter().enumerate() {
-        if let Some(first_char) = entry.surface.chars().next() {
-            if Some(first_char) != current_char {
-                if let Some(ch) = current_char {
-                    index.push((ch, current_byte_offset, current_count));
-                }
-                current_char = Some(first_char);
-                current_byte_offset = byte_offset;
-                current_count = 1;
-            } else {
-                current_count += 1;
+    let right_size = id_maps.right.len();
+    let left_size = id_maps.left.len();
+    let mut matrix = vec![0i16; right_size * left_size];
+    for (&prev_right, &prev_id) in &id_maps.right {
+        for (&curr_left, &curr_id) in &id_maps.left {
+            let (prev_right, curr_left) = (prev_right as usize, curr_left as usize);
+            if prev_right < rows && curr_left < columns {
+                let at = 4 + 2 * (prev_right + rows * curr_left);
+                let idx = (prev_id as usize) * left_size + (curr_id as usize);
+                matrix[idx] = u16_at(at) as i16;
             }
         }
-        byte_offset += 1 + entry_records[i].0.len() as u32 + ENTRY_METADATA_SIZE;
     }
-    if let Some(ch) = current_char {
-        index.push((ch, current_byte_offset, current_count));
+    Ok((matrix, right_size, left_size))
+}
+
+/// Writes `builder`, which already holds the entries, with the matrix and extra sections.
+/// Returns the sizes written.
+fn write_binary(
+    path: &str,
+    mut builder: DictionaryBuilder,
+    matrix: Vec<i16>,
+    right_size: u16,
+    left_size: u16,
+    char_defs: Option<CharDefinitions>,
+    pos_names: &HashMap<u16, String>,
+) -> std::io::Result<BuildStats> {
+    builder.set_connection_matrix(matrix, right_size, left_size);
+    if let Some(defs) = char_defs {
+        builder.set_char_definitions(defs);
+    }
+    for (&pos_id, name) in pos_names {
+        builder.set_pos_name(pos_id, name);
     }
+    let stats = builder.write_to_file(path)?;
 
-    eprintln!("Index has {} unique characters", index.len());
+    eprintln!("Index has {} unique characters", stats.index_keys);
+    println!("Header: {} bytes", stats.header_bytes);
+    println!(
+        "Matrix: {} bytes ({} entries, {}x{})",
+        stats.matrix_bytes,
+        stats.matrix_bytes / 2,
+        right_size,
+        left_size
+    );
+    println!(
+        "Index: {} bytes ({} keys)",
+        stats.index_bytes, stats.index_keys
+    );
+    println!("Character definitions: {} bytes", stats.char_def_bytes);
+    println!(
+        "POS names: {} bytes ({} ids)",
+        stats.pos_name_bytes,
+        pos_names.len()
+    );
+    println!(
+        "Compressed entries ({} bytes) + strings ({} bytes)",
+        stats.entries_bytes, stats.strings_bytes
+    );
+    println!(
+        "Strings: {} bytes, {} with the readings in the other kana form ({:+} bytes)",
+        stats.strings_bytes,
+        stats.other_kana_strings_bytes,
+        stats.strings_bytes as i64 - stats.other_kana_strings_bytes as i64
+    );
+    let uncompressed = stats.entries_bytes + stats.strings_bytes;
+    println!(
+        "Compressed block: {} bytes (from {} bytes uncompressed, {:.1}% of original)",
+        stats.compressed_bytes,
+        uncompressed,
+        100.0 * stats.compressed_bytes as f64 / uncompressed as f64
+    );
 
-    let matrix_byte_size = (matrix.len() * 2) as u32;
-    let index_size = 4 + (index.len() as u32 * INDEX_ENTRY_SIZE);
+    Ok(stats)
+}
 
-    let entry_array_size: u32 = entry_records
-        .iter()
-        .map(|(surf, _, _, _, _)| 1 + surf.len() as u32 + ENTRY_METADATA_SIZE)
-        .sum();
+/// How many first chars and connections [`DictionaryStats`] lists.
+const STATS_TOP: usize = 20;
 
-    let strings_offset = entry_array_size;
+/// Where the bytes of a converted dictionary went, for `--stats` and `--stats-json`.
+#[derive(Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+struct DictionaryStats {
+    /// The first chars with the most entries, most first.
+    top_first_chars: Vec<(char, usize)>,
+    /// Number of entries by the length of their surface and of their reading, in chars.
+    surface_lengths: BTreeMap<usize, usize>,
+    reading_lengths: BTreeMap<usize, usize>,
+    /// See [`BuildStats::readings_bytes`].
+    readings_bytes: usize,
+    distinct_readings_bytes: usize,
+    /// The strings section, where readings share their overlaps.
+    strings_bytes: usize,
+    /// See [`BuildStats::other_kana_strings_bytes`].
+    other_kana_strings_bytes: usize,
+    matrix_cells: usize,
+    matrix_nonzero: usize,
+    sections: Vec<SectionSize>,
+    /// The most expensive connections, most expensive first.
+    expensive_connections: Vec<Connection>,
+}
 
-    println!("Header: {} bytes", HEADER_SIZE);
-    writer.write_all(b"MUCA")?;
-    writer.write_all(&1u16.to_le_bytes())?;
-    writer.write_all(&matrix_size.to_le_bytes())?;
-    writer.write_all(&(entries.len() as u32).to_le_bytes())?;
-    writer.write_all(&strings_offset.to_le_bytes())?;
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+struct SectionSize {
+    name: &'static str,
+    stored_bytes: usize,
+    uncompressed_bytes: usize,
+}
 
-    println!(
-        "Matrix: {} bytes ({} entries, {}x{})",
-        matrix_byte_size,
-        matrix.len(),
-        matrix_size,
-        matrix_size
-    );
-    for &cost in matrix {
-        writer.write_all(&cost.to_le_bytes())?;
+/// A matrix cell: the cost of a token with `left_id` following one with `right_id`.
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+struct Connection {
+    right_id: u16,
+    left_id: u16,
+    cost: i16,
+    right_pos: Option<String>,
+    left_pos: Option<String>,
+}
+
+impl DictionaryStats {
+    /// The statistics of `entries` and of `matrix`, `left_size` cells a row, before writing.
+    fn collect(entries: &[Entry], matrix: &[i16], left_size: usize, id_maps: &IdMaps) -> Self {
+        let mut stats = DictionaryStats::default();
+        let mut first_chars: HashMap<char, usize> = HashMap::new();
+        for entry in entries {
+            if let Some(c) = entry.surface.chars().next() {
+                *first_chars.entry(c).or_default() += 1;
+            }
+            *stats
+                .surface_lengths
+                .entry(entry.surface.chars().count())
+                .or_default() += 1;
+            *stats
+                .reading_lengths
+                .entry(entry.reading.chars().count())
+                .or_default() += 1;
+        }
+        stats.top_first_chars = first_chars.into_iter().collect();
+        stats
+            .top_first_chars
+            .sort_unstable_by_key(|&(c, count)| (std::cmp::Reverse(count), c));
+        stats.top_first_chars.truncate(STATS_TOP);
+
+        stats.matrix_cells = matrix.len();
+        stats.matrix_nonzero = matrix.iter().filter(|&&cost| cost != 0).count();
+        let mut expensive: Vec<usize> = (0..matrix.len()).filter(|&i| matrix[i] > 0).collect();
+        expensive.sort_unstable_by_key(|&i| (std::cmp::Reverse(matrix[i]), i));
+        stats.expensive_connections = expensive
+            .into_iter()
+            .take(STATS_TOP)
+            .map(|i| {
+                let (right_id, left_id) = ((i / left_size) as u16, (i % left_size) as u16);
+                Connection {
+                    right_id,
+                    left_id,
+                    cost: matrix[i],
+                    right_pos: id_maps.right_pos_names.get(&right_id).cloned(),
+                    left_pos: id_maps.pos_names.get(&left_id).cloned(),
+                }
+            })
+            .collect();
+        stats
     }
 
-    println!("Index: {} bytes ({} keys)", index_size, index.len());
-    writer.write_all(&(index.len() as u32).to_le_bytes())?;
-    for (ch, byte_offset, count) in &index {
-        writer.write_all(&(*ch as u32).to_le_bytes())?;
-        writer.write_all(&byte_offset.to_le_bytes())?;
-        writer.write_all(&count.to_le_bytes())?;
+    /// Adds the sizes of the sections as written.
+    fn add_sections(&mut self, build: &BuildStats) {
+        self.readings_bytes = build.readings_bytes;
+        self.distinct_readings_bytes = build.distinct_readings_bytes;
+        self.strings_bytes = build.strings_bytes;
+        self.other_kana_strings_bytes = build.other_kana_strings_bytes;
+        let stored = |name, bytes| SectionSize {
+            name,
+            stored_bytes: bytes,
+            uncompressed_bytes: bytes,
+        };
+        self.sections = vec![
+            stored("header", build.header_bytes),
+            stored("matrix", build.matrix_bytes),
+            stored("index", build.index_bytes),
+            stored("character definitions", build.char_def_bytes),
+            stored("POS names", build.pos_name_bytes),
+            SectionSize {
+                name: "entries and strings",
+                stored_bytes: build.compressed_bytes,
+                uncompressed_bytes: build.entries_bytes + build.strings_bytes,
+            },
+        ];
     }
 
-    // Create zeekstd encoder for compressed block (entries + strings)
-    println!(
-        "Compressing entries ({} bytes) + strings ({} bytes)...",
-        entry_array_size,
-        strings_data.len()
-    );
+    fn print(&self) {
+        let counts =
+            |counts: &mut dyn Iterator<Item = String>| counts.collect::<Vec<_>>().join(", ");
+        println!("Statistics:");
+        println!(
+            "  Most entries by first char: {}",
+            counts(
+                &mut self
+                    .top_first_chars
+                    .iter()
+                    .map(|(c, n)| format!("{} {}", c, n))
+            )
+        );
+        for (name, lengths) in [
+            ("Surface", &self.surface_lengths),
+            ("Reading", &self.reading_lengths),
+        ] {
+            println!(
+                "  {} lengths in chars (length: entries): {}",
+                name,
+                counts(&mut lengths.iter().map(|(len, n)| format!("{}: {}", len, n)))
+            );
+        }
+        println!(
+            "  Readings: {} bytes, {} of them distinct, {} in the strings after sharing overlaps",
+            self.readings_bytes, self.distinct_readings_bytes, self.strings_bytes
+        );
+        println!(
+            "  Strings with the readings in the other kana form: {} bytes",
+            self.other_kana_strings_bytes
+        );
+        println!(
+            "  Matrix: {} of {} cells nonzero ({:.1}%)",
+            self.matrix_nonzero,
+            self.matrix_cells,
+            100.0 * self.matrix_nonzero as f64 / self.matrix_cells.max(1) as f64
+        );
+        println!("  Sections (stored / uncompressed bytes):");
+        for section in &self.sections {
+            println!(
+                "    {}: {} / {}",
+                section.name, section.stored_bytes, section.uncompressed_bytes
+            );
+        }
+        println!("  Most expensive connections (right id -> left id: cost):");
+        for c in &self.expensive_connections {
+            println!(
+                "    {} ({}) -> {} ({}): {}",
+                c.right_id,
+                c.right_pos.as_deref().unwrap_or("?"),
+                c.left_id,
+                c.left_pos.as_deref().unwrap_or("?"),
+                c.cost
+            );
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn write_stats_json(stats: &DictionaryStats, path: &str) -> std::io::Result<()> {
+    let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+    serde_json::to_writer_pretty(file, stats)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "serde"))]
+fn write_stats_json(_stats: &DictionaryStats, _path: &str) -> std::io::Result<()> {
+    unreachable!("--stats-json is rejected without the serde feature")
+}
 
-    let opts = EncodeOptions::new()
-        .checksum_flag(false)
-        .compression_level(9)
-        .frame_size_policy(FrameSizePolicy::Uncompressed(1024 * 128));
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut encoder = Encoder::with_opts(writer, opts)
-        .map_err(|e| std::io::Error::other(format!("zeekstd error: {:?}", e)))?;
+    const UTF8: &[u8] = include_bytes!("../../tests/fixtures/encoding/lex.utf8.csv");
+    const EUC_JP_CSV: &[u8] = include_bytes!("../../tests/fixtures/encoding/lex.eucjp.csv");
 
-    for (surf_bytes, read_off, read_len, pos_id, cost) in &entry_records {
-        encoder.write_all(&[surf_bytes.len() as u8])?;
-        encoder.write_all(surf_bytes)?;
-        encoder.write_all(&read_off.to_le_bytes())?;
-        encoder.write_all(&[*read_len])?;
-        encoder.write_all(&pos_id.to_le_bytes())?;
-        encoder.write_all(&cost.to_le_bytes())?;
+    #[test]
+    fn test_decode_explicit_and_auto() {
+        let expected = std::str::from_utf8(UTF8).unwrap();
+        let path = Path::new("lex.csv");
+        assert_eq!(decode(path, UTF8, InputEncoding::Utf8).unwrap(), expected);
+        assert_eq!(decode(path, UTF8, InputEncoding::Auto).unwrap(), expected);
+        assert_eq!(
+            decode(path, EUC_JP_CSV, InputEncoding::EucJp).unwrap(),
+            expected
+        );
+        assert_eq!(
+            decode(path, EUC_JP_CSV, InputEncoding::Auto).unwrap(),
+            expected
+        );
+
+        let with_bom = [b"\xEF\xBB\xBF", UTF8].concat();
+        assert_eq!(
+            decode(path, &with_bom, InputEncoding::Auto).unwrap(),
+            expected
+        );
+        assert_eq!(
+            decode(path, &with_bom, InputEncoding::Utf8).unwrap(),
+            expected
+        );
     }
 
-    // Write strings immediately after entries in same compressed block
-    encoder.write_all(&strings_data)?;
+    #[test]
+    fn test_decode_wrong_charset_names_the_file() {
+        let path = Path::new("dic/lex.csv");
+        let err = decode(path, EUC_JP_CSV, InputEncoding::Utf8).unwrap_err();
+        assert_eq!(err, "dic/lex.csv: not valid UTF-8");
+        let err = decode(path, UTF8, InputEncoding::EucJp).unwrap_err();
+        assert_eq!(err, "dic/lex.csv: not valid EUC-JP");
+    }
 
-    let compressed_size = encoder
-        .finish()
-        .map_err(|e| std::io::Error::other(format!("zeekstd error: {:?}", e)))?;
-    println!(
-        "Compressed block: {} bytes (from {} bytes uncompressed, {:.1}% of original)",
-        compressed_size,
-        entry_array_size + strings_data.len() as u32,
-        100.0 * compressed_size as f64 / (entry_array_size + strings_data.len() as u32) as f64
-    );
+    fn csv_options(on_duplicate: OnDuplicate) -> CsvOptions {
+        CsvOptions {
+            mode: Mode::Ipadic,
+            reading_column: None,
+            encoding: InputEncoding::Utf8,
+            compiled: false,
+            extra_fields: false,
+            with_lemma: false,
+            kanji_only: false,
+            on_duplicate,
+            pos_filter: PosFilter::default(),
+            strict: false,
+            report: None,
+            overrides: Vec::new(),
+        }
+    }
 
-    Ok(())
+    /// Converts `csv` with a zero matrix and loads the result.
+    fn convert(name: &str, csv: &str, kanji_only: bool) -> mucab::Dictionary<'static> {
+        let dir = env::temp_dir().join(format!("mucab-converter-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lex.csv"), csv).unwrap();
+        std::fs::write(dir.join("matrix.def"), "2 2\n0 0 0\n").unwrap();
+        let input = dir.to_str().unwrap();
+        let (id_maps, entries) = process_csv_files(
+            &[input],
+            &CsvOptions {
+                kanji_only,
+                ..csv_options(OnDuplicate::default())
+            },
+        )
+        .unwrap();
+        let (matrix, right_size, left_size) = load_matrix(
+            &format!("{}/matrix.def", input),
+            InputEncoding::Utf8,
+            &id_maps,
+        )
+        .unwrap();
+        let output = dir.join("mucab.bin");
+        let mut builder = DictionaryBuilder::new();
+        builder.extend_entries(entries);
+        write_binary(
+            output.to_str().unwrap(),
+            builder,
+            matrix,
+            right_size as u16,
+            left_size as u16,
+            None,
+            &id_maps.pos_names,
+        )
+        .unwrap();
+        let dict = mucab::Dictionary::load(&output).unwrap();
+        std::fs::remove_dir_all(&dir).ok();
+        dict
+    }
+
+    #[test]
+    fn test_kana_initial_entries_are_kept() {
+        let csv = "お茶,0,0,100,名詞,一般,*,*,*,*,お茶,オチャ,オチャ\n\
+                   茶,0,0,3000,名詞,一般,*,*,*,*,茶,チャ,チャ\n\
+                   お,1,1,3000,接頭詞,名詞接続,*,*,*,*,お,オ,オ\n\
+                   カラオケ,0,0,100,名詞,一般,*,*,*,*,カラオケ,カラオケ,カラオケ\n";
+
+        let mut dict = convert("all", csv, false);
+        assert_eq!(dict.num_entries(), 4);
+        let tokens = mucab::tokenize("お茶", &mut dict);
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].reading, "オチャ");
+        assert_eq!(mucab::tokenize("カラオケ", &mut dict).len(), 1);
+
+        let mut dict = convert("kanji", csv, true);
+        assert_eq!(dict.num_entries(), 1);
+        let surfaces: Vec<String> = mucab::tokenize("お茶", &mut dict)
+            .into_iter()
+            .map(|t| t.surface.to_string())
+            .collect();
+        assert_eq!(surfaces, ["お", "茶"]);
+    }
+
+    #[test]
+    fn test_asterisk_readings_are_stored_empty() {
+        let csv = "東京,0,0,100,名詞,固有名詞,*,*,*,*,東京,トーキョー,トーキョー\n\
+                   〒,0,0,100,記号,一般,*,*,*,*,〒,*,*\n";
+        let mut dict = convert("asterisk", csv, false);
+        let entry = &dict.lookup_exact("〒")[0];
+        assert!(matches!(
+            entry.reading,
+            mucab::EntryReading::Stored { len: 0, .. }
+        ));
+        assert_eq!(mucab::transliterate("東京〒", &mut dict), "トーキョー〒");
+    }
+
+    #[test]
+    fn test_split_csv_line() {
+        assert_eq!(split_csv_line("a,b,,c").unwrap(), ["a", "b", "", "c"]);
+        assert_eq!(
+            split_csv_line("\"Hello, World\",0,\"say \"\"hi\"\"\"").unwrap(),
+            ["Hello, World", "0", "say \"hi\""]
+        );
+        assert_eq!(split_csv_line("\"\",x").unwrap(), ["", "x"]);
+        assert!(split_csv_line("\"open,x").is_err());
+        assert!(split_csv_line("\"a\"b,x").is_err());
+    }
+
+    #[test]
+    fn test_quoted_and_malformed_rows() {
+        let csv = "\"東京,大阪\",0,0,100,名詞,固有名詞,*,*,*,*,東京大阪,トウキョウオオサカ,トーキョーオーサカ\n\
+                   京都,0,0\n\
+                   京都,x,0,100,名詞,固有名詞,*,*,*,*,京都,キョート,キョート\n\
+                   \"京都,0,0,100,名詞,固有名詞,*,*,*,*,京都,キョート,キョート\n\
+                   神戸,0,0,100,名詞,固有名詞,*,*,*,*,神戸,コーベ,コーベ\n";
+        let mut dict = convert("quoted", csv, false);
+        assert_eq!(dict.num_entries(), 2);
+        assert_eq!(dict.readings_of("東京,大阪"), ["トーキョーオーサカ"]);
+        assert_eq!(dict.readings_of("神戸"), ["コーベ"]);
+        assert!(dict.readings_of("京都").is_empty());
+    }
+
+    #[test]
+    fn test_duplicates_leave_one_entry() {
+        let row = |cost| {
+            format!(
+                "東京,0,0,{},名詞,固有名詞,*,*,*,*,東京,トウキョウ,トーキョー\n",
+                cost
+            )
+        };
+        let csv = [row(3000), row(3000), row(100), row(3000)].concat()
+            + "東京,0,0,100,名詞,固有名詞,*,*,*,*,東京,トウキョウ,ヒガシキョー\n";
+        let mut dict = convert("duplicates", &csv, false);
+        assert_eq!(dict.num_entries(), 2);
+        let costs: Vec<i16> = dict
+            .lookup_exact("東京")
+            .iter()
+            .map(|e| e.word_cost)
+            .collect();
+        assert_eq!(costs, [100, 100]);
+        let mut readings = dict.readings_of("東京");
+        readings.sort();
+        assert_eq!(readings, ["トーキョー", "ヒガシキョー"]);
+    }
+
+    #[test]
+    fn test_output_is_reproducible() {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+
+        let dir = env::temp_dir().join(format!("mucab-converter-repro-{}", std::process::id()));
+        let input = dir.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        // Raw ids out of order within and across files, written in reverse name order.
+        std::fs::write(
+            input.join("b.csv"),
+            "大阪,3,3,100,名詞,固有名詞,地域,一般,*,*,大阪,オオサカ,オーサカ\n\
+             京都,1,1,100,名詞,固有名詞,地域,一般,*,*,京都,キョウト,キョート\n",
+        )
+        .unwrap();
+        std::fs::write(
+            input.join("a.csv"),
+            "東京,2,2,100,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\
+             へ,0,0,100,助詞,格助詞,一般,*,*,*,へ,ヘ,エ\n",
+        )
+        .unwrap();
+        let matrix: String = (0..4)
+            .flat_map(|r| (0..4).map(move |l| format!("{} {} {}\n", r, l, r * 10 + l)))
+            .collect();
+        std::fs::write(input.join("matrix.def"), format!("4 4\n{}", matrix)).unwrap();
+
+        let build = |name: &str| {
+            let output = dir.join(name);
+            convert_dictionary(
+                &[input.to_str().unwrap()],
+                output.to_str().unwrap(),
+                csv_options(OnDuplicate::default()),
+                OutputOptions::default(),
+                true,
+            )
+            .unwrap();
+            std::fs::read(output.join("mucab.bin")).unwrap()
+        };
+        let (first, second) = (build("first"), build("second"));
+        let hash = |bytes: &[u8]| {
+            let mut hasher = DefaultHasher::new();
+            bytes.hash(&mut hasher);
+            hasher.finish()
+        };
+        assert_eq!(hash(&first), hash(&second));
+        assert_eq!(first, second);
+
+        // Compact ids follow the raw ids, whatever order the entries came in.
+        let mut dict = mucab::Dictionary::load(dir.join("first/mucab.bin")).unwrap();
+        let pos_ids: Vec<u16> = ["へ", "京都", "東京", "大阪"]
+            .iter()
+            .map(|surface| dict.lookup_exact(surface)[0].pos_id)
+            .collect();
+        assert_eq!(pos_ids, [0, 1, 2, 3]);
+        assert_eq!(dict.data().connection_cost(3, 1), Some(31));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_export_round_trip() {
+        let dir = env::temp_dir().join(format!("mucab-converter-export-{}", std::process::id()));
+        let input = dir.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(
+            input.join("lex.csv"),
+            "東京,4,4,100,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\
+             東京,4,4,900,名詞,固有名詞,地域,一般,*,*,東京,ヒガシキョウ,ヒガシキョー\n\
+             \"A,B\",2,2,300,名詞,一般,*,*,*,*,\"A,B\",エービー,エービー\n\
+             へ,0,0,100,助詞,格助詞,一般,*,*,*,へ,ヘ,エ\n",
+        )
+        .unwrap();
+        let matrix: String = (0..6)
+            .flat_map(|r| (0..6).map(move |l| format!("{} {} {}\n", r, l, r * 10 - l)))
+            .collect();
+        std::fs::write(input.join("matrix.def"), format!("6 6\n{}", matrix)).unwrap();
+        std::fs::write(
+            input.join("char.def"),
+            "DEFAULT 0 1 0\nKANJI 0 0 2\nALPHA 1 1 0\n\
+             0x0041..0x005A ALPHA\n0x4E00..0x9FFF KANJI\n",
+        )
+        .unwrap();
+        std::fs::write(
+            input.join("unk.def"),
+            "DEFAULT,5,5,3000,記号,一般,*,*,*,*,*\nKANJI,4,4,8000,名詞,一般,*,*,*,*,*\n\
+             ALPHA,2,2,2000,名詞,固有名詞,*,*,*,*,*\n",
+        )
+        .unwrap();
+
+        let build = |input: &Path, name: &str| {
+            let output = dir.join(name);
+            convert_dictionary(
+                &[input.to_str().unwrap()],
+                output.to_str().unwrap(),
+                csv_options(OnDuplicate::default()),
+                OutputOptions::default(),
+                true,
+            )
+            .unwrap();
+            output.join("mucab.bin")
+        };
+        let built = build(&input, "built");
+        let exported = dir.join("exported");
+        export(built.to_str().unwrap(), exported.to_str().unwrap()).unwrap();
+        let rebuilt = build(&exported, "rebuilt");
+        assert_eq!(
+            std::fs::read(&built).unwrap(),
+            std::fs::read(&rebuilt).unwrap()
+        );
+
+        let lex = std::fs::read_to_string(exported.join("lex.csv")).unwrap();
+        assert!(lex.starts_with("\"A,B\",1,1,300,名詞,一般,*,*,*,*,\"A,B\",エービー,エービー\n"));
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_unidic_layout() {
+        let lex = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/unidic/lex.csv");
+        let readings = |reading_column| {
+            let options = CsvOptions {
+                mode: Mode::Unidic,
+                reading_column,
+                ..csv_options(OnDuplicate::default())
+            };
+            let (_, entries) = process_csv_files(&[lex], &options).unwrap();
+            entries
+                .into_iter()
+                .map(|e| (e.surface, e.reading, e.cost))
+                .collect::<Vec<(String, String, i16)>>()
+        };
+        let entry = |surface: &str, reading: &str, cost| (surface.into(), reading.into(), cost);
+
+        // The last row stops at `pron`, which is all the default layout needs.
+        assert_eq!(
+            readings(None),
+            [
+                entry("東京", "トーキョー", 3144),
+                entry("学校", "ガッコー", 2000),
+                entry("に", "ニ", 4000),
+                entry("行く", "イク", 5000),
+                entry("京都", "キョート", 3000),
+            ]
+        );
+        assert_eq!(
+            readings(Some(21)),
+            [
+                entry("東京", "トウキョウ", 3144),
+                entry("学校", "ガッコウ", 2000),
+                entry("に", "ニ", 4000),
+                entry("行く", "イク", 5000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unidic_extra_fields() {
+        let lex = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/unidic/lex.csv");
+        let extras = |reading_column| {
+            let options = CsvOptions {
+                mode: Mode::Unidic,
+                reading_column,
+                extra_fields: true,
+                ..csv_options(OnDuplicate::default())
+            };
+            let (_, entries) = process_csv_files(&[lex], &options).unwrap();
+            entries
+                .into_iter()
+                .map(|e| (e.surface, e.pronunciation, e.accent))
+                .collect::<Vec<(String, Option<String>, Option<u8>)>>()
+        };
+        let entry = |surface: &str, pronunciation: Option<&str>, accent| {
+            (surface.into(), pronunciation.map(str::to_string), accent)
+        };
+
+        // The default reading is the pronunciation, so only the accents are stored.
+        assert_eq!(
+            extras(None),
+            [
+                entry("東京", None, Some(0)),
+                entry("学校", None, Some(0)),
+                entry("に", None, None),
+                entry("行く", None, Some(0)),
+                entry("京都", None, None),
+            ]
+        );
+        assert_eq!(
+            extras(Some(21)),
+            [
+                entry("東京", Some("トーキョー"), Some(0)),
+                entry("学校", Some("ガッコー"), Some(0)),
+                entry("に", None, None),
+                entry("行く", None, Some(0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lemmas() {
+        let dir = env::temp_dir().join(format!("mucab-converter-lemma-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ipadic = dir.join("lex.csv");
+        std::fs::write(
+            &ipadic,
+            "食べ,0,0,3000,動詞,自立,*,*,一段,連用形,食べる,タベ,タベ\n\
+             た,0,0,1000,助動詞,*,*,*,特殊・タ,基本形,た,タ,タ\n\
+             ウィ,0,0,3000,記号,一般,*,*,*,*,*,ウィ,ウィ\n",
+        )
+        .unwrap();
+        let unidic = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/unidic/lex.csv");
+        let lemmas = |mode, input: &str, with_lemma| {
+            let options = CsvOptions {
+                mode,
+                encoding: InputEncoding::Utf8,
+                with_lemma,
+                ..csv_options(OnDuplicate::default())
+            };
+            let (_, entries) = process_csv_files(&[input], &options).unwrap();
+            entries
+                .into_iter()
+                .map(|e| (e.surface, e.lemma))
+                .collect::<Vec<(String, Option<String>)>>()
+        };
+        let entry =
+            |surface: &str, lemma: Option<&str>| (surface.into(), lemma.map(str::to_string));
+
+        let input = dir.to_str().unwrap();
+        assert_eq!(
+            lemmas(Mode::Ipadic, input, true),
+            [
+                entry("食べ", Some("食べる")),
+                entry("た", Some("た")),
+                entry("ウィ", None),
+            ]
+        );
+        assert_eq!(
+            lemmas(Mode::Ipadic, input, false),
+            [entry("食べ", None), entry("た", None), entry("ウィ", None)]
+        );
+        // The last row has no orthBase column.
+        assert_eq!(
+            lemmas(Mode::Unidic, unidic, true),
+            [
+                entry("東京", Some("東京")),
+                entry("学校", Some("学校")),
+                entry("に", Some("に")),
+                entry("行く", Some("行く")),
+                entry("京都", None),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_skipped_rows_are_reported_or_fail_strict_builds() {
+        let dir = env::temp_dir().join(format!("mucab-converter-skip-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let lex = dir.join("lex.csv");
+        std::fs::write(
+            &lex,
+            "東京,0,0,3000,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\
+             京都,0,0,3000,名詞\n\
+             大阪,0,0,99999,名詞,固有名詞,地域,一般,*,*,大阪,オオサカ,オーサカ\n",
+        )
+        .unwrap();
+        let input = dir.to_str().unwrap();
+        let report = dir.join("skipped.tsv");
+        let options = CsvOptions {
+            report: Some(report.clone()),
+            ..csv_options(OnDuplicate::default())
+        };
+        let (_, entries) = process_csv_files(&[input], &options).unwrap();
+        assert_eq!(entries.len(), 1);
+        let lex_name = lex.display();
+        assert_eq!(
+            std::fs::read_to_string(&report).unwrap(),
+            format!(
+                "file\tline\treason\trow\n\
+                 {lex_name}\t2\texpected at least 13 columns, found 5\t京都,0,0,3000,名詞\n\
+                 {lex_name}\t3\tcost 99999 out of range\t大阪,0,0,99999,名詞,固有名詞,地域,一般,*,*,\
+                 大阪,オオサカ,オーサカ\n"
+            )
+        );
+
+        let strict = CsvOptions {
+            strict: true,
+            ..csv_options(OnDuplicate::default())
+        };
+        let Err(err) = process_csv_files(&[input], &strict) else {
+            panic!("a strict build with malformed rows succeeded");
+        };
+        std::fs::remove_dir_all(&dir).ok();
+        assert!(matches!(err, Failure::Dictionary(_)));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "{}:2: expected at least 13 columns, found 5: 京都,0,0,3000,名詞",
+                lex_name
+            )
+        );
+    }
+
+    #[test]
+    fn test_merge_sources() {
+        let dir = env::temp_dir().join(format!("mucab-converter-merge-{}", std::process::id()));
+        let base = dir.join("ipadic");
+        std::fs::create_dir_all(&base).unwrap();
+        std::fs::write(
+            base.join("Noun.csv"),
+            "東京,0,0,3000,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\
+             京都,0,0,3000,名詞,固有名詞,地域,一般,*,*,京都,キョウト,キョート\n",
+        )
+        .unwrap();
+        std::fs::write(base.join("matrix.def"), "1 1\n0 0 0\n").unwrap();
+        // An exact duplicate, a cheaper duplicate and a new word.
+        let overlay = dir.join("overlay.csv");
+        std::fs::write(
+            &overlay,
+            "東京,0,0,3000,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\
+             京都,0,0,100,名詞,固有名詞,地域,一般,*,*,京都,キョウト,キョート\n\
+             京都,0,0,100,名詞,固有名詞,地域,一般,*,*,京都,キョウト,ミヤコ\n",
+        )
+        .unwrap();
+        let inputs = [base.to_str().unwrap(), overlay.to_str().unwrap()];
+        let merge = |on_duplicate| {
+            process_csv_files(&inputs, &csv_options(on_duplicate)).map(|(_, entries)| {
+                entries
+                    .into_iter()
+                    .map(|e| (e.surface, e.reading, e.cost))
+                    .collect::<Vec<_>>()
+            })
+        };
+
+        let entry = |surface: &str, reading: &str, cost| (surface.into(), reading.into(), cost);
+        assert_eq!(
+            merge(OnDuplicate::MinCost).unwrap(),
+            [
+                entry("東京", "トーキョー", 3000),
+                entry("京都", "キョート", 100),
+                entry("京都", "ミヤコ", 100),
+            ]
+        );
+        assert_eq!(
+            merge(OnDuplicate::First).unwrap()[1],
+            entry("京都", "キョート", 3000)
+        );
+        let err = merge(OnDuplicate::Error).unwrap_err();
+        assert!(
+            matches!(&err, Failure::Dictionary(message) if message.contains("overlay.csv:2")),
+            "{}",
+            err
+        );
+
+        let output = dir.join("out");
+        convert_dictionary(
+            &inputs,
+            output.to_str().unwrap(),
+            csv_options(OnDuplicate::MinCost),
+            OutputOptions::default(),
+            true,
+        )
+        .unwrap();
+        let dict = mucab::Dictionary::load(output.join("mucab.bin")).unwrap();
+        assert_eq!(dict.num_entries(), 3);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_older_format_versions() {
+        let dir = env::temp_dir().join(format!("mucab-converter-format-{}", std::process::id()));
+        let input = dir.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(
+            input.join("lex.csv"),
+            "東京,3,5,3000,名詞,固有名詞,地域,一般,*,*,東京,トーキョー,トーキョー\n",
+        )
+        .unwrap();
+        let build = |format_version| {
+            let output = dir.join(format!("v{}", format_version));
+            convert_dictionary(
+                &[input.to_str().unwrap()],
+                output.to_str().unwrap(),
+                csv_options(OnDuplicate::default()),
+                OutputOptions {
+                    format_version: Some(format_version),
+                    ..OutputOptions::default()
+                },
+                true,
+            )
+            .map(|_| mucab::Dictionary::load(output.join("mucab.bin")).unwrap())
+        };
+
+        let mut dict = build(6).unwrap();
+        assert_eq!(dict.format_version(), 6);
+        assert_eq!(dict.data().checksum(), None);
+        assert_eq!(mucab::transliterate("東京", &mut dict), "トーキョー");
+        assert_eq!(build(mucab::FORMAT_VERSION).unwrap().format_version(), 9);
+        // Version 1 has one context id per entry.
+        let err = build(1).err().unwrap();
+        assert!(
+            matches!(&err, Failure::Dictionary(m) if m.contains("version 1")),
+            "{}",
+            err
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_overrides_are_built_in() {
+        let dir = env::temp_dir().join(format!("mucab-converter-overrides-{}", std::process::id()));
+        let input = dir.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(
+            input.join("lex.csv"),
+            "今日,3,5,3000,名詞,副詞可能,*,*,*,*,今日,キョウ,キョウ\n\
+             今日,3,5,3500,名詞,副詞可能,*,*,*,*,今日,コンニチ,コンニチ\n",
+        )
+        .unwrap();
+        let overrides = dir.join("overrides.csv");
+        std::fs::write(
+            &overrides,
+            "# surface,reading,delta\n今日,こんにち,-400\n今日,キョウ,100\n明日,アシタ,-1\n",
+        )
+        .unwrap();
+        let build = |strict| {
+            let output = dir.join("output");
+            convert_dictionary(
+                &[input.to_str().unwrap()],
+                output.to_str().unwrap(),
+                CsvOptions {
+                    overrides: vec![overrides.clone()],
+                    strict,
+                    ..csv_options(OnDuplicate::default())
+                },
+                OutputOptions {
+                    reading_kana: KanaForm::Hiragana,
+                    ..OutputOptions::default()
+                },
+                true,
+            )
+            .map(|_| mucab::Dictionary::load(output.join("mucab.bin")).unwrap())
+        };
+
+        // Either form of a reading matches; 明日 matches nothing, which only --strict minds.
+        let mut dict = build(false).unwrap();
+        let mut costs: Vec<(String, i16)> = dict
+            .entries_starting_with('今')
+            .into_iter()
+            .map(|(entry, reading)| (reading, entry.word_cost))
+            .collect();
+        costs.sort();
+        assert_eq!(
+            costs,
+            [("きょう".to_string(), 3100), ("こんにち".to_string(), 3100)]
+        );
+        let err = build(true).err().unwrap();
+        assert!(
+            matches!(&err, Failure::Dictionary(m) if m.contains("明日,アシタ")),
+            "{}",
+            err
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_reading_kana() {
+        let dir = env::temp_dir().join(format!("mucab-converter-kana-{}", std::process::id()));
+        let input = dir.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(
+            input.join("lex.csv"),
+            "今日,3,5,3000,名詞,副詞可能,*,*,*,*,今日,キョウ,キョウ\n\
+             きょう,3,5,3000,名詞,副詞可能,*,*,*,*,きょう,きょう,きょう\n",
+        )
+        .unwrap();
+        let build = |reading_kana| {
+            let output = dir.join(format!("{:?}", reading_kana));
+            let stats = convert_dictionary(
+                &[input.to_str().unwrap()],
+                output.to_str().unwrap(),
+                csv_options(OnDuplicate::default()),
+                OutputOptions {
+                    reading_kana,
+                    ..OutputOptions::default()
+                },
+                true,
+            )
+            .unwrap();
+            let dict = mucab::Dictionary::load(output.join("mucab.bin")).unwrap();
+            (stats, dict)
+        };
+
+        let (katakana, mut dict) = build(KanaForm::Katakana);
+        assert_eq!(dict.reading_kana(), KanaForm::Katakana);
+        assert_eq!(
+            mucab::transliterate("今日きょう", &mut dict),
+            "キョウきょう"
+        );
+        let (hiragana, mut dict) = build(KanaForm::Hiragana);
+        assert_eq!(dict.reading_kana(), KanaForm::Hiragana);
+        assert_eq!(
+            mucab::transliterate("今日きょう", &mut dict),
+            "きょうきょう"
+        );
+        // The hiragana readings are one string; the katakana ones two.
+        assert_eq!(hiragana.strings_bytes, "きょう".len());
+        assert_eq!(katakana.strings_bytes, 2 * "きょう".len());
+        assert_eq!(katakana.other_kana_strings_bytes, hiragana.strings_bytes);
+        assert_eq!(hiragana.other_kana_strings_bytes, katakana.strings_bytes);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_missing_matrix_is_all_zero() {
+        let dir = env::temp_dir().join(format!("mucab-converter-nomatrix-{}", std::process::id()));
+        let input = dir.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(
+            input.join("lex.csv"),
+            "東京,3,3,3000,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\
+             都,1,1,1000,名詞,接尾,地域,*,*,*,都,ト,ト\n\
+             東京都,5,5,5000,名詞,固有名詞,地域,一般,*,*,東京都,トウキョウト,トーキョート\n",
+        )
+        .unwrap();
+        let build = |name: &str, use_matrix| {
+            let output = dir.join(name);
+            convert_dictionary(
+                &[input.to_str().unwrap()],
+                output.to_str().unwrap(),
+                csv_options(OnDuplicate::default()),
+                OutputOptions::default(),
+                use_matrix,
+            )
+            .unwrap();
+            mucab::Dictionary::load(output.join("mucab.bin")).unwrap()
+        };
+
+        let mut dict = build("missing", true);
+        assert_eq!(dict.data().connection_cost(2, 0), Some(0));
+        assert_eq!(dict.data().connection_cost(2, 3), None);
+        // Word costs alone: 3000 + 1000 is cheaper than 5000.
+        assert_eq!(mucab::tokenize("東京都", &mut dict).len(), 2);
+
+        // A matrix that would make 東京 + 都 expensive is ignored with --no-matrix.
+        let matrix: String = (0..6)
+            .flat_map(|r| (0..6).map(move |l| format!("{} {} 9000\n", r, l)))
+            .collect();
+        std::fs::write(input.join("matrix.def"), format!("6 6\n{}", matrix)).unwrap();
+        assert_eq!(mucab::tokenize("東京都", &mut build("used", true)).len(), 1);
+        assert_eq!(
+            mucab::tokenize("東京都", &mut build("ignored", false)).len(),
+            2
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_pos_filter_shrinks_the_matrix() {
+        let dir = env::temp_dir().join(format!("mucab-converter-pos-{}", std::process::id()));
+        let input = dir.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(
+            input.join("lex.csv"),
+            "東京,3,3,3000,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\
+             都,1,1,1000,名詞,接尾,地域,*,*,*,都,ト,ト\n\
+             東京都,5,5,5000,名詞,固有名詞,地域,一般,*,*,東京都,トウキョウト,トーキョート\n\
+             。,2,2,0,記号,句点,*,*,*,*,。,。,。\n\
+             行く,4,4,100,動詞,自立,*,*,五段・カ行促音便,基本形,行く,イク,イク\n",
+        )
+        .unwrap();
+        // 東京 followed by 都 is expensive, so 東京都 stays one word.
+        let matrix: String = (0..6)
+            .flat_map(|r| (0..6).map(move |l| (r, l)))
+            .map(|(r, l)| format!("{} {} {}\n", r, l, if (r, l) == (3, 1) { 9000 } else { 0 }))
+            .collect();
+        std::fs::write(input.join("matrix.def"), format!("6 6\n{}", matrix)).unwrap();
+        let build = |name: &str, include: &[&str], exclude: &[&str]| {
+            let output = dir.join(name);
+            let strings = |patterns: &[&str]| -> Vec<String> {
+                patterns.iter().map(|p| p.to_string()).collect()
+            };
+            let options = CsvOptions {
+                pos_filter: PosFilter::new(&strings(include), &strings(exclude)),
+                ..csv_options(OnDuplicate::default())
+            };
+            let input = input.to_str().unwrap();
+            convert_dictionary(
+                &[input],
+                output.to_str().unwrap(),
+                options.clone(),
+                OutputOptions::default(),
+                true,
+            )
+            .unwrap();
+            let (id_maps, _) = process_csv_files(&[input], &options).unwrap();
+            let dict = mucab::Dictionary::load(output.join("mucab.bin")).unwrap();
+            (dict, id_maps.dropped_entries, id_maps.dropped_ids())
+        };
+
+        let (mut dict, dropped, ids) = build("symbols", &[], &["記号"]);
+        assert_eq!((dict.num_entries(), dropped, ids), (4, 1, (1, 1)));
+        assert_eq!(dict.data().pos_count(), 4);
+        assert_eq!(dict.data().matrix_size(), 16);
+        assert_eq!(mucab::tokenize("東京都", &mut dict).len(), 1);
+
+        let (mut dict, dropped, _) = build("nouns", &["名詞", "動詞"], &["名詞,接尾"]);
+        assert_eq!((dict.num_entries(), dropped), (3, 2));
+        assert_eq!(dict.data().matrix_size(), 9);
+        assert_eq!(
+            mucab::transliterate("東京に行く", &mut dict),
+            "トーキョーにイク"
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_stats_report() {
+        let dir = env::temp_dir().join(format!("mucab-converter-stats-{}", std::process::id()));
+        let input = dir.join("input");
+        std::fs::create_dir_all(&input).unwrap();
+        std::fs::write(
+            input.join("lex.csv"),
+            "東京,3,3,3000,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\
+             東,1,1,1000,名詞,一般,*,*,*,*,東,ヒガシ,ヒガシ\n\
+             京,1,1,1000,名詞,一般,*,*,*,*,京,キョウ,キョー\n\
+             今日,1,1,1000,名詞,副詞可能,*,*,*,*,今日,キョウ,キョー\n",
+        )
+        .unwrap();
+        std::fs::write(input.join("matrix.def"), "4 4\n3 1 700\n1 3 -50\n1 1 20\n").unwrap();
+        let output = dir.join("out");
+        let stats = convert_dictionary(
+            &[input.to_str().unwrap()],
+            output.to_str().unwrap(),
+            csv_options(OnDuplicate::default()),
+            OutputOptions::default(),
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(stats.top_first_chars, [('東', 2), ('京', 1), ('今', 1)]);
+        assert_eq!(stats.surface_lengths, BTreeMap::from([(1, 2), (2, 2)]));
+        assert_eq!(stats.reading_lengths, BTreeMap::from([(3, 3), (5, 1)]));
+        // キョー is stored once; ヒガシ and トーキョー share nothing with the others.
+        let bytes = |s: &str| s.len();
+        assert_eq!(
+            stats.readings_bytes,
+            bytes("トーキョー") + bytes("ヒガシ") + 2 * bytes("キョー")
+        );
+        assert_eq!(
+            stats.distinct_readings_bytes,
+            stats.readings_bytes - bytes("キョー")
+        );
+        assert!(stats.strings_bytes <= stats.distinct_readings_bytes);
+        assert_eq!((stats.matrix_cells, stats.matrix_nonzero), (4, 3));
+        let costs: Vec<i16> = stats.expensive_connections.iter().map(|c| c.cost).collect();
+        assert_eq!(costs, [700, 20]);
+        assert_eq!(
+            stats.expensive_connections[0].right_pos.as_deref(),
+            Some("名詞,固有名詞,地域,一般,*,*")
+        );
+        let written = std::fs::metadata(output.join("mucab.bin")).unwrap().len() as usize;
+        let stored: usize = stats.sections.iter().map(|s| s.stored_bytes).sum();
+        assert_eq!(stored, written);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_failures_are_classified() {
+        let dir = env::temp_dir().join(format!("mucab-converter-fail-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let matrix = dir.join("matrix.def");
+        std::fs::write(&matrix, "1 1\n0 x 0\n").unwrap();
+        let err = load_matrix(
+            matrix.to_str().unwrap(),
+            InputEncoding::Utf8,
+            &IdMaps::default(),
+        )
+        .unwrap_err();
+        let expected = "matrix.def:2: context ids and cost must be 16-bit integers";
+        assert!(
+            matches!(&err, Failure::Dictionary(message) if message.ends_with(expected)),
+            "{}",
+            err
+        );
+
+        let err = read_decoded(&dir.join("missing.csv"), InputEncoding::Utf8).unwrap_err();
+        assert!(matches!(err, Failure::Io(_)), "{}", err);
+        let err = Failure::from_io("x.bin", std::io::ErrorKind::UnexpectedEof.into());
+        assert!(matches!(err, Failure::Dictionary(_)), "{}", err);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    /// The entries of tests/fixtures/mecab/sys.dic, in IPADIC's layout.
+    const COMPILED_LEX: &str = "東京,3,3,3000,名詞,固有名詞,地域,一般,*,*,東京,トウキョウ,トーキョー\n\
+                                都,1,1,1000,名詞,接尾,地域,*,*,*,都,ト,ト\n\
+                                都,2,2,3500,名詞,一般,*,*,*,*,都,ミヤコ,ミヤコ\n\
+                                東京都,5,5,6000,名詞,固有名詞,地域,一般,*,*,東京都,トウキョウト,トーキョート\n\
+                                京都,4,4,2000,名詞,固有名詞,地域,一般,*,*,京都,キョウト,キョート\n\
+                                東,0,0,2500,名詞,一般,*,*,*,*,東,ヒガシ,ヒガシ\n";
+
+    /// The connection cost from right id `prev_right` to left id `curr_left` in the fixture's
+    /// 6x6 matrix.
+    fn compiled_cost(prev_right: usize, curr_left: usize) -> i16 {
+        ((prev_right * 5 + curr_left * 3) % 7) as i16 * 100 - 200
+    }
+
+    /// Lays `keys` out, sorted and distinct, as the double array [`read_sys_dic`] walks, each
+    /// key ending in its value.
+    fn double_array(keys: &[(Vec<u8>, i32)]) -> Vec<(i32, u32)> {
+        fn place(
+            units: &mut Vec<(i32, u32)>,
+            bases: &mut HashSet<usize>,
+            node: usize,
+            depth: usize,
+            keys: &[(Vec<u8>, i32)],
+        ) {
+            // A key ending here takes code 0, any other its next byte plus one.
+            let code = |key: &[u8]| key.get(depth).map_or(0, |&b| b as usize + 1);
+            let mut codes: Vec<usize> = keys.iter().map(|(key, _)| code(key)).collect();
+            codes.dedup();
+            let free = |at: usize| units.get(at).is_none_or(|&unit| unit == (0, 0));
+            let base = (1..)
+                .find(|&base| !bases.contains(&base) && codes.iter().all(|&c| free(base + c)))
+                .unwrap();
+            bases.insert(base);
+            let end = base + codes.last().unwrap() + 1;
+            if units.len() < end {
+                units.resize(end, (0, 0));
+            }
+            units[node].0 = base as i32;
+            for &c in &codes {
+                units[base + c].1 = base as u32;
+            }
+            for &c in &codes {
+                let group: Vec<_> = keys
+                    .iter()
+                    .filter(|(key, _)| code(key) == c)
+                    .cloned()
+                    .collect();
+                if c == 0 {
+                    units[base].0 = -group[0].1 - 1;
+                } else {
+                    place(units, bases, base + c, depth + 1, &group);
+                }
+            }
+        }
+        let mut units = vec![(0, 0)];
+        place(&mut units, &mut HashSet::new(), 0, 0, keys);
+        units
+    }
+
+    /// Compiles `lex` into sys.dic the way mecab-dict-index does, in EUC-JP.
+    fn sys_dic(lex: &str) -> Vec<u8> {
+        let mut tokens: Vec<(Vec<u8>, [u16; 3], Vec<u8>)> = lex
+            .lines()
+            .map(|line| {
+                let parts: Vec<&str> = line.splitn(5, ',').collect();
+                let surface = EUC_JP.encode(parts[0]).0.into_owned();
+                let ids = [parts[1], parts[2], parts[3]].map(|n| n.parse::<i16>().unwrap() as u16);
+                (surface, ids, EUC_JP.encode(parts[4]).0.into_owned())
+            })
+            .collect();
+        tokens.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut keys: Vec<(Vec<u8>, i32)> = Vec::new();
+        for (idx, (surface, _, _)) in tokens.iter().enumerate() {
+            match keys.last_mut() {
+                Some((key, value)) if key == surface => *value += 1,
+                _ => keys.push((surface.clone(), ((idx as i32) << 8) + 1)),
+            }
+        }
+        let array: Vec<u8> = double_array(&keys)
+            .into_iter()
+            .flat_map(|(base, check)| [base.to_le_bytes(), check.to_le_bytes()].concat())
+            .collect();
+        let (mut token_bytes, mut features) = (Vec::new(), Vec::new());
+        for (_, [left_id, right_id, cost], feature) in &tokens {
+            for field in [*left_id, *right_id, 0, *cost] {
+                token_bytes.extend_from_slice(&field.to_le_bytes());
+            }
+            token_bytes.extend_from_slice(&(features.len() as u32).to_le_bytes());
+            token_bytes.extend_from_slice(&0u32.to_le_bytes());
+            features.extend_from_slice(feature);
+            features.push(0);
+        }
+
+        let size = SYS_DIC_HEADER_BYTES + array.len() + token_bytes.len() + features.len();
+        let header = [
+            SYS_DIC_MAGIC ^ size as u32,
+            SYS_DIC_VERSION,
+            0,
+            tokens.len() as u32,
+            6,
+            6,
+            array.len() as u32,
+            token_bytes.len() as u32,
+            features.len() as u32,
+            0,
+        ];
+        let mut bytes: Vec<u8> = header
+            .iter()
+            .flat_map(|field| field.to_le_bytes())
+            .collect();
+        let mut charset = b"EUC-JP".to_vec();
+        charset.resize(32, 0);
+        bytes.extend([charset, array, token_bytes, features].concat());
+        bytes
+    }
+
+    /// The fixture's matrix, compiled as mecab-dict-index writes matrix.bin.
+    fn matrix_bin() -> Vec<u8> {
+        let mut bytes = [6u16.to_le_bytes(), 6u16.to_le_bytes()].concat();
+        for curr_left in 0..6 {
+            for prev_right in 0..6 {
+                bytes.extend_from_slice(&compiled_cost(prev_right, curr_left).to_le_bytes());
+            }
+        }
+        bytes
+    }
+
+    #[test]
+    fn test_compiled_dictionary_converts_like_its_sources() {
+        // Rewrite the fixtures from `sys_dic` and `matrix_bin` when they change.
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/mecab");
+        assert_eq!(
+            std::fs::read(fixtures.join("sys.dic")).unwrap(),
+            sys_dic(COMPILED_LEX),
+            "tests/fixtures/mecab/sys.dic is out of date"
+        );
+        assert_eq!(
+            std::fs::read(fixtures.join("matrix.bin")).unwrap(),
+            matrix_bin(),
+            "tests/fixtures/mecab/matrix.bin is out of date"
+        );
+
+        let dir = env::temp_dir().join(format!("mucab-converter-compiled-{}", std::process::id()));
+        let sources = dir.join("sources");
+        std::fs::create_dir_all(&sources).unwrap();
+        std::fs::write(sources.join("lex.csv"), COMPILED_LEX).unwrap();
+        let matrix: String = (0..6)
+            .flat_map(|r| (0..6).map(move |l| format!("{} {} {}\n", r, l, compiled_cost(r, l))))
+            .collect();
+        std::fs::write(sources.join("matrix.def"), format!("6 6\n{}", matrix)).unwrap();
+        let convert = |input: &Path, compiled| {
+            let output = dir.join(if compiled { "compiled" } else { "csv" });
+            convert_dictionary(
+                &[input.to_str().unwrap()],
+                output.to_str().unwrap(),
+                CsvOptions {
+                    compiled,
+                    ..csv_options(OnDuplicate::default())
+                },
+                OutputOptions::default(),
+                true,
+            )
+            .unwrap();
+            std::fs::read(output.join("mucab.bin")).unwrap()
+        };
+        let compiled = convert(&fixtures, true);
+        assert!(compiled == convert(&sources, false));
+
+        let mut dict = mucab::Dictionary::from_bytes(compiled).unwrap();
+        assert_eq!(dict.num_entries(), 6);
+        assert_eq!(dict.data().connection_cost(2, 3), Some(compiled_cost(2, 3)));
+        assert_eq!(mucab::transliterate("東京都", &mut dict), "トーキョート");
+
+        let err = read_sys_dic(&fixtures.join("matrix.bin")).unwrap_err();
+        assert!(
+            err.to_string().ends_with("not a compiled MeCab dictionary"),
+            "{}",
+            err
+        );
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_parse_encoding() {
+        assert_eq!(InputEncoding::parse("EUC-JP"), Some(InputEncoding::EucJp));
+        assert_eq!(InputEncoding::parse("utf8"), Some(InputEncoding::Utf8));
+        assert_eq!(InputEncoding::parse("auto"), Some(InputEncoding::Auto));
+        assert_eq!(InputEncoding::parse("shift_jis"), None);
+    }
 }
@@ -1,21 +1,482 @@
-use mucab::{transliterate, Dictionary};
+use mucab::{
+    analyze, format, tokenize, tokenize_nbest, transliterate, transliterate_to,
+    transliterate_with_stats, Analysis, Dictionary, MucabError, TransliterateStats,
+};
+use std::borrow::Cow;
 use std::env;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Removes `flag` and the value after it from `args`, returning the value.
+fn take_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == flag)?;
+    let value = args.get(i + 1).cloned().unwrap_or_default();
+    args.drain(i..(i + 2).min(args.len()));
+    Some(value)
+}
+
+/// Removes `flag` from `args`, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|a| a == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Exit code for bad arguments, and for no dictionary given with none found. 1 means the run
+/// worked but found something, such as `analyze --snapshot-compare` finding differences.
+const EXIT_USAGE: i32 = 2;
+/// Exit code for a dictionary file that can't be used.
+const EXIT_DICTIONARY: i32 = 3;
+/// Exit code for a file or stream that can't be read or written.
+const EXIT_IO: i32 = 4;
+
+fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_USAGE);
+}
+
+fn io_error(context: &str, e: std::io::Error) -> ! {
+    eprintln!("{}: {}", context, e);
+    std::process::exit(EXIT_IO);
+}
+
+fn print_search_paths() {
+    let paths = Dictionary::search_paths();
+    for path in &paths {
+        eprintln!("  {}", path.display());
+    }
+    if paths.is_empty() {
+        eprintln!("  (none: MUCAB_DICT and the data directory are unset)");
+    }
+}
+
+/// Removes the first argument from `args` if it names a dictionary, returning it. The
+/// dictionary can be left out when a default one is installed.
+fn take_dictionary(args: &mut Vec<String>) -> Option<String> {
+    match args.get(1) {
+        Some(arg) if arg.ends_with(".bin") || Path::new(arg).is_file() => Some(args.remove(1)),
+        _ => None,
+    }
+}
+
+/// `named`, or else the default dictionary, exiting with the places looked at if there is none.
+fn dictionary_path(named: Option<String>) -> PathBuf {
+    named
+        .map(PathBuf::from)
+        .or_else(Dictionary::default_path)
+        .unwrap_or_else(|| {
+            eprintln!("No dictionary given, and none found at:");
+            print_search_paths();
+            std::process::exit(EXIT_USAGE);
+        })
+}
+
+/// Loads the dictionary at `path`, exiting with a message if it can't be opened or isn't one.
+fn load_dictionary(path: &Path) -> Dictionary<'static> {
+    Dictionary::load(path).unwrap_or_else(|e| match e {
+        MucabError::Io(e) => io_error(
+            &format!("cannot open dictionary file '{}'", path.display()),
+            e,
+        ),
+        invalid => {
+            eprintln!("dictionary file '{}': {}", path.display(), invalid);
+            std::process::exit(EXIT_DICTIONARY);
+        }
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    /// The joined reading.
+    Plain,
+    /// [`format::format_tsv`]: a token per line, then `EOS`.
+    Tsv,
+    /// A JSON array of tokens.
+    Json,
+}
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        eprintln!("Usage: {} <mucab.bin> <text>", args[0]);
-        std::process::exit(1);
+    let mut args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("analyze") {
+        args.remove(1);
+        analyze_lines(args);
+        return;
+    }
+    let output_format = match take_value(&mut args, "--format").as_deref() {
+        None | Some("plain") => OutputFormat::Plain,
+        Some("tsv") => OutputFormat::Tsv,
+        Some("json") if cfg!(feature = "serde") => OutputFormat::Json,
+        Some("json") => usage_error("--format json needs mucab built with the serde feature"),
+        Some(_) => usage_error("format must be one of plain, tsv or json"),
+    };
+    let input_path = take_value(&mut args, "--input");
+    let output_path = take_value(&mut args, "--output");
+    let lossy = take_flag(&mut args, "--lossy");
+    let bench_runs = take_value(&mut args, "--bench").map(|value| match value.parse() {
+        Ok(runs) if runs > 0 => runs,
+        _ => usage_error("--bench takes a positive number of runs"),
+    });
+    let interactive = take_flag(&mut args, "--interactive");
+    let verbose = take_flag(&mut args, "-v");
+    let which_dict = take_flag(&mut args, "--which-dict");
+    let dot = take_flag(&mut args, "--dot");
+    let named_dict = take_dictionary(&mut args);
+    if which_dict && args.len() == 1 {
+        println!("{}", dictionary_path(named_dict).display());
+        return;
+    }
+    if interactive && args.len() == 1 {
+        interact(&dictionary_path(named_dict), verbose);
+        return;
+    }
+    if dot && args.len() == 2 {
+        let mut dict = load_dictionary(&dictionary_path(named_dict));
+        let graph = mucab::dot::dump_lattice(&args[1], &mut dict);
+        match &output_path {
+            Some(path) => std::fs::write(path, graph).unwrap_or_else(|e| io_error(path, e)),
+            None => print!("{}", graph),
+        }
+        return;
+    }
+    if let (Some(runs), 2) = (bench_runs, args.len()) {
+        bench(&dictionary_path(named_dict), &args[1], runs);
+        return;
+    }
+    let batch = input_path.is_some() || output_path.is_some();
+    if which_dict
+        || dot
+        || interactive
+        || verbose
+        || bench_runs.is_some()
+        || args.len() > 2
+        || batch && args.len() != 1
+    {
+        eprintln!(
+            "Usage: {} [--format plain|tsv|json] [<mucab.bin>] [<text> | -]",
+            args[0]
+        );
+        eprintln!(
+            "       {} [--format plain|tsv|json] [--lossy] [<mucab.bin>] \
+             [--input <file>] [--output <file>]",
+            args[0]
+        );
+        eprintln!("       {} --interactive [-v] [<mucab.bin>]", args[0]);
+        eprintln!("       {} --bench N [<mucab.bin>] <text>", args[0]);
+        eprintln!(
+            "       {} --dot [<mucab.bin>] <text> [--output <file.dot>]",
+            args[0]
+        );
+        eprintln!("       {} --which-dict [<mucab.bin>]", args[0]);
+        eprintln!(
+            "       {} analyze [<mucab.bin>] [--snapshot-out <dir> | --snapshot-compare <dir>] < input.txt",
+            args[0]
+        );
+        eprintln!("Without <mucab.bin>, the first of these that exists is used:");
+        print_search_paths();
+        std::process::exit(EXIT_USAGE);
     }
 
-    let dict_path = &args[1];
-    let input_text = &args[2];
+    let mut dict = load_dictionary(&dictionary_path(named_dict));
 
-    let mut dict = Dictionary::load(dict_path).expect("Failed to load dictionary");
-    println!("Loaded dictionary with {} entries", dict.num_entries);
+    let input_text = match args.get(1).map(String::as_str) {
+        None | Some("-") => {
+            let options = LineOptions {
+                output_format,
+                lossy,
+                flush_lines: output_path.is_none(),
+                progress: input_path.is_some() || output_path.is_some(),
+            };
+            convert_files(
+                input_path.as_deref(),
+                output_path.as_deref(),
+                &mut dict,
+                &options,
+            );
+            return;
+        }
+        Some(text) => text,
+    };
+    if output_format != OutputFormat::Plain {
+        write_result(&mut std::io::stdout(), input_text, &mut dict, output_format)
+            .unwrap_or_else(|e| io_error("cannot write stdout", e));
+        return;
+    }
+    println!("Loaded dictionary with {} entries", dict.num_entries());
 
     println!("Input: {}", input_text);
 
     let result = transliterate(input_text, &mut dict);
     println!("Output: {}", result);
 }
+
+/// Writes the result for one line of input in `output_format`.
+fn write_result(
+    out: &mut impl Write,
+    text: &str,
+    dict: &mut Dictionary,
+    output_format: OutputFormat,
+) -> std::io::Result<()> {
+    match output_format {
+        OutputFormat::Plain => {
+            transliterate_to(text, dict, &mut *out)?;
+            writeln!(out)
+        }
+        OutputFormat::Tsv => format::write_tsv(tokenize(text, dict), &mut *out),
+        #[cfg(feature = "serde")]
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut *out, &tokenize(text, dict))?;
+            writeln!(out)
+        }
+        #[cfg(not(feature = "serde"))]
+        OutputFormat::Json => unreachable!("json output is rejected without the serde feature"),
+    }
+}
+
+/// Converts the file at `input_path`, or stdin, into the file at `output_path`, or stdout.
+fn convert_files(
+    input_path: Option<&str>,
+    output_path: Option<&str>,
+    dict: &mut Dictionary,
+    options: &LineOptions,
+) {
+    let input: Box<dyn BufRead> = match input_path {
+        Some(path) => Box::new(BufReader::new(
+            File::open(path).unwrap_or_else(|e| io_error(path, e)),
+        )),
+        None => Box::new(std::io::stdin().lock()),
+    };
+    let output: Box<dyn Write> = match output_path {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).unwrap_or_else(|e| io_error(path, e)),
+        )),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    if let Err(e) = transliterate_lines(input, output, dict, options) {
+        let context = format!(
+            "failed converting {} into {}",
+            input_path.unwrap_or("stdin"),
+            output_path.unwrap_or("stdout")
+        );
+        io_error(&context, e);
+    }
+}
+
+/// How [`transliterate_lines`] reads, writes and reports.
+struct LineOptions {
+    output_format: OutputFormat,
+    /// Convert lines that aren't UTF-8 with replacement characters instead of skipping them.
+    lossy: bool,
+    /// Flush after every line, so results show up as soon as they are ready in a pipeline.
+    flush_lines: bool,
+    /// Report lines per second to stderr.
+    progress: bool,
+}
+
+/// Converts `input` line by line into `output`, streaming rather than reading it all first.
+/// Stops quietly when the output is a closed pipe, e.g. read by `head`.
+fn transliterate_lines(
+    mut input: impl BufRead,
+    mut output: impl Write,
+    dict: &mut Dictionary,
+    options: &LineOptions,
+) -> std::io::Result<()> {
+    let started = Instant::now();
+    let mut last_report = started;
+    let mut bytes = Vec::new();
+    let mut line_no = 0;
+    loop {
+        bytes.clear();
+        if input.read_until(b'\n', &mut bytes)? == 0 {
+            break;
+        }
+        line_no += 1;
+        let line = bytes.strip_suffix(b"\n").unwrap_or(&bytes);
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let line = match std::str::from_utf8(line) {
+            Ok(line) => Cow::Borrowed(line),
+            Err(_) if options.lossy => String::from_utf8_lossy(line),
+            Err(e) => {
+                eprintln!("line {}: {}, skipping", line_no, e);
+                continue;
+            }
+        };
+
+        let written = write_result(&mut output, &line, dict, options.output_format);
+        let written = written.and_then(|()| match options.flush_lines {
+            true => output.flush(),
+            false => Ok(()),
+        });
+        match written {
+            Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => return Ok(()),
+            written => written?,
+        }
+
+        if options.progress && last_report.elapsed() >= Duration::from_secs(1) {
+            last_report = Instant::now();
+            let rate = line_no as f64 / started.elapsed().as_secs_f64();
+            eprint!("\r{} lines, {:.0} lines/s", line_no, rate);
+        }
+    }
+    output.flush()?;
+    if options.progress {
+        let rate = line_no as f64 / started.elapsed().as_secs_f64();
+        eprintln!(
+            "\r{} lines in {:.1?}, {:.0} lines/s",
+            line_no,
+            started.elapsed(),
+            rate
+        );
+    }
+    Ok(())
+}
+
+/// Loads the dictionary once, then converts each line typed at the prompt until end of input.
+/// With `verbose`, also prints the tokens along the chosen path and its total cost.
+fn interact(dict_path: &Path, verbose: bool) {
+    let started = Instant::now();
+    let mut dict = load_dictionary(dict_path);
+    eprintln!(
+        "Loaded dictionary with {} entries in {:.1?}",
+        dict.num_entries(),
+        started.elapsed()
+    );
+
+    let stdin = std::io::stdin();
+    let mut stdin = stdin.lock();
+    let mut line = String::new();
+    loop {
+        print!("> ");
+        std::io::stdout().flush().ok();
+        line.clear();
+        match stdin.read_line(&mut line) {
+            Ok(0) => {
+                println!();
+                return;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("Failed to read line: {}", e);
+                continue;
+            }
+        }
+        let text = line.trim_end_matches(['\n', '\r']);
+        let converted = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut out = transliterate(text, &mut dict) + "\n";
+            if verbose {
+                let (tokens, cost) = tokenize_nbest(text, &mut dict, 1).pop().unwrap_or_default();
+                out += &format::format_tsv(tokens);
+                out += &format!("cost {}\n", cost);
+            }
+            out
+        }));
+        match converted {
+            Ok(out) => print!("{}", out),
+            Err(_) => eprintln!("Failed to convert {:?}", text),
+        }
+    }
+}
+
+/// Converts `text` once to warm the caches, then `runs` times, and prints the latency spread and
+/// where the time went on average.
+fn bench(dict_path: &Path, text: &str, runs: usize) {
+    let started = Instant::now();
+    let mut dict = load_dictionary(dict_path);
+    println!("Loaded dictionary in {:.1?}", started.elapsed());
+    let (reading, stats) = transliterate_with_stats(text, &mut dict);
+    println!("Output: {}", reading);
+    println!("{} nodes, {} tokens", stats.nodes, stats.tokens);
+
+    let mut latencies = Vec::with_capacity(runs);
+    let mut total = TransliterateStats::default();
+    for _ in 0..runs {
+        let started = Instant::now();
+        let (_, stats) = transliterate_with_stats(text, &mut dict);
+        latencies.push(started.elapsed());
+        total.lookup_ns += stats.lookup_ns;
+        total.viterbi_ns += stats.viterbi_ns;
+        total.readings_ns += stats.readings_ns;
+    }
+    latencies.sort_unstable();
+    let mean = latencies.iter().sum::<Duration>() / runs as u32;
+    let p99 = latencies[(runs * 99).div_ceil(100) - 1];
+    println!(
+        "{} runs: min {:.1?}, mean {:.1?}, p99 {:.1?}",
+        runs, latencies[0], mean, p99
+    );
+    let runs = runs as u64;
+    println!(
+        "mean lattice {:.1?}, viterbi {:.1?}, readings {:.1?}",
+        Duration::from_nanos(total.lookup_ns / runs),
+        Duration::from_nanos(total.viterbi_ns / runs),
+        Duration::from_nanos(total.readings_ns / runs)
+    );
+}
+
+enum SnapshotMode<'a> {
+    None,
+    Write(&'a Path),
+    Compare(&'a Path),
+}
+
+/// Analyses stdin line by line. Snapshot `N.snap` holds the analysis of line `N` (from 1). Exits
+/// with 1 if any line differs from its snapshot.
+fn analyze_lines(mut args: Vec<String>) {
+    let named_dict = take_dictionary(&mut args);
+    let mut dict = load_dictionary(&dictionary_path(named_dict));
+    let mode = match (args.get(1).map(String::as_str), args.get(2)) {
+        (None, _) => SnapshotMode::None,
+        (Some("--snapshot-out"), Some(dir)) => {
+            std::fs::create_dir_all(dir).unwrap_or_else(|e| io_error(dir, e));
+            SnapshotMode::Write(Path::new(dir))
+        }
+        (Some("--snapshot-compare"), Some(dir)) => SnapshotMode::Compare(Path::new(dir)),
+        _ => usage_error("Expected --snapshot-out <dir> or --snapshot-compare <dir>"),
+    };
+
+    let mut differing = 0;
+    let stdin = std::io::stdin();
+    for (i, line) in stdin.lock().lines().enumerate() {
+        let line = line.unwrap_or_else(|e| io_error("cannot read stdin", e));
+        let analysis = analyze(&line, &mut dict);
+        let snapshot_name = format!("{}.snap", i + 1);
+        match mode {
+            SnapshotMode::None => {
+                let tokens: Vec<_> = analysis.tokens.iter().map(|t| &t.token).collect();
+                print!("{}", format::format_tsv(tokens));
+            }
+            SnapshotMode::Write(dir) => {
+                let path = dir.join(&snapshot_name);
+                std::fs::write(&path, analysis.to_bytes())
+                    .unwrap_or_else(|e| io_error(&path.display().to_string(), e));
+            }
+            SnapshotMode::Compare(dir) => {
+                let diffs = match std::fs::read(dir.join(&snapshot_name)) {
+                    Ok(bytes) => match Analysis::from_bytes(&bytes) {
+                        Ok(earlier) => analysis.differences(&earlier),
+                        Err(e) => vec![format!("unreadable snapshot: {}", e)],
+                    },
+                    Err(_) => vec!["no earlier snapshot".to_string()],
+                };
+                if !diffs.is_empty() {
+                    differing += 1;
+                    println!("line {}: {}", i + 1, line);
+                    for diff in diffs {
+                        println!("  {}", diff);
+                    }
+                }
+            }
+        }
+    }
+
+    if differing > 0 {
+        eprintln!("{} line(s) differ", differing);
+        std::process::exit(1);
+    }
+}
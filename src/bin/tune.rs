@@ -0,0 +1,55 @@
+use mucab::overrides::format_overrides;
+use mucab::tune::{parse_corpus, tune, TuneOptions};
+use mucab::Dictionary;
+use std::env;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 4 {
+        eprintln!(
+            "Usage: {} <mucab.bin> <corpus.tsv> <overrides.csv> [--max-delta N] [--nbest N] [--base overrides.csv]",
+            args[0]
+        );
+        std::process::exit(1);
+    }
+
+    let mut options = TuneOptions::default();
+    let mut base = None;
+    let mut rest = args[4..].iter();
+    while let Some(flag) = rest.next() {
+        let value = rest.next().unwrap_or_else(|| {
+            eprintln!("{} needs a value", flag);
+            std::process::exit(1);
+        });
+        match flag.as_str() {
+            "--max-delta" => options.max_delta = value.parse().expect("Invalid --max-delta"),
+            "--nbest" => options.nbest = value.parse().expect("Invalid --nbest"),
+            "--base" => base = Some(value.clone()),
+            _ => {
+                eprintln!("Unknown option {}", flag);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut dict = Dictionary::load(&args[1]).expect("Failed to load dictionary");
+    if let Some(base) = base {
+        let n = dict
+            .load_overrides(&base)
+            .expect("Failed to load overrides");
+        println!("Loaded {} existing overrides", n);
+    }
+
+    let text = std::fs::read_to_string(&args[2]).expect("Failed to read corpus");
+    let corpus = parse_corpus(&text).expect("Failed to parse corpus");
+
+    let report = tune(&mut dict, &corpus, &options);
+    println!(
+        "Correct before: {}/{}, after: {}/{}",
+        report.correct_before, report.sentences, report.correct_after, report.sentences
+    );
+
+    std::fs::write(&args[3], format_overrides(&report.overrides))
+        .expect("Failed to write overrides");
+    println!("Wrote {} overrides to {}", report.overrides.len(), args[3]);
+}
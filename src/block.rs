@@ -0,0 +1,357 @@
+//! Decoded entry blocks: all entries starting with one char, in two allocations.
+//!
+//! A block keeps one byte buffer and a fixed-size record per entry pointing into it. A block
+//! decoded from the file keeps the bytes of its records as they were read, so surfaces are
+//! never copied out; a block merged with added entries packs their surfaces and readings into
+//! a fresh buffer. [`EntryRef`] hands an entry out as slices of its block.
+
+use std::cmp::Ordering;
+
+use crate::{read_record, DictEntry, EntryReading};
+
+#[derive(Debug, Clone, Copy)]
+enum RecordReading {
+    /// `len` bytes at `offset` into the dictionary's strings section.
+    Stored { offset: u32, len: u16 },
+    /// `len` bytes at `start` into the block's buffer.
+    Inline { start: u32, len: u32 },
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Record {
+    surface_start: u32,
+    surface_len: u32,
+    char_len: u16,
+    pos_id: u16,
+    right_id: u16,
+    word_cost: i16,
+    reading: RecordReading,
+    lemma: Option<RecordReading>,
+    /// `pronunciation_len` bytes at `pronunciation_start` into the buffer; none if empty.
+    pronunciation_start: u32,
+    pronunciation_len: u16,
+    accent: Option<u8>,
+}
+
+/// The entries starting with one char, sorted by surface.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EntryBlock {
+    bytes: Box<[u8]>,
+    records: Box<[Record]>,
+}
+
+/// Where an entry's reading lives, borrowed from its block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ReadingRef<'b> {
+    Stored { offset: u32, len: u16 },
+    Inline(&'b str),
+}
+
+/// An entry of an [`EntryBlock`], the borrowed counterpart of [`DictEntry`].
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EntryRef<'b> {
+    pub surface: &'b str,
+    pub char_len: u16,
+    pub pos_id: u16,
+    pub right_id: u16,
+    pub word_cost: i16,
+    pub reading: ReadingRef<'b>,
+    pub lemma: Option<ReadingRef<'b>>,
+    pub pronunciation: Option<&'b str>,
+    pub accent: Option<u8>,
+}
+
+impl EntryRef<'_> {
+    pub fn to_entry(self) -> DictEntry {
+        DictEntry {
+            surface: self.surface.to_string(),
+            char_len: self.char_len,
+            pos_id: self.pos_id,
+            right_id: self.right_id,
+            word_cost: self.word_cost,
+            reading: self.reading.into(),
+            lemma: self.lemma.map(EntryReading::from),
+            pronunciation: self.pronunciation.map(str::to_string),
+            accent: self.accent,
+        }
+    }
+}
+
+impl From<ReadingRef<'_>> for EntryReading {
+    fn from(reading: ReadingRef) -> Self {
+        match reading {
+            ReadingRef::Stored { offset, len } => EntryReading::Stored { offset, len },
+            ReadingRef::Inline(reading) => EntryReading::Inline(reading.into()),
+        }
+    }
+}
+
+impl<'b> From<&'b EntryReading> for ReadingRef<'b> {
+    fn from(reading: &'b EntryReading) -> Self {
+        match reading {
+            &EntryReading::Stored { offset, len } => ReadingRef::Stored { offset, len },
+            EntryReading::Inline(reading) => ReadingRef::Inline(reading),
+        }
+    }
+}
+
+impl<'b> From<&'b DictEntry> for EntryRef<'b> {
+    fn from(entry: &'b DictEntry) -> Self {
+        EntryRef {
+            surface: &entry.surface,
+            char_len: entry.char_len,
+            pos_id: entry.pos_id,
+            right_id: entry.right_id,
+            word_cost: entry.word_cost,
+            reading: (&entry.reading).into(),
+            lemma: entry.lemma.as_ref().map(ReadingRef::from),
+            pronunciation: entry.pronunciation.as_deref(),
+            accent: entry.accent,
+        }
+    }
+}
+
+impl EntryBlock {
+    /// Decodes the `count` entry records of the block of `first_char` in `version`, taking
+    /// `bytes` as the block's buffer.
+    pub fn parse(
+        bytes: Vec<u8>,
+        count: usize,
+        first_char: char,
+        version: u16,
+    ) -> Result<EntryBlock, String> {
+        let mut records = Vec::with_capacity(count);
+        let mut pos = 0;
+        for _ in 0..count {
+            let (record, size) = read_record(&bytes[pos..], version)?;
+            let surface = std::str::from_utf8(record.surface)
+                .map_err(|e| format!("surface is not UTF-8 ({})", e))?;
+            // Lookups match the text after the first char against the rest of the surface.
+            if !surface.starts_with(first_char) {
+                return Err(format!(
+                    "surface {:?} does not start with {:?}",
+                    surface, first_char
+                ));
+            }
+            let (pronunciation_start, pronunciation_len) = match record.pronunciation {
+                Some((pronunciation, at)) => {
+                    std::str::from_utf8(pronunciation)
+                        .map_err(|e| format!("pronunciation is not UTF-8 ({})", e))?;
+                    ((pos + at) as u32, pronunciation.len() as u16)
+                }
+                None => (0, 0),
+            };
+            records.push(Record {
+                surface_start: (pos + record.surface_at) as u32,
+                surface_len: surface.len() as u32,
+                char_len: surface.chars().count() as u16,
+                pos_id: record.pos_id,
+                right_id: record.right_id,
+                word_cost: record.word_cost,
+                reading: RecordReading::Stored {
+                    offset: record.reading_offset,
+                    len: record.reading_len,
+                },
+                lemma: record
+                    .lemma
+                    .map(|(offset, len)| RecordReading::Stored { offset, len }),
+                pronunciation_start,
+                pronunciation_len,
+                accent: record.accent,
+            });
+            pos += size;
+        }
+        let mut block = EntryBlock {
+            bytes: bytes.into_boxed_slice(),
+            records: Vec::new().into_boxed_slice(),
+        };
+        // Lookups rely on surface order; the builder writes it, but don't trust other writers.
+        let sorted = records
+            .windows(2)
+            .all(|w| block.record_surface(&w[0]) <= block.record_surface(&w[1]));
+        if !sorted {
+            records.sort_by(|a, b| block.record_surface(a).cmp(block.record_surface(b)));
+        }
+        block.records = records.into_boxed_slice();
+        Ok(block)
+    }
+
+    /// A block holding `entries`, packed into a new buffer and sorted by surface.
+    pub fn from_entries<'e>(entries: impl IntoIterator<Item = EntryRef<'e>>) -> EntryBlock {
+        let mut entries: Vec<EntryRef> = entries.into_iter().collect();
+        entries.sort_by(|a, b| a.surface.cmp(b.surface));
+        let mut bytes = Vec::new();
+        let mut push = |text: &str| {
+            let start = bytes.len() as u32;
+            bytes.extend_from_slice(text.as_bytes());
+            (start, text.len() as u32)
+        };
+        let records = entries
+            .iter()
+            .map(|entry| {
+                let (surface_start, surface_len) = push(entry.surface);
+                let mut record_reading = |reading| match reading {
+                    ReadingRef::Stored { offset, len } => RecordReading::Stored { offset, len },
+                    ReadingRef::Inline(reading) => {
+                        let (start, len) = push(reading);
+                        RecordReading::Inline { start, len }
+                    }
+                };
+                let reading = record_reading(entry.reading);
+                let lemma = entry.lemma.map(record_reading);
+                let (pronunciation_start, pronunciation_len) = match entry.pronunciation {
+                    Some(pronunciation) => {
+                        let (start, len) = push(pronunciation);
+                        (start, len as u16)
+                    }
+                    None => (0, 0),
+                };
+                Record {
+                    surface_start,
+                    surface_len,
+                    char_len: entry.char_len,
+                    pos_id: entry.pos_id,
+                    right_id: entry.right_id,
+                    word_cost: entry.word_cost,
+                    reading,
+                    lemma,
+                    pronunciation_start,
+                    pronunciation_len,
+                    accent: entry.accent,
+                }
+            })
+            .collect();
+        EntryBlock {
+            bytes: bytes.into_boxed_slice(),
+            records,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    fn text(&self, start: u32, len: u32) -> &str {
+        let bytes = &self.bytes[start as usize..(start + len) as usize];
+        // Surfaces are checked when parsed, inline readings come from `&str`s.
+        std::str::from_utf8(bytes).unwrap_or_default()
+    }
+
+    fn record_surface(&self, record: &Record) -> &str {
+        self.text(record.surface_start, record.surface_len)
+    }
+
+    fn reading_ref(&self, reading: RecordReading) -> ReadingRef<'_> {
+        match reading {
+            RecordReading::Stored { offset, len } => ReadingRef::Stored { offset, len },
+            RecordReading::Inline { start, len } => ReadingRef::Inline(self.text(start, len)),
+        }
+    }
+
+    pub fn get(&self, idx: usize) -> Option<EntryRef<'_>> {
+        let record = self.records.get(idx)?;
+        Some(EntryRef {
+            surface: self.record_surface(record),
+            char_len: record.char_len,
+            pos_id: record.pos_id,
+            right_id: record.right_id,
+            word_cost: record.word_cost,
+            reading: self.reading_ref(record.reading),
+            lemma: record.lemma.map(|lemma| self.reading_ref(lemma)),
+            pronunciation: (record.pronunciation_len > 0)
+                .then(|| self.text(record.pronunciation_start, record.pronunciation_len as u32)),
+            accent: record.accent,
+        })
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = EntryRef<'_>> {
+        (0..self.len()).filter_map(|idx| self.get(idx))
+    }
+
+    pub fn char_len(&self, idx: usize) -> u16 {
+        self.records[idx].char_len
+    }
+
+    /// Index of the first entry in `lo..hi` whose surface bytes `pred` rejects, by binary search:
+    /// the surfaces for which `pred` holds must come first.
+    pub fn partition_point(
+        &self,
+        lo: usize,
+        hi: usize,
+        mut pred: impl FnMut(&[u8]) -> bool,
+    ) -> usize {
+        lo + self.records[lo..hi].partition_point(|record| {
+            let start = record.surface_start as usize;
+            pred(&self.bytes[start..start + record.surface_len as usize])
+        })
+    }
+
+    /// The range of entries whose surface is exactly `surface`.
+    pub fn equal_range(&self, surface: &str) -> std::ops::Range<usize> {
+        let (len, surface) = (self.len(), surface.as_bytes());
+        let first = self.partition_point(0, len, |s| s.cmp(surface) == Ordering::Less);
+        let last = self.partition_point(first, len, |s| s <= surface);
+        first..last
+    }
+
+    /// Adds `delta` to the word cost of entry `idx`.
+    pub fn adjust_cost(&mut self, idx: usize, delta: i16) {
+        let record = &mut self.records[idx];
+        record.word_cost = record.word_cost.saturating_add(delta);
+    }
+
+    /// Replaces the word cost of entry `idx`.
+    pub fn set_cost(&mut self, idx: usize, cost: i16) {
+        self.records[idx].word_cost = cost;
+    }
+
+    /// Bytes held by the block: its buffer and records.
+    pub fn heap_bytes(&self) -> usize {
+        self.bytes.len() + std::mem::size_of_val(&*self.records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(surface: &str, reading: EntryReading) -> DictEntry {
+        DictEntry {
+            surface: surface.to_string(),
+            char_len: surface.chars().count() as u16,
+            pos_id: 1,
+            right_id: 2,
+            word_cost: 30,
+            reading,
+            lemma: None,
+            pronunciation: None,
+            accent: None,
+        }
+    }
+
+    #[test]
+    fn test_entries_round_trip_sorted() {
+        let entries = [
+            entry("東京", EntryReading::Inline("トーキョー".into())),
+            entry("東", EntryReading::Stored { offset: 4, len: 9 }),
+        ];
+        let mut block = EntryBlock::from_entries(entries.iter().map(EntryRef::from));
+        assert_eq!(block.len(), 2);
+        let surfaces: Vec<&str> = block.iter().map(|e| e.surface).collect();
+        assert_eq!(surfaces, ["東", "東京"]);
+        assert_eq!(
+            block.get(1).unwrap().reading,
+            ReadingRef::Inline("トーキョー")
+        );
+        assert_eq!(block.equal_range("東京"), 1..2);
+        assert_eq!(block.equal_range("京"), 0..0);
+
+        block.adjust_cost(0, -40);
+        let owned = block.get(0).unwrap().to_entry();
+        assert_eq!((owned.surface.as_str(), owned.word_cost), ("東", -10));
+        block.set_cost(1, 7);
+        assert_eq!(block.get(1).unwrap().word_cost, 7);
+        assert_eq!(owned.reading, EntryReading::Stored { offset: 4, len: 9 });
+        assert!(block.get(2).is_none());
+    }
+}
@@ -0,0 +1,632 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+#[cfg(feature = "fs")]
+use std::fs::File;
+#[cfg(feature = "fs")]
+use std::io::BufWriter;
+use std::io::Write;
+#[cfg(feature = "fs")]
+use std::path::Path;
+use xxhash_rust::xxh64::xxh64;
+#[cfg(feature = "zstd")]
+use zeekstd::{EncodeOptions, Encoder, FrameSizePolicy};
+
+use crate::kana::KanaForm;
+use crate::pos::PosNames;
+use crate::unknown::CharDefinitions;
+use crate::{
+    entry_layout, header_size, EXTRA_ACCENT, EXTRA_PRONUNCIATION, FLAG_HIRAGANA_READINGS,
+    FLAG_KEEP_VU, FLAG_UNCOMPRESSED, FORMAT_VERSION, INDEX_ENTRY_SIZE, SUPPORTED_FORMAT_VERSIONS,
+};
+
+const DEFAULT_FRAME_SIZE: u32 = 1024 * 128;
+const DEFAULT_COMPRESSION_LEVEL: i32 = 9;
+
+pub struct Entry {
+    pub surface: String,
+    /// Left context id.
+    pub pos_id: u16,
+    pub right_id: u16,
+    pub cost: i16,
+    pub reading: String,
+    /// See [`crate::DictEntry::lemma`]. Requires format version 9.
+    pub lemma: Option<String>,
+    /// See [`crate::DictEntry::pronunciation`]. Requires format version 8 or later, as does
+    /// `accent`.
+    pub pronunciation: Option<String>,
+    pub accent: Option<u8>,
+}
+
+/// Sizes of the sections written by [`DictionaryBuilder::write`].
+#[derive(Debug, Clone, Default)]
+pub struct BuildStats {
+    pub header_bytes: usize,
+    pub matrix_bytes: usize,
+    pub index_bytes: usize,
+    pub index_keys: usize,
+    pub char_def_bytes: usize,
+    pub pos_name_bytes: usize,
+    pub entries_bytes: usize,
+    pub strings_bytes: usize,
+    /// Bytes of the readings of all entries, and of the distinct ones: what the strings would
+    /// take without sharing, and without sharing overlaps between readings.
+    pub readings_bytes: usize,
+    pub distinct_readings_bytes: usize,
+    /// What `strings_bytes` would be with the readings in the other kana form: in hiragana for
+    /// a katakana build, in katakana otherwise.
+    pub other_kana_strings_bytes: usize,
+    /// Size of the entries and strings region as written; the sum of the two above when
+    /// compression is off.
+    pub compressed_bytes: usize,
+}
+
+/// Serializes dictionary entries and a connection matrix into the mucab.bin format.
+pub struct DictionaryBuilder {
+    entries: Vec<Entry>,
+    matrix: Vec<i16>,
+    left_size: u16,
+    right_size: u16,
+    version: u16,
+    char_defs: Option<CharDefinitions>,
+    /// POS names by pos id.
+    pos_names: Vec<Option<String>>,
+    frame_size: u32,
+    compression_level: i32,
+    compress: bool,
+    reading_kana: KanaForm,
+}
+
+impl Default for DictionaryBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DictionaryBuilder {
+    pub fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            matrix: Vec::new(),
+            left_size: 0,
+            right_size: 0,
+            version: FORMAT_VERSION,
+            char_defs: None,
+            pos_names: Vec::new(),
+            frame_size: DEFAULT_FRAME_SIZE,
+            compression_level: DEFAULT_COMPRESSION_LEVEL,
+            compress: cfg!(feature = "zstd"),
+            reading_kana: KanaForm::Katakana,
+        }
+    }
+
+    pub fn add_entry(&mut self, surface: &str, reading: &str, pos_id: u16, cost: i16) {
+        self.add_entry_with_context(surface, reading, pos_id, pos_id, cost);
+    }
+
+    pub fn add_entry_with_context(
+        &mut self,
+        surface: &str,
+        reading: &str,
+        left_id: u16,
+        right_id: u16,
+        cost: i16,
+    ) {
+        self.entries.push(Entry {
+            surface: surface.to_string(),
+            pos_id: left_id,
+            right_id,
+            cost,
+            reading: reading.to_string(),
+            lemma: None,
+            pronunciation: None,
+            accent: None,
+        });
+    }
+
+    pub fn extend_entries(&mut self, entries: impl IntoIterator<Item = Entry>) {
+        self.entries.extend(entries);
+    }
+
+    /// Sets a square `matrix_size` × `matrix_size` connection matrix.
+    pub fn set_matrix(&mut self, matrix: Vec<i16>, matrix_size: u16) {
+        self.set_connection_matrix(matrix, matrix_size, matrix_size);
+    }
+
+    /// Sets the connection matrix, indexed as `prev_right_id * left_size + curr_left_id`.
+    pub fn set_connection_matrix(&mut self, matrix: Vec<i16>, right_size: u16, left_size: u16) {
+        assert_eq!(matrix.len(), right_size as usize * left_size as usize);
+        self.matrix = matrix;
+        self.right_size = right_size;
+        self.left_size = left_size;
+    }
+
+    /// Character categories and unknown-word templates for text the entries don't cover. Template
+    /// ids must already be in the same id space as the entries. Requires format version 3.
+    pub fn set_char_definitions(&mut self, defs: CharDefinitions) {
+        self.char_defs = Some(defs);
+    }
+
+    /// Names the part of speech of entries with left id `pos_id` (see [`crate::pos`]). Requires
+    /// format version 4.
+    pub fn set_pos_name(&mut self, pos_id: u16, name: &str) {
+        let idx = pos_id as usize;
+        if self.pos_names.len() <= idx {
+            self.pos_names.resize(idx + 1, None);
+        }
+        self.pos_names[idx] = Some(name.to_string());
+    }
+
+    /// Selects the on-disk format version to write. Version 1 has a single pos id per entry and
+    /// a square matrix, so it can only represent entries whose left and right ids agree. Versions
+    /// before 3 cannot store character definitions, versions before 4 cannot store POS names,
+    /// versions before 5 cannot store surfaces or readings longer than 255 bytes, versions
+    /// before 6 are always compressed, versions before 7 carry no checksum, and versions before 8
+    /// cannot store pronunciations or accents, and versions before 9 cannot store lemmas.
+    pub fn format_version(&mut self, version: u16) {
+        assert!(
+            SUPPORTED_FORMAT_VERSIONS.contains(&version),
+            "unsupported format version {}",
+            version
+        );
+        self.version = version;
+    }
+
+    /// Uncompressed size of each seekable zstd frame (128 KiB by default). Smaller frames make
+    /// cold lookups decompress less at the cost of a worse compression ratio.
+    pub fn frame_size(&mut self, bytes: u32) {
+        assert!(bytes > 0, "frame size must be positive");
+        self.frame_size = bytes;
+    }
+
+    /// zstd compression level of the entries and strings region (9 by default).
+    pub fn compression_level(&mut self, level: i32) {
+        assert!(
+            (1..=22).contains(&level),
+            "compression level must be between 1 and 22"
+        );
+        self.compression_level = level;
+    }
+
+    /// Whether to compress the entries and strings region (the default with the `zstd`
+    /// feature, which compressing needs). Uncompressed dictionaries are larger, but lookups and
+    /// readings are plain seeks and reads of the file. Requires format version 6.
+    pub fn compress(&mut self, enabled: bool) {
+        self.compress = enabled;
+    }
+
+    /// Stores readings and pronunciations in `form` rather than as given, recording it in the
+    /// header for [`crate::DictionaryData::reading_kana`]. Tokenizers asking for that form then
+    /// have nothing to convert, and readings that only agree once converted share their bytes in
+    /// the strings. Requires format version 6 unless `form` is katakana.
+    pub fn reading_kana(&mut self, form: KanaForm) {
+        self.reading_kana = form;
+    }
+
+    pub fn num_entries(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn write_to_file<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<BuildStats> {
+        let file = File::create(path)?;
+        self.write(BufWriter::new(file))
+    }
+
+    pub fn write<W: Write>(&mut self, mut writer: W) -> std::io::Result<BuildStats> {
+        // Sort by first character, then by surface
+        self.entries.sort_by(|a, b| {
+            let a_first = a.surface.chars().next();
+            let b_first = b.surface.chars().next();
+            match (a_first, b_first) {
+                (Some(ac), Some(bc)) => ac.cmp(&bc).then_with(|| a.surface.cmp(&b.surface)),
+                _ => a.surface.cmp(&b.surface),
+            }
+        });
+        let entries = &self.entries;
+        let matrix = &self.matrix;
+        let mut stats = BuildStats::default();
+
+        if self.version == 1 {
+            if self.left_size != self.right_size {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    "format version 1 requires a square matrix",
+                ));
+            }
+            if let Some(e) = entries.iter().find(|e| e.pos_id != e.right_id) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "format version 1 cannot store differing left/right ids ({})",
+                        e.surface
+                    ),
+                ));
+            }
+        }
+        let (len_size, metadata_size) = entry_layout(self.version);
+        let max_len = if len_size == 1 {
+            u8::MAX as usize
+        } else {
+            u16::MAX as usize
+        };
+        if let Some(e) = entries
+            .iter()
+            .find(|e| e.surface.len() > max_len || e.reading.len() > max_len)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "format version {} cannot store surfaces or readings over {} bytes ({})",
+                    self.version, max_len, e.surface
+                ),
+            ));
+        }
+
+        if self.version < 3 && self.char_defs.is_some() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "format version {} cannot store character definitions",
+                    self.version
+                ),
+            ));
+        }
+
+        if self.version < 4 && !self.pos_names.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("format version {} cannot store POS names", self.version),
+            ));
+        }
+
+        if self.version < 6 && !self.compress {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "format version {} cannot store an uncompressed region",
+                    self.version
+                ),
+            ));
+        }
+
+        if self.version < 6 && self.reading_kana != KanaForm::Katakana {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "format version {} cannot store readings in {:?}",
+                    self.version, self.reading_kana
+                ),
+            ));
+        }
+
+        if self.version < 8 {
+            if let Some(e) = entries
+                .iter()
+                .find(|e| e.pronunciation.is_some() || e.accent.is_some())
+            {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "format version {} cannot store pronunciations or accents ({})",
+                        self.version, e.surface
+                    ),
+                ));
+            }
+        }
+        if let Some(e) = entries.iter().find(|e| {
+            e.lemma
+                .as_ref()
+                .is_some_and(|lemma| lemma.is_empty() || lemma.len() > u16::MAX as usize)
+        }) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("lemmas must have 1 to {} bytes ({})", u16::MAX, e.surface),
+            ));
+        }
+        if self.version < 9 {
+            if let Some(e) = entries.iter().find(|e| e.lemma.is_some()) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!(
+                        "format version {} cannot store lemmas ({})",
+                        self.version, e.surface
+                    ),
+                ));
+            }
+        }
+        if let Some(e) = entries.iter().find(|e| {
+            e.pronunciation
+                .as_ref()
+                .is_some_and(|p| p.is_empty() || p.len() > u16::MAX as usize - 7)
+        }) {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "pronunciations must have 1 to {} bytes ({})",
+                    u16::MAX as usize - 7,
+                    e.surface
+                ),
+            ));
+        }
+
+        // First, build entry_records with compressed strings. Repeated readings and lemmas share
+        // one copy; a new one is appended, minus its longest prefix that the strings already end
+        // with. Converting to hiragana keeps every reading's length.
+        let readings: Vec<Cow<str>> = entries
+            .iter()
+            .map(|e| self.reading_kana.apply(&e.reading))
+            .collect();
+        let mut strings_data = Vec::new(); // Compressed supersequence
+        let mut entry_records = Vec::new();
+        let mut string_offsets: HashMap<&str, u32> = HashMap::new();
+
+        for (entry, reading) in entries.iter().zip(&readings) {
+            let reading_len = reading.len() as u16;
+            stats.readings_bytes += reading.len();
+            let (reading_offset, new) = intern(&mut strings_data, &mut string_offsets, reading);
+            if new {
+                stats.distinct_readings_bytes += reading.len();
+            }
+            let lemma = match &entry.lemma {
+                Some(lemma) => (
+                    intern(&mut strings_data, &mut string_offsets, lemma).0,
+                    lemma.len() as u16,
+                ),
+                None => (0, 0),
+            };
+
+            let mut extras = Vec::new();
+            if self.version >= 8 {
+                let mut push_extra = |tag: u8, value: &[u8]| {
+                    extras.push(tag);
+                    extras.extend_from_slice(&(value.len() as u16).to_le_bytes());
+                    extras.extend_from_slice(value);
+                };
+                if let Some(pronunciation) = &entry.pronunciation {
+                    push_extra(
+                        EXTRA_PRONUNCIATION,
+                        self.reading_kana.apply(pronunciation).as_bytes(),
+                    );
+                }
+                if let Some(accent) = entry.accent {
+                    push_extra(EXTRA_ACCENT, &[accent]);
+                }
+                extras.splice(0..0, (extras.len() as u16).to_le_bytes());
+            }
+
+            entry_records.push((
+                entry.surface.as_bytes(),
+                reading_offset,
+                reading_len,
+                entry.pos_id,
+                entry.right_id,
+                entry.cost,
+                lemma,
+                extras,
+            ));
+        }
+
+        // Now build index with byte offsets
+        let mut index: Vec<(char, u32, usize)> = Vec::new();
+        let mut current_char: Option<char> = None;
+        let mut current_byte_offset = 0u32;
+        let mut current_count = 0usize;
+        let mut byte_offset = 0u32;
+
+        for (i, entry) in entries.iter().enumerate() {
+            if let Some(first_char) = entry.surface.chars().next() {
+                if Some(first_char) != current_char {
+                    if let Some(ch) = current_char {
+                        index.push((ch, current_byte_offset, current_count));
+                    }
+                    current_char = Some(first_char);
+                    current_byte_offset = byte_offset;
+                    current_count = 1;
+                } else {
+                    current_count += 1;
+                }
+            }
+            let (surface, extras) = (entry_records[i].0, &entry_records[i].7);
+            byte_offset += (len_size + surface.len() + metadata_size + extras.len()) as u32;
+        }
+        if let Some(ch) = current_char {
+            index.push((ch, current_byte_offset, current_count));
+        }
+        // Index counts are u16; kana-initial words make the biggest blocks.
+        if let Some(&(ch, _, count)) = index
+            .iter()
+            .find(|(_, _, count)| *count > u16::MAX as usize)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "{} entries start with {:?}, more than an index block holds ({})",
+                    count,
+                    ch,
+                    u16::MAX
+                ),
+            ));
+        }
+
+        let entry_array_size: u32 = entry_records
+            .iter()
+            .map(|(surf, _, _, _, _, _, _, extras)| {
+                (len_size + surf.len() + metadata_size + extras.len()) as u32
+            })
+            .sum();
+
+        let strings_offset = entry_array_size;
+
+        // Everything after the header is assembled first, so the header can carry its checksum.
+        let mut body = Vec::new();
+        stats.matrix_bytes = matrix.len() * 2;
+        for &cost in matrix {
+            body.extend_from_slice(&cost.to_le_bytes());
+        }
+
+        stats.index_keys = index.len();
+        stats.index_bytes = 4 + index.len() * INDEX_ENTRY_SIZE;
+        body.extend_from_slice(&(index.len() as u32).to_le_bytes());
+        for (ch, byte_offset, count) in &index {
+            body.extend_from_slice(&(*ch as u32).to_le_bytes());
+            body.extend_from_slice(&byte_offset.to_le_bytes());
+            body.extend_from_slice(&(*count as u16).to_le_bytes());
+        }
+
+        if self.version >= 3 {
+            let mut section = Vec::new();
+            match &self.char_defs {
+                Some(defs) => defs.write_to(&mut section)?,
+                None => section.extend_from_slice(&0u16.to_le_bytes()),
+            }
+            stats.char_def_bytes = section.len();
+            body.extend_from_slice(&section);
+        }
+
+        if self.version >= 4 {
+            let mut section = Vec::new();
+            PosNames::from_ids(&self.pos_names).write_to(&mut section)?;
+            stats.pos_name_bytes = section.len();
+            body.extend_from_slice(&section);
+        }
+
+        stats.entries_bytes = entry_array_size as usize;
+        stats.strings_bytes = strings_data.len();
+        let other_kana = match self.reading_kana {
+            KanaForm::Katakana => KanaForm::Hiragana,
+            KanaForm::Hiragana | KanaForm::HiraganaKeepVu => KanaForm::Katakana,
+        };
+        stats.other_kana_strings_bytes = strings_len(entries, other_kana);
+
+        let mut region = Vec::with_capacity(entry_array_size as usize + strings_data.len());
+        for (surf_bytes, read_off, read_len, pos_id, right_id, cost, lemma, extras) in
+            &entry_records
+        {
+            let surf_len = surf_bytes.len() as u16;
+            region.extend_from_slice(&surf_len.to_le_bytes()[..len_size]);
+            region.extend_from_slice(surf_bytes);
+            region.extend_from_slice(&read_off.to_le_bytes());
+            region.extend_from_slice(&read_len.to_le_bytes()[..len_size]);
+            region.extend_from_slice(&pos_id.to_le_bytes());
+            if self.version >= 2 {
+                region.extend_from_slice(&right_id.to_le_bytes());
+            }
+            region.extend_from_slice(&cost.to_le_bytes());
+            if self.version >= 9 {
+                region.extend_from_slice(&lemma.0.to_le_bytes());
+                region.extend_from_slice(&lemma.1.to_le_bytes());
+            }
+            region.extend_from_slice(extras);
+        }
+        // Strings follow the entries in the same region
+        region.extend_from_slice(&strings_data);
+
+        if self.compress {
+            stats.compressed_bytes = self.compress_region(&region, &mut body)?;
+        } else {
+            body.extend_from_slice(&region);
+            stats.compressed_bytes = region.len();
+        }
+
+        stats.header_bytes = header_size(self.version);
+        writer.write_all(b"MUCA")?;
+        writer.write_all(&self.version.to_le_bytes())?;
+        writer.write_all(&self.left_size.to_le_bytes())?;
+        writer.write_all(&(entries.len() as u32).to_le_bytes())?;
+        writer.write_all(&strings_offset.to_le_bytes())?;
+        if self.version >= 2 {
+            writer.write_all(&self.right_size.to_le_bytes())?;
+        }
+        if self.version >= 6 {
+            let mut flags = if self.compress { 0 } else { FLAG_UNCOMPRESSED };
+            flags |= match self.reading_kana {
+                KanaForm::Katakana => 0,
+                KanaForm::Hiragana => FLAG_HIRAGANA_READINGS,
+                KanaForm::HiraganaKeepVu => FLAG_HIRAGANA_READINGS | FLAG_KEEP_VU,
+            };
+            writer.write_all(&flags.to_le_bytes())?;
+        }
+        if self.version >= 7 {
+            writer.write_all(&xxh64(&body, 0).to_le_bytes())?;
+        }
+        writer.write_all(&body)?;
+        writer.flush()?;
+
+        Ok(stats)
+    }
+
+    /// Appends `region` to `body` as seekable zstd frames, returning the compressed size.
+    #[cfg(feature = "zstd")]
+    fn compress_region(&self, region: &[u8], body: &mut Vec<u8>) -> std::io::Result<usize> {
+        let opts = EncodeOptions::new()
+            .checksum_flag(false)
+            .compression_level(self.compression_level)
+            .frame_size_policy(FrameSizePolicy::Uncompressed(self.frame_size));
+
+        let mut encoder = Encoder::with_opts(body, opts)
+            .map_err(|e| std::io::Error::other(format!("zeekstd error: {:?}", e)))?;
+        encoder.write_all(region)?;
+        let compressed_size = encoder
+            .finish()
+            .map_err(|e| std::io::Error::other(format!("zeekstd error: {:?}", e)))?;
+        Ok(compressed_size as usize)
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    fn compress_region(&self, _region: &[u8], _body: &mut Vec<u8>) -> std::io::Result<usize> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "compressing the entries and strings needs the `zstd` feature",
+        ))
+    }
+}
+
+/// Length of the strings [`DictionaryBuilder::write`] would build for `entries`, with their
+/// readings in `form`.
+fn strings_len(entries: &[Entry], form: KanaForm) -> usize {
+    let readings: Vec<Cow<str>> = entries.iter().map(|e| form.apply(&e.reading)).collect();
+    let mut strings = Vec::new();
+    let mut offsets = HashMap::new();
+    for (entry, reading) in entries.iter().zip(&readings) {
+        intern(&mut strings, &mut offsets, reading);
+        if let Some(lemma) = &entry.lemma {
+            intern(&mut strings, &mut offsets, lemma);
+        }
+    }
+    strings.len()
+}
+
+/// The offset of `text` in `strings`, appending it unless an earlier string put it there, and
+/// whether it was appended. A new string overlaps the longest suffix of `strings` it starts
+/// with.
+fn intern<'e>(
+    strings: &mut Vec<u8>,
+    offsets: &mut HashMap<&'e str, u32>,
+    text: &'e str,
+) -> (u32, bool) {
+    if let Some(&offset) = offsets.get(text) {
+        return (offset, false);
+    }
+    let bytes = text.as_bytes();
+    // An overlap starts with the string's first byte, so only those positions are compared,
+    // longest overlap first.
+    let mut best_overlap = 0;
+    let search_start = strings.len().saturating_sub(bytes.len());
+    if let Some(&first) = bytes.first() {
+        for start in search_start..strings.len() {
+            if strings[start] != first {
+                continue;
+            }
+            let suffix_len = strings.len() - start;
+            if strings[start..] == bytes[..suffix_len] {
+                best_overlap = suffix_len;
+                break;
+            }
+        }
+    }
+
+    let offset = (strings.len() - best_overlap) as u32;
+    strings.extend_from_slice(&bytes[best_overlap..]);
+    offsets.insert(text, offset);
+    (offset, true)
+}
@@ -0,0 +1,348 @@
+//! Bounded caches of decoded entry blocks, and of decompressed frames.
+//!
+//! Every [`crate::Dictionary`] handle keeps the blocks it has used, and its
+//! [`crate::DictionaryData`] keeps the blocks any handle has decoded. Both are unbounded unless
+//! given a [`CacheLimit`], in which case the least recently used blocks are dropped first.
+//!
+//! A handle on a compressed dictionary also keeps the last few zstd frames it decompressed
+//! whole, so that readings close to each other are not decompressed again for every read; see
+//! [`crate::LoadOptions::frame_cache`].
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::block::EntryBlock;
+
+/// How much a block cache may hold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CacheLimit {
+    #[default]
+    Unbounded,
+    /// At most this many blocks.
+    Blocks(usize),
+    /// At most about this many bytes, as estimated by [`CacheStats::approx_bytes`].
+    Bytes(usize),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Block lookups answered from the cache.
+    pub hits: u64,
+    /// Block lookups that had to go further: to the shared cache, or to the decoder.
+    pub misses: u64,
+    pub resident_blocks: usize,
+    /// Size of the resident blocks' records, surfaces and in-memory readings.
+    pub approx_bytes: usize,
+    /// Reads served from the handle's decompressed frames; always zero for the shared cache,
+    /// which holds none.
+    pub frame_hits: u64,
+    /// Frames the handle decompressed whole to serve a read.
+    pub frame_misses: u64,
+    pub resident_frames: usize,
+}
+
+struct Slot {
+    block: Arc<EntryBlock>,
+    bytes: usize,
+    last_used: u64,
+}
+
+/// Entry blocks by first char, with LRU eviction.
+pub(crate) struct EntryCache {
+    slots: HashMap<char, Slot>,
+    limit: CacheLimit,
+    clock: u64,
+    bytes: usize,
+    hits: u64,
+    misses: u64,
+}
+
+impl EntryCache {
+    pub(crate) fn new(limit: CacheLimit) -> Self {
+        EntryCache {
+            slots: HashMap::new(),
+            limit,
+            clock: 0,
+            bytes: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Looks up a block, counting a hit or a miss and marking it as recently used.
+    pub(crate) fn get(&mut self, first_char: char) -> Option<&Arc<EntryBlock>> {
+        self.clock += 1;
+        match self.slots.get_mut(&first_char) {
+            Some(slot) => {
+                self.hits += 1;
+                slot.last_used = self.clock;
+                Some(&slot.block)
+            }
+            None => {
+                self.misses += 1;
+                #[cfg(feature = "trace")]
+                tracing::debug!(%first_char, "entry cache miss");
+                None
+            }
+        }
+    }
+
+    /// Looks up a block without touching the statistics or the LRU order.
+    pub(crate) fn peek(&self, first_char: char) -> Option<&Arc<EntryBlock>> {
+        self.slots.get(&first_char).map(|slot| &slot.block)
+    }
+
+    /// Stores `block`, keeping an already cached block for the same char, and returns whichever
+    /// is now cached. Older blocks are evicted to honour the limit; the returned one never is.
+    pub(crate) fn insert(&mut self, first_char: char, block: Arc<EntryBlock>) -> Arc<EntryBlock> {
+        self.clock += 1;
+        let clock = self.clock;
+        let slot = self.slots.entry(first_char).or_insert_with(|| {
+            let bytes = block.heap_bytes();
+            Slot {
+                block,
+                bytes,
+                last_used: clock,
+            }
+        });
+        if slot.last_used == clock {
+            self.bytes += slot.bytes;
+        }
+        slot.last_used = clock;
+        let block = slot.block.clone();
+        self.evict(Some(first_char));
+        block
+    }
+
+    pub(crate) fn remove(&mut self, first_char: char) {
+        if let Some(slot) = self.slots.remove(&first_char) {
+            self.bytes -= slot.bytes;
+        }
+    }
+
+    pub(crate) fn set_limit(&mut self, limit: CacheLimit) {
+        self.limit = limit;
+        self.evict(None);
+    }
+
+    pub(crate) fn limit(&self) -> CacheLimit {
+        self.limit
+    }
+
+    pub(crate) fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            resident_blocks: self.slots.len(),
+            approx_bytes: self.bytes,
+            ..CacheStats::default()
+        }
+    }
+
+    fn over_limit(&self) -> bool {
+        match self.limit {
+            CacheLimit::Unbounded => false,
+            CacheLimit::Blocks(max) => self.slots.len() > max,
+            CacheLimit::Bytes(max) => self.bytes > max,
+        }
+    }
+
+    /// Drops least recently used blocks until within the limit, sparing `keep`. Finding the
+    /// oldest block is a scan, which only happens on a miss in an already full cache.
+    fn evict(&mut self, keep: Option<char>) {
+        while self.over_limit() {
+            let oldest = self
+                .slots
+                .iter()
+                .filter(|(&c, _)| Some(c) != keep)
+                .min_by_key(|(_, slot)| slot.last_used)
+                .map(|(&c, _)| c);
+            match oldest {
+                Some(c) => self.remove(c),
+                None => break,
+            }
+        }
+    }
+}
+
+#[cfg(feature = "zstd")]
+struct Frame {
+    index: u32,
+    bytes: Box<[u8]>,
+    last_used: u64,
+}
+
+/// Decompressed frames by index, with LRU eviction. It holds a handful of frames, so finding
+/// one is a scan.
+#[cfg(feature = "zstd")]
+pub(crate) struct FrameCache {
+    frames: Vec<Frame>,
+    capacity: usize,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+}
+
+#[cfg(feature = "zstd")]
+impl FrameCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        FrameCache {
+            frames: Vec::with_capacity(capacity),
+            capacity,
+            clock: 0,
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// The bytes of frame `index`, from `decompress` unless cached. A new frame takes the place
+    /// of the least recently used one once the cache is full.
+    pub(crate) fn get_or_insert_with<E>(
+        &mut self,
+        index: u32,
+        decompress: impl FnOnce() -> Result<Box<[u8]>, E>,
+    ) -> Result<&[u8], E> {
+        self.clock += 1;
+        let slot = match self.frames.iter().position(|frame| frame.index == index) {
+            Some(slot) => {
+                self.hits += 1;
+                slot
+            }
+            None => {
+                self.misses += 1;
+                #[cfg(feature = "trace")]
+                tracing::debug!(index, "frame cache miss");
+                let frame = Frame {
+                    index,
+                    bytes: decompress()?,
+                    last_used: 0,
+                };
+                if self.frames.len() < self.capacity {
+                    self.frames.push(frame);
+                    self.frames.len() - 1
+                } else {
+                    let (oldest, _) = self
+                        .frames
+                        .iter()
+                        .enumerate()
+                        .min_by_key(|(_, frame)| frame.last_used)
+                        .expect("a frame cache that is full has frames");
+                    self.frames[oldest] = frame;
+                    oldest
+                }
+            }
+        };
+        let frame = &mut self.frames[slot];
+        frame.last_used = self.clock;
+        Ok(&frame.bytes)
+    }
+
+    /// `stats` with the frame counts filled in.
+    pub(crate) fn add_stats(&self, stats: CacheStats) -> CacheStats {
+        CacheStats {
+            frame_hits: self.hits,
+            frame_misses: self.misses,
+            resident_frames: self.frames.len(),
+            ..stats
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DictEntry, EntryReading};
+
+    fn block(surface: &str) -> Arc<EntryBlock> {
+        let entry = DictEntry {
+            surface: surface.to_string(),
+            char_len: surface.chars().count() as u16,
+            pos_id: 0,
+            right_id: 0,
+            word_cost: 0,
+            reading: EntryReading::Stored { offset: 0, len: 0 },
+            lemma: None,
+            pronunciation: None,
+            accent: None,
+        };
+        Arc::new(EntryBlock::from_entries([(&entry).into()]))
+    }
+
+    #[test]
+    fn test_least_recently_used_block_is_evicted() {
+        let mut cache = EntryCache::new(CacheLimit::Blocks(2));
+        cache.insert('a', block("a"));
+        cache.insert('b', block("b"));
+        assert!(cache.get('a').is_some());
+        cache.insert('c', block("c"));
+        assert!(cache.peek('b').is_none());
+        assert!(cache.peek('a').is_some() && cache.peek('c').is_some());
+        assert!(cache.get('b').is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.resident_blocks), (1, 1, 2));
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_least_recently_used_frame_is_replaced() {
+        let mut cache = FrameCache::new(2);
+        let mut decompressed = Vec::new();
+        let mut get = |cache: &mut FrameCache, index: u32| {
+            let bytes = cache
+                .get_or_insert_with(index, || {
+                    decompressed.push(index);
+                    Ok::<_, ()>(vec![index as u8; 3].into_boxed_slice())
+                })
+                .unwrap();
+            bytes[0]
+        };
+        assert_eq!(get(&mut cache, 0), 0);
+        assert_eq!(get(&mut cache, 1), 1);
+        assert_eq!(get(&mut cache, 0), 0);
+        assert_eq!(get(&mut cache, 2), 2);
+        assert_eq!(get(&mut cache, 0), 0);
+        assert_eq!(get(&mut cache, 1), 1);
+        assert_eq!(decompressed, [0, 1, 2, 1]);
+
+        let stats = cache.add_stats(CacheStats::default());
+        assert_eq!(
+            (stats.frame_hits, stats.frame_misses, stats.resident_frames),
+            (2, 4, 2)
+        );
+        assert!(cache.get_or_insert_with(7, || Err("corrupt")).is_err());
+    }
+
+    #[test]
+    fn test_byte_limit_keeps_the_newest_block() {
+        let one = block("ab").heap_bytes();
+        let mut cache = EntryCache::new(CacheLimit::Bytes(one * 2));
+        cache.insert('a', block("ab"));
+        cache.insert('b', block("bc"));
+        assert_eq!(cache.stats().approx_bytes, one * 2);
+        cache.insert('c', block("cd"));
+        assert_eq!(cache.stats().resident_blocks, 2);
+
+        // A block larger than the limit still stays until the next insert.
+        cache.set_limit(CacheLimit::Bytes(1));
+        assert_eq!(cache.stats().resident_blocks, 0);
+        cache.insert('d', block("de"));
+        assert_eq!(cache.stats().resident_blocks, 1);
+        assert_eq!(cache.stats().approx_bytes, one);
+    }
+
+    #[test]
+    fn test_insert_keeps_existing_block() {
+        let mut cache = EntryCache::new(CacheLimit::Unbounded);
+        let first = cache.insert('a', block("a"));
+        let second = cache.insert('a', block("a"));
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(cache.stats().approx_bytes, first.heap_bytes());
+        cache.remove('a');
+        assert_eq!(cache.stats(), CacheStats::default());
+    }
+}
@@ -0,0 +1,75 @@
+//! Giving up on a conversion part way: the state behind [`crate::transliterate_with_deadline`]
+//! and [`crate::transliterate_cancellable`].
+//!
+//! The lattice builder and the Viterbi search ask [`Stop::due`] at every position, which looks
+//! at the clock or the flag only every [`CHECK_INTERVAL`] positions; once it has said yes it says
+//! so at every position after, so both loops unwind straight away and leave their lattice to be
+//! dropped.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Lattice positions between two looks at the clock or the flag.
+pub(crate) const CHECK_INTERVAL: usize = 256;
+
+#[derive(Debug, Clone)]
+pub(crate) enum Trigger {
+    Deadline(Instant),
+    Flag(Arc<AtomicBool>),
+}
+
+/// A [`Trigger`] installed on a handle for one conversion, and whether it went off.
+#[derive(Debug)]
+pub(crate) struct Stop {
+    trigger: Trigger,
+    fired: bool,
+}
+
+impl Stop {
+    pub fn new(trigger: Trigger) -> Stop {
+        Stop {
+            trigger,
+            fired: false,
+        }
+    }
+
+    /// Whether to stop at lattice position `pos`.
+    pub fn due(&mut self, pos: usize) -> bool {
+        if !self.fired && pos.is_multiple_of(CHECK_INTERVAL) {
+            self.fired = match &self.trigger {
+                Trigger::Deadline(deadline) => Instant::now() >= *deadline,
+                Trigger::Flag(flag) => flag.load(Ordering::Relaxed),
+            };
+        }
+        self.fired
+    }
+
+    pub fn fired(&self) -> bool {
+        self.fired
+    }
+
+    pub fn is_deadline(&self) -> bool {
+        matches!(self.trigger, Trigger::Deadline(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flag_is_only_read_at_intervals() {
+        let flag = Arc::new(AtomicBool::new(false));
+        let mut stop = Stop::new(Trigger::Flag(flag.clone()));
+        assert!(!stop.due(0));
+        flag.store(true, Ordering::Relaxed);
+        assert!(!stop.due(1));
+        assert!(!stop.due(CHECK_INTERVAL - 1));
+        assert!(stop.due(CHECK_INTERVAL));
+        // Once fired it stays fired, whatever the position.
+        flag.store(false, Ordering::Relaxed);
+        assert!(stop.due(CHECK_INTERVAL + 1));
+        assert!(stop.fired() && !stop.is_deadline());
+    }
+}
@@ -0,0 +1,107 @@
+//! Items grouped by text position, as the lattice and its nodes are, kept in one buffer.
+//!
+//! A `Vec` per position costs an allocation for every position that gets an item and leaves
+//! most of them with room for more than they hold; a lattice over a long text has as many
+//! positions as chars. [`Columns`] keeps every item in one `Vec` and where each column starts
+//! in another.
+
+use std::ops::Index;
+
+/// Columns of items for positions `0..len()`; `columns[pos]` is the slice at `pos`.
+#[derive(Debug, Clone)]
+pub(crate) struct Columns<T> {
+    items: Vec<T>,
+    /// Start of each column in `items`. One more than the number of closed columns, the last
+    /// being where the open column starts.
+    starts: Vec<usize>,
+}
+
+impl<T> Columns<T> {
+    /// No columns, ready for items of column 0.
+    pub fn with_capacity(columns: usize) -> Self {
+        let mut starts = Vec::with_capacity(columns + 1);
+        starts.push(0);
+        Columns {
+            items: Vec::new(),
+            starts,
+        }
+    }
+
+    /// Adds `item` to the open column.
+    pub fn push(&mut self, item: T) {
+        self.items.push(item);
+    }
+
+    /// Closes the open column, so that the next items go into the column after it.
+    pub fn close(&mut self) {
+        self.starts.push(self.items.len());
+    }
+
+    /// `items` keyed by column, grouped into `len` columns. Each column keeps its items in
+    /// the order they came in.
+    pub fn group(len: usize, mut items: Vec<(usize, T)>) -> Self {
+        items.sort_by_key(|&(column, _)| column);
+        let mut starts = Vec::with_capacity(len + 1);
+        starts.push(0);
+        let mut items = items.into_iter().peekable();
+        let mut grouped = Vec::with_capacity(items.len());
+        for column in 0..len {
+            while let Some((_, item)) = items.next_if(|&(c, _)| c == column) {
+                grouped.push(item);
+            }
+            starts.push(grouped.len());
+        }
+        Columns {
+            items: grouped,
+            starts,
+        }
+    }
+
+    /// Number of closed columns.
+    pub fn len(&self) -> usize {
+        self.starts.len() - 1
+    }
+
+    /// Number of items over all columns.
+    pub fn items(&self) -> usize {
+        self.items.len()
+    }
+
+    /// The closed columns in order.
+    pub fn iter(&self) -> impl Iterator<Item = &[T]> {
+        (0..self.len()).map(|pos| &self[pos])
+    }
+}
+
+impl<T> Index<usize> for Columns<T> {
+    type Output = [T];
+
+    /// The items of column `pos`, the open one included.
+    fn index(&self, pos: usize) -> &[T] {
+        let end = self
+            .starts
+            .get(pos + 1)
+            .copied()
+            .unwrap_or(self.items.len());
+        &self.items[self.starts[pos]..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grouping_keeps_arrival_order() {
+        let columns = Columns::group(4, vec![(2, 'a'), (1, 'b'), (2, 'c'), (3, 'd'), (2, 'e')]);
+        let grouped: Vec<&[char]> = columns.iter().collect();
+        assert_eq!(grouped, [&[][..], &['b'], &['a', 'c', 'e'], &['d']]);
+        assert_eq!(columns.items(), 5);
+
+        let mut columns = Columns::with_capacity(2);
+        columns.push(1);
+        columns.close();
+        columns.push(2);
+        assert_eq!((columns.len(), &columns[1]), (1, &[2][..]));
+    }
+}
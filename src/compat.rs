@@ -0,0 +1,48 @@
+//! Pinning the observable analysis behavior.
+//!
+//! Changes that affect which path wins (unknown word handling, extra connection costs) are
+//! introduced as a new [`AnalysisCompat`] level. Earlier levels stay selectable, so a caller can
+//! upgrade the crate and keep producing the same tokens and costs it did before.
+
+/// Analysis behavior level. The default tracks the newest level.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AnalysisCompat {
+    /// Text the dictionary does not cover becomes one unknown node per character at a flat cost
+    /// of 10000, even when the dictionary carries char.def data. BOS connects with right id 0
+    /// and the path ends without an EOS connection cost.
+    V1,
+    /// Unknown words follow the dictionary's char.def categories and templates when present,
+    /// and the last node pays the connection cost to EOS (left id 0).
+    V2,
+    /// Without char.def data, a run of characters that no entry starts with becomes a single
+    /// unknown node, charged the flat cost once, instead of one node per character.
+    V3,
+    /// Without char.def data, a prolonged sound mark ー after katakana stays in the unmatched
+    /// run even when entries start with it, so an unknown katakana word is not cut at its ー.
+    #[default]
+    V4,
+}
+
+impl AnalysisCompat {
+    pub const LATEST: AnalysisCompat = AnalysisCompat::V4;
+
+    /// Whether unknown nodes come from char.def categories rather than the flat fallback.
+    pub fn categorizes_unknowns(self) -> bool {
+        self >= AnalysisCompat::V2
+    }
+
+    /// Whether the path pays a connection cost into EOS.
+    pub fn connects_eos(self) -> bool {
+        self >= AnalysisCompat::V2
+    }
+
+    /// Whether the flat fallback covers runs of characters absent from the index with one node.
+    pub fn coalesces_unmatched_runs(self) -> bool {
+        self >= AnalysisCompat::V3
+    }
+
+    /// Whether an unmatched katakana run carries on over a ー that starts entries.
+    pub fn extends_runs_over_long_vowels(self) -> bool {
+        self >= AnalysisCompat::V4
+    }
+}
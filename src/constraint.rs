@@ -0,0 +1,378 @@
+//! Tokenizing text that is partly annotated already, as MeCab's partial parsing does: spans
+//! pinned to be one token each, optionally with a reading or part of speech of their own.
+//!
+//! Candidates that cross either end of a pinned span, or lie inside it, are left out of the
+//! lattice. Those covering the span exactly stay, less those of another part of speech when the
+//! constraint names one. When none is left, a node of its own stands in for the span: with the
+//! constraint's part of speech, connected through the matrix with no word cost, or as a
+//! flat-cost unknown word without one. The rest of the text is searched as usual, so the tokens
+//! around a pinned span are the cheapest ones that fit against it.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+use crate::columns::Columns;
+use crate::{
+    is_whitespace_run, lattice_candidates, lattice_chunks, node_context, passthrough_token,
+    path_tokens, search_lattice, Dictionary, LatticeNode, NodeKind, Token,
+};
+
+/// A span of the text, in chars, to come out as a single token.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Constraint {
+    pub chars: Range<usize>,
+    /// The token's reading, instead of whatever the dictionary or the surface gives.
+    pub reading: Option<String>,
+    /// The token's part of speech (left id): only entries with it are kept for the span.
+    pub pos_id: Option<u16>,
+}
+
+impl Constraint {
+    /// `chars` as one token, read and tagged however the search finds cheapest.
+    pub fn span(chars: Range<usize>) -> Self {
+        Constraint {
+            chars,
+            ..Constraint::default()
+        }
+    }
+}
+
+/// Why [`tokenize_with_constraints`] refused its constraints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConstraintError {
+    /// The span is empty or ends past the text, which is `len` chars long.
+    OutOfRange { chars: Range<usize>, len: usize },
+    /// Two spans share chars; `first` starts no later than `second`.
+    Overlap {
+        first: Range<usize>,
+        second: Range<usize>,
+    },
+    /// The part of speech is outside the dictionary's connection matrix.
+    OutsideMatrix { pos_id: u16 },
+}
+
+impl fmt::Display for ConstraintError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConstraintError::OutOfRange { chars, len } => write!(
+                f,
+                "constraint on chars {:?} is empty or past the end of the text ({} chars)",
+                chars, len
+            ),
+            ConstraintError::Overlap { first, second } => write!(
+                f,
+                "constraints on chars {:?} and {:?} overlap",
+                first, second
+            ),
+            ConstraintError::OutsideMatrix { pos_id } => write!(
+                f,
+                "constraint pos id {} is outside the connection matrix",
+                pos_id
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConstraintError {}
+
+/// Whether a node over `start..end` would cut into one of the `pinned` spans: overlap it
+/// without being it.
+pub(crate) fn cuts_into(pinned: &[Range<usize>], start: usize, end: usize) -> bool {
+    pinned
+        .iter()
+        .any(|pin| start < pin.end && pin.start < end && (start, end) != (pin.start, pin.end))
+}
+
+/// [`crate::tokenize`], with each span of `constraints` coming out as one token, as the
+/// [module docs](self) describe. Constraints may come in any order but must not overlap.
+///
+/// Text is cut into lattices as [`crate::tokenize`] cuts it, except that pieces a span runs
+/// across are analysed together, runs of whitespace included.
+pub fn tokenize_with_constraints<'t>(
+    text: &'t str,
+    dict: &mut Dictionary,
+    constraints: &[Constraint],
+) -> Result<Vec<Token<'t>>, ConstraintError> {
+    let len = text.chars().count();
+    let mut sorted: Vec<&Constraint> = constraints.iter().collect();
+    sorted.sort_by_key(|c| (c.chars.start, c.chars.end));
+    for constraint in &sorted {
+        let chars = &constraint.chars;
+        if chars.start >= chars.end || chars.end > len {
+            return Err(ConstraintError::OutOfRange {
+                chars: chars.clone(),
+                len,
+            });
+        }
+        if let Some(pos_id) = constraint.pos_id {
+            if !dict.data.connects(pos_id, pos_id) {
+                return Err(ConstraintError::OutsideMatrix { pos_id });
+            }
+        }
+    }
+    for pair in sorted.windows(2) {
+        if pair[1].chars.start < pair[0].chars.end {
+            return Err(ConstraintError::Overlap {
+                first: pair[0].chars.clone(),
+                second: pair[1].chars.clone(),
+            });
+        }
+    }
+
+    // Pieces as `lattice_chunks` cuts them, joined across every boundary a span runs over.
+    let crossed = |at: usize| {
+        sorted
+            .iter()
+            .any(|c| c.chars.start < at && at < c.chars.end)
+    };
+    let mut groups: Vec<(usize, usize, usize)> = Vec::new();
+    let (mut byte_start, mut char_start, mut byte_end, mut char_end) = (0, 0, 0, 0);
+    for chunk in lattice_chunks(text, dict.max_lattice_chars) {
+        byte_end += chunk.len();
+        char_end += chunk.chars().count();
+        if !crossed(char_end) {
+            groups.push((byte_start, byte_end, char_start));
+            (byte_start, char_start) = (byte_end, char_end);
+        }
+    }
+
+    let mut tokens = Vec::new();
+    for (byte_start, byte_end, char_start) in groups {
+        let piece = &text[byte_start..byte_end];
+        let char_end = char_start + piece.chars().count();
+        let pins: Vec<(Range<usize>, &Constraint)> = sorted
+            .iter()
+            .filter(|c| char_start <= c.chars.start && c.chars.end <= char_end)
+            .map(|&c| (c.chars.start - char_start..c.chars.end - char_start, c))
+            .collect();
+        let piece_tokens = if pins.is_empty() && is_whitespace_run(piece) {
+            vec![passthrough_token(piece)]
+        } else {
+            constrained_tokens(piece, dict, &pins)
+        };
+        tokens.extend(piece_tokens.into_iter().map(|t| t.offset_by(byte_start)));
+    }
+    Ok(tokens)
+}
+
+/// The tokens of one lattice over `text`, with `pins` in its chars.
+fn constrained_tokens<'t>(
+    text: &'t str,
+    dict: &mut Dictionary,
+    pins: &[(Range<usize>, &Constraint)],
+) -> Vec<Token<'t>> {
+    let (candidates, chars) = lattice_candidates(text, dict);
+    let spans: Vec<Range<usize>> = pins.iter().map(|(span, _)| span.clone()).collect();
+    let mut had_candidates = vec![false; chars.len()];
+    for &(_, (_, start)) in &candidates {
+        had_candidates[start] = true;
+    }
+
+    let mut kept = Vec::with_capacity(candidates.len());
+    for (end, (kind, start)) in candidates {
+        if cuts_into(&spans, start, end) {
+            continue;
+        }
+        let pinned = pins
+            .iter()
+            .find(|(span, _)| (span.start, span.end) == (start, end));
+        if let Some(pos_id) = pinned.and_then(|(_, c)| c.pos_id) {
+            let node = LatticeNode {
+                start_pos: start,
+                end_pos: end,
+                kind,
+                cost: 0,
+                prev_node: None,
+            };
+            let is_dict = matches!(kind, NodeKind::Dict { .. });
+            if !is_dict || node_context(dict, &node).map(|(left_id, _)| left_id) != Some(pos_id) {
+                continue;
+            }
+        }
+        kept.push((end, (kind, start)));
+    }
+
+    for (span, constraint) in pins {
+        if kept
+            .iter()
+            .any(|&(end, (_, start))| (start, end) == (span.start, span.end))
+        {
+            continue;
+        }
+        let kind = match constraint.pos_id {
+            Some(pos_id) => NodeKind::Pinned { pos_id },
+            None => NodeKind::Unknown { template: None },
+        };
+        kept.push((span.end, (kind, span.start)));
+    }
+    // A char whose candidates all cut into a span still needs a way past it.
+    let mut has_candidates = vec![false; chars.len()];
+    for &(_, (_, start)) in &kept {
+        has_candidates[start] = true;
+    }
+    for start in 0..chars.len() {
+        if had_candidates[start] && !has_candidates[start] && !cuts_into(&spans, start, start + 1) {
+            kept.push((start + 1, (NodeKind::Unknown { template: None }, start)));
+        }
+    }
+
+    let lattice = Columns::group(chars.len() + 1, kept);
+    let nodes = search_lattice(&lattice, &chars, dict, &spans);
+    let mut tokens = path_tokens(text, dict, &nodes, &chars);
+
+    let mut char_at: Vec<usize> = text.char_indices().map(|(i, _)| i).collect();
+    char_at.push(text.len());
+    for (span, constraint) in pins {
+        let Some(reading) = &constraint.reading else {
+            continue;
+        };
+        let bytes = (char_at[span.start], char_at[span.end]);
+        if let Some(token) = tokens
+            .iter_mut()
+            .find(|t| (t.start_byte, t.end_byte) == bytes)
+        {
+            token.reading = Cow::Owned(reading.clone());
+        }
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DictionaryBuilder;
+    use crate::testutil::load_built;
+    use crate::tokenize;
+
+    fn dict() -> Dictionary<'static> {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("松本", "マツモト", 0, 100);
+        builder.add_entry("松", "マツ", 0, 400);
+        builder.add_entry("本", "ホン", 0, 300);
+        builder.add_entry("本田", "ホンダ", 1, 100);
+        builder.add_entry("田", "タ", 1, 300);
+        builder.add_entry("さん", "サン", 2, 100);
+        builder.set_matrix(vec![0; 9], 3);
+        builder.set_pos_name(1, "名詞,固有名詞,人名");
+        load_built(builder)
+    }
+
+    fn summary(tokens: &[Token]) -> Vec<(String, String, u16)> {
+        tokens
+            .iter()
+            .map(|t| (t.surface.to_string(), t.reading.to_string(), t.pos_id))
+            .collect()
+    }
+
+    fn pairs(tokens: &[Token]) -> Vec<(String, String)> {
+        tokens
+            .iter()
+            .map(|t| (t.surface.to_string(), t.reading.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_pinned_spans_shape_the_path() {
+        let mut dict = dict();
+        let text = "松本田さん";
+        let free = tokenize(text, &mut dict);
+        assert_eq!(
+            pairs(&free),
+            [("松本", "マツモト"), ("田", "タ"), ("さん", "サン")]
+                .map(|(s, r)| (s.into(), r.into()))
+        );
+
+        // Pinning 本田 forces 松 in front of it.
+        let tokens = tokenize_with_constraints(text, &mut dict, &[Constraint::span(1..3)]).unwrap();
+        assert_eq!(
+            pairs(&tokens),
+            [("松", "マツ"), ("本田", "ホンダ"), ("さん", "サン")]
+                .map(|(s, r)| (s.into(), r.into()))
+        );
+        assert_eq!((tokens[1].start_byte, tokens[1].end_byte), (3, 9));
+
+        // A span no entry covers becomes one token, read as given or as written.
+        let constraints = [Constraint {
+            chars: 0..3,
+            reading: Some("マツモトダ".to_string()),
+            pos_id: Some(1),
+        }];
+        let tokens = tokenize_with_constraints(text, &mut dict, &constraints).unwrap();
+        assert_eq!(
+            summary(&tokens),
+            [("松本田", "マツモトダ", 1), ("さん", "サン", 2)].map(|(s, r, p)| (
+                s.into(),
+                r.into(),
+                p
+            ))
+        );
+        assert!(!tokens[0].is_unknown);
+        assert_eq!(tokens[0].pos.as_deref(), Some("名詞,固有名詞,人名"));
+        let tokens = tokenize_with_constraints(text, &mut dict, &[Constraint::span(0..3)]).unwrap();
+        assert_eq!(tokens[0].reading, "松本田");
+        assert!(tokens[0].is_unknown);
+
+        // With a part of speech, entries with another one do not count.
+        let constraints = [Constraint {
+            pos_id: Some(1),
+            ..Constraint::span(0..2)
+        }];
+        let tokens = tokenize_with_constraints(text, &mut dict, &constraints).unwrap();
+        assert_eq!(summary(&tokens)[0], ("松本".into(), "松本".into(), 1));
+    }
+
+    #[test]
+    fn test_spans_across_pieces_and_bad_constraints() {
+        let mut dict = dict();
+        // The span runs over the whitespace that would otherwise split the text.
+        let constraints = [Constraint {
+            reading: Some("ホンダサン".to_string()),
+            ..Constraint::span(1..5)
+        }];
+        let tokens = tokenize_with_constraints("松本 田さん 本", &mut dict, &constraints).unwrap();
+        assert_eq!(
+            pairs(&tokens),
+            [
+                ("松", "マツ"),
+                ("本 田さ", "ホンダサン"),
+                ("ん", "ん"),
+                (" ", " "),
+                ("本", "ホン")
+            ]
+            .map(|(s, r)| (s.into(), r.into()))
+        );
+
+        let err = tokenize_with_constraints(
+            "松本田",
+            &mut dict,
+            &[Constraint::span(1..3), Constraint::span(0..2)],
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            ConstraintError::Overlap {
+                first: 0..2,
+                second: 1..3
+            }
+        );
+        let err =
+            tokenize_with_constraints("松本", &mut dict, &[Constraint::span(1..3)]).unwrap_err();
+        assert_eq!(
+            err,
+            ConstraintError::OutOfRange {
+                chars: 1..3,
+                len: 2
+            }
+        );
+        let constraints = [Constraint {
+            pos_id: Some(3),
+            ..Constraint::span(0..1)
+        }];
+        let err = tokenize_with_constraints("松本", &mut dict, &constraints).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "constraint pos id 3 is outside the connection matrix"
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Graphviz rendering of the lattice a text is segmented over, to see why a path won.
+//!
+//! Every node the forward pass kept is drawn with its surface, reading, word cost and the cost
+//! of the cheapest path reaching it; edges from each possible predecessor carry the connection
+//! cost. The chosen path is drawn bold, unknown-word nodes are filled orange and user dictionary
+//! entries light blue.
+
+use std::fmt::Write;
+
+use crate::{best_path, connection_cost, eos_cost, node_context, viterbi, Dictionary, LatticeNode};
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn node_id(pos: usize, idx: usize) -> String {
+    format!("n{}_{}", pos, idx)
+}
+
+/// The reading of a dictionary node, from the user dictionary for user entries.
+fn node_reading(dict: &mut Dictionary, node: &LatticeNode) -> Option<String> {
+    let reading = dict.node_entry(node)?.to_entry().reading;
+    if node.is_user() {
+        Some(dict.user.as_deref_mut()?.entry_reading((&reading).into()))
+    } else {
+        Some(dict.entry_reading((&reading).into()))
+    }
+}
+
+/// Renders the lattice of `text` as a Graphviz DOT digraph, left to right from BOS to EOS.
+pub fn dump_lattice(text: &str, dict: &mut Dictionary) -> String {
+    let (nodes, chars) = viterbi(text, dict);
+    let (path, total) = best_path(dict, &nodes);
+    let mut on_path = vec![(0, 0)];
+    on_path.extend_from_slice(&path);
+
+    let mut out = String::from("digraph lattice {\n  rankdir=LR;\n  node [shape=box];\n");
+    out.push_str("  n0_0 [label=\"BOS\"];\n");
+    for (pos, nodes_at) in nodes.iter().enumerate().skip(1) {
+        for (idx, node) in nodes_at.iter().enumerate() {
+            let surface: String = chars[node.start_pos..node.end_pos].iter().collect();
+            let context = node_context(dict, node);
+            let word_cost = context.map_or(dict.viterbi.unknown_cost, |(_, cost)| cost as i32);
+            let (reading, style) = if node.is_unknown() {
+                (surface.clone(), ", style=filled, fillcolor=orange")
+            } else if node.is_user() {
+                let reading = node_reading(dict, node).unwrap_or_default();
+                (reading, ", style=filled, fillcolor=lightblue")
+            } else {
+                (node_reading(dict, node).unwrap_or_default(), "")
+            };
+            writeln!(
+                out,
+                "  {} [label=\"{}\\n{}\\nword {}\\ntotal {}\"{}];",
+                node_id(pos, idx),
+                escape(&surface),
+                escape(&reading),
+                word_cost,
+                node.cost,
+                style
+            )
+            .unwrap();
+
+            for (prev_idx, prev) in nodes[node.start_pos].iter().enumerate() {
+                // Legacy unknown nodes connect through `unknown_pos_id`, if at all.
+                let left_id = context.map(|(left_id, _)| left_id);
+                let label = match left_id.or(dict.viterbi.unknown_pos_id) {
+                    Some(left_id) => format!("{}", connection_cost(dict, prev, left_id)),
+                    None => String::new(),
+                };
+                let bold = on_path
+                    .windows(2)
+                    .any(|pair| pair[0] == (node.start_pos, prev_idx) && pair[1] == (pos, idx));
+                writeln!(
+                    out,
+                    "  {} -> {} [label=\"{}\"{}];",
+                    node_id(node.start_pos, prev_idx),
+                    node_id(pos, idx),
+                    label,
+                    if bold { ", style=bold" } else { "" }
+                )
+                .unwrap();
+            }
+        }
+    }
+
+    let len = nodes.len() - 1;
+    writeln!(out, "  eos [label=\"EOS\\ntotal {}\"];", total).unwrap();
+    for (idx, node) in nodes[len].iter().enumerate() {
+        let bold = path.last() == Some(&(len, idx));
+        writeln!(
+            out,
+            "  {} -> eos [label=\"{}\"{}];",
+            node_id(len, idx),
+            eos_cost(dict, node),
+            if bold { ", style=bold" } else { "" }
+        )
+        .unwrap();
+    }
+    out.push_str("}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::fixture;
+
+    #[test]
+    fn test_lattice_marks_the_chosen_path() {
+        let mut dict = fixture(
+            &[
+                ("東京", "トーキョー", 0, 100),
+                ("東", "ヒガシ", 0, 300),
+                ("京", "ケイ", 0, 300),
+            ],
+            1,
+        );
+        let dot = dump_lattice("東京X", &mut dict);
+        assert!(dot.starts_with("digraph lattice {\n"));
+        assert!(dot.ends_with("}\n"));
+        assert!(dot.contains("[label=\"東京\\nトーキョー\\nword 100\\ntotal 100\"];"));
+        assert!(dot.contains("[label=\"東\\nヒガシ\\nword 300\\ntotal 300\"];"));
+        assert!(dot.contains("[label=\"X\\nX\\nword 10000\\ntotal 10100\", style=filled"));
+
+        let bold: Vec<&str> = dot.lines().filter(|l| l.contains("style=bold")).collect();
+        assert_eq!(bold.len(), 3, "{}", dot);
+        // BOS -> 東京 -> X -> EOS; 東 and 京 are off the path.
+        assert!(bold[0].starts_with("  n0_0 -> n2_"));
+        assert!(bold[2].ends_with("-> eos [label=\"0\", style=bold];"));
+        assert!(dot.contains("  eos [label=\"EOS\\ntotal 10100\"];"));
+    }
+}
@@ -0,0 +1,88 @@
+//! Dictionaries compiled into the program, behind the `embed` feature.
+//!
+//! Hand the bytes of a dictionary file to [`DictionaryData::from_static`], usually from
+//! `include_bytes!`, and handles read entries and readings straight from them: nothing is
+//! copied but the index, the character definitions and the POS names, which are parsed as
+//! [`DictionaryData::from_bytes`] parses them. The connection matrix is read in place too, on
+//! little-endian targets, if the bytes are placed at an even address. `include_bytes!` makes
+//! no promise about that; wrap it in a type aligned to 2 to be sure, as
+//! `examples/embedded.rs` does.
+
+use std::sync::Arc;
+
+use crate::{Dictionary, DictionaryData, MucabError};
+
+impl DictionaryData {
+    /// Like [`Self::from_bytes`], for a dictionary file that lives as long as the program,
+    /// without copying it; see the [module docs](crate::embed).
+    pub fn from_static(bytes: &'static [u8]) -> Result<Self, MucabError> {
+        Self::from_memory(Arc::new(bytes), Some(bytes))
+    }
+
+    /// Whether the connection matrix is read in place from the dictionary file rather than
+    /// decoded into memory of its own.
+    pub fn matrix_is_borrowed(&self) -> bool {
+        matches!(self.matrix, crate::Matrix::Borrowed(_))
+    }
+}
+
+impl Dictionary<'_> {
+    /// Loads through [`DictionaryData::from_static`].
+    pub fn from_static(bytes: &'static [u8]) -> Result<Self, MucabError> {
+        Ok(Self::with_data(Arc::new(DictionaryData::from_static(
+            bytes,
+        )?))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DictionaryBuilder;
+    use crate::transliterate;
+
+    /// tests/fixtures/embedded.bin, uncompressed so that builds without zstd read it.
+    fn fixture_builder() -> DictionaryBuilder {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("東京", "トーキョー", 0, 100);
+        builder.add_entry("東", "ヒガシ", 0, 300);
+        builder.add_entry("京都", "キョート", 0, 100);
+        builder.add_entry("都", "ト", 0, 100);
+        builder.add_entry("大阪", "オーサカ", 0, 100);
+        builder.add_entry("府", "フ", 0, 100);
+        builder.set_matrix(vec![0], 1);
+        builder.compress(false);
+        builder
+    }
+
+    #[repr(C, align(2))]
+    struct Aligned<B: ?Sized>(B);
+
+    static FIXTURE: &Aligned<[u8]> = &Aligned(*include_bytes!("../tests/fixtures/embedded.bin"));
+
+    #[test]
+    fn test_static_dictionary_borrows_its_matrix() {
+        // Rewrite the fixture from `fixture_builder` when the format changes.
+        let mut rebuilt = Vec::new();
+        fixture_builder().write(&mut rebuilt).unwrap();
+        assert_eq!(
+            rebuilt, FIXTURE.0,
+            "tests/fixtures/embedded.bin is out of date"
+        );
+
+        let data = DictionaryData::from_static(&FIXTURE.0).unwrap();
+        assert_eq!(data.matrix_is_borrowed(), cfg!(target_endian = "little"));
+        let mut dict = Dictionary::with_data(Arc::new(data)).unwrap();
+        assert_eq!(
+            transliterate("東京都と大阪府", &mut dict),
+            "トーキョートとオーサカフ"
+        );
+
+        // One byte off and the cells are no longer aligned: they are decoded instead.
+        let shifted: &'static [u8] = Box::leak([&[0], &FIXTURE.0[..]].concat().into_boxed_slice());
+        let data = DictionaryData::from_static(&shifted[1..]).unwrap();
+        assert!(!data.matrix_is_borrowed());
+        let mut dict = Dictionary::with_data(Arc::new(data)).unwrap();
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+    }
+}
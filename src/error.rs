@@ -0,0 +1,110 @@
+//! The error type of loading and checking dictionaries, and of conversions given up on.
+
+use std::fmt;
+use std::ops::RangeInclusive;
+
+/// Why a dictionary could not be loaded or failed a check, or why a conversion stopped short.
+#[derive(Debug)]
+pub enum MucabError {
+    /// Reading the file failed: it is missing, unreadable, or the read itself broke off.
+    Io(std::io::Error),
+    /// The file is not a well-formed mucab dictionary: bad magic, truncated, sections that
+    /// contradict the header, or contents that do not match the header checksum.
+    Corrupt(String),
+    /// The header names a format version this build cannot read.
+    UnsupportedVersion {
+        found: u16,
+        supported: RangeInclusive<u16>,
+    },
+    /// Reading the file needs a feature this build of the crate leaves out, such as `zstd` for
+    /// a compressed dictionary.
+    MissingFeature(&'static str),
+    /// [`crate::transliterate_with_deadline`] ran out of time. `partial` is the reading of the
+    /// pieces of the text converted before it did.
+    Timeout { partial: String },
+    /// [`crate::transliterate_cancellable`] saw its flag set; `partial` as for `Timeout`.
+    Cancelled { partial: String },
+}
+
+impl fmt::Display for MucabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MucabError::Io(e) => write!(f, "{}", e),
+            MucabError::Corrupt(message) => write!(f, "{}", message),
+            MucabError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "unsupported format version {} (supported: {}..={})",
+                found,
+                supported.start(),
+                supported.end()
+            ),
+            MucabError::MissingFeature(feature) => write!(
+                f,
+                "reading this dictionary needs the `{}` feature, which this build leaves out",
+                feature
+            ),
+            MucabError::Timeout { .. } => write!(f, "conversion timed out"),
+            MucabError::Cancelled { .. } => write!(f, "conversion cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for MucabError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            MucabError::Io(e) => Some(e),
+            MucabError::Corrupt(_)
+            | MucabError::UnsupportedVersion { .. }
+            | MucabError::MissingFeature(_)
+            | MucabError::Timeout { .. }
+            | MucabError::Cancelled { .. } => None,
+        }
+    }
+}
+
+/// Parse errors are [`std::io::ErrorKind::InvalidData`] or `UnexpectedEof` and become
+/// [`MucabError::Corrupt`]; a `MucabError` carried inside an I/O error is unwrapped.
+impl From<std::io::Error> for MucabError {
+    fn from(e: std::io::Error) -> Self {
+        match e.kind() {
+            std::io::ErrorKind::InvalidData | std::io::ErrorKind::UnexpectedEof => {
+                let message = e.to_string();
+                match e.into_inner().map(|inner| inner.downcast::<MucabError>()) {
+                    Some(Ok(inner)) => *inner,
+                    _ => MucabError::Corrupt(message),
+                }
+            }
+            _ => MucabError::Io(e),
+        }
+    }
+}
+
+/// Lets `?` carry a [`MucabError`] through code returning [`std::io::Result`]; anything but
+/// [`MucabError::Io`] becomes [`std::io::ErrorKind::InvalidData`].
+impl From<MucabError> for std::io::Error {
+    fn from(e: MucabError) -> Self {
+        match e {
+            MucabError::Io(e) => e,
+            invalid => std::io::Error::new(std::io::ErrorKind::InvalidData, invalid),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_io_errors_convert_both_ways() {
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        assert!(matches!(MucabError::from(missing), MucabError::Io(_)));
+
+        let truncated = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "truncated");
+        assert!(matches!(MucabError::from(truncated), MucabError::Corrupt(m) if m == "truncated"));
+
+        let io: std::io::Error = MucabError::Corrupt("bad index".into()).into();
+        assert_eq!(io.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(io.to_string(), "bad index");
+        assert!(matches!(MucabError::from(io), MucabError::Corrupt(m) if m == "bad index"));
+    }
+}
@@ -0,0 +1,128 @@
+//! C interface, behind the `ffi` feature. The library builds as a `cdylib` (`libmucab.so`,
+//! `mucab.dll`); `include/mucab.h` declares these functions and is regenerated with
+//! `cbindgen --config cbindgen.toml --output include/mucab.h`.
+//!
+//! Strings in both directions are NUL-terminated UTF-8. Functions that fail return `NULL` and
+//! leave a message for [`mucab_last_error`]; none of them lets a panic cross into C. A
+//! `MucabDict` must not be used from two threads at once.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use crate::Dictionary;
+
+/// A loaded dictionary, opaque to C.
+pub struct MucabDict(Dictionary<'static>);
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    // Messages come from Rust strings and cannot hold a NUL, bar a file name with one.
+    let message = CString::new(message.replace('\0', "\\0")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Runs `f`, turning an error or a panic into `NULL` and a message for [`mucab_last_error`].
+fn guard<T>(f: impl FnOnce() -> Result<*mut T, String>) -> *mut T {
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(value)) => value,
+        Ok(Err(message)) => {
+            set_last_error(message);
+            ptr::null_mut()
+        }
+        Err(panic) => {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            set_last_error(format!("panic inside mucab: {}", reason));
+            ptr::null_mut()
+        }
+    }
+}
+
+/// # Safety
+///
+/// `s` must be `NULL` or point to a NUL-terminated string that stays alive for `'a`.
+unsafe fn utf8_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, String> {
+    if s.is_null() {
+        return Err(format!("{} is NULL", name));
+    }
+    CStr::from_ptr(s)
+        .to_str()
+        .map_err(|e| format!("{} is not UTF-8: {}", name, e))
+}
+
+/// Loads the dictionary at `path`, or returns `NULL` on failure. Free it with
+/// [`mucab_dict_free`].
+///
+/// # Safety
+///
+/// `path` must be `NULL` or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mucab_dict_load(path: *const c_char) -> *mut MucabDict {
+    guard(|| {
+        let path = utf8_arg(path, "path")?;
+        let dict = Dictionary::load(path)
+            .map_err(|e| format!("cannot load dictionary '{}': {}", path, e))?;
+        Ok(Box::into_raw(Box::new(MucabDict(dict))))
+    })
+}
+
+/// Frees a dictionary from [`mucab_dict_load`]; `NULL` is ignored.
+///
+/// # Safety
+///
+/// `dict` must be `NULL` or a pointer from [`mucab_dict_load`] that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mucab_dict_free(dict: *mut MucabDict) {
+    if !dict.is_null() {
+        // Dropping only frees memory and closes the file; a panic here has nowhere to go.
+        let _ = catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(dict))));
+    }
+}
+
+/// Transliterates `text` as [`crate::transliterate`] does. The result is owned by the caller
+/// and freed with [`mucab_string_free`]; `NULL` means failure.
+///
+/// # Safety
+///
+/// `dict` must be `NULL` or a live pointer from [`mucab_dict_load`], not in use on another
+/// thread, and `text` `NULL` or a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn mucab_transliterate(
+    dict: *mut MucabDict,
+    text: *const c_char,
+) -> *mut c_char {
+    guard(|| {
+        let dict = dict.as_mut().ok_or("dict is NULL")?;
+        let text = utf8_arg(text, "text")?;
+        let output = crate::transliterate(text, &mut dict.0);
+        let output = CString::new(output).map_err(|_| "the reading contains a NUL byte")?;
+        Ok(output.into_raw())
+    })
+}
+
+/// Frees a string from [`mucab_transliterate`]; `NULL` is ignored.
+///
+/// # Safety
+///
+/// `s` must be `NULL` or a pointer from [`mucab_transliterate`] that has not been freed.
+#[no_mangle]
+pub unsafe extern "C" fn mucab_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// The message of the last failure on this thread, or `NULL` if nothing has failed yet. The
+/// string belongs to mucab and stays valid until the next failure on the same thread.
+#[no_mangle]
+pub extern "C" fn mucab_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ref().map_or(ptr::null(), |e| e.as_ptr()))
+}
@@ -0,0 +1,556 @@
+//! Line-oriented text formats for token sequences, and parsers that read them back.
+//!
+//! Both formats print one token per line and end each analysed text with an `EOS` line.
+//!
+//! - TSV: `surface<TAB>reading<TAB>pos_id<TAB>word_cost`. Unknown tokens carry `*` in both the
+//!   pos_id and word_cost columns, which parse back as 0 for them.
+//! - MeCab: `surface<TAB>features`, where features are the nine IPADIC-style comma separated
+//!   fields with the reading in the eighth. Unknown tokens get seven `*` fields and no reading.
+//!   pos_id and word_cost are not part of this format; they parse back as 0, and unknown tokens
+//!   parse back with their surface as reading.
+//!
+//! Neither format keeps the part-of-speech name, pronunciation, accent or lemma of a token:
+//! they parse back as `None`, so only tokens without them, such as those [`crate::tokenize`]
+//! gives for a dictionary without POS names or extra fields, come back unchanged from TSV.
+//!
+//! Neither format stores byte spans: parsing gives tokens the spans their surfaces would have
+//! one after the other, as they do in the output of [`crate::tokenize`].
+//!
+//! Every field escapes `\` as `\\`, tab as `\t`, newline as `\n` and carriage return as `\r`,
+//! so a token line never contains a raw separator. In MeCab features, fields containing `,` or
+//! `"` are additionally quoted CSV-style (`"a,""b"""`) before escaping.
+
+use std::fmt;
+
+use crate::punctuation::is_punctuation;
+use crate::{OwnedToken, Token};
+
+const EOS: &str = "EOS";
+const MECAB_UNKNOWN_FIELDS: usize = 7;
+const MECAB_KNOWN_FIELDS: usize = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based line number the problem was found on.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn error(line: usize, message: impl Into<String>) -> ParseError {
+    ParseError {
+        line,
+        message: message.into(),
+    }
+}
+
+fn escape(field: &str, out: &mut String) {
+    for c in field.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\t' => out.push_str("\\t"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            c => out.push(c),
+        }
+    }
+}
+
+fn unescape(field: &str, line: usize) -> Result<String, ParseError> {
+    let mut out = String::with_capacity(field.len());
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('\\') => out.push('\\'),
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(other) => return Err(error(line, format!("unknown escape '\\{}'", other))),
+            None => return Err(error(line, "dangling '\\' at end of field")),
+        }
+    }
+    Ok(out)
+}
+
+fn csv_quote(field: &str, out: &mut String) {
+    if field.contains([',', '"']) {
+        out.push('"');
+        out.push_str(&field.replace('"', "\"\""));
+        out.push('"');
+    } else {
+        out.push_str(field);
+    }
+}
+
+fn csv_split(features: &str, line: usize) -> Result<Vec<String>, ParseError> {
+    let mut fields = Vec::new();
+    let mut chars = features.chars().peekable();
+    loop {
+        let mut field = String::new();
+        if chars.peek() == Some(&'"') {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') if chars.peek() == Some(&'"') => {
+                        chars.next();
+                        field.push('"');
+                    }
+                    Some('"') => break,
+                    Some(c) => field.push(c),
+                    None => return Err(error(line, "unterminated quoted field")),
+                }
+            }
+            match chars.next() {
+                None => {
+                    fields.push(field);
+                    return Ok(fields);
+                }
+                Some(',') => fields.push(field),
+                Some(c) => {
+                    return Err(error(
+                        line,
+                        format!("unexpected '{}' after quoted field", c),
+                    ))
+                }
+            }
+        } else {
+            loop {
+                match chars.next() {
+                    Some(',') => break,
+                    Some('"') => return Err(error(line, "stray '\"' in unquoted field")),
+                    Some(c) => field.push(c),
+                    None => {
+                        fields.push(field);
+                        return Ok(fields);
+                    }
+                }
+            }
+            fields.push(field);
+        }
+    }
+}
+
+fn tsv_line(token: &Token, out: &mut String) {
+    escape(token.surface, out);
+    out.push('\t');
+    escape(&token.reading, out);
+    if token.is_unknown {
+        out.push_str("\t*\t*\n");
+    } else {
+        out.push_str(&format!("\t{}\t{}\n", token.pos_id, token.word_cost));
+    }
+}
+
+/// Formats `tokens` as TSV, one token per line followed by an `EOS` line. Accepts borrowed
+/// [`Token`]s as well as [`OwnedToken`]s.
+pub fn format_tsv<'t, T: Into<Token<'t>>>(tokens: impl IntoIterator<Item = T>) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        tsv_line(&token.into(), &mut out);
+    }
+    out.push_str(EOS);
+    out.push('\n');
+    out
+}
+
+/// [`format_tsv`], writing each line to `out` as it is formatted.
+pub fn write_tsv<'t, T: Into<Token<'t>>>(
+    tokens: impl IntoIterator<Item = T>,
+    mut out: impl std::io::Write,
+) -> std::io::Result<()> {
+    let mut line = String::new();
+    for token in tokens {
+        line.clear();
+        tsv_line(&token.into(), &mut line);
+        out.write_all(line.as_bytes())?;
+    }
+    writeln!(out, "{}", EOS)
+}
+
+/// Formats `tokens` in MeCab's default output layout, followed by an `EOS` line. Accepts
+/// borrowed [`Token`]s as well as [`OwnedToken`]s.
+pub fn format_mecab<'t, T: Into<Token<'t>>>(tokens: impl IntoIterator<Item = T>) -> String {
+    let mut out = String::new();
+    for token in tokens {
+        let token = token.into();
+        escape(token.surface, &mut out);
+        out.push('\t');
+        let mut features = String::new();
+        if token.is_unknown {
+            features.push_str(&["*"; MECAB_UNKNOWN_FIELDS].join(","));
+        } else {
+            features.push_str(&["*"; MECAB_KNOWN_FIELDS - 2].join(","));
+            features.push(',');
+            csv_quote(&token.reading, &mut features);
+            features.push_str(",*");
+        }
+        escape(&features, &mut out);
+        out.push('\n');
+    }
+    out.push_str(EOS);
+    out.push('\n');
+    out
+}
+
+fn parse_tsv_line(line: &str, line_no: usize) -> Result<OwnedToken, ParseError> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 4 {
+        return Err(error(
+            line_no,
+            format!("expected 4 tab separated fields, found {}", fields.len()),
+        ));
+    }
+    let surface = unescape(fields[0], line_no)?;
+    let reading = unescape(fields[1], line_no)?;
+    match (fields[2], fields[3]) {
+        ("*", "*") => Ok(OwnedToken {
+            is_punctuation: is_punctuation(&surface),
+            surface,
+            reading,
+            pos_id: 0,
+            pos: None,
+            word_cost: 0,
+            is_unknown: true,
+            start_byte: 0,
+            end_byte: 0,
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        }),
+        (pos_id, word_cost) => Ok(OwnedToken {
+            surface,
+            reading,
+            pos_id: pos_id
+                .parse()
+                .map_err(|_| error(line_no, format!("invalid pos_id '{}'", pos_id)))?,
+            pos: None,
+            word_cost: word_cost
+                .parse()
+                .map_err(|_| error(line_no, format!("invalid word_cost '{}'", word_cost)))?,
+            is_unknown: false,
+            is_punctuation: false,
+            start_byte: 0,
+            end_byte: 0,
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        }),
+    }
+}
+
+fn parse_mecab_line(line: &str, line_no: usize) -> Result<OwnedToken, ParseError> {
+    let Some((surface, features)) = line.split_once('\t') else {
+        return Err(error(line_no, "missing tab between surface and features"));
+    };
+    if features.contains('\t') {
+        return Err(error(line_no, "unexpected tab in features"));
+    }
+    let surface = unescape(surface, line_no)?;
+    let fields = csv_split(&unescape(features, line_no)?, line_no)?;
+    match fields.len() {
+        MECAB_UNKNOWN_FIELDS => Ok(OwnedToken {
+            is_punctuation: is_punctuation(&surface),
+            reading: surface.clone(),
+            surface,
+            pos_id: 0,
+            pos: None,
+            word_cost: 0,
+            is_unknown: true,
+            start_byte: 0,
+            end_byte: 0,
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        }),
+        MECAB_KNOWN_FIELDS => Ok(OwnedToken {
+            surface,
+            reading: fields[MECAB_KNOWN_FIELDS - 2].clone(),
+            pos_id: 0,
+            pos: None,
+            word_cost: 0,
+            is_unknown: false,
+            is_punctuation: false,
+            start_byte: 0,
+            end_byte: 0,
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        }),
+        n => Err(error(
+            line_no,
+            format!(
+                "expected {} or {} features, found {}",
+                MECAB_UNKNOWN_FIELDS, MECAB_KNOWN_FIELDS, n
+            ),
+        )),
+    }
+}
+
+fn parse_all(
+    input: &str,
+    parse_line: fn(&str, usize) -> Result<OwnedToken, ParseError>,
+) -> Result<Vec<Vec<OwnedToken>>, ParseError> {
+    let mut sentences = Vec::new();
+    let mut current = Vec::new();
+    let mut open = false;
+    let mut line_no = 0;
+    let mut lines: Vec<&str> = input.split('\n').collect();
+    // The newline terminating the last line leaves an empty piece behind.
+    if lines.last() == Some(&"") {
+        lines.pop();
+    }
+    for (i, line) in lines.into_iter().enumerate() {
+        line_no = i + 1;
+        if line == EOS {
+            sentences.push(std::mem::take(&mut current));
+            open = false;
+        } else {
+            let mut token = parse_line(line, line_no)?;
+            token.start_byte = current.last().map_or(0, |t: &OwnedToken| t.end_byte);
+            token.end_byte = token.start_byte + token.surface.len();
+            current.push(token);
+            open = true;
+        }
+    }
+    if open || sentences.is_empty() {
+        return Err(error(line_no, "missing EOS line"));
+    }
+    Ok(sentences)
+}
+
+fn parse_one(
+    input: &str,
+    parse_line: fn(&str, usize) -> Result<OwnedToken, ParseError>,
+) -> Result<Vec<OwnedToken>, ParseError> {
+    let mut sentences = parse_all(input, parse_line)?;
+    if sentences.len() != 1 {
+        return Err(error(
+            1,
+            format!(
+                "expected a single EOS-terminated text, found {}",
+                sentences.len()
+            ),
+        ));
+    }
+    Ok(sentences.pop().unwrap())
+}
+
+/// Parses the output of [`format_tsv`] for a single text into [`OwnedToken`]s.
+pub fn parse_tsv(input: &str) -> Result<Vec<OwnedToken>, ParseError> {
+    parse_one(input, parse_tsv_line)
+}
+
+/// Parses several concatenated [`format_tsv`] outputs, one token list per `EOS`.
+pub fn parse_tsv_all(input: &str) -> Result<Vec<Vec<OwnedToken>>, ParseError> {
+    parse_all(input, parse_tsv_line)
+}
+
+/// Parses the output of [`format_mecab`] for a single text into [`OwnedToken`]s.
+pub fn parse_mecab(input: &str) -> Result<Vec<OwnedToken>, ParseError> {
+    parse_one(input, parse_mecab_line)
+}
+
+/// Parses several concatenated [`format_mecab`] outputs, one token list per `EOS`.
+pub fn parse_mecab_all(input: &str) -> Result<Vec<Vec<OwnedToken>>, ParseError> {
+    parse_all(input, parse_mecab_line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(surface: &str, reading: &str, pos_id: u16, word_cost: i16) -> OwnedToken {
+        OwnedToken {
+            surface: surface.to_string(),
+            reading: reading.to_string(),
+            pos_id,
+            pos: None,
+            word_cost,
+            is_unknown: false,
+            is_punctuation: false,
+            start_byte: 0,
+            end_byte: surface.len(),
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        }
+    }
+
+    fn unknown(surface: &str) -> OwnedToken {
+        OwnedToken {
+            surface: surface.to_string(),
+            reading: surface.to_string(),
+            pos_id: 0,
+            pos: None,
+            word_cost: 0,
+            is_unknown: true,
+            is_punctuation: is_punctuation(surface),
+            start_byte: 0,
+            end_byte: surface.len(),
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        }
+    }
+
+    /// `tokens` with the spans they would have one after the other in a text.
+    fn laid_out(mut tokens: Vec<OwnedToken>) -> Vec<OwnedToken> {
+        let mut offset = 0;
+        for token in &mut tokens {
+            token.start_byte = offset;
+            offset += token.surface.len();
+            token.end_byte = offset;
+        }
+        tokens
+    }
+
+    /// Small xorshift generator so the property tests are reproducible without extra crates.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        fn hostile_string(&mut self) -> String {
+            const ALPHABET: &[&str] = &[
+                "\t", "\n", "\r", "\\", "\\t", ",", "\"", "*", "EOS", " ", "あ", "漢", "\u{0}",
+                "😀", "a", "\\\\n",
+            ];
+            (0..self.below(6))
+                .map(|_| ALPHABET[self.below(ALPHABET.len())])
+                .collect()
+        }
+
+        fn token(&mut self) -> OwnedToken {
+            let surface = self.hostile_string();
+            let is_unknown = self.below(4) == 0;
+            OwnedToken {
+                is_punctuation: is_unknown && is_punctuation(&surface),
+                surface,
+                reading: self.hostile_string(),
+                pos_id: self.next() as u16,
+                pos: None,
+                word_cost: self.next() as i16,
+                is_unknown,
+                start_byte: 0,
+                end_byte: 0,
+                pronunciation: None,
+                accent: None,
+                lemma: None,
+            }
+        }
+    }
+
+    #[test]
+    fn test_tsv_layout() {
+        let tokens = vec![token("東京", "トーキョー", 3, -120), unknown("X")];
+        assert_eq!(
+            format_tsv(&tokens),
+            "東京\tトーキョー\t3\t-120\nX\tX\t*\t*\nEOS\n"
+        );
+        assert_eq!(format_tsv(&Vec::<OwnedToken>::new()), "EOS\n");
+    }
+
+    #[test]
+    fn test_mecab_layout() {
+        let tokens = vec![token("東京", "トーキョー", 3, -120), unknown("X")];
+        assert_eq!(
+            format_mecab(&tokens),
+            "東京\t*,*,*,*,*,*,*,トーキョー,*\nX\t*,*,*,*,*,*,*\nEOS\n"
+        );
+    }
+
+    #[test]
+    fn test_escaping_hostile_surface() {
+        let tokens = vec![token("a\tb\nc\\", "x,\"y\"", 1, 2)];
+        let tsv = format_tsv(&tokens);
+        assert_eq!(tsv, "a\\tb\\nc\\\\\tx,\"y\"\t1\t2\nEOS\n");
+        assert_eq!(parse_tsv(&tsv).unwrap(), tokens);
+
+        let mecab = format_mecab(&tokens);
+        assert!(mecab.contains("\"x,\"\"y\"\"\""));
+        assert_eq!(parse_mecab(&mecab).unwrap()[0].reading, "x,\"y\"");
+    }
+
+    #[test]
+    fn test_parse_errors_carry_line_numbers() {
+        let err = parse_tsv("a\tb\t1\t2\nbroken\nEOS\n").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(parse_tsv("a\tb\t1\t2\n").is_err());
+        assert!(parse_tsv("a\\q\tb\t1\t2\nEOS\n").is_err());
+        assert!(parse_tsv("a\tb\tx\t2\nEOS\n").is_err());
+        assert!(parse_tsv("EOS\nEOS\n").is_err());
+        assert!(parse_mecab("a\t\"unterminated\nEOS\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_all_keeps_sentence_boundaries() {
+        let first = vec![token("東京", "トーキョー", 3, 1)];
+        let second = laid_out(vec![unknown("X"), token("都", "ト", 2, 5)]);
+        let input =
+            format_tsv(&first) + &format_tsv(&second) + &format_tsv(&Vec::<OwnedToken>::new());
+        assert_eq!(
+            parse_tsv_all(&input).unwrap(),
+            vec![first, second, Vec::new()]
+        );
+    }
+
+    #[test]
+    fn test_tsv_round_trip_property() {
+        let mut rng = Rng(0x9e3779b97f4a7c15);
+        for _ in 0..500 {
+            let tokens = laid_out((0..rng.below(5)).map(|_| rng.token()).collect());
+            let formatted = format_tsv(&tokens);
+            let mut written = Vec::new();
+            write_tsv(&tokens, &mut written).unwrap();
+            assert_eq!(written, formatted.as_bytes());
+            let parsed = parse_tsv(&formatted).unwrap();
+            assert_eq!(format_tsv(&parsed), formatted);
+            for (parsed, original) in parsed.iter().zip(&tokens) {
+                assert_eq!(parsed.surface, original.surface);
+                assert_eq!(parsed.reading, original.reading);
+                assert_eq!(parsed.is_unknown, original.is_unknown);
+                if !original.is_unknown {
+                    assert_eq!(parsed, original);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mecab_round_trip_property() {
+        let mut rng = Rng(0xdeadbeefcafef00d);
+        for _ in 0..500 {
+            let tokens: Vec<OwnedToken> = (0..rng.below(5)).map(|_| rng.token()).collect();
+            let formatted = format_mecab(&tokens);
+            let parsed = parse_mecab(&formatted).unwrap();
+            assert_eq!(format_mecab(&parsed), formatted);
+            for (parsed, original) in parsed.iter().zip(&tokens) {
+                assert_eq!(parsed.surface, original.surface);
+                assert_eq!(parsed.is_unknown, original.is_unknown);
+                if !original.is_unknown {
+                    assert_eq!(parsed.reading, original.reading);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Furigana: text annotated with readings instead of replaced by them.
+//!
+//! Only dictionary tokens whose surface contains something other than kana get a reading, and
+//! kana inside such a surface is matched against the reading so the annotation covers just the
+//! kanji around it: 食べる with reading タベル becomes 食(た)べる, 取り扱い becomes
+//! 取(と)り扱(あつか)い. When the kana cannot be matched the whole surface is annotated.
+
+use crate::kana::{katakana_to_hiragana, KanaForm};
+use crate::Token;
+
+/// A piece of text and, if it is annotated, its reading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RubySegment<'t> {
+    pub surface: &'t str,
+    pub reading: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RubyFormat {
+    /// `漢字(かんじ)`
+    #[default]
+    Parenthesized,
+    /// `<ruby>漢字<rt>かんじ</rt></ruby>`, with the text HTML-escaped.
+    Html,
+}
+
+fn is_kana(c: char) -> bool {
+    matches!(c, 'ぁ'..='ゖ' | 'ゝ' | 'ゞ' | 'ァ'..='ヺ' | 'ー' | 'ヽ' | 'ヾ')
+}
+
+/// Splits `surface` into alternating runs of kana and of everything else.
+fn runs(surface: &str) -> Vec<(&str, bool)> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    let mut kind = surface.chars().next().is_some_and(is_kana);
+    for (i, c) in surface.char_indices() {
+        if is_kana(c) != kind {
+            runs.push((&surface[start..i], kind));
+            start = i;
+            kind = !kind;
+        }
+    }
+    runs.push((&surface[start..], kind));
+    runs
+}
+
+/// Assigns a slice of `reading` to each non-kana run, with every kana run matching `reading`
+/// literally (as hiragana). Returns the reading char count per run.
+fn align(runs: &[(&str, bool)], reading: &[char]) -> Option<Vec<usize>> {
+    fn go(runs: &[(&str, bool)], reading: &[char], out: &mut Vec<usize>) -> bool {
+        let Some(&(run, is_kana_run)) = runs.first() else {
+            return reading.is_empty();
+        };
+        if is_kana_run {
+            let kana: Vec<char> = run.chars().map(katakana_to_hiragana).collect();
+            if !reading.starts_with(&kana) {
+                return false;
+            }
+            out.push(kana.len());
+            if go(&runs[1..], &reading[kana.len()..], out) {
+                return true;
+            }
+            out.pop();
+            return false;
+        }
+        // Leave at least one reading char for each later non-kana run.
+        let later = runs[1..].iter().filter(|(_, k)| !k).count();
+        for len in 1..=reading.len().saturating_sub(later) {
+            out.push(len);
+            if go(&runs[1..], &reading[len..], out) {
+                return true;
+            }
+            out.pop();
+        }
+        false
+    }
+    let mut out = Vec::new();
+    go(runs, reading, &mut out).then_some(out)
+}
+
+/// Segments for one dictionary token, its reading rendered in `kana`.
+fn token_segments<'t>(surface: &'t str, reading: &str, kana: KanaForm) -> Vec<RubySegment<'t>> {
+    let plain = |surface| RubySegment {
+        surface,
+        reading: None,
+    };
+    if surface.chars().all(|c| is_kana(c) || c.is_ascii()) {
+        return vec![plain(surface)];
+    }
+    let chars: Vec<char> = reading.chars().collect();
+    let hiragana: Vec<char> = chars.iter().copied().map(katakana_to_hiragana).collect();
+    let span = |chars: &[char]| kana.apply(&chars.iter().collect::<String>()).into_owned();
+    let runs = runs(surface);
+    let Some(lengths) = align(&runs, &hiragana) else {
+        return vec![RubySegment {
+            surface,
+            reading: Some(span(&chars)),
+        }];
+    };
+
+    let mut segments = Vec::with_capacity(runs.len());
+    let mut at = 0;
+    for ((run, is_kana_run), len) in runs.into_iter().zip(lengths) {
+        segments.push(if is_kana_run {
+            plain(run)
+        } else {
+            RubySegment {
+                surface: run,
+                reading: Some(span(&chars[at..at + len])),
+            }
+        });
+        at += len;
+    }
+    segments
+}
+
+/// Annotation segments for `tokens`, readings in `kana`. Unknown tokens, tokens read as they
+/// are written, and tokens that are all kana or ASCII are passed through without a reading.
+pub fn ruby_segments<'t>(tokens: &[Token<'t>], kana: KanaForm) -> Vec<RubySegment<'t>> {
+    let mut segments = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        if token.is_unknown || token.reading == token.surface {
+            segments.push(RubySegment {
+                surface: token.surface,
+                reading: None,
+            });
+        } else {
+            segments.extend(token_segments(token.surface, &token.reading, kana));
+        }
+    }
+    segments
+}
+
+fn push_escaped(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+}
+
+pub fn format_ruby(segments: &[RubySegment], format: RubyFormat) -> String {
+    let mut out = String::new();
+    for segment in segments {
+        match (&segment.reading, format) {
+            (None, RubyFormat::Parenthesized) => out.push_str(segment.surface),
+            (None, RubyFormat::Html) => push_escaped(&mut out, segment.surface),
+            (Some(reading), RubyFormat::Parenthesized) => {
+                out.push_str(segment.surface);
+                out.push('(');
+                out.push_str(reading);
+                out.push(')');
+            }
+            (Some(reading), RubyFormat::Html) => {
+                out.push_str("<ruby>");
+                push_escaped(&mut out, segment.surface);
+                out.push_str("<rt>");
+                push_escaped(&mut out, reading);
+                out.push_str("</rt></ruby>");
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn annotate(surface: &str, reading: &str) -> String {
+        format_ruby(
+            &token_segments(surface, reading, KanaForm::Hiragana),
+            RubyFormat::Parenthesized,
+        )
+    }
+
+    #[test]
+    fn test_okurigana_alignment() {
+        assert_eq!(annotate("漢字", "カンジ"), "漢字(かんじ)");
+        assert_eq!(annotate("食べる", "タベル"), "食(た)べる");
+        assert_eq!(annotate("お茶", "オチャ"), "お茶(ちゃ)");
+        assert_eq!(annotate("取り扱い", "トリアツカイ"), "取(と)り扱(あつか)い");
+        // The kana cannot be matched, so the whole surface is annotated.
+        assert_eq!(annotate("食べる", "クウ"), "食べる(くう)");
+    }
+
+    #[test]
+    fn test_kana_and_ascii_pass_through() {
+        assert_eq!(annotate("それは", "ソレハ"), "それは");
+        assert_eq!(annotate("CD", "シーディー"), "CD");
+        assert_eq!(annotate("コーヒー", "コーヒー"), "コーヒー");
+    }
+
+    #[test]
+    fn test_html() {
+        let segments = vec![
+            RubySegment {
+                surface: "<東京>",
+                reading: None,
+            },
+            RubySegment {
+                surface: "漢字",
+                reading: Some("かんじ".to_string()),
+            },
+        ];
+        assert_eq!(
+            format_ruby(&segments, RubyFormat::Html),
+            "&lt;東京&gt;<ruby>漢字<rt>かんじ</rt></ruby>"
+        );
+    }
+
+    #[test]
+    fn test_katakana_readings() {
+        let segments = token_segments("食べる", "タベル", KanaForm::Katakana);
+        assert_eq!(segments[0].reading.as_deref(), Some("タ"));
+    }
+}
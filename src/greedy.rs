@@ -0,0 +1,229 @@
+//! Greedy longest-match tokenization, a cheaper alternative to the lattice search.
+//!
+//! At each position the longest entry of the dictionary or its user dictionary is taken, the
+//! one with the lowest word cost on a tie, and a char that starts no entry becomes an unknown
+//! token of its own. No lattice is built and the connection matrix is never consulted; on the
+//! text of `cargo bench --bench greedy` this takes a little over half the time
+//! [`crate::tokenize`] does, most of the rest going to lookups and readings.
+//!
+//! The segmentation is worse for it. A long entry wins even when taking it leaves the text
+//! after it to split badly, where a shorter one would have let a better word through; which
+//! parts of speech may follow each other plays no part; and unknown words are not grouped by
+//! character class, so an unknown katakana word comes out one char at a time. Use it where
+//! latency matters more than the reading, such as completing input as it is typed.
+
+use std::borrow::Cow;
+
+use crate::block::ReadingRef;
+use crate::{is_missing_reading, punctuation, Dictionary, MissingReading, PathStrings, Token};
+
+/// The entry taken at a position: from the user dictionary or not, its block and index.
+#[derive(Clone, Copy)]
+struct Pick {
+    user: bool,
+    entry_char: char,
+    local_idx: usize,
+    char_len: usize,
+    pos_id: u16,
+    word_cost: i16,
+    accent: Option<u8>,
+}
+
+/// Where a picked entry's reading comes from.
+enum Slot {
+    Inline(String),
+    /// Index into the spans of the system (0) or user (1) dictionary.
+    Span(usize, usize),
+}
+
+/// The longest of `matches` in `dict`, the cheapest among the longest. `best` is only replaced
+/// by a strictly better match.
+fn longest(dict: &mut Dictionary, user: bool, matches: &[(char, usize)], best: &mut Option<Pick>) {
+    for &(entry_char, local_idx) in matches {
+        let Some(entry) = dict.get_entry(entry_char, local_idx) else {
+            continue;
+        };
+        let (char_len, word_cost) = (entry.char_len as usize, entry.word_cost);
+        let better = best.is_none_or(|b| {
+            char_len > b.char_len || (char_len == b.char_len && word_cost < b.word_cost)
+        });
+        if better {
+            *best = Some(Pick {
+                user,
+                entry_char,
+                local_idx,
+                char_len,
+                pos_id: entry.pos_id,
+                word_cost,
+                accent: entry.accent,
+            });
+        }
+    }
+}
+
+/// Tokenizes `text` by longest match, as described in the [module docs](self). User entries
+/// win full ties with system ones. Readings follow [`Dictionary::missing_reading`] as they do
+/// in [`crate::tokenize`]; unknown tokens keep their surface.
+pub fn tokenize<'t>(text: &'t str, dict: &mut Dictionary) -> Vec<Token<'t>> {
+    let chars: Vec<char> = text.chars().collect();
+    let byte_offsets: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect();
+
+    let mut matches = Vec::new();
+    let mut picks = Vec::new();
+    let mut pos = 0;
+    while pos < chars.len() {
+        let mut best = None;
+        if let Some(user) = dict.user.as_deref_mut() {
+            user.lookup(&chars, pos, &mut matches);
+            longest(user, true, &matches, &mut best);
+        }
+        dict.lookup(&chars, pos, &mut matches);
+        longest(dict, false, &matches, &mut best);
+        let end = pos + best.map_or(1, |pick| pick.char_len);
+        picks.push((pos, end, best));
+        pos = end;
+    }
+
+    // Stored readings of system and user entries, each read in one go through their own
+    // dictionary.
+    let mut spans: [Vec<(u32, u16)>; 2] = [Vec::new(), Vec::new()];
+    let mut slots = Vec::with_capacity(picks.len());
+    let mut pronunciations = Vec::with_capacity(picks.len());
+    let mut lemmas = PathStrings::default();
+    for &(_, _, pick) in &picks {
+        let Some(pick) = pick else {
+            slots.push(None);
+            pronunciations.push(None);
+            lemmas.push(false, None);
+            continue;
+        };
+        let source = match dict.user.as_deref_mut() {
+            Some(user) if pick.user => user,
+            _ => &mut *dict,
+        };
+        let entry = source.get_entry(pick.entry_char, pick.local_idx);
+        pronunciations.push(entry.and_then(|entry| entry.pronunciation.map(str::to_string)));
+        lemmas.push(pick.user, entry.and_then(|entry| entry.lemma));
+        slots.push(match entry.map(|entry| entry.reading) {
+            None => None,
+            Some(ReadingRef::Inline(reading)) => Some(Slot::Inline(reading.to_string())),
+            Some(ReadingRef::Stored { offset, len }) => {
+                let spans = &mut spans[pick.user as usize];
+                spans.push((offset, len));
+                Some(Slot::Span(pick.user as usize, spans.len() - 1))
+            }
+        });
+    }
+    let system = dict.read_readings(&spans[0]);
+    let user = match dict.user.as_deref_mut() {
+        Some(user) => user.read_readings(&spans[1]),
+        None => Vec::new(),
+    };
+    let mut readings = [system, user];
+    let lemmas = lemmas.read(dict);
+
+    let use_surface = dict.missing_reading == MissingReading::Surface;
+    let unknown_pos_id = dict.viterbi.unknown_pos_id.unwrap_or(0);
+    picks
+        .into_iter()
+        .zip(slots)
+        .zip(pronunciations.into_iter().zip(lemmas))
+        .map(|(((start, end, pick), slot), (pronunciation, lemma))| {
+            let (start_byte, end_byte) = (byte_offsets[start], byte_offsets[end]);
+            let surface = &text[start_byte..end_byte];
+            let (Some(pick), Some(slot)) = (pick, slot) else {
+                return Token {
+                    surface,
+                    reading: Cow::Borrowed(surface),
+                    pos_id: unknown_pos_id,
+                    pos: None,
+                    word_cost: 0,
+                    is_unknown: true,
+                    is_punctuation: punctuation::is_punctuation(surface),
+                    start_byte,
+                    end_byte,
+                    pronunciation: None,
+                    accent: None,
+                    lemma: None,
+                };
+            };
+            let reading = match slot {
+                Slot::Inline(reading) => reading,
+                Slot::Span(source, i) => std::mem::take(&mut readings[source][i]),
+            };
+            let reading = if use_surface && is_missing_reading(&reading) {
+                surface.to_string()
+            } else {
+                reading
+            };
+            Token {
+                surface,
+                reading: Cow::Owned(reading),
+                pos_id: pick.pos_id,
+                // User entries use the system id space, so their names come from `dict` too.
+                pos: dict
+                    .pos_name(pick.pos_id)
+                    .map(|name| name.to_string().into()),
+                word_cost: pick.word_cost,
+                is_unknown: false,
+                is_punctuation: false,
+                start_byte,
+                end_byte,
+                pronunciation: pronunciation.map(Cow::Owned),
+                accent: pick.accent,
+                lemma: lemma.map(Cow::Owned),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::fixture;
+    use crate::tokenizer::{Mode, Options, Tokenizer};
+
+    #[test]
+    fn test_longest_match_wins_over_the_cheaper_path() {
+        let mut dict = fixture(
+            &[
+                ("東", "ヒガシ", 0, 100),
+                ("東京", "トーキョー", 0, 500),
+                ("京都", "キョート", 0, 100),
+                ("都", "ト", 0, 100),
+                ("都", "ミヤコ", 0, 50),
+            ],
+            1,
+        );
+        assert_eq!(crate::transliterate("東京都", &mut dict), "ヒガシキョート");
+
+        let tokens = tokenize("東京都XY", &mut dict);
+        let summary: Vec<(&str, &str, bool)> = tokens
+            .iter()
+            .map(|t| (t.surface, &*t.reading, t.is_unknown))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("東京", "トーキョー", false),
+                ("都", "ミヤコ", false),
+                ("X", "X", true),
+                ("Y", "Y", true),
+            ]
+        );
+        assert_eq!((tokens[3].start_byte, tokens[3].end_byte), (10, 11));
+
+        let mut tokenizer = Tokenizer::new(dict).with_options(Options {
+            mode: Mode::Greedy,
+            ..Options::default()
+        });
+        assert_eq!(
+            tokenizer.transliterate("東京都。東京都"),
+            "トーキョーミヤコ。トーキョーミヤコ"
+        );
+    }
+}
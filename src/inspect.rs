@@ -0,0 +1,415 @@
+//! Looking inside a dictionary file: its header and section sizes, how large each first char's
+//! block is, the entries under one first char, and a check that the file matches its checksum
+//! and every entry and reading decodes.
+
+use crate::{parse_record, DictEntry, Dictionary, EntryReading, KanaForm, MucabError};
+
+/// Header fields and section sizes of a loaded dictionary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DictionaryInfo {
+    pub format_version: u16,
+    /// xxh64 of everything after the header, from format version 7.
+    pub checksum: Option<u64>,
+    /// Matrix columns: left context ids of the following token.
+    pub left_size: usize,
+    /// Matrix rows: right context ids of the preceding token.
+    pub right_size: usize,
+    pub num_entries: usize,
+    pub index_keys: usize,
+    pub char_categories: usize,
+    pub compressed: bool,
+    /// See [`crate::DictionaryData::reading_kana`].
+    pub reading_kana: KanaForm,
+    /// Size of the entries and strings region in the file.
+    pub region_bytes: u64,
+    /// Size of the entries and strings region once decompressed.
+    pub uncompressed_bytes: u64,
+    /// Where the strings start in the decompressed region.
+    pub strings_offset: u64,
+}
+
+/// The stored block of one first char, as [`Dictionary::index_stats`] lists it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexStat {
+    pub first_char: char,
+    pub entries: usize,
+    /// Length of the block in the decompressed entries region: what a cache miss on
+    /// `first_char` decodes, and about what the cached block holds.
+    pub bytes: usize,
+}
+
+/// Problems [`Dictionary::verify`] lists; it counts the rest without describing them.
+pub const MAX_REPORTED_PROBLEMS: usize = 100;
+
+/// What [`Dictionary::verify`] decoded, and what it found wrong.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct VerifyReport {
+    /// Index blocks that lie inside the entries and were decoded.
+    pub blocks: usize,
+    /// Entries decoded from those blocks.
+    pub entries: usize,
+    /// Stored readings found inside the strings and valid UTF-8.
+    pub readings: usize,
+    /// The first [`MAX_REPORTED_PROBLEMS`] problems, each saying where it is.
+    pub problems: Vec<String>,
+    /// Problems found in all, described or not.
+    pub problem_count: usize,
+}
+
+impl VerifyReport {
+    /// Whether no problem was found.
+    pub fn is_sound(&self) -> bool {
+        self.problem_count == 0
+    }
+
+    fn problem(&mut self, problem: String) {
+        self.problem_count += 1;
+        if self.problems.len() < MAX_REPORTED_PROBLEMS {
+            self.problems.push(problem);
+        }
+    }
+}
+
+impl Dictionary<'_> {
+    pub fn info(&mut self) -> std::io::Result<DictionaryInfo> {
+        let file_len = self.data.file_len()?;
+        let data = &self.data;
+        Ok(DictionaryInfo {
+            format_version: data.version,
+            checksum: data.checksum,
+            left_size: data.left_size,
+            right_size: data.right_size,
+            num_entries: data.num_entries,
+            index_keys: data.index.len(),
+            char_categories: data.char_defs.as_ref().map_or(0, |d| d.categories.len()),
+            compressed: data.compressed,
+            reading_kana: data.reading_kana,
+            region_bytes: file_len.saturating_sub(data.region_start),
+            uncompressed_bytes: self.region.len()?,
+            strings_offset: data.strings_offset,
+        })
+    }
+
+    /// Every stored block, largest first (by first char among equal sizes), from the index
+    /// alone: nothing is read or decoded. Entries added with [`Dictionary::add_entry`] are not
+    /// counted.
+    pub fn index_stats(&self) -> Vec<IndexStat> {
+        let mut stats: Vec<IndexStat> = self
+            .data
+            .index
+            .iter()
+            .map(|(&first_char, &(offset, count))| {
+                let range = self.block_range(offset);
+                IndexStat {
+                    first_char,
+                    entries: count,
+                    bytes: range.end.saturating_sub(range.start) as usize,
+                }
+            })
+            .collect();
+        stats.sort_unstable_by(|a, b| b.bytes.cmp(&a.bytes).then(a.first_char.cmp(&b.first_char)));
+        stats
+    }
+
+    /// Length of the stored block of `first_char`, as in [`Self::index_stats`]; `None` if no
+    /// stored entry starts with it.
+    pub fn block_size(&self, first_char: char) -> Option<usize> {
+        let &(offset, _) = self.data.index.get(&first_char)?;
+        let range = self.block_range(offset);
+        Some(range.end.saturating_sub(range.start) as usize)
+    }
+
+    /// The entries starting with `first_char`, stored or added, by surface, with their readings.
+    pub fn entries_starting_with(&mut self, first_char: char) -> Vec<(DictEntry, String)> {
+        if !self.has_block(first_char) {
+            return Vec::new();
+        }
+        let entries = self.load_block(first_char);
+        entries
+            .iter()
+            .map(|entry| (entry.to_entry(), self.entry_reading(entry.reading)))
+            .collect()
+    }
+
+    /// Checks the file against its header checksum (see [`crate::DictionaryData::verify_checksum`]),
+    /// then decodes every index block and reading straight from the file, bypassing the caches:
+    /// that each block holds as many entries as the index says and they add up to the header's
+    /// count, that surfaces and readings are UTF-8, that readings lie inside the strings and
+    /// context ids inside the matrix. Each inconsistency is described with where it is; an error
+    /// means the file could not be read (or decompressed) at all.
+    pub fn verify(&mut self) -> Result<VerifyReport, MucabError> {
+        let data = self.data.clone();
+        let mut report = VerifyReport::default();
+        match data.verify_checksum() {
+            Ok(()) => {}
+            Err(MucabError::Io(e)) => return Err(MucabError::Io(e)),
+            Err(problem) => report.problem(problem.to_string()),
+        }
+
+        let total = self.region.len()?;
+        let region = self.region.read_range(0..total)?;
+
+        let strings_offset = data.strings_offset as usize;
+        if strings_offset > region.len() {
+            report.problem(format!(
+                "strings offset {} is past the end of the region ({} bytes)",
+                strings_offset,
+                region.len()
+            ));
+            return Ok(report);
+        }
+        let strings = &region[strings_offset..];
+
+        let mut keys: Vec<(char, u64, usize)> = data
+            .index
+            .iter()
+            .map(|(&c, &(offset, count))| (c, offset, count))
+            .collect();
+        keys.sort_unstable();
+        let indexed: usize = keys.iter().map(|&(_, _, count)| count).sum();
+        if indexed != data.num_entries {
+            report.problem(format!(
+                "header counts {} entries, the index {}",
+                data.num_entries, indexed
+            ));
+        }
+
+        for (first_char, offset, count) in keys {
+            let range = self.block_range(offset);
+            if range.start > range.end || range.end > data.strings_offset {
+                report.problem(format!(
+                    "block {:?}: bytes {}..{} are outside the entries ({} bytes)",
+                    first_char, range.start, range.end, data.strings_offset
+                ));
+                continue;
+            }
+            let block = &region[range.start as usize..range.end as usize];
+            report.blocks += 1;
+            let mut pos = 0;
+            let mut previous: Option<String> = None;
+            let mut complete = true;
+            for i in 0..count {
+                let at = format!(
+                    "block {:?} entry {} (byte {})",
+                    first_char,
+                    i,
+                    range.start as usize + pos
+                );
+                let (entry, size) = match parse_record(&block[pos..], data.version) {
+                    Ok(record) => record,
+                    Err(e) => {
+                        report.problem(format!("{}: {}", at, e));
+                        complete = false;
+                        break;
+                    }
+                };
+                pos += size;
+                report.entries += 1;
+
+                if !entry.surface.starts_with(first_char) {
+                    report.problem(format!(
+                        "{}: surface {:?} does not start with {:?}",
+                        at, entry.surface, first_char
+                    ));
+                }
+                if previous.as_ref().is_some_and(|p| *p > entry.surface) {
+                    report.problem(format!("{}: {:?} is out of order", at, entry.surface));
+                }
+                if entry.pos_id as usize >= data.left_size {
+                    report.problem(format!(
+                        "{}: left id {} is outside the matrix ({})",
+                        at, entry.pos_id, data.left_size
+                    ));
+                }
+                if entry.right_id as usize >= data.right_size {
+                    report.problem(format!(
+                        "{}: right id {} is outside the matrix ({})",
+                        at, entry.right_id, data.right_size
+                    ));
+                }
+                if let EntryReading::Stored { offset, len } = entry.reading {
+                    let (start, end) = (offset as usize, offset as usize + len as usize);
+                    match strings.get(start..end) {
+                        None => report.problem(format!(
+                            "{}: reading {}..{} is past the end of the strings ({} bytes)",
+                            at,
+                            start,
+                            end,
+                            strings.len()
+                        )),
+                        Some(bytes) => match std::str::from_utf8(bytes) {
+                            Ok(_) => report.readings += 1,
+                            Err(e) => report.problem(format!(
+                                "{}: reading at {} is not UTF-8 ({})",
+                                at, start, e
+                            )),
+                        },
+                    }
+                }
+                previous = Some(entry.surface);
+            }
+            if complete && pos < block.len() {
+                report.problem(format!(
+                    "block {:?}: {} bytes left after {} entries",
+                    first_char,
+                    block.len() - pos,
+                    count
+                ));
+            }
+        }
+        // Blocks that broke off or sit outside the entries have said so; this is what it adds
+        // up to.
+        if indexed == data.num_entries && report.entries != data.num_entries {
+            report.problem(format!(
+                "decoded {} entries, the header counts {}",
+                report.entries, data.num_entries
+            ));
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::DictionaryBuilder;
+    #[cfg(feature = "fs")]
+    use crate::testutil::temp_path;
+    #[cfg(feature = "fs")]
+    use crate::LoadOptions;
+    use crate::HEADER_SIZE;
+
+    fn builder() -> DictionaryBuilder {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("東京", "トーキョー", 0, 100);
+        builder.add_entry("東", "ヒガシ", 1, 200);
+        builder.add_entry("京都", "キョート", 0, 100);
+        builder.set_matrix(vec![0; 4], 2);
+        builder
+    }
+
+    /// Writes `builder` uncompressed, lets `corrupt` edit the entries and strings region, and
+    /// verifies the result. The checksum is updated to match, as if the file had been written so.
+    fn verify_corrupted(corrupt: impl FnOnce(&mut [u8])) -> Vec<String> {
+        let mut builder = builder();
+        builder.compress(false);
+        let mut bytes = Vec::new();
+        let stats = builder.write(&mut bytes).unwrap();
+        let region_start = bytes.len() - stats.compressed_bytes;
+        corrupt(&mut bytes[region_start..]);
+        let checksum = xxhash_rust::xxh64::xxh64(&bytes[HEADER_SIZE..], 0);
+        bytes[HEADER_SIZE - 8..HEADER_SIZE].copy_from_slice(&checksum.to_le_bytes());
+        Dictionary::from_bytes(bytes)
+            .unwrap()
+            .verify()
+            .unwrap()
+            .problems
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_info_and_entries() {
+        let path = temp_path("inspect.bin");
+        let stats = builder().write_to_file(&path).unwrap();
+        let mut dict = Dictionary::load(&path).unwrap();
+        let info = dict.info().unwrap();
+        assert_eq!(info.format_version, crate::FORMAT_VERSION);
+        assert!(info.checksum.is_some());
+        assert_eq!((info.left_size, info.right_size), (2, 2));
+        assert_eq!((info.num_entries, info.index_keys), (3, 2));
+        assert_eq!(info.compressed, cfg!(feature = "zstd"));
+        assert_eq!(info.region_bytes, stats.compressed_bytes as u64);
+        assert_eq!(
+            info.uncompressed_bytes,
+            (stats.entries_bytes + stats.strings_bytes) as u64
+        );
+        assert_eq!(info.strings_offset, stats.entries_bytes as u64);
+
+        let entries = dict.entries_starting_with('東');
+        let listed: Vec<(&str, &str, u16)> = entries
+            .iter()
+            .map(|(e, reading)| (e.surface.as_str(), reading.as_str(), e.pos_id))
+            .collect();
+        assert_eq!(listed, [("東", "ヒガシ", 1), ("東京", "トーキョー", 0)]);
+        assert!(dict.entries_starting_with('大').is_empty());
+
+        // The blocks fill the entries back to back; 東 holds two records to 京's one.
+        let stats = dict.index_stats();
+        assert_eq!(
+            stats
+                .iter()
+                .map(|s| (s.first_char, s.entries))
+                .collect::<Vec<_>>(),
+            [('東', 2), ('京', 1)]
+        );
+        assert_eq!(
+            stats.iter().map(|s| s.bytes).sum::<usize>(),
+            info.strings_offset as usize
+        );
+        assert_eq!(dict.block_size('京'), Some(stats[1].bytes));
+        assert_eq!(dict.block_size('大'), None);
+        let report = dict.verify().unwrap();
+        assert!(report.is_sound(), "{:?}", report);
+        assert_eq!((report.blocks, report.entries, report.readings), (2, 3, 3));
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_verify_reports_corruption() {
+        // Records are a u16 surface length, the surface, a u32 reading offset and a u16 reading
+        // length; the first is 京都 (6 bytes).
+        let problems =
+            verify_corrupted(|region| region[12..14].copy_from_slice(&u16::MAX.to_le_bytes()));
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].starts_with("block '京' entry 0 (byte 0): reading"));
+        assert!(problems[0].contains("past the end of the strings"));
+
+        let problems = verify_corrupted(|region| region[2] = 0xFF);
+        assert!(
+            problems[0].contains("surface is not UTF-8"),
+            "{:?}",
+            problems
+        );
+
+        let problems = verify_corrupted(|region| region[0] = 0xFF);
+        assert!(problems[0].contains("truncated"), "{:?}", problems);
+    }
+
+    #[test]
+    fn test_report_lists_the_first_problems() {
+        let mut report = VerifyReport::default();
+        for i in 0..MAX_REPORTED_PROBLEMS + 5 {
+            report.problem(format!("problem {}", i));
+        }
+        assert!(!report.is_sound());
+        assert_eq!(report.problem_count, MAX_REPORTED_PROBLEMS + 5);
+        assert_eq!(report.problems.len(), MAX_REPORTED_PROBLEMS);
+        assert_eq!(report.problems[0], "problem 0");
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_checksum_mismatch() {
+        let mut builder = builder();
+        builder.compress(false);
+        let mut bytes = Vec::new();
+        builder.write(&mut bytes).unwrap();
+        // The last byte of the last reading: the file still parses, but no longer hashes right.
+        *bytes.last_mut().unwrap() ^= 1;
+        let path = temp_path("checksum.bin");
+        std::fs::write(&path, &bytes).unwrap();
+
+        let mut dict = Dictionary::load(&path).unwrap();
+        let problems = dict.verify().unwrap().problems;
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+        assert!(problems[0].starts_with("checksum mismatch"));
+
+        let options = LoadOptions {
+            verify_checksum: true,
+            ..LoadOptions::default()
+        };
+        let err = Dictionary::load_with_options(&path, options).err().unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(matches!(err, MucabError::Corrupt(m) if m.starts_with("checksum mismatch")));
+    }
+}
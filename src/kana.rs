@@ -0,0 +1,87 @@
+//! Script of the readings produced for dictionary words.
+//!
+//! Dictionary readings are katakana. Converting to hiragana maps U+30A1..=U+30F6 onto
+//! U+3041..=U+3096 and the iteration marks ヽヾ onto ゝゞ. Everything else is kept: the long
+//! vowel mark ー and the middle dot ・ are shared by both scripts, ヷヸヹヺ and ヿ have no
+//! hiragana form, and halfwidth katakana is left to the caller.
+
+use std::borrow::Cow;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum KanaForm {
+    /// Readings as stored in the dictionary.
+    #[default]
+    Katakana,
+    /// Readings in hiragana, ヴ becoming ゔ.
+    Hiragana,
+    /// Readings in hiragana, except that ヴ stays katakana since ゔ is rarely used.
+    HiraganaKeepVu,
+}
+
+const OFFSET: u32 = 'ア' as u32 - 'あ' as u32;
+
+/// The hiragana counterpart of `c`, or `c` itself.
+pub fn katakana_to_hiragana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' | 'ヽ' | 'ヾ' => char::from_u32(c as u32 - OFFSET).unwrap_or(c),
+        _ => c,
+    }
+}
+
+impl KanaForm {
+    /// Renders a dictionary reading in this form, borrowing when nothing changes.
+    pub fn apply<'r>(&self, reading: &'r str) -> Cow<'r, str> {
+        let keep_vu = match self {
+            KanaForm::Katakana => return Cow::Borrowed(reading),
+            KanaForm::Hiragana => false,
+            KanaForm::HiraganaKeepVu => true,
+        };
+        let converts = |c: char| katakana_to_hiragana(c) != c && !(keep_vu && c == 'ヴ');
+        if !reading.chars().any(converts) {
+            return Cow::Borrowed(reading);
+        }
+        Cow::Owned(
+            reading
+                .chars()
+                .map(|c| {
+                    if converts(c) {
+                        katakana_to_hiragana(c)
+                    } else {
+                        c
+                    }
+                })
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hiragana() {
+        assert_eq!(KanaForm::Hiragana.apply("カンジ"), "かんじ");
+        assert_eq!(KanaForm::Hiragana.apply("トーキョー"), "とーきょー");
+        assert_eq!(KanaForm::Hiragana.apply("ァヵヶヽヾ"), "ぁゕゖゝゞ");
+        assert_eq!(KanaForm::Hiragana.apply("ヴァイオリン"), "ゔぁいおりん");
+        assert_eq!(
+            KanaForm::HiraganaKeepVu.apply("ヴァイオリン"),
+            "ヴぁいおりん"
+        );
+    }
+
+    #[test]
+    fn test_unconvertible_chars_are_kept() {
+        assert_eq!(KanaForm::Hiragana.apply("ヷヸヹヺ・ヿ"), "ヷヸヹヺ・ヿ");
+        assert_eq!(KanaForm::Hiragana.apply("ｶﾝｼﾞ漢字abc"), "ｶﾝｼﾞ漢字abc");
+        assert!(matches!(
+            KanaForm::Hiragana.apply("ー・"),
+            Cow::Borrowed("ー・")
+        ));
+        assert!(matches!(
+            KanaForm::Katakana.apply("カンジ"),
+            Cow::Borrowed("カンジ")
+        ));
+    }
+}
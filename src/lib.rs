@@ -1,19 +1,156 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::convert::Infallible;
+#[cfg(feature = "fs")]
 use std::fs::File;
-use std::io::{BufReader, Read, Seek, SeekFrom};
+#[cfg(feature = "fs")]
+use std::io::BufReader;
+use std::io::{Read, Seek, SeekFrom, Write};
+#[cfg(feature = "fs")]
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use xxhash_rust::xxh64::Xxh64;
+#[cfg(feature = "zstd")]
 use zeekstd::Decoder;
 
-const HEADER_SIZE: usize = 16;
-const ENTRY_METADATA_SIZE: usize = 9;
-const DEFAULT_CAPACITY: usize = 1024;
+pub use analysis::{Analysis, AnalysisToken};
+#[cfg(feature = "rayon")]
+pub use batch::transliterate_batch;
+use block::{EntryBlock, EntryRef, ReadingRef};
+use cache::EntryCache;
+#[cfg(feature = "zstd")]
+use cache::FrameCache;
+pub use cache::{CacheLimit, CacheStats};
+use cancel::{Stop, Trigger};
+use columns::Columns;
+pub use compat::AnalysisCompat;
+pub use constraint::{tokenize_with_constraints, Constraint, ConstraintError};
+pub use error::MucabError;
+pub use kana::KanaForm;
+pub use normalize::Normalization;
+use overrides::{CostOverride, SurfaceOverride};
+use pos::PosNames;
+pub use token::{OwnedToken, Token};
+pub use tokenizer::{Mode, Options, TokenIterator, Tokenizer};
+use unknown::{CharDefinitions, UnknownTemplate};
 
-type Lattice = Vec<Vec<((char, usize), usize)>>;
+pub mod analysis;
+#[cfg(feature = "rayon")]
+mod batch;
+mod block;
+pub mod builder;
+pub mod cache;
+mod cancel;
+mod columns;
+pub mod compat;
+pub mod constraint;
+pub mod dot;
+#[cfg(feature = "embed")]
+pub mod embed;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod format;
+pub mod furigana;
+pub mod greedy;
+pub mod inspect;
+pub mod kana;
+pub mod normalize;
+pub mod numbers;
+pub mod overrides;
+pub mod pos;
+pub mod punctuation;
+#[cfg(feature = "python")]
+mod python;
+pub mod romaji;
+pub mod script;
+#[cfg(test)]
+mod testutil;
+mod token;
+pub mod tokenizer;
+pub mod tune;
+pub mod unknown;
+pub mod user;
+
+/// Format version written by [`builder::DictionaryBuilder`] unless told otherwise.
+pub const FORMAT_VERSION: u16 = 9;
+/// Format versions [`Dictionary::load`] reads and [`builder::DictionaryBuilder`] can write.
+pub const SUPPORTED_FORMAT_VERSIONS: std::ops::RangeInclusive<u16> = 1..=FORMAT_VERSION;
+
+pub(crate) const HEADER_SIZE_V1: usize = 16;
+pub(crate) const HEADER_SIZE_V2: usize = 18;
+pub(crate) const HEADER_SIZE_V6: usize = 20;
+pub(crate) const HEADER_SIZE: usize = 28;
+/// Header flag: the entries and strings region is stored raw rather than zstd-compressed.
+pub(crate) const FLAG_UNCOMPRESSED: u16 = 1;
+/// Header flag: readings and pronunciations were converted to hiragana when the file was built.
+pub(crate) const FLAG_HIRAGANA_READINGS: u16 = 2;
+/// Header flag, with [`FLAG_HIRAGANA_READINGS`]: ヴ was kept in katakana.
+pub(crate) const FLAG_KEEP_VU: u16 = 4;
+pub(crate) const ENTRY_METADATA_SIZE_V1: usize = 9;
+pub(crate) const ENTRY_METADATA_SIZE_V2: usize = 11;
+pub(crate) const ENTRY_METADATA_SIZE_V5: usize = 12;
+pub(crate) const ENTRY_METADATA_SIZE: usize = 18;
+/// Tag of an entry's pronunciation in its extra fields, followed by the UTF-8 text.
+pub(crate) const EXTRA_PRONUNCIATION: u8 = 1;
+/// Tag of an entry's accent type in its extra fields, followed by one byte.
+pub(crate) const EXTRA_ACCENT: u8 = 2;
+/// Bytes of one index key: the char, the block's byte offset and its entry count.
+pub(crate) const INDEX_ENTRY_SIZE: usize = 10;
+/// Readings further apart than this in the strings region are read separately.
+const READING_GAP: u64 = 4096;
+const UNKNOWN_COST: i32 = 10000;
+/// Characters one lattice spans at most by default; see [`Dictionary::set_max_lattice_chars`].
+pub const DEFAULT_MAX_LATTICE_CHARS: usize = 8192;
+
+/// What a lattice node stands for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeKind {
+    Bos,
+    /// A dictionary entry: its first char and index within that char's entries, in the user
+    /// dictionary if `user` is set. Indices are kept narrow, as there are nodes for every
+    /// char of the text.
+    Dict {
+        entry_char: char,
+        local_idx: u32,
+        user: bool,
+    },
+    /// Text no entry covers, from an unknown-word template (index into `Dictionary`'s flattened
+    /// template list), or from the flat fallback without one: a single character, or a run of
+    /// characters no entry starts with (see [`AnalysisCompat::coalesces_unmatched_runs`]).
+    Unknown {
+        template: Option<u32>,
+    },
+    /// A span [`constraint`] pins to one token with this part of speech, where no candidate
+    /// of the dictionary fits it: connected through the matrix with `pos_id` on both sides and
+    /// no word cost.
+    Pinned {
+        pos_id: u16,
+    },
+}
+
+/// Candidate nodes by end position, each with the kind and start position of the node.
+type Lattice = Columns<(NodeKind, usize)>;
+
+/// Lattice nodes by end position, BOS alone at 0.
+type Nodes = Columns<LatticeNode>;
+
+/// Candidates keyed by end position, in the order they were found.
+type Candidates = Vec<(usize, (NodeKind, usize))>;
 
+/// A path through the node lattice as `(end_pos, node_idx)` pairs in text order, BOS excluded.
+type NodePath = Vec<(usize, usize)>;
+
+#[cfg(feature = "fs")]
 struct OffsetFile<R: Read + Seek> {
     reader: R,
     base_offset: u64,
 }
 
+#[cfg(feature = "fs")]
 impl<R: Read + Seek> OffsetFile<R> {
     fn new(mut r: R, base_offset: u64) -> std::io::Result<Self> {
         r.seek(SeekFrom::Start(base_offset))?;
@@ -24,12 +161,14 @@ impl<R: Read + Seek> OffsetFile<R> {
     }
 }
 
+#[cfg(feature = "fs")]
 impl<R: Read + Seek> Read for OffsetFile<R> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         self.reader.read(buf)
     }
 }
 
+#[cfg(feature = "fs")]
 impl<R: Read + Seek> Seek for OffsetFile<R> {
     fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
         let adjusted_pos = match pos {
@@ -42,123 +181,806 @@ impl<R: Read + Seek> Seek for OffsetFile<R> {
     }
 }
 
+/// A read position of its own in a file shared with other readers. Reads are positional, so
+/// readers on the same open file never move each other.
+#[cfg(feature = "fs")]
+struct SharedFile {
+    file: Arc<File>,
+    pos: u64,
+}
+
+#[cfg(feature = "fs")]
+impl SharedFile {
+    fn new(file: Arc<File>, pos: u64) -> Self {
+        Self { file, pos }
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Read for SharedFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = read_at(&self.file, buf, self.pos)?;
+        self.pos += n as u64;
+        Ok(n)
+    }
+}
+
+#[cfg(feature = "fs")]
+impl Seek for SharedFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => self.pos.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.file.metadata()?.len().checked_add_signed(offset),
+        };
+        self.pos = target.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "seek before the start of the file",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(all(feature = "fs", unix))]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+#[cfg(all(feature = "fs", windows))]
+fn read_at(file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+/// Without positional reads, the file's own cursor is moved: readers sharing the file must not
+/// read at the same time on these targets.
+#[cfg(all(feature = "fs", not(any(unix, windows))))]
+fn read_at(mut file: &File, buf: &mut [u8], offset: u64) -> std::io::Result<usize> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read(buf)
+}
+
+/// Counts the bytes read through it, so parse errors can say where they happened.
+struct CountingReader<R> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count += n as u64;
+        Ok(n)
+    }
+}
+
+/// A dictionary entry. `pos_id` is the left context id, used when the entry follows another
+/// token; `right_id` is used for whatever follows the entry. Version 1 dictionaries store a
+/// single id, in which case both are equal.
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DictEntry {
     pub surface: String,
+    /// Length of `surface` in chars.
+    pub char_len: u16,
     pub pos_id: u16,
+    pub right_id: u16,
     pub word_cost: i16,
-    pub reading_offset: u32,
-    pub reading_len: u8,
+    pub reading: EntryReading,
+    /// Where the dictionary form of the word lives, 食べる for 食べた, if the dictionary stores
+    /// lemmas (format version 9, converted with `--with-lemma`); read a stored one with
+    /// [`Dictionary::read_lemma_at`].
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub lemma: Option<EntryReading>,
+    /// How the word is pronounced, where the dictionary gives a pronunciation that differs from
+    /// the reading, such as ワ for the particle は. Only format versions 8 and up store one.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub pronunciation: Option<String>,
+    /// The accent nucleus: the mora after which the pitch falls, 0 for a word without a fall,
+    /// as UniDic's `aType`. Only format versions 8 and up store one.
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub accent: Option<u8>,
 }
 
-pub struct Dictionary<'a> {
-    decoder: Decoder<'a, OffsetFile<BufReader<File>>>,
+/// Where an entry's reading lives. With the `serde` feature it serializes as
+/// `{"stored":{"offset":..,"len":..}}` or `{"inline":".."}`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum EntryReading {
+    /// `len` bytes at `offset` into the dictionary's strings section.
+    Stored { offset: u32, len: u16 },
+    /// Held in memory, for entries added with [`Dictionary::add_entry`].
+    Inline(Box<str>),
+}
+
+/// What the analysis functions emit for an entry stored without a reading: an empty reading, or
+/// the literal `*` IPADIC uses for one and older converters kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingReading {
+    /// The entry's surface, as for an unknown word.
+    #[default]
+    Surface,
+    /// The stored reading as is.
+    Stored,
+}
+
+/// How the flat unknown-word fallback enters the path: the nodes over text no entry covers when
+/// the dictionary has no char.def data, or under [`AnalysisCompat::V1`]. Unknown nodes from
+/// char.def templates take their costs and ids from the templates instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViterbiConfig {
+    /// Cost of reaching an unknown node from any predecessor, on top of the connection cost.
+    pub unknown_cost: i32,
+    /// Left and right id unknown nodes connect through the matrix with, on both sides, reported
+    /// as their `pos_id`. `None` leaves the matrix out on either side of them.
+    pub unknown_pos_id: Option<u16>,
+}
+
+impl Default for ViterbiConfig {
+    fn default() -> Self {
+        ViterbiConfig {
+            unknown_cost: UNKNOWN_COST,
+            unknown_pos_id: Some(0),
+        }
+    }
+}
+
+fn is_missing_reading(reading: &str) -> bool {
+    reading.is_empty() || reading == "*"
+}
+
+/// The read-only core of a loaded dictionary: header, matrix, index, character definitions, POS
+/// names and a cache of decoded entry blocks shared by every handle. It is `Send + Sync`; put it
+/// in an [`Arc`] and give each thread its own [`Dictionary`] with [`Dictionary::with_data`].
+pub struct DictionaryData {
+    backing: Backing,
+    region_start: u64,
+    /// The entries and strings region is zstd-compressed; always set before format version 6.
+    compressed: bool,
+    /// The form the readings were stored in; katakana before format version 6.
+    reading_kana: KanaForm,
     strings_offset: u64,
     pub num_entries: usize,
     index: HashMap<char, (u64, usize)>,
-    entry_cache: HashMap<char, Vec<DictEntry>>,
-    matrix: Vec<i16>,
-    matrix_size: usize,
+    /// Start offsets of every entry block, sorted.
+    block_offsets: Vec<u64>,
+    /// Decoded entry blocks by first char, filled in by whichever handle reads them first.
+    entry_cache: Mutex<EntryCache>,
+    /// The whole strings region, once [`Dictionary::preload_all`] has run.
+    strings: OnceLock<Vec<u8>>,
+    /// Decompressed frames each handle keeps; see [`LoadOptions::frame_cache`].
+    frame_cache: usize,
+    matrix: Matrix,
+    left_size: usize,
+    right_size: usize,
+    version: u16,
+    /// xxh64 of everything after the header; stored from format version 7.
+    checksum: Option<u64>,
+    char_defs: Option<CharDefinitions>,
+    /// Flattened unknown-word templates of every category, in category order.
+    templates: Vec<UnknownTemplate>,
+    /// `(first, count)` into `templates`, per category.
+    template_ranges: Vec<(usize, usize)>,
+    /// Empty before format version 4.
+    pos_names: PosNames,
+}
+
+/// A per-thread handle on a [`DictionaryData`]: its own reader over the dictionary file, the
+/// entry blocks it has used, and its cost overrides. Creating one is cheap compared to loading
+/// the data; the analysis functions take `&mut Dictionary`.
+pub struct Dictionary<'a> {
+    data: Arc<DictionaryData>,
+    region: RegionReader<'a>,
+    /// Entry blocks by first char, with this handle's cost overrides applied.
+    entry_cache: EntryCache,
+    /// Word cost deltas by surface, then reading; applied as blocks enter `entry_cache`.
+    cost_overrides: HashMap<String, HashMap<String, i16>>,
+    /// Costs set or shifted for every reading of a surface, applied before `cost_overrides`.
+    surface_overrides: HashMap<String, SurfaceOverride>,
+    compat: AnalysisCompat,
+    /// Consulted alongside this dictionary by every lookup; see [`Self::set_user_dictionary`].
+    user: Option<Box<Dictionary<'a>>>,
+    /// Entries from [`Self::add_entry`] by first char, merged into blocks as they enter
+    /// `entry_cache`.
+    added: HashMap<char, Vec<DictEntry>>,
+    max_lattice_chars: usize,
+    missing_reading: MissingReading,
+    viterbi: ViterbiConfig,
+    /// The first failure to read entries or readings since [`Self::take_error`] last ran.
+    error: Option<MucabError>,
+    /// Set for the length of a [`transliterate_with_deadline`] or [`transliterate_cancellable`]
+    /// call.
+    stop: Option<Stop>,
 }
 
 #[derive(Debug, Clone)]
 struct LatticeNode {
     start_pos: usize,
     end_pos: usize,
-    entry_char: char,
-    entry_local_idx: usize,
+    kind: NodeKind,
     cost: i32,
     prev_node: Option<usize>,
 }
 
-impl<'a> Dictionary<'a> {
-    fn get_matrix_cost(&self, prev_id: u16, curr_id: u16) -> i16 {
-        let idx = (prev_id as usize) * self.matrix_size + (curr_id as usize);
-        self.matrix.get(idx).copied().unwrap_or(0)
+impl LatticeNode {
+    fn is_unknown(&self) -> bool {
+        matches!(self.kind, NodeKind::Unknown { .. })
     }
 
-    fn get_entry(&mut self, first_char: char, local_idx: usize) -> Option<&DictEntry> {
-        if !self.index.contains_key(&first_char) {
-            return None;
+    /// The entry comes from the user dictionary.
+    fn is_user(&self) -> bool {
+        matches!(self.kind, NodeKind::Dict { user: true, .. })
+    }
+
+    /// The unknown-word template behind an unknown node, `None` for the flat fallback.
+    fn template(&self) -> Option<usize> {
+        match self.kind {
+            NodeKind::Unknown { template } => template.map(|t| t as usize),
+            _ => None,
+        }
+    }
+}
+
+/// The whole dictionary file in memory: a mapping of it or bytes handed to
+/// [`DictionaryData::from_bytes`].
+type SharedBytes = Arc<dyn AsRef<[u8]> + Send + Sync>;
+
+/// Where a [`Dictionary`] handle reads the entries and strings region from.
+enum Backing {
+    /// The file the data was parsed from, kept open: every handle reads this file, even once
+    /// another has been written or renamed over its path.
+    #[cfg(feature = "fs")]
+    File(Arc<File>),
+    Memory(SharedBytes),
+}
+
+/// The entries and strings region of a dictionary in memory, as seen by a [`Cursor`].
+struct MemoryRegion {
+    bytes: SharedBytes,
+    start: usize,
+}
+
+impl AsRef<[u8]> for MemoryRegion {
+    fn as_ref(&self) -> &[u8] {
+        &(*self.bytes).as_ref()[self.start..]
+    }
+}
+
+/// The entries and strings region of the file or of the dictionary in memory.
+enum RegionSource {
+    #[cfg(feature = "fs")]
+    File(OffsetFile<BufReader<SharedFile>>),
+    Memory(std::io::Cursor<MemoryRegion>),
+}
+
+impl Read for RegionSource {
+    /// Running out of region is an error rather than a read of nothing: the zstd decoder keeps
+    /// asking for the rest of a frame, and would ask forever for one that was cut short.
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = match self {
+            #[cfg(feature = "fs")]
+            RegionSource::File(r) => r.read(buf)?,
+            RegionSource::Memory(r) => r.read(buf)?,
+        };
+        if read == 0 && !buf.is_empty() {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "entries and strings region ends early",
+            ));
         }
-        self.entry_cache.get(&first_char)?.get(local_idx)
+        Ok(read)
     }
+}
 
-    fn read_reading_at(&mut self, offset: u32, len: u8) -> String {
-        let start = self.strings_offset + offset as u64;
-        let end = start + len as u64;
-        self.decoder.set_offset(start).unwrap();
-        self.decoder.set_offset_limit(end).unwrap();
-        let mut reading_bytes = vec![0u8; len as usize];
-        self.decoder.read_exact(&mut reading_bytes).unwrap();
-        String::from_utf8(reading_bytes).unwrap()
+impl Seek for RegionSource {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match self {
+            #[cfg(feature = "fs")]
+            RegionSource::File(r) => r.seek(pos),
+            RegionSource::Memory(r) => r.seek(pos),
+        }
     }
+}
 
-    fn bulk_read_entries(&mut self, first_char: char) -> Vec<DictEntry> {
-        let (byte_offset, count) = *self.index.get(&first_char).unwrap();
-        let mut entries = Vec::with_capacity(count);
+/// Reads byte ranges of the entries and strings region, through the seekable zstd decoder
+/// unless the dictionary was written uncompressed.
+enum RegionReader<'a> {
+    #[cfg(feature = "zstd")]
+    Compressed(Decoder<'a, RegionSource>, FrameCache),
+    /// The marker holds the decoder's lifetime in builds without the decoder.
+    Raw(RegionSource, std::marker::PhantomData<&'a ()>),
+}
 
-        self.decoder.set_offset(byte_offset).unwrap();
+#[cfg(feature = "zstd")]
+fn zeekstd_error(e: impl std::fmt::Debug) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("zeekstd error: {:?}", e),
+    )
+}
 
-        for _ in 0..count {
-            let mut surf_len = 0u8;
-            self.decoder
-                .read_exact(std::slice::from_mut(&mut surf_len))
-                .unwrap();
-            let surf_len = surf_len as usize;
+#[cfg(feature = "zstd")]
+fn compressed_reader<'a>(
+    source: RegionSource,
+    frame_cache: usize,
+) -> std::io::Result<RegionReader<'a>> {
+    Ok(RegionReader::Compressed(
+        Decoder::new(source).map_err(zeekstd_error)?,
+        FrameCache::new(frame_cache),
+    ))
+}
 
-            let mut surf_bytes = vec![0u8; surf_len];
-            self.decoder.read_exact(&mut surf_bytes).unwrap();
+/// Compressed dictionaries cannot be read without the decoder; convert them with compression
+/// off instead.
+#[cfg(not(feature = "zstd"))]
+fn compressed_reader<'a>(
+    _source: RegionSource,
+    _frame_cache: usize,
+) -> std::io::Result<RegionReader<'a>> {
+    Err(MucabError::MissingFeature("zstd").into())
+}
 
-            let mut entry_buf = [0u8; ENTRY_METADATA_SIZE];
-            self.decoder.read_exact(&mut entry_buf).unwrap();
+/// Frames larger than this are never cached whole, whatever the seek table claims; reads in
+/// them decompress just the range asked for.
+#[cfg(feature = "zstd")]
+const MAX_CACHED_FRAME_BYTES: u64 = 1024 * 1024;
 
-            let read_off =
-                u32::from_le_bytes([entry_buf[0], entry_buf[1], entry_buf[2], entry_buf[3]]);
-            let read_len = entry_buf[4];
-            let pos_id = u16::from_le_bytes([entry_buf[5], entry_buf[6]]);
-            let cost = i16::from_le_bytes([entry_buf[7], entry_buf[8]]);
+/// Decompresses `range` of the region, which the caller has checked is inside it.
+#[cfg(feature = "zstd")]
+fn decompress_range(
+    decoder: &mut Decoder<RegionSource>,
+    range: std::ops::Range<u64>,
+) -> std::io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; (range.end - range.start) as usize];
+    // The explicit limit replaces whatever the previous read left behind.
+    decoder.set_offset(range.start).map_err(zeekstd_error)?;
+    decoder.set_offset_limit(range.end).map_err(zeekstd_error)?;
+    decoder.read_exact(&mut buf)?;
+    Ok(buf)
+}
 
-            entries.push(DictEntry {
-                surface: String::from_utf8(surf_bytes).unwrap(),
-                pos_id,
-                word_cost: cost,
-                reading_offset: read_off,
-                reading_len: read_len,
-            });
+/// Bounds of the frames `range` spans, if they are few enough for `frames` to hold and small
+/// enough to be worth caching.
+#[cfg(feature = "zstd")]
+fn cached_frames(
+    decoder: &Decoder<RegionSource>,
+    frames: &FrameCache,
+    range: &std::ops::Range<u64>,
+) -> Option<Vec<(u32, std::ops::Range<u64>)>> {
+    let table = decoder.seek_table();
+    if range.is_empty() || range.end > table.size_decomp() {
+        return None;
+    }
+    let (first, last) = (
+        table.frame_index_decomp(range.start),
+        table.frame_index_decomp(range.end - 1),
+    );
+    if (last - first) as usize >= frames.capacity() {
+        return None;
+    }
+    (first..=last)
+        .map(|index| {
+            let start = table.frame_start_decomp(index).ok()?;
+            let end = table.frame_end_decomp(index).ok()?;
+            (end - start <= MAX_CACHED_FRAME_BYTES).then_some((index, start..end))
+        })
+        .collect()
+}
+
+impl RegionReader<'_> {
+    fn read_range(&mut self, range: std::ops::Range<u64>) -> std::io::Result<Vec<u8>> {
+        let mut buf = vec![0u8; (range.end - range.start) as usize];
+        match self {
+            #[cfg(feature = "zstd")]
+            RegionReader::Compressed(decoder, frames) => {
+                let Some(spans) = cached_frames(decoder, frames, &range) else {
+                    return decompress_range(decoder, range);
+                };
+                for (index, span) in spans {
+                    let frame = frames.get_or_insert_with(index, || {
+                        decompress_range(decoder, span.clone()).map(Vec::into_boxed_slice)
+                    })?;
+                    let (from, to) = (range.start.max(span.start), range.end.min(span.end));
+                    let bytes = frame
+                        .get((from - span.start) as usize..(to - span.start) as usize)
+                        .ok_or_else(|| {
+                            std::io::Error::new(
+                                std::io::ErrorKind::InvalidData,
+                                "frame is shorter than the seek table says",
+                            )
+                        })?;
+                    buf[(from - range.start) as usize..(to - range.start) as usize]
+                        .copy_from_slice(bytes);
+                }
+            }
+            RegionReader::Raw(source, _) => {
+                source.seek(SeekFrom::Start(range.start))?;
+                source.read_exact(&mut buf)?;
+            }
         }
+        Ok(buf)
+    }
 
-        entries
+    /// Size of the region, decompressed.
+    fn len(&mut self) -> std::io::Result<u64> {
+        match self {
+            #[cfg(feature = "zstd")]
+            RegionReader::Compressed(decoder, _) => Ok(decoder.seek_table().size_decomp()),
+            RegionReader::Raw(source, _) => source.seek(SeekFrom::End(0)),
+        }
+    }
+}
+
+/// How [`DictionaryData::load_with_options`] checks the file, and how its handles read it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoadOptions {
+    /// Hash the whole file and compare it with the header checksum before returning, as
+    /// [`DictionaryData::verify_checksum`] does. Off by default, since it reads every byte.
+    pub verify_checksum: bool,
+    /// Decompressed zstd frames each handle keeps, [`DEFAULT_FRAME_CACHE`] by default. Reads
+    /// inside a kept frame are copied out of it instead of decompressing the frame up to them
+    /// again; 0 decompresses every read. Unused for uncompressed dictionaries.
+    pub frame_cache: usize,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            verify_checksum: false,
+            frame_cache: DEFAULT_FRAME_CACHE,
+        }
+    }
+}
+
+/// Frames each handle keeps unless [`LoadOptions::frame_cache`] says otherwise: a megabyte of
+/// frames as the converter writes them by default.
+pub const DEFAULT_FRAME_CACHE: usize = 8;
+
+/// What [`DictionaryData::load_with_progress`] is reading when it reports progress. The char
+/// definitions and POS names, short as they are, count towards the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadPhase {
+    Header,
+    /// The connection matrix, reported every [`LOAD_PROGRESS_STEP`] bytes.
+    Matrix,
+    /// The index, reported about every [`LOAD_PROGRESS_STEP`] bytes of it.
+    Index,
+    /// The sections were checked against each other and the dictionary is usable.
+    Ready,
+}
+
+/// Bytes read between two progress reports of [`DictionaryData::load_with_progress`].
+pub const LOAD_PROGRESS_STEP: usize = 64 * 1024;
+
+/// The callback of [`DictionaryData::load_with_progress`].
+type Progress<'p> = &'p mut dyn FnMut(LoadPhase, f32);
+
+impl DictionaryData {
+    /// Loads with [`LoadOptions::default`]: the header, matrix and index are parsed and checked
+    /// against each other, but the entries and strings are only read when used.
+    #[cfg(feature = "fs")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MucabError> {
+        Self::load_with_options(path, LoadOptions::default())
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn load_with_options<P: AsRef<Path>>(
+        path: P,
+        options: LoadOptions,
+    ) -> Result<Self, MucabError> {
+        Self::load_file(path.as_ref(), options, &mut |_, _| {})
+    }
+
+    /// Like [`Self::load`], calling `progress` with the phase it is in and how far into that
+    /// phase it is, from 0.0 to 1.0, for front ends to show while a large dictionary loads.
+    /// Every phase is reported at 0.0 when it starts and at 1.0 when it ends, [`LoadPhase::Ready`]
+    /// only at 1.0, after which the data is returned.
+    #[cfg(feature = "fs")]
+    pub fn load_with_progress<P: AsRef<Path>>(
+        path: P,
+        mut progress: impl FnMut(LoadPhase, f32),
+    ) -> Result<Self, MucabError> {
+        Self::load_file(path.as_ref(), LoadOptions::default(), &mut progress)
+    }
+
+    #[cfg(feature = "fs")]
+    #[cfg_attr(
+        feature = "trace",
+        tracing::instrument(level = "debug", skip_all, fields(path = %path.display()))
+    )]
+    fn load_file(
+        path: &Path,
+        options: LoadOptions,
+        progress: Progress,
+    ) -> Result<Self, MucabError> {
+        let opened = Arc::new(File::open(path)?);
+        let mut file = BufReader::new(SharedFile::new(opened.clone(), 0));
+        let backing = Backing::File(opened);
+        let mut data = Self::parse(&mut file, backing, None, progress)?;
+        data.region_start = file.stream_position()?;
+        data.frame_cache = options.frame_cache;
+        data.check_structure()?;
+        if options.verify_checksum {
+            data.verify_checksum()?;
+        }
+        progress(LoadPhase::Ready, 1.0);
+        Ok(data)
+    }
+
+    /// Like [`Self::load`], but maps the file into memory: the header, matrix and index are
+    /// parsed straight from the mapping, and every handle reads from it without reopening the
+    /// file. The file must not be modified while the data is alive.
+    #[cfg(feature = "mmap")]
+    pub fn load_mmap<P: AsRef<Path>>(path: P) -> Result<Self, MucabError> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        // SAFETY: the mapping is read-only, and callers are told not to modify the file while
+        // it is in use.
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::from_memory(Arc::new(map), None)
+    }
+
+    /// Like [`Self::load`], for a dictionary file already read into memory, as where there is
+    /// no filesystem to load from. Every handle reads from `bytes`; call
+    /// [`Self::verify_checksum`] to check them against the header.
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, MucabError> {
+        Self::from_memory(Arc::new(bytes), None)
+    }
+
+    /// Parses the file in `bytes`. With `static_bytes`, the same file for the life of the
+    /// program, the matrix is borrowed from it where [`static_matrix`] allows.
+    fn from_memory(
+        bytes: SharedBytes,
+        static_bytes: Option<&'static [u8]>,
+    ) -> Result<Self, MucabError> {
+        let all: &[u8] = (*bytes).as_ref();
+        let mut rest = all;
+        let backing = Backing::Memory(bytes.clone());
+        let mut data = Self::parse(&mut rest, backing, static_bytes, &mut |_, _| {})?;
+        data.region_start = (all.len() - rest.len()) as u64;
+        data.check_structure()?;
+        Ok(data)
+    }
+
+    /// Opens a reader over the entries and strings region, with a read position of its own.
+    fn open_region<'a>(&self) -> std::io::Result<RegionReader<'a>> {
+        let source = match &self.backing {
+            #[cfg(feature = "fs")]
+            Backing::File(file) => {
+                let file = BufReader::new(SharedFile::new(file.clone(), 0));
+                RegionSource::File(OffsetFile::new(file, self.region_start)?)
+            }
+            Backing::Memory(bytes) => RegionSource::Memory(std::io::Cursor::new(MemoryRegion {
+                bytes: bytes.clone(),
+                start: self.region_start as usize,
+            })),
+        };
+        if self.compressed {
+            compressed_reader(source, self.frame_cache)
+        } else {
+            Ok(RegionReader::Raw(source, std::marker::PhantomData))
+        }
+    }
+
+    /// Size of the whole dictionary file.
+    fn file_len(&self) -> std::io::Result<u64> {
+        match &self.backing {
+            #[cfg(feature = "fs")]
+            Backing::File(file) => Ok(file.metadata()?.len()),
+            Backing::Memory(bytes) => Ok((**bytes).as_ref().len() as u64),
+        }
+    }
+
+    /// The checks that need no more than the header, the index and the region's size: a file
+    /// cut short in the region or an index pointing past the entries fails here rather than at
+    /// some later lookup.
+    fn check_structure(&self) -> Result<(), MucabError> {
+        let region_len = self
+            .open_region()
+            .and_then(|mut region| region.len())
+            .map_err(|e| match MucabError::from(e) {
+                MucabError::Corrupt(message) => MucabError::Corrupt(format!(
+                    "the entries and strings region at byte {} cannot be read, the file may be \
+                     truncated ({})",
+                    self.region_start, message
+                )),
+                io => io,
+            })?;
+        if self.strings_offset > region_len {
+            return Err(MucabError::Corrupt(format!(
+                "strings offset {} is past the end of the entries and strings ({} bytes)",
+                self.strings_offset, region_len
+            )));
+        }
+        if let Some((first_char, (offset, _))) = self
+            .index
+            .iter()
+            .find(|(_, &(offset, _))| offset >= self.strings_offset)
+        {
+            return Err(MucabError::Corrupt(format!(
+                "index block {:?} starts at byte {}, past the entries ({} bytes)",
+                first_char, offset, self.strings_offset
+            )));
+        }
+        for (idx, template) in self.templates.iter().enumerate() {
+            self.check_context_ids(
+                || format!("unknown-word template {}", idx),
+                template.left_id,
+                template.right_id,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// The version of the format the file is in, one of [`SUPPORTED_FORMAT_VERSIONS`].
+    pub fn format_version(&self) -> u16 {
+        self.version
+    }
+
+    /// The checksum stored in the header, from format version 7.
+    pub fn checksum(&self) -> Option<u64> {
+        self.checksum
+    }
+
+    /// The form readings and pronunciations are stored in: katakana, as the sources have them,
+    /// unless the builder converted them (see [`builder::DictionaryBuilder::reading_kana`]).
+    pub fn reading_kana(&self) -> KanaForm {
+        self.reading_kana
+    }
+
+    /// xxh64 of everything in the file after the header.
+    fn contents_checksum(&self) -> std::io::Result<u64> {
+        let header_size = header_size(self.version);
+        let mut hasher = Xxh64::new(0);
+        match &self.backing {
+            #[cfg(feature = "fs")]
+            Backing::File(file) => {
+                let mut file = SharedFile::new(file.clone(), header_size as u64);
+                let mut buf = vec![0u8; 64 * 1024];
+                loop {
+                    let n = file.read(&mut buf)?;
+                    if n == 0 {
+                        break;
+                    }
+                    hasher.update(&buf[..n]);
+                }
+            }
+            Backing::Memory(bytes) => hasher.update(&(**bytes).as_ref()[header_size..]),
+        }
+        Ok(hasher.digest())
+    }
+
+    /// Reads the whole file and compares it with the header checksum, failing with
+    /// [`MucabError::Corrupt`] if they differ. Dictionaries older than format version 7 have no
+    /// checksum and always pass.
+    pub fn verify_checksum(&self) -> Result<(), MucabError> {
+        let Some(expected) = self.checksum else {
+            return Ok(());
+        };
+        let actual = self.contents_checksum()?;
+        if actual != expected {
+            return Err(MucabError::Corrupt(format!(
+                "checksum mismatch: the header says {:016x}, the contents hash to {:016x}",
+                expected, actual
+            )));
+        }
+        Ok(())
     }
 
-    pub fn load(path: &str) -> std::io::Result<Self> {
-        let mut file = BufReader::new(File::open(path)?);
+    /// Parses everything before the entries and strings region. `region_start` is left for the
+    /// caller to fill in from wherever `file` stopped. A file that ends early fails with
+    /// [`std::io::ErrorKind::UnexpectedEof`], saying where.
+    fn parse<R: Read>(
+        file: &mut R,
+        backing: Backing,
+        static_bytes: Option<&'static [u8]>,
+        progress: Progress,
+    ) -> std::io::Result<Self> {
+        let mut file = CountingReader {
+            inner: file,
+            count: 0,
+        };
+        let parsed = Self::parse_sections(&mut file, backing, static_bytes, progress);
+        parsed.map_err(|e| match e.kind() {
+            std::io::ErrorKind::UnexpectedEof => std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                format!("dictionary truncated at byte {}", file.count),
+            ),
+            _ => e,
+        })
+    }
 
+    fn parse_sections<R: Read>(
+        file: &mut CountingReader<R>,
+        backing: Backing,
+        static_bytes: Option<&'static [u8]>,
+        progress: Progress,
+    ) -> std::io::Result<Self> {
+        progress(LoadPhase::Header, 0.0);
         let mut header = [0u8; HEADER_SIZE];
-        file.read_exact(&mut header)?;
+        file.read_exact(&mut header[..HEADER_SIZE_V1])?;
 
         if &header[0..4] != b"MUCA" {
             return Err(std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
-                "Invalid magic number",
+                "not a mucab dictionary (bad magic)",
             ));
         }
 
-        let matrix_size = u16::from_le_bytes([header[6], header[7]]) as usize;
+        let version = u16::from_le_bytes([header[4], header[5]]);
+        if !SUPPORTED_FORMAT_VERSIONS.contains(&version) {
+            return Err(MucabError::UnsupportedVersion {
+                found: version,
+                supported: SUPPORTED_FORMAT_VERSIONS,
+            }
+            .into());
+        }
+
+        let left_size = u16::from_le_bytes([header[6], header[7]]) as usize;
         let num_entries =
             u32::from_le_bytes([header[8], header[9], header[10], header[11]]) as usize;
         let strings_offset =
             u32::from_le_bytes([header[12], header[13], header[14], header[15]]) as u64;
+        let right_size = if version == 1 {
+            left_size
+        } else {
+            file.read_exact(&mut header[HEADER_SIZE_V1..HEADER_SIZE_V2])?;
+            u16::from_le_bytes([header[16], header[17]]) as usize
+        };
+        let flags = if version >= 6 {
+            file.read_exact(&mut header[HEADER_SIZE_V2..HEADER_SIZE_V6])?;
+            u16::from_le_bytes([header[18], header[19]])
+        } else {
+            0
+        };
+        let checksum = if version >= 7 {
+            file.read_exact(&mut header[HEADER_SIZE_V6..HEADER_SIZE])?;
+            Some(u64::from_le_bytes(header[20..28].try_into().unwrap()))
+        } else {
+            None
+        };
 
-        // Read matrix
-        let matrix_elements = matrix_size * matrix_size;
-        let mut matrix_bytes = vec![0u8; matrix_elements * 2];
-        file.read_exact(&mut matrix_bytes)?;
+        progress(LoadPhase::Header, 1.0);
 
-        let mut matrix = vec![0i16; matrix_elements];
-        for i in 0..matrix_elements {
-            matrix[i] = i16::from_le_bytes([matrix_bytes[i * 2], matrix_bytes[i * 2 + 1]]);
-        }
+        // Read matrix: one row per right id of the previous token
+        progress(LoadPhase::Matrix, 0.0);
+        let matrix_elements = left_size * right_size;
+        let borrowed =
+            static_bytes.and_then(|bytes| static_matrix(bytes, file.count, matrix_elements));
+        let matrix = match borrowed {
+            Some(cells) => {
+                let skipped = std::io::copy(
+                    &mut file.by_ref().take(matrix_elements as u64 * 2),
+                    &mut std::io::sink(),
+                )?;
+                debug_assert_eq!(skipped, matrix_elements as u64 * 2);
+                Matrix::Borrowed(cells)
+            }
+            None => {
+                let mut matrix_bytes = vec![0u8; matrix_elements * 2];
+                let (total, mut done) = (matrix_bytes.len(), 0);
+                for chunk in matrix_bytes.chunks_mut(LOAD_PROGRESS_STEP) {
+                    file.read_exact(chunk)?;
+                    done += chunk.len();
+                    if done < total {
+                        progress(LoadPhase::Matrix, done as f32 / total as f32);
+                    }
+                }
+                Matrix::Owned(decode_matrix(&matrix_bytes))
+            }
+        };
+        progress(LoadPhase::Matrix, 1.0);
 
         // Read index immediately after matrix (no seek needed)
         let mut index_count_buf = [0u8; 4];
@@ -167,10 +989,24 @@ impl<'a> Dictionary<'a> {
 
         let mut index: HashMap<char, (u64, usize)> = HashMap::new();
 
-        for _ in 0..num_index_keys {
+        progress(LoadPhase::Index, 0.0);
+        let keys_per_step = LOAD_PROGRESS_STEP / INDEX_ENTRY_SIZE;
+        for key in 0..num_index_keys {
+            if key > 0 && key % keys_per_step == 0 {
+                progress(LoadPhase::Index, key as f32 / num_index_keys as f32);
+            }
             let mut char_buf = [0u8; 4];
             file.read_exact(&mut char_buf)?;
-            let ch = char::from_u32(u32::from_le_bytes(char_buf)).unwrap();
+            let ch = char::from_u32(u32::from_le_bytes(char_buf)).ok_or_else(|| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!(
+                        "index key at byte {} is not a character ({:#x})",
+                        file.count - 4,
+                        u32::from_le_bytes(char_buf)
+                    ),
+                )
+            })?;
 
             let mut offset_buf = [0u8; 4];
             file.read_exact(&mut offset_buf)?;
@@ -182,224 +1018,4595 @@ impl<'a> Dictionary<'a> {
 
             index.insert(ch, (byte_offset, count));
         }
+        let mut block_offsets: Vec<u64> = index.values().map(|&(offset, _)| offset).collect();
+        block_offsets.sort_unstable();
 
-        let compressed_start = file.stream_position()?;
-        let offset_file = OffsetFile::new(file, compressed_start)?;
-        let decoder = Decoder::new(offset_file).map_err(|e| {
-            std::io::Error::new(
-                std::io::ErrorKind::InvalidData,
-                format!("zeekstd error: {:?}", e),
-            )
-        })?;
+        let char_defs = if version >= 3 {
+            CharDefinitions::read_from(file)?
+        } else {
+            None
+        };
+        let mut templates = Vec::new();
+        let mut template_ranges = Vec::new();
+        for category in char_defs.iter().flat_map(|d| &d.categories) {
+            template_ranges.push((templates.len(), category.templates.len()));
+            templates.extend_from_slice(&category.templates);
+        }
+        let pos_names = if version >= 4 {
+            PosNames::read_from(file)?
+        } else {
+            PosNames::default()
+        };
+        progress(LoadPhase::Index, 1.0);
 
-        Ok(Dictionary {
-            decoder,
+        Ok(DictionaryData {
+            backing,
+            region_start: 0,
+            compressed: flags & FLAG_UNCOMPRESSED == 0,
+            reading_kana: reading_kana_from_flags(flags),
             strings_offset,
             num_entries,
             index,
-            entry_cache: HashMap::new(),
+            block_offsets,
+            entry_cache: Mutex::new(EntryCache::new(CacheLimit::Unbounded)),
+            strings: OnceLock::new(),
+            frame_cache: DEFAULT_FRAME_CACHE,
             matrix,
-            matrix_size,
+            left_size,
+            right_size,
+            version,
+            checksum,
+            char_defs,
+            templates,
+            template_ranges,
+            pos_names,
         })
     }
 
-    fn lookup(&mut self, text: &str, start: usize) -> Vec<(char, usize)> {
-        let chars: Vec<char> = text.chars().collect();
-        if start >= chars.len() {
-            return Vec::with_capacity(DEFAULT_CAPACITY);
-        }
+    /// Bounds the shared block cache. Handles opened afterwards start with the same limit for
+    /// their own caches.
+    pub fn set_cache_limit(&self, limit: CacheLimit) {
+        self.entry_cache.lock().unwrap().set_limit(limit);
+    }
 
-        let first_char = chars[start];
-        let mut matches = Vec::with_capacity(DEFAULT_CAPACITY);
+    /// Statistics of the shared block cache; a miss here is a block decoded from the file.
+    pub fn cache_stats(&self) -> CacheStats {
+        self.entry_cache.lock().unwrap().stats()
+    }
 
-        if !self.index.contains_key(&first_char) {
-            return matches;
+    /// Cost of connecting a node with right id `prev_id` to one with left id `curr_id`, or `None`
+    /// when either id is outside the matrix.
+    pub fn connection_cost(&self, prev_id: u16, curr_id: u16) -> Option<i16> {
+        if prev_id as usize >= self.right_size || curr_id as usize >= self.left_size {
+            return None;
         }
+        let idx = (prev_id as usize) * self.left_size + (curr_id as usize);
+        self.matrix.get(idx).copied()
+    }
 
-        if !self.entry_cache.contains_key(&first_char) {
-            let entries = self.bulk_read_entries(first_char);
-            self.entry_cache.insert(first_char, entries);
-        }
+    /// Number of cells in the connection matrix: [`Self::pos_count`] left ids by as many right
+    /// ids as `matrix_size() / pos_count()`. Version 1 matrices are square.
+    pub fn matrix_size(&self) -> usize {
+        self.matrix.len()
+    }
 
-        let cached_entries = &self.entry_cache[&first_char];
+    /// Number of left context ids, the ids entries' `pos_id` is drawn from.
+    pub fn pos_count(&self) -> usize {
+        self.left_size
+    }
 
-        for (i, entry) in cached_entries.iter().enumerate() {
-            let entry_chars: Vec<char> = entry.surface.chars().collect();
+    /// The non-zero cells of the connection matrix as `(prev_id, curr_id, cost)`, in the order
+    /// [`Self::connection_cost`] reads them: by right id of the previous node, then left id.
+    pub fn connection_costs(&self) -> impl Iterator<Item = (u16, u16, i16)> + '_ {
+        let left_size = self.left_size.max(1);
+        self.matrix
+            .iter()
+            .enumerate()
+            .filter(|&(_, &cost)| cost != 0)
+            .map(move |(idx, &cost)| ((idx / left_size) as u16, (idx % left_size) as u16, cost))
+    }
 
-            if start + entry_chars.len() <= chars.len() {
-                let matches_surface = entry_chars
-                    .iter()
-                    .enumerate()
-                    .all(|(j, &c)| chars[start + j] == c);
+    /// The part-of-speech features of entries with left id `pos_id` (see [`pos`]), or `None` for
+    /// ids without a name and for dictionaries older than format version 4.
+    pub fn pos_name(&self, pos_id: u16) -> Option<&str> {
+        self.pos_names.get(pos_id)
+    }
 
-                if matches_surface {
-                    matches.push((first_char, i));
-                }
-            }
-        }
+    /// Character categories and unknown-word templates, for dictionaries that store them.
+    pub fn char_definitions(&self) -> Option<&CharDefinitions> {
+        self.char_defs.as_ref()
+    }
 
-        matches
+    /// Whether a node with right id `right_id` and left id `left_id` can take part in a lattice
+    /// scored by this dictionary's matrix. Without a matrix every connection is free.
+    fn connects(&self, left_id: u16, right_id: u16) -> bool {
+        self.matrix.is_empty()
+            || ((left_id as usize) < self.left_size && (right_id as usize) < self.right_size)
     }
-}
 
-fn build_lattice<'a>(text: &str, dict: &mut Dictionary<'a>) -> (Lattice, Vec<char>) {
-    let chars: Vec<char> = text.chars().collect();
-    let len = chars.len();
-    let mut lattice = vec![Vec::with_capacity(DEFAULT_CAPACITY); len + 1];
+    /// Fails unless [`Self::connects`] holds for these ids, naming what has them with `what`.
+    fn check_context_ids(
+        &self,
+        what: impl FnOnce() -> String,
+        left_id: u16,
+        right_id: u16,
+    ) -> Result<(), MucabError> {
+        if self.connects(left_id, right_id) {
+            return Ok(());
+        }
+        Err(MucabError::Corrupt(format!(
+            "{} has context ids {}/{}, outside the {}x{} connection matrix",
+            what(),
+            left_id,
+            right_id,
+            self.right_size,
+            self.left_size
+        )))
+    }
 
-    for start in 0..len {
-        let matches = dict.lookup(text, start);
-        for (entry_char, entry_local_idx) in matches {
-            if let Some(entry) = dict.get_entry(entry_char, entry_local_idx) {
-                let end = start + entry.surface.chars().count();
-                lattice[end].push(((entry_char, entry_local_idx), start));
-            }
+    /// [`Self::connection_cost`] for the lattice, where a dictionary without a matrix connects
+    /// everything for free.
+    ///
+    /// # Panics
+    ///
+    /// If the matrix exists and either id is outside it, which the checks on templates at load,
+    /// on the unknown-word fallback and on entries joining the lattice rule out.
+    fn get_matrix_cost(&self, prev_right_id: u16, curr_left_id: u16) -> i16 {
+        if self.matrix.is_empty() {
+            return 0;
+        }
+        match self.connection_cost(prev_right_id, curr_left_id) {
+            Some(cost) => cost,
+            None => panic!(
+                "connection {} -> {} is outside the {}x{} connection matrix",
+                prev_right_id, curr_left_id, self.right_size, self.left_size
+            ),
         }
     }
+}
 
-    (lattice, chars)
+/// Where [`Dictionary::search_paths`] looks, given a way to read environment variables.
+#[cfg(feature = "fs")]
+fn dictionary_search_paths(var: impl Fn(&str) -> Option<std::ffi::OsString>) -> Vec<PathBuf> {
+    let var = |name: &str| {
+        var(name)
+            .filter(|value| !value.is_empty())
+            .map(PathBuf::from)
+    };
+    let mut paths = Vec::new();
+    paths.extend(var("MUCAB_DICT"));
+    let data_dir = if cfg!(windows) {
+        var("APPDATA")
+    } else {
+        var("XDG_DATA_HOME").or_else(|| var("HOME").map(|home| home.join(".local/share")))
+    };
+    paths.extend(data_dir.map(|dir| dir.join("mucab").join("mucab.bin")));
+    paths
 }
 
-pub fn transliterate<'a>(text: &str, dict: &mut Dictionary<'a>) -> String {
-    if text.is_empty() {
-        return String::new();
+impl<'a> Dictionary<'a> {
+    /// Loads through [`DictionaryData::load`].
+    #[cfg(feature = "fs")]
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, MucabError> {
+        Ok(Self::with_data(Arc::new(DictionaryData::load(path)?))?)
     }
 
-    let (lattice, chars) = build_lattice(text, dict);
-    let len = chars.len();
+    /// Loads through [`DictionaryData::load_with_progress`].
+    #[cfg(feature = "fs")]
+    pub fn load_with_progress<P: AsRef<Path>>(
+        path: P,
+        progress: impl FnMut(LoadPhase, f32),
+    ) -> Result<Self, MucabError> {
+        let data = DictionaryData::load_with_progress(path, progress)?;
+        Ok(Self::with_data(Arc::new(data))?)
+    }
 
-    let mut nodes: Vec<Vec<LatticeNode>> = vec![Vec::with_capacity(DEFAULT_CAPACITY); len + 1];
-    let bos_node = LatticeNode {
-        start_pos: 0,
-        end_pos: 0,
-        entry_char: '\0',
-        entry_local_idx: 0,
-        cost: 0,
-        prev_node: None,
-    };
-    nodes[0].push(bos_node);
+    /// Loads through [`DictionaryData::load_with_options`].
+    #[cfg(feature = "fs")]
+    pub fn load_with_options<P: AsRef<Path>>(
+        path: P,
+        options: LoadOptions,
+    ) -> Result<Self, MucabError> {
+        let data = DictionaryData::load_with_options(path, options)?;
+        Ok(Self::with_data(Arc::new(data))?)
+    }
 
-    for pos in 1..=len {
-        if lattice[pos].is_empty() {
-            if !nodes[pos - 1].is_empty() {
-                let prev_nodes: Vec<_> = nodes[pos - 1].iter().cloned().enumerate().collect();
-                for (prev_idx, prev_node) in prev_nodes {
-                    nodes[pos].push(LatticeNode {
-                        start_pos: pos - 1,
-                        end_pos: pos,
-                        entry_char: '\0',
-                        entry_local_idx: 0,
-                        cost: prev_node.cost + 10000,
-                        prev_node: Some(prev_idx),
-                    });
-                }
-            }
-            continue;
-        }
+    /// Where a dictionary is looked for when none is named, in order: `$MUCAB_DICT`, then
+    /// `mucab/mucab.bin` in the platform's data directory (`$XDG_DATA_HOME`, falling back to
+    /// `~/.local/share`, or `%APPDATA%` on Windows).
+    #[cfg(feature = "fs")]
+    pub fn search_paths() -> Vec<PathBuf> {
+        dictionary_search_paths(|name| std::env::var_os(name))
+    }
 
-        for &((entry_char, entry_local_idx), start_pos) in &lattice[pos] {
-            if nodes[start_pos].is_empty() {
-                continue;
-            }
+    /// The first of [`Self::search_paths`] that is a file.
+    #[cfg(feature = "fs")]
+    pub fn default_path() -> Option<PathBuf> {
+        Self::search_paths().into_iter().find(|path| path.is_file())
+    }
 
-            let Some(entry) = dict.get_entry(entry_char, entry_local_idx) else {
-                continue;
-            };
-            let entry_pos_id = entry.pos_id;
-            let entry_word_cost = entry.word_cost;
-            let mut best_cost = i32::MAX;
-            let mut best_prev = None;
+    /// Loads through [`DictionaryData::load_mmap`].
+    #[cfg(feature = "mmap")]
+    pub fn load_mmap<P: AsRef<Path>>(path: P) -> Result<Self, MucabError> {
+        Ok(Self::with_data(Arc::new(DictionaryData::load_mmap(path)?))?)
+    }
 
-            for (prev_idx, prev_node) in nodes[start_pos].iter().enumerate() {
-                let prev_pos_id = if start_pos == 0 || prev_node.entry_char == '\0' {
-                    0
-                } else {
-                    dict.get_entry(prev_node.entry_char, prev_node.entry_local_idx)
-                        .map(|e| e.pos_id)
-                        .unwrap_or(0)
-                };
+    /// Loads through [`DictionaryData::from_bytes`].
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, MucabError> {
+        Ok(Self::with_data(Arc::new(DictionaryData::from_bytes(
+            bytes,
+        )?))?)
+    }
+
+    /// Opens a new handle on already loaded data, with a reader of its own on the data's open
+    /// dictionary file or its bytes in memory.
+    pub fn with_data(data: Arc<DictionaryData>) -> std::io::Result<Self> {
+        let region = data.open_region()?;
+        let limit = data.entry_cache.lock().unwrap().limit();
+        Ok(Dictionary {
+            data,
+            region,
+            entry_cache: EntryCache::new(limit),
+            cost_overrides: HashMap::new(),
+            surface_overrides: HashMap::new(),
+            compat: AnalysisCompat::default(),
+            user: None,
+            added: HashMap::new(),
+            max_lattice_chars: DEFAULT_MAX_LATTICE_CHARS,
+            missing_reading: MissingReading::default(),
+            viterbi: ViterbiConfig::default(),
+            error: None,
+            stop: None,
+        })
+    }
 
-                let conn_cost = dict.get_matrix_cost(prev_pos_id, entry_pos_id) as i32;
-                let total_cost = prev_node.cost + entry_word_cost as i32 + conn_cost;
+    /// A new handle on the same data, with this handle's settings, cost overrides, added
+    /// entries and user dictionary, for another thread. It decodes blocks again as it needs
+    /// them unless the shared cache holds them; it does not parse the file again.
+    ///
+    /// Its reader shares the open dictionary file of the data, reading it at a position of its
+    /// own, so it reads the file the index was parsed from even if another has been written over
+    /// its path since.
+    pub fn try_clone(&self) -> Result<Dictionary<'a>, MucabError> {
+        let user = match &self.user {
+            Some(user) => Some(Box::new(user.try_clone()?)),
+            None => None,
+        };
+        Ok(Dictionary {
+            data: self.data.clone(),
+            region: self.data.open_region()?,
+            entry_cache: EntryCache::new(self.entry_cache.limit()),
+            cost_overrides: self.cost_overrides.clone(),
+            surface_overrides: self.surface_overrides.clone(),
+            compat: self.compat,
+            user,
+            added: self.added.clone(),
+            max_lattice_chars: self.max_lattice_chars,
+            missing_reading: self.missing_reading,
+            viterbi: self.viterbi,
+            error: None,
+            stop: None,
+        })
+    }
 
-                if total_cost < best_cost {
-                    best_cost = total_cost;
-                    best_prev = Some(prev_idx);
-                }
-            }
+    /// Switches this handle to the dictionary at `path`, as [`Self::replace_data`] does, once it
+    /// has loaded and passed [`DictionaryData::verify_checksum`]. The new data starts with this
+    /// handle's cache limit. On any failure the handle keeps its old data.
+    #[cfg(feature = "fs")]
+    pub fn reload<P: AsRef<Path>>(&mut self, path: P) -> Result<(), MucabError> {
+        let options = LoadOptions {
+            verify_checksum: true,
+            ..LoadOptions::default()
+        };
+        let data = DictionaryData::load_with_options(path, options)?;
+        data.set_cache_limit(self.entry_cache.limit());
+        self.replace_data(Arc::new(data))
+    }
 
-            if best_prev.is_some() {
-                nodes[pos].push(LatticeNode {
-                    start_pos,
-                    end_pos: pos,
-                    entry_char,
-                    entry_local_idx,
-                    cost: best_cost,
-                    prev_node: best_prev,
-                });
-            }
+    /// Switches this handle to `data`, dropping the blocks it decoded from the old data; its
+    /// settings, cost overrides, added entries and user dictionary carry over. Other handles
+    /// keep the data they have until they are switched too, and the old data is freed with the
+    /// last of them, so a server can publish new data for its workers to pick up between
+    /// conversions. Old data keeps reading the file it was loaded from, for handles opened on it
+    /// later too, even once the new file has been renamed over its path.
+    ///
+    /// Fails, keeping the old data, if no reader can be set up on the new dictionary's file or, as in [`Self::set_user_dictionary`], the user dictionary does not fit its
+    /// matrix.
+    pub fn replace_data(&mut self, data: Arc<DictionaryData>) -> Result<(), MucabError> {
+        let region = data.open_region()?;
+        if let Some(user) = self.user.as_deref_mut() {
+            check_user_entries(&data, user)?;
         }
+        self.data = data;
+        self.region = region;
+        self.entry_cache = EntryCache::new(self.entry_cache.limit());
+        Ok(())
     }
 
-    let mut result = Vec::with_capacity(DEFAULT_CAPACITY);
-    if nodes[len].is_empty() {
-        return text.to_string();
+    /// The shared data behind this handle, for opening further handles or inspecting the
+    /// connection matrix.
+    pub fn data(&self) -> &Arc<DictionaryData> {
+        &self.data
     }
 
-    if let Some((current_node_idx, _)) = nodes[len].iter().enumerate().min_by_key(|(_, n)| n.cost) {
-        let mut current_pos = len;
-        let mut current_node_idx = current_node_idx;
+    /// See [`DictionaryData::pos_name`].
+    pub fn pos_name(&self, pos_id: u16) -> Option<&str> {
+        self.data.pos_name(pos_id)
+    }
 
-        while current_pos > 0 {
-            let node = &nodes[current_pos][current_node_idx];
-            if node.start_pos == 0 && node.end_pos == 0 {
+    pub fn num_entries(&self) -> usize {
+        self.data.num_entries
+    }
+
+    /// See [`DictionaryData::format_version`].
+    pub fn format_version(&self) -> u16 {
+        self.data.version
+    }
+
+    /// See [`DictionaryData::reading_kana`].
+    pub fn reading_kana(&self) -> KanaForm {
+        self.data.reading_kana
+    }
+
+    /// Bounds this handle's block cache and the shared one behind it. Unbounded by default.
+    pub fn set_cache_limit(&mut self, limit: CacheLimit) {
+        self.entry_cache.set_limit(limit);
+        self.data.set_cache_limit(limit);
+    }
+
+    /// Statistics of this handle's block cache and decompressed frames; see
+    /// [`DictionaryData::cache_stats`] for the shared block cache.
+    pub fn cache_stats(&self) -> CacheStats {
+        let stats = self.entry_cache.stats();
+        match &self.region {
+            #[cfg(feature = "zstd")]
+            RegionReader::Compressed(_, frames) => frames.add_stats(stats),
+            _ => stats,
+        }
+    }
+
+    /// The analysis behavior level used by the functions taking this handle.
+    pub fn compat(&self) -> AnalysisCompat {
+        self.compat
+    }
+
+    pub fn set_compat(&mut self, compat: AnalysisCompat) {
+        self.compat = compat;
+    }
+
+    pub fn missing_reading(&self) -> MissingReading {
+        self.missing_reading
+    }
+
+    /// Sets what entries without a reading contribute, user dictionary entries included.
+    pub fn set_missing_reading(&mut self, missing_reading: MissingReading) {
+        self.missing_reading = missing_reading;
+    }
+
+    pub fn viterbi_config(&self) -> ViterbiConfig {
+        self.viterbi
+    }
+
+    /// Sets the cost and connection of the flat unknown-word fallback.
+    ///
+    /// # Panics
+    ///
+    /// If `unknown_pos_id` is outside the connection matrix.
+    pub fn set_viterbi_config(&mut self, viterbi: ViterbiConfig) {
+        if let Some(pos_id) = viterbi.unknown_pos_id {
+            assert!(
+                self.data.connects(pos_id, pos_id),
+                "unknown_pos_id {} is outside the connection matrix",
+                pos_id
+            );
+        }
+        self.viterbi = viterbi;
+    }
+
+    /// The most characters [`transliterate`] and [`tokenize`] put in one lattice.
+    pub fn max_lattice_chars(&self) -> usize {
+        self.max_lattice_chars
+    }
+
+    /// Bounds the characters analysed in one lattice, and so the memory a conversion takes.
+    /// Longer texts are cut after hard sentence boundaries (`。！？` and line breaks) into pieces
+    /// that fit, analysed separately and joined in order; a sentence longer than the bound is
+    /// cut wherever it reaches it. Texts that fit are analysed whole. At least 1; defaults to
+    /// [`DEFAULT_MAX_LATTICE_CHARS`].
+    pub fn set_max_lattice_chars(&mut self, max_chars: usize) {
+        self.max_lattice_chars = max_chars.max(1);
+    }
+
+    /// Overlays `user` on this dictionary: its entries are looked up alongside the system ones
+    /// and compete in the same lattice, their context ids connecting through this dictionary's
+    /// matrix. A surface in both yields both candidates. The user dictionary keeps its own cost
+    /// overrides and cache settings.
+    ///
+    /// Fails with [`MucabError::Corrupt`] if a user entry has a context id outside this
+    /// dictionary's matrix, as one built for another system dictionary may, or if the two store
+    /// their readings in different kana forms.
+    pub fn set_user_dictionary(&mut self, mut user: Dictionary<'a>) -> Result<(), MucabError> {
+        check_user_entries(&self.data, &mut user)?;
+        self.user = Some(Box::new(user));
+        Ok(())
+    }
+
+    pub fn user_dictionary(&self) -> Option<&Dictionary<'a>> {
+        self.user.as_deref()
+    }
+
+    pub fn user_dictionary_mut(&mut self) -> Option<&mut Dictionary<'a>> {
+        self.user.as_deref_mut()
+    }
+
+    /// The dictionary entry behind a lattice node, `None` for BOS and unknown nodes.
+    fn node_entry(&mut self, node: &LatticeNode) -> Option<EntryRef<'_>> {
+        match node.kind {
+            NodeKind::Dict {
+                entry_char,
+                local_idx,
+                user: true,
+            } => self
+                .user
+                .as_mut()?
+                .get_entry(entry_char, local_idx as usize),
+            NodeKind::Dict {
+                entry_char,
+                local_idx,
+                user: false,
+            } => self.get_entry(entry_char, local_idx as usize),
+            NodeKind::Bos | NodeKind::Unknown { .. } | NodeKind::Pinned { .. } => None,
+        }
+    }
+
+    /// The char.def data, if the dictionary has it and the compat level uses it.
+    fn char_definitions(&self) -> Option<&CharDefinitions> {
+        self.data
+            .char_defs
+            .as_ref()
+            .filter(|_| self.compat.categorizes_unknowns())
+    }
+
+    fn get_matrix_cost(&self, prev_right_id: u16, curr_left_id: u16) -> i16 {
+        self.data.get_matrix_cost(prev_right_id, curr_left_id)
+    }
+
+    fn unknown_template(&self, idx: usize) -> Option<&UnknownTemplate> {
+        self.char_definitions()?;
+        self.data.templates.get(idx)
+    }
+
+    /// Whether any entry, on disk or added, starts with `first_char`.
+    fn has_block(&self, first_char: char) -> bool {
+        self.data.index.contains_key(&first_char) || self.added.contains_key(&first_char)
+    }
+
+    /// Whether any entry of this dictionary or its user dictionary starts with `first_char`.
+    fn starts_entries(&self, first_char: char) -> bool {
+        self.has_block(first_char)
+            || self
+                .user
+                .as_deref()
+                .is_some_and(|user| user.has_block(first_char))
+    }
+
+    fn get_entry(&mut self, first_char: char, local_idx: usize) -> Option<EntryRef<'_>> {
+        if !self.has_block(first_char) {
+            return None;
+        }
+        // The block may have been evicted since the lookup that produced this index.
+        self.load_block(first_char);
+        self.entry_cache.peek(first_char)?.get(local_idx)
+    }
+
+    #[cfg_attr(feature = "trace", tracing::instrument(level = "trace", skip(self)))]
+    fn read_reading_at(&mut self, offset: u32, len: u16) -> Result<String, MucabError> {
+        let bytes = match self.data.strings.get() {
+            Some(strings) => {
+                let (start, end) = (offset as usize, offset as usize + len as usize);
+                strings.get(start..end).map(<[u8]>::to_vec).ok_or_else(|| {
+                    MucabError::Corrupt(format!(
+                        "reading {}..{} is past the end of the strings ({} bytes)",
+                        start,
+                        end,
+                        strings.len()
+                    ))
+                })?
+            }
+            None => {
+                let start = self.data.strings_offset + offset as u64;
+                self.region
+                    .read_range(start..start + len as u64)
+                    .map_err(|e| reading_error(offset, e.into()))?
+            }
+        };
+        String::from_utf8(bytes).map_err(|e| reading_error(offset, not_utf8(e)))
+    }
+
+    /// The lemma `len` bytes at `offset` into the strings, where a [`DictEntry::lemma`] stored
+    /// in the file points.
+    pub fn read_lemma_at(&mut self, offset: u32, len: u16) -> Result<String, MucabError> {
+        self.read_reading_at(offset, len)
+    }
+
+    /// Whether the conversion under way should give up at lattice position `pos`.
+    fn stop_due(&mut self, pos: usize) -> bool {
+        self.stop.as_mut().is_some_and(|stop| stop.due(pos))
+    }
+
+    /// Keeps `error` for [`Self::take_error`] unless an earlier one is still there.
+    fn record_error(&mut self, error: MucabError) {
+        self.error.get_or_insert(error);
+    }
+
+    /// The first error met reading entries or readings since the last call, from this handle
+    /// or its user dictionary. The analysis functions carry on past one: an entry block that
+    /// cannot be decoded counts as empty, leaving its text to unknown words, and a reading that
+    /// cannot be read as empty.
+    pub fn take_error(&mut self) -> Option<MucabError> {
+        self.error
+            .take()
+            .or_else(|| self.user.as_deref_mut()?.take_error())
+    }
+
+    /// Reads the readings at `spans` (offset and length into the strings region), in their
+    /// order. The reader visits them sorted by offset, and spans less than [`READING_GAP`]
+    /// bytes apart share a single read, so a whole path costs a few forward reads rather than
+    /// one seek per token.
+    fn read_readings(&mut self, spans: &[(u32, u16)]) -> Vec<String> {
+        if self.data.strings.get().is_some() {
+            return spans
+                .iter()
+                .map(|&(offset, len)| self.entry_reading(ReadingRef::Stored { offset, len }))
+                .collect();
+        }
+
+        let mut order: Vec<usize> = (0..spans.len()).collect();
+        order.sort_by_key(|&i| spans[i].0);
+        let span_end = |i: usize| spans[i].0 as u64 + spans[i].1 as u64;
+
+        let mut readings = vec![String::new(); spans.len()];
+        let mut run_start = 0;
+        while run_start < order.len() {
+            let start = spans[order[run_start]].0 as u64;
+            let mut end = span_end(order[run_start]);
+            let mut run_end = run_start + 1;
+            while run_end < order.len() && spans[order[run_end]].0 as u64 <= end + READING_GAP {
+                end = end.max(span_end(order[run_end]));
+                run_end += 1;
+            }
+
+            let base = self.data.strings_offset;
+            match self.region.read_range(base + start..base + end) {
+                Ok(buf) => {
+                    for &i in &order[run_start..run_end] {
+                        let (offset, len) = spans[i];
+                        let from = (offset as u64 - start) as usize;
+                        match String::from_utf8(buf[from..from + len as usize].to_vec()) {
+                            Ok(reading) => readings[i] = reading,
+                            Err(e) => self.record_error(reading_error(offset, not_utf8(e))),
+                        }
+                    }
+                }
+                Err(e) => self.record_error(reading_error(start as u32, e.into())),
+            }
+            run_start = run_end;
+        }
+        readings
+    }
+
+    /// Byte range of the entry block starting at `byte_offset`. Blocks are stored back to back,
+    /// so each ends where the next begins, and the last one where the strings start.
+    fn block_range(&self, byte_offset: u64) -> std::ops::Range<u64> {
+        let next = self
+            .data
+            .block_offsets
+            .partition_point(|&offset| offset <= byte_offset);
+        let end = self
+            .data
+            .block_offsets
+            .get(next)
+            .copied()
+            .unwrap_or(self.data.strings_offset);
+        byte_offset..end
+    }
+
+    #[cfg_attr(
+        feature = "trace",
+        tracing::instrument(
+            level = "debug",
+            skip(self),
+            fields(count = self.data.index[&first_char].1)
+        )
+    )]
+    fn bulk_read_entries(&mut self, first_char: char) -> Result<EntryBlock, MucabError> {
+        let (byte_offset, count) = self.data.index[&first_char];
+
+        // Reading the block in one go keeps cold lookups cheap however many frames it spans.
+        let range = self.block_range(byte_offset);
+        let block = self
+            .region
+            .read_range(range)
+            .map_err(|e| block_error(first_char, byte_offset, e.into()))?;
+        EntryBlock::parse(block, count, first_char, self.data.version)
+            .map_err(|e| block_error(first_char, byte_offset, MucabError::Corrupt(e)))
+    }
+
+    /// Decompresses the whole entries and strings block once, filling the shared entry cache
+    /// for every index key and keeping the strings in memory, so later lookups and readings
+    /// never touch the file. Every handle on the same [`DictionaryData`] benefits.
+    pub fn preload_all(&mut self) -> std::io::Result<()> {
+        if self.data.strings.get().is_some() {
+            return Ok(());
+        }
+        let total = self.region.len()?;
+        let mut all = self.region.read_range(0..total)?;
+
+        let blocks: Vec<(char, EntryBlock)> = self
+            .data
+            .index
+            .iter()
+            .map(|(&first_char, &(byte_offset, count))| {
+                let range = self.block_range(byte_offset);
+                let block = all[range.start as usize..range.end as usize].to_vec();
+                let entries = EntryBlock::parse(block, count, first_char, self.data.version)
+                    .map_err(|e| block_error(first_char, byte_offset, MucabError::Corrupt(e)))?;
+                Ok((first_char, entries))
+            })
+            .collect::<Result<_, MucabError>>()?;
+        {
+            let mut cache = self.data.entry_cache.lock().unwrap();
+            for (first_char, entries) in blocks {
+                cache.insert(first_char, Arc::new(entries));
+            }
+        }
+        let strings = all.split_off(self.data.strings_offset as usize);
+        self.data.strings.set(strings).ok();
+        Ok(())
+    }
+
+    /// Makes the entry block for `first_char` available in this handle's cache: from the shared
+    /// cache if another handle already decoded it, otherwise by decoding and publishing it.
+    fn load_block(&mut self, first_char: char) -> Arc<EntryBlock> {
+        if let Some(block) = self.entry_cache.get(first_char) {
+            return block.clone();
+        }
+        let block = if self.data.index.contains_key(&first_char) {
+            let shared = self
+                .data
+                .entry_cache
+                .lock()
+                .unwrap()
+                .get(first_char)
+                .cloned();
+            match shared {
+                Some(block) => block,
+                None => match self.bulk_read_entries(first_char) {
+                    Ok(block) => self
+                        .data
+                        .entry_cache
+                        .lock()
+                        .unwrap()
+                        .insert(first_char, Arc::new(block)),
+                    // Kept out of the shared cache, so every handle meets the error itself.
+                    Err(e) => {
+                        self.record_error(e);
+                        Arc::new(EntryBlock::default())
+                    }
+                },
+            }
+        } else {
+            Arc::new(EntryBlock::default())
+        };
+        let block = self.merge_added(first_char, block);
+        let block = self.apply_overrides(block);
+        self.entry_cache.insert(first_char, block)
+    }
+
+    /// Adds the entries from [`Self::add_entry`] to a block, keeping it sorted by surface.
+    fn merge_added(&self, first_char: char, block: Arc<EntryBlock>) -> Arc<EntryBlock> {
+        let Some(added) = self.added.get(&first_char) else {
+            return block;
+        };
+        let entries = block.iter().chain(added.iter().map(EntryRef::from));
+        Arc::new(EntryBlock::from_entries(entries))
+    }
+
+    /// Adds a word to this handle without rebuilding the dictionary. It is looked up like the
+    /// stored entries, takes cost overrides, and stays available however the cache is limited.
+    /// `pos_id` serves as both the left and the right context id. `reading` is put in the form
+    /// of the stored readings, [`Self::reading_kana`].
+    ///
+    /// # Panics
+    ///
+    /// If `surface` is empty or longer than 65535 chars.
+    pub fn add_entry(&mut self, surface: &str, reading: &str, pos_id: u16, word_cost: i16) {
+        let char_len = surface.chars().count();
+        assert!(
+            (1..=u16::MAX as usize).contains(&char_len),
+            "surface must have 1 to 65535 chars"
+        );
+        let first_char = surface.chars().next().unwrap();
+        self.added.entry(first_char).or_default().push(DictEntry {
+            surface: surface.to_string(),
+            char_len: char_len as u16,
+            pos_id,
+            right_id: pos_id,
+            word_cost,
+            reading: EntryReading::Inline(self.data.reading_kana.apply(reading).into()),
+            lemma: None,
+            pronunciation: None,
+            accent: None,
+        });
+        self.entry_cache.remove(first_char);
+    }
+
+    /// The text of `reading`, empty if it cannot be read (see [`Self::take_error`]).
+    fn entry_reading(&mut self, reading: ReadingRef) -> String {
+        match reading {
+            ReadingRef::Stored { offset, len } => {
+                self.read_reading_at(offset, len).unwrap_or_else(|e| {
+                    self.record_error(e);
+                    String::new()
+                })
+            }
+            ReadingRef::Inline(reading) => reading.to_string(),
+        }
+    }
+
+    /// Returns `block` with this handle's cost overrides applied, copying it only if one matches.
+    fn apply_overrides(&mut self, block: Arc<EntryBlock>) -> Arc<EntryBlock> {
+        let mut adjusted: Option<EntryBlock> = None;
+        for (i, entry) in block.iter().enumerate() {
+            if let Some(o) = self.surface_overrides.get(entry.surface) {
+                let cost = o.apply(entry.word_cost);
+                if cost != entry.word_cost {
+                    let entries = adjusted.get_or_insert_with(|| block.as_ref().clone());
+                    entries.set_cost(i, cost);
+                }
+            }
+            let Some(by_reading) = self.cost_overrides.get(entry.surface) else {
+                continue;
+            };
+            let by_reading = by_reading.clone();
+            let reading = self.entry_reading(entry.reading);
+            if let Some(&delta) = by_reading.get(&reading) {
+                let entries = adjusted.get_or_insert_with(|| block.as_ref().clone());
+                entries.adjust_cost(i, delta);
+            }
+        }
+        adjusted.map(Arc::new).unwrap_or(block)
+    }
+
+    /// Sets the word cost of every entry with this surface, whatever its reading, to `cost`,
+    /// replacing an earlier call's; boosts and [`Self::adjust_cost`] adjustments still count on
+    /// top. Takes effect on the next lookup through this handle only.
+    pub fn override_cost(&mut self, surface: &str, cost: i16) {
+        self.surface_override(surface).cost = Some(cost);
+        self.settle_surface_override(surface);
+    }
+
+    /// Adds `delta` to the word cost of every entry with this surface, whatever its reading, on
+    /// top of earlier boosts: a negative one makes the lattice prefer the surface. Takes effect
+    /// on the next lookup through this handle only.
+    pub fn boost(&mut self, surface: &str, delta: i16) {
+        let o = self.surface_override(surface);
+        o.delta = o.delta.saturating_add(delta);
+        self.settle_surface_override(surface);
+    }
+
+    fn surface_override(&mut self, surface: &str) -> &mut SurfaceOverride {
+        self.surface_overrides
+            .entry(surface.to_string())
+            .or_insert_with(|| SurfaceOverride {
+                surface: surface.to_string(),
+                cost: None,
+                delta: 0,
+            })
+    }
+
+    /// Drops the override of `surface` if it no longer changes anything, and the cached block
+    /// carrying the old costs.
+    fn settle_surface_override(&mut self, surface: &str) {
+        if let Some(SurfaceOverride {
+            cost: None,
+            delta: 0,
+            ..
+        }) = self.surface_overrides.get(surface)
+        {
+            self.surface_overrides.remove(surface);
+        }
+        if let Some(first_char) = surface.chars().next() {
+            self.entry_cache.remove(first_char);
+        }
+    }
+
+    /// The cost overrides and boosts currently in effect, sorted by surface.
+    pub fn list_overrides(&self) -> Vec<SurfaceOverride> {
+        let mut overrides: Vec<SurfaceOverride> =
+            self.surface_overrides.values().cloned().collect();
+        overrides.sort_by(|a, b| a.surface.cmp(&b.surface));
+        overrides
+    }
+
+    /// Drops every cost override, boost and [`Self::adjust_cost`] adjustment, returning this
+    /// handle to the dictionary's own costs.
+    pub fn clear_overrides(&mut self) {
+        let surfaces = self
+            .surface_overrides
+            .keys()
+            .chain(self.cost_overrides.keys());
+        for first_char in surfaces.filter_map(|s| s.chars().next()) {
+            self.entry_cache.remove(first_char);
+        }
+        self.surface_overrides.clear();
+        self.cost_overrides.clear();
+    }
+
+    /// Adds `delta` to the word cost of every entry with this surface and reading, on top of any
+    /// earlier adjustment. Takes effect on the next lookup through this handle only.
+    pub fn adjust_cost(&mut self, surface: &str, reading: &str, delta: i16) {
+        let by_reading = self.cost_overrides.entry(surface.to_string()).or_default();
+        let total = by_reading.entry(reading.to_string()).or_insert(0);
+        *total = total.saturating_add(delta);
+        if *total == 0 {
+            by_reading.remove(reading);
+            if by_reading.is_empty() {
+                self.cost_overrides.remove(surface);
+            }
+        }
+        // The cached block carries the old cost; rebuild it from the shared one on next use.
+        if let Some(first_char) = surface.chars().next() {
+            self.entry_cache.remove(first_char);
+        }
+    }
+
+    /// Applies every adjustment in an overrides CSV (see [`overrides`]), returning how many
+    /// lines were read.
+    #[cfg(feature = "fs")]
+    pub fn load_overrides<P: AsRef<Path>>(&mut self, path: P) -> std::io::Result<usize> {
+        let text = std::fs::read_to_string(path)?;
+        let overrides = overrides::parse_overrides(&text)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        for o in &overrides {
+            self.adjust_cost(&o.surface, &o.reading, o.delta);
+        }
+        Ok(overrides.len())
+    }
+
+    /// The cost adjustments currently in effect, sorted by surface and reading.
+    pub fn cost_overrides(&self) -> Vec<CostOverride> {
+        let mut overrides: Vec<CostOverride> = self
+            .cost_overrides
+            .iter()
+            .flat_map(|(surface, by_reading)| {
+                by_reading.iter().map(|(reading, &delta)| CostOverride {
+                    surface: surface.clone(),
+                    reading: reading.clone(),
+                    delta,
+                })
+            })
+            .collect();
+        overrides.sort_by(|a, b| (&a.surface, &a.reading).cmp(&(&b.surface, &b.reading)));
+        overrides
+    }
+
+    /// All entries with exactly this surface, stored or added, with cost overrides applied. The
+    /// user dictionary is not consulted; ask [`Self::user_dictionary_mut`] for its entries.
+    pub fn lookup_exact(&mut self, surface: &str) -> Vec<DictEntry> {
+        let Some(first_char) = surface.chars().next() else {
+            return Vec::new();
+        };
+        if !self.has_block(first_char) {
+            return Vec::new();
+        }
+        let entries = self.load_block(first_char);
+        entries
+            .equal_range(surface)
+            .filter_map(|i| entries.get(i))
+            .map(|e| e.to_entry())
+            .collect()
+    }
+
+    /// Every first char entries start with, and how many entries start with it (stored plus
+    /// added).
+    pub fn index_keys(&self) -> BTreeMap<char, usize> {
+        let mut keys: BTreeMap<char, usize> = self
+            .data
+            .index
+            .iter()
+            .map(|(&c, &(_, count))| (c, count))
+            .collect();
+        for (&c, entries) in &self.added {
+            *keys.entry(c).or_default() += entries.len();
+        }
+        keys
+    }
+
+    /// All entries with their first char, in [`Self::index_keys`] order and by surface within a
+    /// char. Blocks are loaded one at a time as the iterator reaches them, through the caches.
+    pub fn iter_entries(&mut self) -> impl Iterator<Item = (char, DictEntry)> + use<'_, 'a> {
+        let keys: Vec<char> = self.index_keys().into_keys().collect();
+        keys.into_iter().flat_map(move |c| {
+            let block = self.load_block(c);
+            (0..block.len()).filter_map(move |i| Some((c, block.get(i)?.to_entry())))
+        })
+    }
+
+    /// The distinct readings of the entries [`Self::lookup_exact`] finds, in entry order.
+    pub fn readings_of(&mut self, surface: &str) -> Vec<String> {
+        let mut readings: Vec<String> = Vec::new();
+        for entry in self.lookup_exact(surface) {
+            let reading = self.entry_reading((&entry.reading).into());
+            if !readings.contains(&reading) {
+                readings.push(reading);
+            }
+        }
+        readings
+    }
+
+    /// Replaces `matches` with the entries whose surface occurs in `chars` at `start`, as
+    /// `(first_char, local_idx)`.
+    ///
+    /// Blocks are sorted by surface, so the entries sharing the text's first `depth` chars form a
+    /// contiguous range. Each step narrows that range by binary search on the next char's bytes;
+    /// entries exactly `depth` chars long sort first within it and are the matches of that length.
+    fn lookup(&mut self, chars: &[char], start: usize, matches: &mut Vec<(char, usize)>) {
+        matches.clear();
+        if start >= chars.len() {
+            return;
+        }
+
+        let first_char = chars[start];
+        if !self.has_block(first_char) {
+            return;
+        }
+
+        let entries = self.load_block(first_char);
+        let (mut lo, mut hi) = (0, entries.len());
+        let mut depth = 1;
+        let mut prefix_bytes = first_char.len_utf8();
+        loop {
+            while lo < hi && entries.char_len(lo) as usize == depth {
+                matches.push((first_char, lo));
+                lo += 1;
+            }
+            if lo == hi || start + depth >= chars.len() {
                 break;
             }
 
-            if node.entry_char == '\0' && node.cost >= 10000 {
-                result.push(chars[node.start_pos].to_string());
-            } else if let Some(entry) = dict.get_entry(node.entry_char, node.entry_local_idx) {
-                let read_off = entry.reading_offset;
-                let read_len = entry.reading_len;
-                let reading = dict.read_reading_at(read_off, read_len);
-                result.push(reading);
+            let mut buf = [0u8; 4];
+            let next = chars[start + depth].encode_utf8(&mut buf).as_bytes();
+            // The entry's bytes after the matched prefix, cut to the length of `next`.
+            fn key(surface: &[u8], from: usize, len: usize) -> &[u8] {
+                let bytes = &surface[from..];
+                &bytes[..bytes.len().min(len)]
+            }
+            let first =
+                entries.partition_point(lo, hi, |s| key(s, prefix_bytes, next.len()) < next);
+            let last =
+                entries.partition_point(first, hi, |s| key(s, prefix_bytes, next.len()) <= next);
+            (lo, hi) = (first, last);
+            depth += 1;
+            prefix_bytes += next.len();
+        }
+    }
+}
+
+/// The connection matrix, row by right id of the previous node.
+enum Matrix {
+    Owned(Box<[i16]>),
+    /// Cells read in place from a dictionary in static memory; see [`static_matrix`].
+    Borrowed(&'static [i16]),
+}
+
+impl std::ops::Deref for Matrix {
+    type Target = [i16];
+
+    fn deref(&self) -> &[i16] {
+        match self {
+            Matrix::Owned(cells) => cells,
+            Matrix::Borrowed(cells) => cells,
+        }
+    }
+}
+
+/// The `cells` matrix cells at byte `start` of `bytes`, as they are, if they can be read in
+/// place: on a little-endian target, and with `bytes` placed so that they are aligned for
+/// `i16`. `None` if not, or if `bytes` ends first.
+fn static_matrix(bytes: &'static [u8], start: u64, cells: usize) -> Option<&'static [i16]> {
+    if cfg!(target_endian = "big") {
+        return None;
+    }
+    let start = usize::try_from(start).ok()?;
+    let bytes = bytes.get(start..start.checked_add(cells.checked_mul(2)?)?)?;
+    // SAFETY: every bit pattern is a valid i16, and little-endian i16s are stored as they are.
+    let (head, cells, tail) = unsafe { bytes.align_to::<i16>() };
+    (head.is_empty() && tail.is_empty()).then_some(cells)
+}
+
+/// Decodes little-endian i16 matrix cells. Working on exact two-byte chunks lets the compiler
+/// turn this into plain copies on little-endian targets.
+fn decode_matrix(bytes: &[u8]) -> Box<[i16]> {
+    bytes
+        .chunks_exact(2)
+        .map(|b| i16::from_le_bytes([b[0], b[1]]))
+        .collect()
+}
+
+/// Size of the header of `version`: the checksum came with version 7, the flags with 6 and the
+/// right matrix size with 2.
+pub(crate) fn header_size(version: u16) -> usize {
+    match version {
+        1 => HEADER_SIZE_V1,
+        2..=5 => HEADER_SIZE_V2,
+        6 => HEADER_SIZE_V6,
+        _ => HEADER_SIZE,
+    }
+}
+
+/// Sizes in an entry record of `version`: the length prefix of the surface, and the metadata
+/// after it. Reading lengths take as many bytes as the surface length. From version 9 the
+/// metadata ends with the offset and length of the lemma in the strings, both 0 for none, as
+/// for a reading. From version 8 the metadata is followed by the extra fields: their length as
+/// a `u16`, then each field as a tag, its length as a `u16` and its bytes. Readers skip tags
+/// they don't know.
+pub(crate) fn entry_layout(version: u16) -> (usize, usize) {
+    match version {
+        1 => (1, ENTRY_METADATA_SIZE_V1),
+        2..=4 => (1, ENTRY_METADATA_SIZE_V2),
+        5..=8 => (2, ENTRY_METADATA_SIZE_V5),
+        _ => (2, ENTRY_METADATA_SIZE),
+    }
+}
+
+/// The fields of one entry record, its surface still undecoded.
+pub(crate) struct RawRecord<'b> {
+    pub surface: &'b [u8],
+    /// Offset of the surface from the start of the record.
+    pub surface_at: usize,
+    pub pos_id: u16,
+    pub right_id: u16,
+    pub word_cost: i16,
+    pub reading_offset: u32,
+    pub reading_len: u16,
+    /// Offset and length of the lemma in the strings, if the record has one.
+    pub lemma: Option<(u32, u16)>,
+    /// The pronunciation from the extra fields, and its offset from the start of the record.
+    pub pronunciation: Option<(&'b [u8], usize)>,
+    pub accent: Option<u8>,
+}
+
+/// Splits the entry record at the start of `bytes` into its fields, returning them with the
+/// record's size.
+pub(crate) fn read_record(bytes: &[u8], version: u16) -> Result<(RawRecord<'_>, usize), String> {
+    let (len_size, metadata_size) = entry_layout(version);
+    let read_len = |bytes: &[u8]| match len_size {
+        1 => bytes[0] as u16,
+        _ => u16::from_le_bytes([bytes[0], bytes[1]]),
+    };
+
+    if bytes.len() < len_size {
+        return Err(format!("record truncated to {} bytes", bytes.len()));
+    }
+    let surf_len = read_len(bytes) as usize;
+    let size = len_size + surf_len + metadata_size;
+    if bytes.len() < size {
+        return Err(format!(
+            "record of {} bytes truncated to {}",
+            size,
+            bytes.len()
+        ));
+    }
+    let entry_buf = &bytes[len_size + surf_len..size];
+
+    let reading_offset =
+        u32::from_le_bytes([entry_buf[0], entry_buf[1], entry_buf[2], entry_buf[3]]);
+    let reading_len = read_len(&entry_buf[4..]);
+    let ids = &entry_buf[4 + len_size..];
+    let pos_id = u16::from_le_bytes([ids[0], ids[1]]);
+    let (right_id, word_cost) = if version == 1 {
+        (pos_id, i16::from_le_bytes([ids[2], ids[3]]))
+    } else {
+        (
+            u16::from_le_bytes([ids[2], ids[3]]),
+            i16::from_le_bytes([ids[4], ids[5]]),
+        )
+    };
+    let lemma = match ids.get(6..12) {
+        Some(&[a, b, c, d, e, f]) if version >= 9 && u16::from_le_bytes([e, f]) > 0 => {
+            Some((u32::from_le_bytes([a, b, c, d]), u16::from_le_bytes([e, f])))
+        }
+        _ => None,
+    };
+
+    let mut record = RawRecord {
+        surface: &bytes[len_size..len_size + surf_len],
+        surface_at: len_size,
+        pos_id,
+        right_id,
+        word_cost,
+        reading_offset,
+        reading_len,
+        lemma,
+        pronunciation: None,
+        accent: None,
+    };
+    if version < 8 {
+        return Ok((record, size));
+    }
+
+    let Some(extras_len) = bytes.get(size..size + 2) else {
+        return Err(format!("record of {} bytes has no extra fields", size));
+    };
+    let extras_at = size + 2;
+    let size = extras_at + u16::from_le_bytes([extras_len[0], extras_len[1]]) as usize;
+    let Some(mut extras) = bytes.get(extras_at..size) else {
+        return Err(format!(
+            "extra fields of {} bytes truncated to {}",
+            size - extras_at,
+            bytes.len() - extras_at
+        ));
+    };
+    while !extras.is_empty() {
+        let value_at = size - extras.len() + 3;
+        let (tag, value) = match *extras {
+            [tag, a, b, ref rest @ ..] if rest.len() >= u16::from_le_bytes([a, b]) as usize => {
+                (tag, &rest[..u16::from_le_bytes([a, b]) as usize])
+            }
+            _ => return Err("extra field truncated".to_string()),
+        };
+        match (tag, value) {
+            (EXTRA_PRONUNCIATION, _) => record.pronunciation = Some((value, value_at)),
+            (EXTRA_ACCENT, &[accent]) => record.accent = Some(accent),
+            (EXTRA_ACCENT, _) => return Err("accent is not one byte".to_string()),
+            _ => {}
+        }
+        extras = &extras[3 + value.len()..];
+    }
+    Ok((record, size))
+}
+
+/// Decodes the entry record at the start of `bytes`, returning it with the record's size.
+pub(crate) fn parse_record(bytes: &[u8], version: u16) -> Result<(DictEntry, usize), String> {
+    let (record, size) = read_record(bytes, version)?;
+    let surface = String::from_utf8(record.surface.to_vec())
+        .map_err(|e| format!("surface is not UTF-8 ({})", e))?;
+    let pronunciation = match record.pronunciation {
+        Some((bytes, _)) => Some(
+            String::from_utf8(bytes.to_vec())
+                .map_err(|e| format!("pronunciation is not UTF-8 ({})", e))?,
+        ),
+        None => None,
+    };
+    let entry = DictEntry {
+        char_len: surface.chars().count() as u16,
+        surface,
+        pos_id: record.pos_id,
+        right_id: record.right_id,
+        word_cost: record.word_cost,
+        reading: EntryReading::Stored {
+            offset: record.reading_offset,
+            len: record.reading_len,
+        },
+        lemma: record
+            .lemma
+            .map(|(offset, len)| EntryReading::Stored { offset, len }),
+        pronunciation,
+        accent: record.accent,
+    };
+    Ok((entry, size))
+}
+
+#[cfg_attr(
+    feature = "trace",
+    tracing::instrument(level = "debug", skip_all, fields(bytes = text.len()))
+)]
+fn build_lattice<'a>(text: &str, dict: &mut Dictionary<'a>) -> (Lattice, Vec<char>) {
+    let (candidates, chars) = lattice_candidates(text, dict);
+    (Columns::group(chars.len() + 1, candidates), chars)
+}
+
+/// The candidates of the lattice over `text`, before they are grouped by end position.
+fn lattice_candidates<'a>(text: &str, dict: &mut Dictionary<'a>) -> (Candidates, Vec<char>) {
+    let chars: Vec<char> = text.chars().collect();
+    let len = chars.len();
+    let mut candidates = Candidates::new();
+    let mut matches = Vec::new();
+    // End of the last same-category run, keyed by its primary category.
+    let mut run: Option<(u8, usize)> = None;
+    // End of the last run of characters absent from the index.
+    let mut unmatched_end = 0;
+
+    for start in 0..len {
+        if dict.stop_due(start) {
+            break;
+        }
+        dict.lookup(&chars, start, &mut matches);
+        let mut has_match = !matches.is_empty();
+        for &(entry_char, entry_local_idx) in &matches {
+            let Some(entry) = dict.get_entry(entry_char, entry_local_idx) else {
+                continue;
+            };
+            let (end, left_id, right_id) = (
+                start + entry.char_len as usize,
+                entry.pos_id,
+                entry.right_id,
+            );
+            // Entries the matrix cannot connect stay out of the lattice.
+            let what = || format!("entry {} of block {:?}", entry_local_idx, entry_char);
+            if let Err(e) = dict.data.check_context_ids(what, left_id, right_id) {
+                dict.record_error(e);
+                continue;
+            }
+            let kind = NodeKind::Dict {
+                entry_char,
+                local_idx: entry_local_idx as u32,
+                user: false,
+            };
+            candidates.push((end, (kind, start)));
+        }
+        if let Some(user) = dict.user.as_deref_mut() {
+            user.lookup(&chars, start, &mut matches);
+            for &(entry_char, entry_local_idx) in &matches {
+                let Some(entry) = user.get_entry(entry_char, entry_local_idx) else {
+                    continue;
+                };
+                let (end, left_id, right_id) = (
+                    start + entry.char_len as usize,
+                    entry.pos_id,
+                    entry.right_id,
+                );
+                let what = || format!("user entry {} of block {:?}", entry_local_idx, entry_char);
+                if let Err(e) = dict.data.check_context_ids(what, left_id, right_id) {
+                    user.record_error(e);
+                    continue;
+                }
+                let kind = NodeKind::Dict {
+                    entry_char,
+                    local_idx: entry_local_idx as u32,
+                    user: true,
+                };
+                candidates.push((end, (kind, start)));
+                has_match = true;
+            }
+        }
+
+        if let Some(defs) = dict.char_definitions() {
+            add_unknown_candidates(
+                defs,
+                &dict.data.template_ranges,
+                &chars,
+                start,
+                has_match,
+                &mut run,
+                &mut candidates,
+            );
+        } else if dict.compat.coalesces_unmatched_runs() && !dict.starts_entries(chars[start]) {
+            // Every start inside a run ends where the run does, so a path entering it after an
+            // entry that ends midway still gets through.
+            if start >= unmatched_end {
+                unmatched_end = start + 1;
+                while unmatched_end < len
+                    && (!dict.starts_entries(chars[unmatched_end])
+                        || (dict.compat.extends_runs_over_long_vowels()
+                            && is_long_vowel_after_katakana(&chars, unmatched_end)))
+                {
+                    unmatched_end += 1;
+                }
+            }
+            candidates.push((unmatched_end, (NodeKind::Unknown { template: None }, start)));
+        }
+    }
+
+    (candidates, chars)
+}
+
+/// Whether `chars[i]` is a prolonged sound mark lengthening the katakana before it.
+fn is_long_vowel_after_katakana(chars: &[char], i: usize) -> bool {
+    chars[i] == 'ー' && i > 0 && matches!(chars[i - 1], 'ァ'..='ヺ' | 'ー')
+}
+
+/// Adds the unknown-word candidates starting at `start`, following the category rules of
+/// char.def: nothing when the dictionary matched and the category is not `invoke`, otherwise a
+/// node over the whole run (`group`) plus nodes of 1..=`length` characters, falling back to a
+/// single character when neither applies and nothing else starts here.
+fn add_unknown_candidates(
+    defs: &CharDefinitions,
+    template_ranges: &[(usize, usize)],
+    chars: &[char],
+    start: usize,
+    has_match: bool,
+    run: &mut Option<(u8, usize)>,
+    candidates: &mut Candidates,
+) {
+    let (category_idx, _) = defs.classify(chars[start]);
+    let category = &defs.categories[category_idx as usize];
+    if has_match && !category.invoke {
+        return;
+    }
+
+    // A run starting inside an earlier run of the same primary category ends where it did.
+    let run_end = match *run {
+        Some((cat, end)) if cat == category_idx && start < end => end,
+        _ => {
+            let bit = 1u32 << category_idx;
+            let mut end = start + 1;
+            while end < chars.len() && defs.classify(chars[end]).1 & bit != 0 {
+                end += 1;
+            }
+            *run = Some((category_idx, end));
+            end
+        }
+    };
+
+    let mut ends = Vec::new();
+    if category.group {
+        ends.push(run_end);
+    }
+    for length in 1..=category.length as usize {
+        let end = start + length;
+        if end > run_end {
+            break;
+        }
+        if !ends.contains(&end) {
+            ends.push(end);
+        }
+    }
+    if ends.is_empty() && !has_match {
+        ends.push(start + 1);
+    }
+
+    let (first, count) = template_ranges[category_idx as usize];
+    for end in ends {
+        for template in first..first + count {
+            let kind = NodeKind::Unknown {
+                template: Some(template as u32),
+            };
+            candidates.push((end, (kind, start)));
+        }
+    }
+}
+
+fn viterbi<'a>(text: &str, dict: &mut Dictionary<'a>) -> (Nodes, Vec<char>) {
+    let (lattice, chars) = build_lattice(text, dict);
+    (search_lattice(&lattice, &chars, dict, &[]), chars)
+}
+
+/// Connects the candidates of `lattice` into nodes, each keeping its cheapest predecessor.
+/// Characters nothing ends at get a flat-cost unknown node of their own, unless it would cut
+/// into one of the `pinned` spans of [`constraint::tokenize_with_constraints`].
+#[cfg_attr(
+    feature = "trace",
+    tracing::instrument(level = "debug", skip_all, fields(chars = chars.len()))
+)]
+fn search_lattice(
+    lattice: &Lattice,
+    chars: &[char],
+    dict: &mut Dictionary,
+    pinned: &[std::ops::Range<usize>],
+) -> Nodes {
+    let len = chars.len();
+
+    let mut nodes = Nodes::with_capacity(len + 1);
+    let bos_node = LatticeNode {
+        start_pos: 0,
+        end_pos: 0,
+        kind: NodeKind::Bos,
+        cost: 0,
+        prev_node: None,
+    };
+    nodes.push(bos_node);
+    nodes.close();
+
+    for pos in 1..=len {
+        if dict.stop_due(pos) {
+            break;
+        }
+        // Characters absent from the index are already covered by flat-cost unknown runs.
+        let covered_by_run =
+            dict.compat.coalesces_unmatched_runs() && !dict.starts_entries(chars[pos - 1]);
+        let fill = lattice[pos].is_empty() && dict.char_definitions().is_none() && !covered_by_run;
+        if fill && !constraint::cuts_into(pinned, pos - 1, pos) {
+            // One node after the cheapest predecessor stands for all of them; `nbest_paths` still
+            // branches over every predecessor.
+            let mut cheapest: Option<(usize, i32)> = None;
+            for (prev_idx, prev_node) in nodes[pos - 1].iter().enumerate() {
+                let cost = prev_node
+                    .cost
+                    .saturating_add(unknown_edge_cost(dict, prev_node));
+                if cheapest.is_none_or(|(_, best)| cost < best) {
+                    cheapest = Some((prev_idx, cost));
+                }
+            }
+            if let Some((prev_idx, cost)) = cheapest {
+                nodes.push(LatticeNode {
+                    start_pos: pos - 1,
+                    end_pos: pos,
+                    kind: NodeKind::Unknown { template: None },
+                    cost,
+                    prev_node: Some(prev_idx),
+                });
+            }
+            nodes.close();
+            continue;
+        }
+
+        for &(kind, start_pos) in &lattice[pos] {
+            if nodes[start_pos].is_empty() {
+                continue;
+            }
+
+            let node = LatticeNode {
+                start_pos,
+                end_pos: pos,
+                kind,
+                cost: 0,
+                prev_node: None,
+            };
+            // Unmatched runs are legacy unknown nodes, connected through `unknown_pos_id`.
+            let context = match kind {
+                NodeKind::Unknown { template: None } => None,
+                _ => match node_context(dict, &node) {
+                    Some(context) => Some(context),
+                    None => continue,
+                },
+            };
+            // An `Option` rather than an `i32::MAX` start, so a path saturated at `i32::MAX` is
+            // still taken.
+            let mut best: Option<(usize, i32)> = None;
+
+            for (prev_idx, prev_node) in nodes[start_pos].iter().enumerate() {
+                let step = match context {
+                    Some((left_id, word_cost)) => edge_cost(dict, prev_node, left_id, word_cost),
+                    None => unknown_edge_cost(dict, prev_node),
+                };
+                let total_cost = prev_node.cost.saturating_add(step);
+
+                if best.is_none_or(|(_, best_cost)| total_cost < best_cost) {
+                    best = Some((prev_idx, total_cost));
+                }
+            }
+
+            if let Some((prev_idx, cost)) = best {
+                nodes.push(LatticeNode {
+                    cost,
+                    prev_node: Some(prev_idx),
+                    ..node
+                });
+            }
+        }
+        nodes.close();
+    }
+
+    nodes
+}
+
+/// Left id and word cost of a non-BOS node: from its dictionary entry, or from its unknown-word
+/// template. `None` for legacy unknown nodes, which are not connected through the matrix.
+fn node_context(dict: &mut Dictionary, node: &LatticeNode) -> Option<(u16, i16)> {
+    match node.kind {
+        NodeKind::Pinned { pos_id } => Some((pos_id, 0)),
+        NodeKind::Unknown { .. } => dict
+            .unknown_template(node.template()?)
+            .map(|t| (t.left_id, t.cost)),
+        _ => dict.node_entry(node).map(|e| (e.pos_id, e.word_cost)),
+    }
+}
+
+/// Right id `node` connects to its successor with: 0 for BOS, [`ViterbiConfig::unknown_pos_id`]
+/// for legacy unknown nodes, `None` when it does not connect through the matrix.
+fn node_right_id(dict: &mut Dictionary, node: &LatticeNode) -> Option<u16> {
+    match node.kind {
+        NodeKind::Bos => Some(0),
+        NodeKind::Unknown { .. } => match node.template().and_then(|t| dict.unknown_template(t)) {
+            Some(template) => Some(template.right_id),
+            None => dict.viterbi.unknown_pos_id,
+        },
+        NodeKind::Dict { .. } => Some(dict.node_entry(node).map(|e| e.right_id).unwrap_or(0)),
+        NodeKind::Pinned { pos_id } => Some(pos_id),
+    }
+}
+
+/// Connection cost from `prev_node` into left id `left_id`, 0 if `prev_node` does not connect.
+fn connection_cost(dict: &mut Dictionary, prev_node: &LatticeNode, left_id: u16) -> i32 {
+    node_right_id(dict, prev_node).map_or(0, |prev_right_id| {
+        dict.get_matrix_cost(prev_right_id, left_id) as i32
+    })
+}
+
+/// Cost of stepping from `prev_node` into a node with the given left id and word cost.
+fn edge_cost(dict: &mut Dictionary, prev_node: &LatticeNode, left_id: u16, word_cost: i16) -> i32 {
+    word_cost as i32 + connection_cost(dict, prev_node, left_id)
+}
+
+/// Cost of stepping from `prev_node` into a legacy unknown node: the flat unknown cost, plus the
+/// connection into [`ViterbiConfig::unknown_pos_id`] when there is one.
+fn unknown_edge_cost(dict: &mut Dictionary, prev_node: &LatticeNode) -> i32 {
+    let connection = match dict.viterbi.unknown_pos_id {
+        Some(pos_id) => connection_cost(dict, prev_node, pos_id),
+        None => 0,
+    };
+    dict.viterbi.unknown_cost.saturating_add(connection)
+}
+
+/// Cost of ending the path after `node`: the connection into EOS, whose left id is 0, from
+/// [`AnalysisCompat::V2`] on.
+fn eos_cost(dict: &mut Dictionary, node: &LatticeNode) -> i32 {
+    if !dict.compat.connects_eos() {
+        return 0;
+    }
+    connection_cost(dict, node, 0)
+}
+
+/// Strings of entries along a path, readings or lemmas, gathered so that the stored ones of system
+/// and user entries are each read through their own dictionary in one go.
+#[derive(Default)]
+struct PathStrings {
+    slots: Vec<StringSlot>,
+    spans: [Vec<(u32, u16)>; 2],
+}
+
+enum StringSlot {
+    Missing,
+    Inline(String),
+    /// Index into the spans of the system (0) or user (1) dictionary.
+    Span(usize, usize),
+}
+
+impl PathStrings {
+    /// Adds the next string, of a user entry if `user`.
+    fn push(&mut self, user: bool, string: Option<ReadingRef>) {
+        let source = user as usize;
+        self.slots.push(match string {
+            None => StringSlot::Missing,
+            Some(ReadingRef::Inline(string)) => StringSlot::Inline(string.to_string()),
+            Some(ReadingRef::Stored { offset, len }) => {
+                self.spans[source].push((offset, len));
+                StringSlot::Span(source, self.spans[source].len() - 1)
+            }
+        });
+    }
+
+    /// The strings in the order they were added.
+    fn read(self, dict: &mut Dictionary) -> Vec<Option<String>> {
+        let system = dict.read_readings(&self.spans[0]);
+        let user = match dict.user.as_deref_mut() {
+            Some(user) => user.read_readings(&self.spans[1]),
+            None => Vec::new(),
+        };
+        let mut strings: [Vec<Option<String>>; 2] =
+            [system, user].map(|r| r.into_iter().map(Some).collect());
+        self.slots
+            .into_iter()
+            .map(|slot| match slot {
+                StringSlot::Missing => None,
+                StringSlot::Inline(string) => Some(string),
+                StringSlot::Span(source, i) => strings[source][i].take(),
+            })
+            .collect()
+    }
+}
+
+/// The lemmas of the entries along `path`, `None` where there is none.
+fn path_lemmas(
+    dict: &mut Dictionary,
+    nodes: &Nodes,
+    path: &[(usize, usize)],
+) -> Vec<Option<String>> {
+    let mut lemmas = PathStrings::default();
+    for &(pos, idx) in path {
+        let node = &nodes[pos][idx];
+        lemmas.push(node.is_user(), dict.node_entry(node).and_then(|e| e.lemma));
+    }
+    lemmas.read(dict)
+}
+
+/// Dictionary readings of the nodes along `path`, decoded together with
+/// [`Dictionary::read_readings`]. `None` for unknown nodes, whose reading is their surface, and
+/// for entries that cannot be found. Entries without a reading get their surface under
+/// [`MissingReading::Surface`]. An unknown node starting with the iteration mark 々 right after a
+/// known node does get a reading: the known node's reading once per 々, then the rest of its
+/// surface.
+fn path_readings(
+    dict: &mut Dictionary,
+    nodes: &Nodes,
+    path: &[(usize, usize)],
+    chars: &[char],
+) -> Vec<Option<String>> {
+    let mut readings = PathStrings::default();
+    for &(pos, idx) in path {
+        let node = &nodes[pos][idx];
+        readings.push(node.is_user(), dict.node_entry(node).map(|e| e.reading));
+    }
+    let readings = readings.read(dict);
+    let use_surface = dict.missing_reading == MissingReading::Surface;
+    let mut readings: Vec<Option<String>> = readings
+        .into_iter()
+        .zip(path)
+        .map(|(reading, &(pos, idx))| match reading {
+            Some(reading) if use_surface && is_missing_reading(&reading) => dict
+                .node_entry(&nodes[pos][idx])
+                .map(|e| e.surface.to_string()),
+            reading => reading,
+        })
+        .collect();
+    repeat_iteration_marks(nodes, path, chars, &mut readings);
+    readings
+}
+
+/// Reads the 々 marks that open an unknown node as repeats of the known node before it. The
+/// repeat is the plain reading, without the voicing 人々 (ひとびと) takes.
+fn repeat_iteration_marks(
+    nodes: &Nodes,
+    path: &[(usize, usize)],
+    chars: &[char],
+    readings: &mut [Option<String>],
+) {
+    for i in 1..path.len() {
+        let (pos, idx) = path[i];
+        let node = &nodes[pos][idx];
+        let surface = &chars[node.start_pos..node.end_pos];
+        let marks = surface.iter().take_while(|&&c| c == '々').count();
+        let (prev_pos, prev_idx) = path[i - 1];
+        if marks == 0 || !node.is_unknown() || nodes[prev_pos][prev_idx].is_unknown() {
+            continue;
+        }
+        let Some(previous) = readings[i - 1].as_deref() else {
+            continue;
+        };
+        let mut reading = previous.repeat(marks);
+        reading.extend(&surface[marks..]);
+        readings[i] = Some(reading);
+    }
+}
+
+/// Joins the readings along `path`, unknown nodes contributing their surface.
+fn path_reading(
+    dict: &mut Dictionary,
+    nodes: &Nodes,
+    path: &[(usize, usize)],
+    chars: &[char],
+) -> String {
+    let mut result = String::new();
+    let pushed = for_each_path_reading(dict, nodes, path, chars, |reading| {
+        result.push_str(reading);
+        Ok::<_, Infallible>(())
+    });
+    let Ok(()) = pushed;
+    result
+}
+
+/// Hands the reading of each node on `path` to `emit` in text order, stopping at its first
+/// error. Unknown nodes without a reading give their surface; other nodes without one are
+/// skipped.
+fn for_each_path_reading<E>(
+    dict: &mut Dictionary,
+    nodes: &Nodes,
+    path: &[(usize, usize)],
+    chars: &[char],
+    mut emit: impl FnMut(&str) -> Result<(), E>,
+) -> Result<(), E> {
+    for (&(pos, idx), reading) in path.iter().zip(path_readings(dict, nodes, path, chars)) {
+        let node = &nodes[pos][idx];
+        match reading {
+            Some(reading) => emit(&reading)?,
+            None if node.is_unknown() => emit(
+                &chars[node.start_pos..node.end_pos]
+                    .iter()
+                    .collect::<String>(),
+            )?,
+            None => {}
+        }
+    }
+    Ok(())
+}
+
+/// Backtracks from the end node that is cheapest including its EOS cost, returning the path as
+/// `(end_pos, node_idx)` pairs in text order and its total cost. The BOS node is not included.
+fn best_path(dict: &mut Dictionary, nodes: &Nodes) -> (NodePath, i32) {
+    let len = nodes.len() - 1;
+    let mut path = Vec::new();
+
+    let mut best: Option<(usize, i32)> = None;
+    for (idx, node) in nodes[len].iter().enumerate() {
+        let total = node.cost.saturating_add(eos_cost(dict, node));
+        if best.is_none_or(|(_, cost)| total < cost) {
+            best = Some((idx, total));
+        }
+    }
+
+    if let Some((current_node_idx, _)) = best {
+        let mut current_pos = len;
+        let mut current_node_idx = current_node_idx;
+
+        while current_pos > 0 {
+            let node = &nodes[current_pos][current_node_idx];
+            if node.kind == NodeKind::Bos {
+                break;
+            }
+
+            path.push((current_pos, current_node_idx));
+
+            if let Some(prev_idx) = node.prev_node {
+                current_pos = node.start_pos;
+                current_node_idx = prev_idx;
+            } else {
+                break;
+            }
+        }
+    }
+
+    path.reverse();
+    (path, best.map_or(0, |(_, cost)| cost))
+}
+
+/// Builds the token for `node`, slicing its surface out of `text`. `byte_offsets[i]` is the byte
+/// offset of char `i`, with one trailing entry for `text.len()`; `reading` comes from
+/// [`path_readings`].
+fn node_token<'t>(
+    dict: &mut Dictionary,
+    node: &LatticeNode,
+    reading: Option<String>,
+    text: &'t str,
+    byte_offsets: &[usize],
+) -> Option<Token<'t>> {
+    let (start_byte, end_byte) = (byte_offsets[node.start_pos], byte_offsets[node.end_pos]);
+    let surface = &text[start_byte..end_byte];
+    // User entries use the system id space, so their names come from `dict` too.
+    let pos_name = |dict: &Dictionary, pos_id| dict.pos_name(pos_id).map(|n| n.to_string().into());
+    if node.is_unknown() {
+        let template = node
+            .template()
+            .and_then(|t| dict.unknown_template(t))
+            .map(|t| (t.left_id, t.cost));
+        let legacy_pos_id = dict.viterbi.unknown_pos_id.unwrap_or(0);
+        let (pos_id, word_cost) = template.unwrap_or((legacy_pos_id, 0));
+        Some(Token {
+            surface,
+            reading: reading.map_or(Cow::Borrowed(surface), Cow::Owned),
+            pos_id,
+            pos: template.and_then(|_| pos_name(dict, pos_id)),
+            word_cost,
+            is_unknown: true,
+            is_punctuation: punctuation::is_punctuation(surface),
+            start_byte,
+            end_byte,
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        })
+    } else if let NodeKind::Pinned { pos_id } = node.kind {
+        Some(Token {
+            surface,
+            reading: reading.map_or(Cow::Borrowed(surface), Cow::Owned),
+            pos_id,
+            pos: pos_name(dict, pos_id),
+            word_cost: 0,
+            is_unknown: false,
+            is_punctuation: false,
+            start_byte,
+            end_byte,
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        })
+    } else {
+        let entry = dict.node_entry(node)?;
+        let (pos_id, word_cost, accent) = (entry.pos_id, entry.word_cost, entry.accent);
+        let pronunciation = entry.pronunciation.map(|p| Cow::Owned(p.to_string()));
+        Some(Token {
+            surface,
+            reading: Cow::Owned(reading?),
+            pos_id,
+            pos: pos_name(dict, pos_id),
+            word_cost,
+            is_unknown: false,
+            is_punctuation: false,
+            start_byte,
+            end_byte,
+            pronunciation,
+            accent,
+            lemma: None,
+        })
+    }
+}
+
+/// Checks that every entry of `user` has context ids inside the matrix of `data`.
+fn check_user_entries(data: &DictionaryData, user: &mut Dictionary) -> Result<(), MucabError> {
+    if user.data.reading_kana != data.reading_kana {
+        return Err(MucabError::Corrupt(format!(
+            "user dictionary readings are stored as {:?}, the dictionary's as {:?}",
+            user.data.reading_kana, data.reading_kana
+        )));
+    }
+    let first_chars: Vec<char> = user
+        .data
+        .index
+        .keys()
+        .chain(user.added.keys())
+        .copied()
+        .collect();
+    for first_char in first_chars {
+        let block = user.load_block(first_char);
+        for entry in block.iter() {
+            data.check_context_ids(
+                || format!("user entry {:?}", entry.surface),
+                entry.pos_id,
+                entry.right_id,
+            )?;
+        }
+    }
+    match user.take_error() {
+        Some(error) => Err(error),
+        None => Ok(()),
+    }
+}
+
+/// The form of the readings stored in a file whose header has `flags`.
+fn reading_kana_from_flags(flags: u16) -> KanaForm {
+    match (
+        flags & FLAG_HIRAGANA_READINGS != 0,
+        flags & FLAG_KEEP_VU != 0,
+    ) {
+        (false, _) => KanaForm::Katakana,
+        (true, false) => KanaForm::Hiragana,
+        (true, true) => KanaForm::HiraganaKeepVu,
+    }
+}
+
+/// `error` from reading the entry block of `first_char` at `byte_offset` into the entries, which
+/// a corrupt block's message names.
+fn block_error(first_char: char, byte_offset: u64, error: MucabError) -> MucabError {
+    match error {
+        MucabError::Corrupt(message) => MucabError::Corrupt(format!(
+            "entry block {:?} at byte {}: {}",
+            first_char, byte_offset, message
+        )),
+        error => error,
+    }
+}
+
+/// `error` from reading the reading at `offset` into the strings, which a corrupt reading's
+/// message names.
+fn reading_error(offset: u32, error: MucabError) -> MucabError {
+    match error {
+        MucabError::Corrupt(message) => MucabError::Corrupt(format!(
+            "reading at byte {} of the strings: {}",
+            offset, message
+        )),
+        error => error,
+    }
+}
+
+fn not_utf8(error: std::string::FromUtf8Error) -> MucabError {
+    MucabError::Corrupt(format!("not UTF-8 ({})", error.utf8_error()))
+}
+
+fn is_sentence_end(c: char) -> bool {
+    matches!(c, '。' | '！' | '？')
+}
+
+/// Whether `chunk`, a piece from [`lattice_chunks`], is a run of whitespace.
+fn is_whitespace_run(chunk: &str) -> bool {
+    chunk.starts_with(char::is_whitespace)
+}
+
+/// Cuts `text` into the pieces analysed as separate lattices. Runs of whitespace are pieces of
+/// their own, copied through rather than analysed. The text between them is one piece if it has
+/// at most `max_chars` characters, otherwise as many whole sentences as fit in each piece, or
+/// `max_chars` characters of a sentence that does not fit on its own.
+fn lattice_chunks(text: &str, max_chars: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut rest = text;
+    while !rest.is_empty() {
+        let blank = is_whitespace_run(rest);
+        let cut = rest
+            .find(|c: char| c.is_whitespace() != blank)
+            .unwrap_or(rest.len());
+        let (segment, tail) = rest.split_at(cut);
+        if blank {
+            chunks.push(segment);
+        } else {
+            push_sentence_chunks(segment, max_chars, &mut chunks);
+        }
+        rest = tail;
+    }
+    chunks
+}
+
+fn push_sentence_chunks<'t>(text: &'t str, max_chars: usize, chunks: &mut Vec<&'t str>) {
+    let mut rest = text;
+    while !rest.is_empty() {
+        let mut cut = rest.len();
+        let mut sentence_end = None;
+        for (count, (i, c)) in rest.char_indices().enumerate() {
+            if count == max_chars {
+                cut = sentence_end.unwrap_or(i);
+                break;
+            }
+            if is_sentence_end(c) {
+                sentence_end = Some(i + c.len_utf8());
+            }
+        }
+        let (chunk, tail) = rest.split_at(cut);
+        chunks.push(chunk);
+        rest = tail;
+    }
+}
+
+/// A piece of a text, as [`for_each_piece`] hands it over.
+enum Piece<'p> {
+    /// A run of whitespace, copied through.
+    Blank(&'p str),
+    /// Text of `chars` characters no path gets through, copied through.
+    Unreached { text: &'p str, chars: usize },
+    /// Text analysed along its cheapest `path`, whose total cost, EOS included, is `cost`.
+    Path {
+        text: &'p str,
+        nodes: &'p Nodes,
+        chars: &'p [char],
+        path: &'p NodePath,
+        cost: i32,
+    },
+}
+
+impl<'p> Piece<'p> {
+    /// The text the piece covers.
+    fn text(&self) -> &'p str {
+        match *self {
+            Piece::Blank(text) | Piece::Unreached { text, .. } | Piece::Path { text, .. } => text,
+        }
+    }
+
+    /// Hands the reading of the piece to `emit`, a token at a time along a path, stopping at its
+    /// first error.
+    fn for_each_reading<E>(
+        &self,
+        dict: &mut Dictionary,
+        mut emit: impl FnMut(&str) -> Result<(), E>,
+    ) -> Result<(), E> {
+        match *self {
+            Piece::Blank(text) | Piece::Unreached { text, .. } => emit(text),
+            Piece::Path {
+                nodes, chars, path, ..
+            } => for_each_path_reading(dict, nodes, path, chars, emit),
+        }
+    }
+
+    /// Appends the reading of the piece to `output`.
+    fn push_reading(&self, dict: &mut Dictionary, output: &mut String) -> Result<(), Infallible> {
+        self.for_each_reading(dict, |reading| {
+            output.push_str(reading);
+            Ok(())
+        })
+    }
+}
+
+/// Cuts `text` with [`lattice_chunks`], finds the cheapest path through each piece that is not
+/// whitespace, and hands the pieces to `sink` in order, stopping at its first error. Once the
+/// [`Stop`] of `dict` fires, the piece under way is dropped and no more are handed over. With
+/// `stats`, the lookup and search times and the lattice and path sizes are added to it.
+fn for_each_piece<E>(
+    text: &str,
+    dict: &mut Dictionary,
+    mut stats: Option<&mut TransliterateStats>,
+    mut sink: impl FnMut(&mut Dictionary, Piece) -> Result<(), E>,
+) -> Result<(), E> {
+    // The clock is only read for stats.
+    let timing = stats.is_some();
+    let elapsed_ns = |since: Option<Instant>| since.map_or(0, |t| t.elapsed().as_nanos() as u64);
+    for chunk in lattice_chunks(text, dict.max_lattice_chars) {
+        if is_whitespace_run(chunk) {
+            sink(dict, Piece::Blank(chunk))?;
+            continue;
+        }
+        let started = timing.then(Instant::now);
+        let (lattice, chars) = build_lattice(chunk, dict);
+        let lookup_ns = elapsed_ns(started);
+
+        let started = timing.then(Instant::now);
+        let nodes = search_lattice(&lattice, &chars, dict, &[]);
+        drop(lattice);
+        if dict.stop.as_ref().is_some_and(Stop::fired) {
+            break;
+        }
+        let reached = !nodes[chars.len()].is_empty();
+        let (path, cost) = if reached {
+            best_path(dict, &nodes)
+        } else {
+            (Vec::new(), 0)
+        };
+        if let Some(stats) = stats.as_deref_mut() {
+            stats.lookup_ns += lookup_ns;
+            stats.viterbi_ns += elapsed_ns(started);
+            stats.largest_lattice = stats.largest_lattice.max(chars.len());
+            stats.nodes += nodes.items();
+            stats.tokens += path.len();
+        }
+
+        let piece = if reached {
+            Piece::Path {
+                text: chunk,
+                nodes: &nodes,
+                chars: &chars,
+                path: &path,
+                cost,
+            }
+        } else {
+            Piece::Unreached {
+                text: chunk,
+                chars: chars.len(),
+            }
+        };
+        sink(dict, piece)?;
+    }
+    Ok(())
+}
+
+/// Converts `text` to its reading along the cheapest path, returning a newly allocated `String`.
+/// Runs of whitespace split the text: they are copied to the output as they are, and the text
+/// between them is converted independently. Texts longer than [`Dictionary::max_lattice_chars`]
+/// are converted a few sentences at a time.
+pub fn transliterate<'a>(text: &str, dict: &mut Dictionary<'a>) -> String {
+    let mut output = String::new();
+    let Ok(()) = for_each_piece(text, dict, None, |dict, piece| {
+        piece.push_reading(dict, &mut output)
+    });
+    output
+}
+
+/// [`transliterate`], writing the reading to `writer` as it goes instead of collecting it: each
+/// piece the text is cut into is written as soon as its path is found, one token at a time, so
+/// only one piece's lattice and path are held at once. `writer` is not flushed.
+///
+/// Errors are those of `writer`, as [`MucabError::Io`]; whatever was written before one stays
+/// written.
+pub fn transliterate_to<W: Write>(
+    text: &str,
+    dict: &mut Dictionary,
+    mut writer: W,
+) -> Result<(), MucabError> {
+    let mut emit = |reading: &str| writer.write_all(reading.as_bytes());
+    for_each_piece(text, dict, None, |dict, piece| {
+        piece.for_each_reading(dict, &mut emit)
+    })
+    .map_err(MucabError::Io)
+}
+
+/// [`transliterate`], giving up once `timeout` has passed: the error is [`MucabError::Timeout`],
+/// holding the reading of the pieces the text was cut into that were converted by then.
+///
+/// The clock is read while building each piece's lattice and while searching it, at its first
+/// character and every 256 after, so a call overruns its deadline by at most the time 256
+/// characters of either take, plus reading out the path of a piece whose search had finished.
+/// The lattice of the piece under way is dropped before returning.
+pub fn transliterate_with_deadline(
+    text: &str,
+    dict: &mut Dictionary,
+    timeout: Duration,
+) -> Result<String, MucabError> {
+    transliterate_until(text, dict, Trigger::Deadline(Instant::now() + timeout))
+}
+
+/// [`transliterate`], giving up with [`MucabError::Cancelled`] once `cancel` is set, by another
+/// thread for instance. The flag is read where [`transliterate_with_deadline`] reads the clock.
+pub fn transliterate_cancellable(
+    text: &str,
+    dict: &mut Dictionary,
+    cancel: &Arc<AtomicBool>,
+) -> Result<String, MucabError> {
+    transliterate_until(text, dict, Trigger::Flag(cancel.clone()))
+}
+
+fn transliterate_until(
+    text: &str,
+    dict: &mut Dictionary,
+    trigger: Trigger,
+) -> Result<String, MucabError> {
+    dict.stop = Some(Stop::new(trigger));
+    let mut output = String::new();
+    let Ok(()) = for_each_piece(text, dict, None, |dict, piece| {
+        piece.push_reading(dict, &mut output)
+    });
+    match dict.stop.take() {
+        Some(stop) if stop.fired() && stop.is_deadline() => {
+            Err(MucabError::Timeout { partial: output })
+        }
+        Some(stop) if stop.fired() => Err(MucabError::Cancelled { partial: output }),
+        _ => Ok(output),
+    }
+}
+
+/// Where the time of one [`transliterate_with_stats`] call went.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TransliterateStats {
+    /// Building the lattice: dictionary lookups and unknown-word candidates.
+    pub lookup_ns: u64,
+    /// Connecting the lattice and picking the cheapest path.
+    pub viterbi_ns: u64,
+    /// Decoding the readings along the path.
+    pub readings_ns: u64,
+    /// Lattice nodes, the BOS node included.
+    pub nodes: usize,
+    /// Nodes on the chosen path.
+    pub tokens: usize,
+    /// Chars of the longest piece of the text analysed in one lattice.
+    pub largest_lattice: usize,
+}
+
+/// [`transliterate`], also timing each stage. Counts and times add up over the pieces a long text
+/// is cut into.
+pub fn transliterate_with_stats(text: &str, dict: &mut Dictionary) -> (String, TransliterateStats) {
+    let mut output = String::new();
+    let mut stats = TransliterateStats::default();
+    let mut readings_ns = 0;
+    let Ok(()) = for_each_piece(text, dict, Some(&mut stats), |dict, piece| {
+        let started = Instant::now();
+        piece.push_reading(dict, &mut output)?;
+        readings_ns += started.elapsed().as_nanos() as u64;
+        Ok::<_, Infallible>(())
+    });
+    stats.readings_ns = readings_ns;
+    (output, stats)
+}
+
+/// The result of [`transliterate_detailed`]: the conversion and how confident it was.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TransliterateDetails {
+    /// The same string [`transliterate`] returns.
+    pub output: String,
+    /// Total cost of the chosen path, EOS connection included: the quantity the search
+    /// minimises. Summed over the pieces a long text is cut into.
+    pub cost: i32,
+    /// Tokens on the chosen path.
+    pub tokens: usize,
+    /// Characters covered by unknown-word tokens, copied through without a dictionary reading.
+    /// Whitespace between the analysed pieces counts neither here nor in `tokens`.
+    pub unknown_chars: usize,
+}
+
+impl TransliterateDetails {
+    /// The share of input characters that went through the unknown fallback, from 0 to 1.
+    pub fn unknown_ratio(&self, text: &str) -> f64 {
+        let total = text.chars().count();
+        if total == 0 {
+            0.0
+        } else {
+            self.unknown_chars as f64 / total as f64
+        }
+    }
+}
+
+/// [`transliterate`], also reporting the path cost and how much of `text` had no dictionary
+/// entry. If no path reaches the end, the text is copied unchanged and every character counts as
+/// unknown.
+pub fn transliterate_detailed(text: &str, dict: &mut Dictionary) -> TransliterateDetails {
+    let mut details = TransliterateDetails::default();
+    let Ok(()) = for_each_piece(text, dict, None, |dict, piece| {
+        match piece {
+            Piece::Blank(_) => {}
+            Piece::Unreached { chars, .. } => details.unknown_chars += chars,
+            Piece::Path {
+                nodes, path, cost, ..
+            } => {
+                details.unknown_chars += path
+                    .iter()
+                    .map(|&(pos, idx)| &nodes[pos][idx])
+                    .filter(|node| node.is_unknown())
+                    .map(|node| node.end_pos - node.start_pos)
+                    .sum::<usize>();
+                details.cost = details.cost.saturating_add(cost);
+                details.tokens += path.len();
+            }
+        }
+        piece.push_reading(dict, &mut details.output)
+    });
+    details
+}
+
+/// Converts `text` to Hepburn romaji: the readings along the cheapest path, romanized token by
+/// token (see [`romaji`]). Text without a kana reading is copied unchanged.
+pub fn transliterate_romaji(
+    text: &str,
+    dict: &mut Dictionary,
+    style: romaji::RomajiStyle,
+) -> String {
+    let tokens = tokenize(text, dict);
+    romaji::readings_to_romaji(tokens.iter().map(|t| t.reading.as_ref()), style)
+}
+
+/// Converts `text` token by token, emitting what `f` returns for each token of [`tokenize`],
+/// unknown and whitespace tokens included. With `|token| token.reading.to_string()` it gives the
+/// same string as [`transliterate`].
+pub fn transliterate_map(
+    text: &str,
+    dict: &mut Dictionary,
+    mut f: impl FnMut(&Token) -> String,
+) -> String {
+    tokenize(text, dict).iter().map(&mut f).collect()
+}
+
+/// `text` with each dictionary word annotated with its hiragana reading, as
+/// [`furigana::RubyFormat`] describes. See [`furigana`] for which parts get a reading.
+pub fn transliterate_furigana(
+    text: &str,
+    dict: &mut Dictionary,
+    format: furigana::RubyFormat,
+) -> String {
+    let tokens = tokenize(text, dict);
+    let segments = furigana::ruby_segments(&tokens, KanaForm::Hiragana);
+    furigana::format_ruby(&segments, format)
+}
+
+/// Splits `text` along the cheapest path, returning one token per lattice node. Text not covered
+/// by the dictionary becomes unknown tokens whose reading is the surface itself: grouped by
+/// character category when the dictionary carries char.def data and its [`AnalysisCompat`] level
+/// uses it; otherwise one per run of characters no entry starts with (one per character before
+/// [`AnalysisCompat::V3`]). An unknown token opening with 々 after a known token repeats that
+/// token's reading instead.
+/// Joining the readings gives the same string as [`transliterate`].
+///
+/// Each run of whitespace becomes one unknown token, and the text between runs is tokenized
+/// independently. Texts longer than [`Dictionary::max_lattice_chars`] are tokenized a few
+/// sentences at a time.
+///
+/// The returned [`Token`]s borrow their surfaces from `text`; use [`Token::into_owned`] to keep
+/// them around independently.
+pub fn tokenize<'t>(text: &'t str, dict: &mut Dictionary) -> Vec<Token<'t>> {
+    let mut offset = 0;
+    lattice_chunks(text, dict.max_lattice_chars)
+        .into_iter()
+        .flat_map(|chunk| {
+            let tokens = tokenize_chunk(chunk, dict);
+            let chunk_offset = offset;
+            offset += chunk.len();
+            tokens.into_iter().map(move |t| t.offset_by(chunk_offset))
+        })
+        .collect()
+}
+
+/// One unknown token over all of `text`, for pieces that are not analysed.
+fn passthrough_token(text: &str) -> Token<'_> {
+    Token {
+        surface: text,
+        reading: Cow::Borrowed(text),
+        pos_id: 0,
+        pos: None,
+        word_cost: 0,
+        is_unknown: true,
+        is_punctuation: punctuation::is_punctuation(text),
+        start_byte: 0,
+        end_byte: text.len(),
+        pronunciation: None,
+        accent: None,
+        lemma: None,
+    }
+}
+
+fn tokenize_chunk<'t>(text: &'t str, dict: &mut Dictionary) -> Vec<Token<'t>> {
+    if is_whitespace_run(text) {
+        return vec![passthrough_token(text)];
+    }
+    let (nodes, chars) = viterbi(text, dict);
+    path_tokens(text, dict, &nodes, &chars)
+}
+
+/// The tokens along the cheapest path through `nodes`, the lattice over `text`: one unknown
+/// token over all of it if no path gets through.
+fn path_tokens<'t>(
+    text: &'t str,
+    dict: &mut Dictionary,
+    nodes: &Nodes,
+    chars: &[char],
+) -> Vec<Token<'t>> {
+    if nodes[chars.len()].is_empty() {
+        return vec![passthrough_token(text)];
+    }
+    let (path, _) = best_path(dict, nodes);
+    path_node_tokens(text, dict, nodes, chars, &path)
+        .into_iter()
+        .map(|(_, token)| token)
+        .collect()
+}
+
+/// The tokens along `path` through `nodes`, the lattice over `text`, each with its node.
+fn path_node_tokens<'t, 'n>(
+    text: &'t str,
+    dict: &mut Dictionary,
+    nodes: &'n Nodes,
+    chars: &[char],
+    path: &[(usize, usize)],
+) -> Vec<(&'n LatticeNode, Token<'t>)> {
+    let byte_offsets: Vec<usize> = text
+        .char_indices()
+        .map(|(i, _)| i)
+        .chain(std::iter::once(text.len()))
+        .collect();
+
+    let readings = path_readings(dict, nodes, path, chars);
+    let lemmas = path_lemmas(dict, nodes, path);
+    path.iter()
+        .zip(readings.into_iter().zip(lemmas))
+        .filter_map(|(&(pos, idx), (reading, lemma))| {
+            let node = &nodes[pos][idx];
+            let token = node_token(dict, node, reading, text, &byte_offsets)?;
+            Some((
+                node,
+                Token {
+                    lemma: lemma.map(Cow::Owned),
+                    ..token
+                },
+            ))
+        })
+        .collect()
+}
+
+/// Like [`tokenize`], but returns an owned [`Analysis`] that also records each token's char span
+/// and the running path cost, suitable for snapshotting. The text is cut into pieces as
+/// [`tokenize`] cuts it; costs run on from one piece to the next, and a whitespace run or a piece
+/// no path gets through adds nothing to them.
+pub fn analyze(text: &str, dict: &mut Dictionary) -> Analysis {
+    let mut analysis = Analysis {
+        text: text.to_string(),
+        tokens: Vec::new(),
+        total_cost: 0,
+    };
+    let (mut byte_start, mut char_start) = (0, 0);
+    let Ok(()) = for_each_piece(text, dict, None, |dict, piece| {
+        let piece_text = piece.text();
+        let path_cost = analysis.total_cost;
+        match piece {
+            Piece::Blank(text) | Piece::Unreached { text, .. } => {
+                analysis.tokens.push(AnalysisToken {
+                    token: passthrough_token(text).offset_by(byte_start).into_owned(),
+                    start: char_start,
+                    end: char_start + text.chars().count(),
+                    path_cost,
+                });
+            }
+            Piece::Path {
+                text,
+                nodes,
+                chars,
+                path,
+                cost,
+            } => {
+                for (node, token) in path_node_tokens(text, dict, nodes, chars, path) {
+                    analysis.tokens.push(AnalysisToken {
+                        token: token.offset_by(byte_start).into_owned(),
+                        start: char_start + node.start_pos,
+                        end: char_start + node.end_pos,
+                        path_cost: path_cost.saturating_add(node.cost),
+                    });
+                }
+                analysis.total_cost = path_cost.saturating_add(cost);
+            }
+        }
+        byte_start += piece_text.len();
+        char_start += piece_text.chars().count();
+        Ok::<_, Infallible>(())
+    });
+    analysis
+}
+
+/// Returns up to `n` distinct conversions of `text`, cheapest first, each as an owned `String`
+/// with its total path cost.
+///
+/// Paths are enumerated with an A* search running backwards from the end of the lattice, using the
+/// Viterbi forward costs as an exact heuristic. Paths whose joined readings are identical are only
+/// reported once. Ties between equally expensive paths are resolved in favour of the path the
+/// forward pass would pick, so the first candidate always equals [`transliterate`]'s output.
+///
+/// The text is cut into pieces as [`transliterate`] cuts it, and the candidates of each piece are
+/// combined into the cheapest conversions of the whole text.
+pub fn transliterate_nbest<'a>(
+    text: &str,
+    dict: &mut Dictionary<'a>,
+    n: usize,
+) -> Vec<(String, i32)> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let mut pieces = Vec::new();
+    let Ok(()) = for_each_piece(text, dict, None, |dict, piece| {
+        pieces.push(piece_nbest(dict, &piece, n));
+        Ok::<_, Infallible>(())
+    });
+    combine_nbest(&pieces, n)
+        .into_iter()
+        .map(|(_, reading, cost)| (reading, cost))
+        .collect()
+}
+
+/// Like [`transliterate_nbest`], but returns each path as the tokens along it.
+pub fn tokenize_nbest<'t>(
+    text: &'t str,
+    dict: &mut Dictionary,
+    n: usize,
+) -> Vec<(Vec<Token<'t>>, i32)> {
+    if n == 0 {
+        return Vec::new();
+    }
+    let mut pieces = Vec::new();
+    let mut piece_tokens: Vec<Vec<Vec<Token<'t>>>> = Vec::new();
+    let mut byte_start = 0;
+    let Ok(()) = for_each_piece(text, dict, None, |dict, piece| {
+        // The same span as the piece's text, but borrowed for `'t`.
+        let piece_text = &text[byte_start..byte_start + piece.text().len()];
+        let candidates = piece_nbest(dict, &piece, n);
+        piece_tokens.push(
+            candidates
+                .iter()
+                .map(|(path, _, _)| match piece {
+                    Piece::Path { nodes, chars, .. } => {
+                        path_node_tokens(piece_text, dict, nodes, chars, path)
+                            .into_iter()
+                            .map(|(_, token)| token.offset_by(byte_start))
+                            .collect()
+                    }
+                    _ => vec![passthrough_token(piece_text).offset_by(byte_start)],
+                })
+                .collect(),
+        );
+        pieces.push(candidates);
+        byte_start += piece_text.len();
+        Ok::<_, Infallible>(())
+    });
+    combine_nbest(&pieces, n)
+        .into_iter()
+        .map(|(picks, _, cost)| {
+            let tokens = picks
+                .into_iter()
+                .enumerate()
+                .flat_map(|(piece, pick)| piece_tokens[piece][pick].iter().cloned())
+                .collect();
+            (tokens, cost)
+        })
+        .collect()
+}
+
+/// Up to `n` candidates of one piece, as [`nbest_paths`] gives them; a piece without a path is
+/// its own single candidate, with an empty path and no cost.
+fn piece_nbest(dict: &mut Dictionary, piece: &Piece, n: usize) -> Vec<(NodePath, String, i32)> {
+    match *piece {
+        Piece::Blank(text) | Piece::Unreached { text, .. } => {
+            vec![(Vec::new(), text.to_string(), 0)]
+        }
+        Piece::Path { nodes, chars, .. } => nbest_paths(dict, nodes, chars, n),
+    }
+}
+
+/// Up to `n` cheapest ways to pick one of the candidates of every piece, cheapest first, each as
+/// the index picked in each piece, the joined reading and the total cost. `pieces` holds the
+/// candidates of each piece, cheapest first. Picks with the same joined reading are kept once;
+/// among equally cheap picks, earlier candidates win, so the first pick takes the first
+/// candidate of every piece.
+fn combine_nbest(
+    pieces: &[Vec<(NodePath, String, i32)>],
+    n: usize,
+) -> Vec<(Vec<usize>, String, i32)> {
+    let mut combined: Vec<(Vec<usize>, String, i32)> = vec![(Vec::new(), String::new(), 0)];
+    for candidates in pieces {
+        let mut sums: Vec<(i32, usize, usize)> = Vec::new();
+        for (i, &(_, _, cost)) in combined.iter().enumerate() {
+            for (j, &(_, _, piece_cost)) in candidates.iter().enumerate() {
+                sums.push((cost.saturating_add(piece_cost), i, j));
+            }
+        }
+        // Stable, so ties keep the order the picks were listed in.
+        sums.sort_by_key(|&(cost, _, _)| cost);
+
+        let mut next = Vec::new();
+        let mut seen = HashSet::new();
+        for (cost, i, j) in sums {
+            let reading = format!("{}{}", combined[i].1, candidates[j].1);
+            if seen.insert(reading.clone()) {
+                let mut picks: Vec<usize> = combined[i].0.clone();
+                picks.push(j);
+                next.push((picks, reading, cost));
+                if next.len() == n {
+                    break;
+                }
+            }
+        }
+        combined = next;
+    }
+    combined
+}
+
+/// The A* enumeration behind [`transliterate_nbest`]: up to `n` paths with distinct readings,
+/// each as `(end_pos, node_idx)` pairs in text order (BOS excluded), its joined reading and cost.
+fn nbest_paths(
+    dict: &mut Dictionary,
+    nodes: &Nodes,
+    chars: &[char],
+    n: usize,
+) -> Vec<(NodePath, String, i32)> {
+    let len = nodes.len() - 1;
+
+    // Each search state is a suffix of a path: a node plus the cost of everything after it.
+    // `next` links back towards the end of the text, so walking it yields the path in order.
+    struct State {
+        pos: usize,
+        idx: usize,
+        suffix_cost: i32,
+        next: Option<usize>,
+    }
+
+    let mut states: Vec<State> = Vec::new();
+    // Max-heap on (lowest f first, most recently pushed first); together with pushing candidates
+    // in reverse order this reproduces the forward pass's first-minimum tie-breaking.
+    let mut heap: BinaryHeap<(Reverse<i32>, usize)> = BinaryHeap::new();
+
+    for idx in (0..nodes[len].len()).rev() {
+        let suffix_cost = eos_cost(dict, &nodes[len][idx]);
+        states.push(State {
+            pos: len,
+            idx,
+            suffix_cost,
+            next: None,
+        });
+        heap.push((
+            Reverse(nodes[len][idx].cost.saturating_add(suffix_cost)),
+            states.len() - 1,
+        ));
+    }
+
+    let mut results = Vec::new();
+    let mut seen = HashSet::new();
+
+    while let Some((Reverse(_), state_id)) = heap.pop() {
+        let state = &states[state_id];
+        let node = nodes[state.pos][state.idx].clone();
+
+        if node.kind == NodeKind::Bos {
+            let mut path = Vec::new();
+            let mut cursor = state.next;
+            while let Some(id) = cursor {
+                let s = &states[id];
+                path.push((s.pos, s.idx));
+                cursor = s.next;
+            }
+            let joined = path_reading(dict, nodes, &path, chars);
+            if seen.insert(joined.clone()) {
+                results.push((path, joined, state.suffix_cost));
+                if results.len() == n {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        let suffix_cost = state.suffix_cost;
+        let mut candidates = Vec::new();
+        if let Some((left_id, word_cost)) = node_context(dict, &node) {
+            for (prev_idx, prev_node) in nodes[node.start_pos].iter().enumerate() {
+                candidates.push((prev_idx, edge_cost(dict, prev_node, left_id, word_cost)));
+            }
+        } else if node.is_unknown() {
+            // Legacy unknown nodes keep only their cheapest predecessor; branch over the others.
+            for (prev_idx, prev_node) in nodes[node.start_pos].iter().enumerate() {
+                candidates.push((prev_idx, unknown_edge_cost(dict, prev_node)));
+            }
+        }
+
+        for &(prev_idx, step) in candidates.iter().rev() {
+            let prev_suffix = suffix_cost.saturating_add(step);
+            states.push(State {
+                pos: node.start_pos,
+                idx: prev_idx,
+                suffix_cost: prev_suffix,
+                next: Some(state_id),
+            });
+            let f = nodes[node.start_pos][prev_idx]
+                .cost
+                .saturating_add(prev_suffix);
+            heap.push((Reverse(f), states.len() - 1));
+        }
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{DictionaryBuilder, Entry};
+    use crate::testutil::{compress_modes, fixture, load_built};
+
+    fn tokyo_fixture() -> Dictionary<'static> {
+        fixture(
+            &[
+                ("東京", "トーキョー", 0, 100),
+                ("都", "ト", 0, 100),
+                ("東京都", "トーキョート", 0, 250),
+                ("東", "ヒガシ", 0, 300),
+                ("京都", "キョート", 0, 100),
+                ("京", "ケイ", 0, 300),
+            ],
+            1,
+        )
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_nihon() {
+        let mut dict = Dictionary::load("out/mucab.bin").expect("Failed to load dictionary");
+        let result = transliterate("日本", &mut dict);
+        assert_eq!(result, "ニッポン");
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_nihongo() {
+        let mut dict = Dictionary::load("out/mucab.bin").expect("Failed to load dictionary");
+        let result = transliterate("日本語", &mut dict);
+        assert_eq!(result, "ニホンゴ");
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_tokyo() {
+        let mut dict = Dictionary::load("out/mucab.bin").expect("Failed to load dictionary");
+        let result = transliterate("東京", &mut dict);
+        assert_eq!(result, "トーキョー");
+    }
+
+    #[test]
+    fn test_nbest_ambiguous_segmentation() {
+        let mut dict = tokyo_fixture();
+        let results = transliterate_nbest("東京都", &mut dict, 5);
+        assert_eq!(
+            results,
+            vec![
+                ("トーキョート".to_string(), 200),
+                ("ヒガシキョート".to_string(), 400),
+                ("ヒガシケイト".to_string(), 700),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_nbest_first_matches_transliterate() {
+        let mut dict = tokyo_fixture();
+        for text in [
+            "東京都",
+            "京都",
+            "東京",
+            "東京X都",
+            "X東京都X",
+            "東京 京都\n",
+            "",
+        ] {
+            let best = transliterate(text, &mut dict);
+            let nbest = transliterate_nbest(text, &mut dict, 1);
+            assert_eq!(nbest.len(), 1);
+            assert_eq!(nbest[0].0, best, "for {:?}", text);
+        }
+
+        // Whitespace splits the text before any entry is looked up, so "New York" never matches.
+        let mut dict = fixture(&[("New York", "ニューヨーク", 0, 100)], 1);
+        let best = transliterate("New York", &mut dict);
+        assert_eq!(best, "New York");
+        assert_eq!(transliterate_nbest("New York", &mut dict, 2)[0].0, best);
+        let tokens = tokenize("New York", &mut dict);
+        assert!(tokens.iter().all(|t| t.is_unknown));
+        assert_eq!(tokenize_nbest("New York", &mut dict, 2)[0].0, tokens);
+        let analysis = analyze("New York", &mut dict);
+        let readings: String = analysis
+            .tokens
+            .iter()
+            .map(|t| t.token.reading.as_str())
+            .collect();
+        assert_eq!(readings, best);
+    }
+
+    #[test]
+    fn test_nbest_truncates_and_is_deterministic() {
+        let mut dict = tokyo_fixture();
+        let two = transliterate_nbest("東京都", &mut dict, 2);
+        assert_eq!(two.len(), 2);
+        assert_eq!(two, transliterate_nbest("東京都", &mut dict, 2));
+        assert!(transliterate_nbest("東京都", &mut dict, 0).is_empty());
+    }
+
+    fn context_builder() -> DictionaryBuilder {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry_with_context("甲", "コウ", 0, 1, 0);
+        builder.add_entry_with_context("乙", "オツ", 0, 0, 100);
+        builder.add_entry_with_context("乙", "キノト", 1, 0, 200);
+        // 2 right ids × 3 left ids; only "right id 1 followed by left id 1" is cheap.
+        let mut matrix = vec![0i16; 2 * 3];
+        matrix[3 + 1] = -500;
+        builder.set_connection_matrix(matrix, 2, 3);
+        builder
+    }
+
+    #[test]
+    fn test_connection_uses_prev_right_and_curr_left_ids() {
+        let mut dict = load_built(context_builder());
+        assert_eq!(transliterate("甲乙", &mut dict), "コウキノト");
+        assert_eq!(transliterate("乙", &mut dict), "オツ");
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_version_1_still_loads() {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("東京", "トーキョー", 0, 100);
+        builder.add_entry("都", "ト", 1, 100);
+        builder.set_matrix(vec![0, 0, 0, 0], 2);
+        builder.format_version(1);
+        let mut dict = load_built(builder);
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+    }
+
+    #[test]
+    fn test_version_1_rejects_split_ids() {
+        let mut builder = context_builder();
+        builder.format_version(1);
+        assert!(builder.write(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_pos_names() {
+        let pos_builder = || {
+            let mut builder = DictionaryBuilder::new();
+            builder.add_entry("東京", "トーキョー", 0, 100);
+            builder.add_entry("へ", "エ", 1, 100);
+            builder.set_matrix(vec![0; 4], 2);
+            builder.set_pos_name(0, "名詞,固有名詞,地域,一般,*,*");
+            builder
+        };
+        let mut dict = load_built(pos_builder());
+        assert_eq!(dict.pos_name(0), Some("名詞,固有名詞,地域,一般,*,*"));
+        assert_eq!(dict.pos_name(1), None);
+        assert_eq!(dict.pos_name(7), None);
+        let tokens = tokenize("東京へX", &mut dict);
+        let pos: Vec<Option<&str>> = tokens.iter().map(|t| t.pos.as_deref()).collect();
+        assert_eq!(pos, [Some("名詞,固有名詞,地域,一般,*,*"), None, None]);
+
+        let mut builder = pos_builder();
+        builder.format_version(3);
+        assert!(builder.write(Vec::new()).is_err());
+        // Version 3 is always compressed.
+        #[cfg(feature = "zstd")]
+        {
+            let mut builder = DictionaryBuilder::new();
+            builder.add_entry("東京", "トーキョー", 0, 100);
+            builder.set_matrix(vec![0], 1);
+            builder.format_version(3);
+            let mut dict = load_built(builder);
+            assert_eq!(dict.pos_name(0), None);
+            assert_eq!(tokenize("東京", &mut dict)[0].pos, None);
+        }
+    }
+
+    #[test]
+    fn test_oversized_block_is_rejected() {
+        let mut builder = DictionaryBuilder::new();
+        for i in 0..=u16::MAX as u32 {
+            builder.add_entry(&format!("あ{}", i), "ア", 0, 0);
+        }
+        builder.set_matrix(vec![0], 1);
+        let err = builder.write(Vec::new()).err().unwrap();
+        assert!(err.to_string().contains("entries start with 'あ'"));
+    }
+
+    #[test]
+    fn test_long_surfaces_round_trip() {
+        let surface = "長".repeat(100);
+        let reading = "ナ".repeat(100);
+        assert_eq!(surface.len(), 300);
+        let long_builder = || {
+            let mut builder = DictionaryBuilder::new();
+            builder.add_entry(&surface, &reading, 0, 100);
+            builder.add_entry("東京", "トーキョー", 0, 100);
+            builder.set_matrix(vec![0], 1);
+            builder
+        };
+
+        let mut dict = load_built(long_builder());
+        let text = format!("{}東京", surface);
+        let tokens = tokenize(&text, &mut dict);
+        assert_eq!(tokens[0].surface, surface);
+        assert_eq!(tokens[0].reading, reading);
+        assert_eq!(tokens[1].reading, "トーキョー");
+
+        let mut builder = long_builder();
+        builder.format_version(4);
+        let err = builder.write(Vec::new()).err().unwrap();
+        assert!(err.to_string().contains("over 255 bytes"));
+    }
+
+    #[test]
+    fn test_pronunciations_and_accents() {
+        let builder = |extras: bool| {
+            let mut builder = DictionaryBuilder::new();
+            builder.add_entry("東京", "トウキョウ", 0, 100);
+            builder.add_entry("駅", "エキ", 0, 100);
+            builder.extend_entries([Entry {
+                surface: "は".to_string(),
+                pos_id: 0,
+                right_id: 0,
+                cost: 100,
+                reading: "ハ".to_string(),
+                lemma: None,
+                pronunciation: extras.then(|| "ワ".to_string()),
+                accent: None,
+            }]);
+            builder.extend_entries([Entry {
+                surface: "東京".to_string(),
+                pos_id: 0,
+                right_id: 0,
+                cost: 50,
+                reading: "トーキョー".to_string(),
+                lemma: None,
+                pronunciation: None,
+                accent: extras.then_some(0),
+            }]);
+            builder.set_matrix(vec![0], 1);
+            builder
+        };
+
+        let mut dict = load_built(builder(true));
+        let tokens = tokenize("東京駅は", &mut dict);
+        let extras: Vec<_> = tokens
+            .iter()
+            .map(|t| (t.surface, t.pronunciation.as_deref(), t.accent))
+            .collect();
+        assert_eq!(
+            extras,
+            [
+                ("東京", None, Some(0)),
+                ("駅", None, None),
+                ("は", Some("ワ"), None)
+            ]
+        );
+        assert_eq!(
+            tokens[2].clone().into_owned().pronunciation.as_deref(),
+            Some("ワ")
+        );
+        let entry = dict.get_entry('は', 0).unwrap().to_entry();
+        assert_eq!(entry.pronunciation.as_deref(), Some("ワ"));
+
+        let mut tokenizer = Tokenizer::new(dict).with_options(Options {
+            pronunciation: true,
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.transliterate("東京駅は"), "トーキョーエキワ");
+        assert_eq!(
+            crate::greedy::tokenize("は", tokenizer.dictionary_mut())[0].pronunciation,
+            Some("ワ".into())
+        );
+
+        // Version 7 has nowhere to put them, but reads the same without them.
+        let mut older = builder(true);
+        older.format_version(7);
+        let err = older.write(Vec::new()).err().unwrap();
+        assert!(
+            err.to_string().contains("pronunciations or accents"),
+            "{}",
+            err
+        );
+        let mut older = builder(false);
+        older.format_version(7);
+        let mut dict = load_built(older);
+        assert_eq!(dict.format_version(), 7);
+        assert_eq!(transliterate("東京駅は", &mut dict), "トーキョーエキハ");
+    }
+
+    #[test]
+    fn test_lemmas() {
+        let builder = |lemmas: bool| {
+            let mut builder = DictionaryBuilder::new();
+            builder.add_entry("を", "ヲ", 0, 100);
+            builder.extend_entries([("食べ", "タベ", "食べる"), ("た", "タ", "た")].map(
+                |(surface, reading, lemma)| Entry {
+                    surface: surface.to_string(),
+                    pos_id: 0,
+                    right_id: 0,
+                    cost: 100,
+                    reading: reading.to_string(),
+                    lemma: lemmas.then(|| lemma.to_string()),
+                    pronunciation: None,
+                    accent: None,
+                },
+            ));
+            builder.set_matrix(vec![0], 1);
+            builder
+        };
+
+        let mut dict = load_built(builder(true));
+        let lemmas = |tokens: Vec<Token>| -> Vec<Option<String>> {
+            tokens
+                .into_iter()
+                .map(|t| t.lemma.map(Cow::into_owned))
+                .collect()
+        };
+        let expected = [
+            None,
+            Some("食べる".to_string()),
+            Some("た".to_string()),
+            None,
+        ];
+        assert_eq!(lemmas(tokenize("を食べたX", &mut dict)), expected);
+        assert_eq!(
+            lemmas(crate::greedy::tokenize("を食べたX", &mut dict)),
+            expected
+        );
+        let analysis = analyze("食べた", &mut dict);
+        assert_eq!(analysis.tokens[0].token.lemma.as_deref(), Some("食べる"));
+
+        let entry = dict.get_entry('食', 0).unwrap().to_entry();
+        let Some(EntryReading::Stored { offset, len }) = entry.lemma else {
+            panic!("lemma not stored: {:?}", entry.lemma);
+        };
+        assert_eq!(dict.read_lemma_at(offset, len).unwrap(), "食べる");
+
+        // Version 8 has nowhere to put them; dictionaries without them give none.
+        let mut older = builder(true);
+        older.format_version(8);
+        let err = older.write(Vec::new()).err().unwrap();
+        assert!(err.to_string().contains("lemmas"), "{}", err);
+        let mut older = builder(false);
+        older.format_version(8);
+        let mut dict = load_built(older);
+        assert_eq!(lemmas(tokenize("を食べた", &mut dict)), [None, None, None]);
+        let mut dict = load_built(builder(false));
+        assert_eq!(dict.format_version(), FORMAT_VERSION);
+        assert_eq!(lemmas(tokenize("を食べた", &mut dict)), [None, None, None]);
+    }
+
+    #[test]
+    fn test_unknown_extra_fields_are_skipped() {
+        let mut record = vec![3, 0];
+        record.extend_from_slice("東".as_bytes());
+        record.extend_from_slice(&[0; 12]);
+        // A field from a later writer, then the accent.
+        let extras = [&[9, 2, 0, 7, 7][..], &[EXTRA_ACCENT, 1, 0, 2]].concat();
+        record.extend_from_slice(&(extras.len() as u16).to_le_bytes());
+        record.extend_from_slice(&extras);
+        let (entry, size) = parse_record(&record, 8).unwrap();
+        assert_eq!(
+            (entry.surface.as_str(), entry.accent, size),
+            ("東", Some(2), record.len())
+        );
+        assert_eq!(entry.pronunciation, None);
+
+        record.pop();
+        let err = parse_record(&record, 8).err().unwrap();
+        assert!(err.contains("truncated"), "{}", err);
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_readings_survive_string_sharing() {
+        // Repeated readings, readings that continue the previous one, and empty readings.
+        let entries = [
+            ("橋", "ハシ", 0),
+            ("箸", "ハシ", 0),
+            ("端", "ハシ", 1),
+            ("端っこ", "ハシッコ", 0),
+            ("走る", "ハシル", 0),
+            ("柱", "ハシラ", 0),
+            ("シ", "シ", 1),
+            ("ッ", "", 1),
+            ("鹿", "シカ", 0),
+            ("鹿肉", "シカニク", 0),
+            ("肉", "ニク", 0),
+        ];
+        let mut builder = DictionaryBuilder::new();
+        for &(surface, reading, pos_id) in &entries {
+            builder.add_entry(surface, reading, pos_id, 100);
+        }
+        builder.set_matrix(vec![0; 4], 2);
+        let path = crate::testutil::temp_path("shared.bin");
+        let stats = builder.write_to_file(&path).unwrap();
+        let mut dict = Dictionary::load(&path).unwrap();
+
+        let total: usize = entries.iter().map(|(_, reading, _)| reading.len()).sum();
+        assert!(stats.strings_bytes < total, "{}", stats.strings_bytes);
+        for &(surface, reading, pos_id) in &entries {
+            let first = surface.chars().next().unwrap();
+            let stored = dict.entries_starting_with(first);
+            assert!(
+                stored
+                    .iter()
+                    .any(|(e, r)| e.surface == surface && e.pos_id == pos_id && r == reading),
+                "{} ({}) not in {:?}",
+                surface,
+                reading,
+                stored
+            );
+        }
+        assert!(dict.verify().unwrap().is_sound());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_clone_reads_the_file_it_was_loaded_from() {
+        let write = |path: &Path, reading: &str, compress: bool| {
+            let mut builder = DictionaryBuilder::new();
+            builder.add_entry("東京", reading, 0, 100);
+            builder.set_matrix(vec![0], 1);
+            builder.compress(compress);
+            builder.write_to_file(path).unwrap();
+        };
+        for &compress in crate::testutil::compress_modes() {
+            let path = crate::testutil::temp_path("published.bin");
+            write(&path, "トーキョー", compress);
+            let dict = Dictionary::load(&path).unwrap();
+
+            // Publish a new dictionary over the path, as an atomic rename does.
+            let next = crate::testutil::temp_path("next.bin");
+            write(&next, "トウキョウ", compress);
+            std::fs::rename(&next, &path).unwrap();
+
+            let mut clone = dict.try_clone().unwrap();
+            assert_eq!(transliterate("東京", &mut clone), "トーキョー");
+            assert!(clone.take_error().is_none());
+            dict.data().verify_checksum().unwrap();
+            std::fs::remove_file(&path).ok();
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_unsupported_version_is_rejected() {
+        let mut bytes = Vec::new();
+        context_builder().write(&mut bytes).unwrap();
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let path = crate::testutil::temp_path("future.bin");
+        std::fs::write(&path, &bytes).unwrap();
+        let err = Dictionary::load(path.to_str().unwrap()).err().unwrap();
+        std::fs::remove_file(&path).ok();
+        assert!(matches!(
+            &err,
+            MucabError::UnsupportedVersion { found, supported }
+                if *found == FORMAT_VERSION + 1 && *supported == SUPPORTED_FORMAT_VERSIONS
+        ));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "unsupported format version {} (supported: 1..={})",
+                FORMAT_VERSION + 1,
+                FORMAT_VERSION
+            )
+        );
+    }
+
+    /// Loads `bytes` and, if that works, converts some text with them. Either may fail but
+    /// neither may panic.
+    fn load_and_convert(bytes: Vec<u8>) -> Result<(), MucabError> {
+        let mut dict = Dictionary::from_bytes(bytes)?;
+        for text in ["東京2024年", "年東京コーヒー"] {
+            transliterate(text, &mut dict);
+            transliterate_nbest(text, &mut dict, 3);
+        }
+        if let Some(error) = dict.take_error() {
+            return Err(error);
+        }
+        dict.preload_all()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_key_outside_unicode_is_corrupt() {
+        let mut bytes = Vec::new();
+        char_def_builder().write(&mut bytes).unwrap();
+        let key = ('年' as u32).to_le_bytes();
+        let at = bytes.windows(4).position(|w| w == key).unwrap();
+        bytes[at..at + 4].copy_from_slice(&0xD800u32.to_le_bytes());
+        let err = Dictionary::from_bytes(bytes).err().unwrap();
+        assert!(matches!(err, MucabError::Corrupt(_)), "{}", err);
+        assert_eq!(
+            err.to_string(),
+            format!("index key at byte {} is not a character (0xd800)", at)
+        );
+    }
+
+    #[test]
+    fn test_bit_flips_never_panic() {
+        for &compress in compress_modes() {
+            let mut builder = char_def_builder();
+            builder.set_pos_name(0, "名詞,一般");
+            builder.compress(compress);
+            let mut bytes = Vec::new();
+            builder.write(&mut bytes).unwrap();
+            let mut failures = 0;
+            for at in 0..bytes.len() {
+                for mask in [0x01, 0x80, 0xff] {
+                    let mut flipped = bytes.clone();
+                    flipped[at] ^= mask;
+                    failures += load_and_convert(flipped).is_err() as usize;
+                }
+            }
+            assert!(failures > 0);
+        }
+    }
+
+    #[test]
+    fn test_corrupt_block_is_reported_after_conversion() {
+        let mut builder = char_def_builder();
+        builder.compress(false);
+        let mut bytes = Vec::new();
+        let stats = builder.write(&mut bytes).unwrap();
+        // The first record's surface length, now longer than the block.
+        let region_start = bytes.len() - stats.compressed_bytes;
+        bytes[region_start] = 0xff;
+        let mut dict = Dictionary::from_bytes(bytes.clone()).unwrap();
+        let converted = transliterate("東京年", &mut dict);
+        let err = dict.take_error().unwrap();
+        assert!(err.to_string().starts_with("entry block"), "{}", err);
+        assert!(dict.take_error().is_none());
+        assert!(converted.contains("ネン") ^ converted.contains("トーキョー"));
+        assert!(Dictionary::from_bytes(bytes)
+            .unwrap()
+            .preload_all()
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_dictionary_search_paths() {
+        let env = |vars: &'static [(&str, &str)]| {
+            move |name: &str| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.into())
+            }
+        };
+        let data_file = |dir: &str| Path::new(dir).join("mucab").join("mucab.bin");
+        let paths = dictionary_search_paths(env(&[
+            ("MUCAB_DICT", "/dicts/ipadic.bin"),
+            ("XDG_DATA_HOME", "/data"),
+            ("APPDATA", "/data"),
+        ]));
+        assert_eq!(
+            paths,
+            [PathBuf::from("/dicts/ipadic.bin"), data_file("/data")]
+        );
+        assert_eq!(
+            dictionary_search_paths(env(&[("MUCAB_DICT", "")])),
+            [] as [PathBuf; 0]
+        );
+        if !cfg!(windows) {
+            let paths = dictionary_search_paths(env(&[("HOME", "/home/u")]));
+            assert_eq!(paths, [data_file("/home/u/.local/share")]);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_damaged_file_errors() {
+        let mut bytes = Vec::new();
+        context_builder().write(&mut bytes).unwrap();
+        let load = |bytes: &[u8]| {
+            let path = crate::testutil::temp_path("damaged.bin");
+            std::fs::write(&path, bytes).unwrap();
+            let err = Dictionary::load(&path).err().unwrap();
+            std::fs::remove_file(&path).ok();
+            err
+        };
+
+        let corrupt = |err: MucabError| match err {
+            MucabError::Corrupt(message) => message,
+            other => panic!("expected corruption, got {}", other),
+        };
+
+        let message = corrupt(load(&bytes[..HEADER_SIZE + 3]));
+        assert_eq!(
+            message,
+            format!("dictionary truncated at byte {}", HEADER_SIZE + 3)
+        );
+        let message = corrupt(load(b"PNG\0 not a dictionary"));
+        assert_eq!(message, "not a mucab dictionary (bad magic)");
+        // The seek table at the end of the compressed region is gone.
+        #[cfg(feature = "zstd")]
+        corrupt(load(&bytes[..bytes.len() - 10]));
+
+        let mut builder = context_builder();
+        builder.compress(false);
+        let mut bytes = Vec::new();
+        let stats = builder.write(&mut bytes).unwrap();
+        let region_start = bytes.len() - stats.compressed_bytes;
+        let message = corrupt(load(&bytes[..region_start + 4]));
+        assert!(message.starts_with("strings offset"), "{}", message);
+
+        // The first index block's offset, after the matrix, the key count and the key.
+        let offset_at = HEADER_SIZE + stats.matrix_bytes + 8;
+        bytes[offset_at..offset_at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let message = corrupt(load(&bytes));
+        assert!(message.contains("past the entries"), "{}", message);
+    }
+
+    #[test]
+    fn test_tokenize_matches_transliterate() {
+        let mut dict = tokyo_fixture();
+        let tokens = tokenize("東京X都", &mut dict);
+        let surfaces: Vec<&str> = tokens.iter().map(|t| t.surface).collect();
+        assert_eq!(surfaces, ["東京", "X", "都"]);
+        assert!(tokens[1].is_unknown);
+        assert!(!tokens[0].is_unknown);
+        assert_eq!(tokens[0].word_cost, 100);
+        let joined: String = tokens.iter().map(|t| t.reading.as_ref()).collect();
+        assert_eq!(joined, transliterate("東京X都", &mut dict));
+        assert!(tokenize("", &mut dict).is_empty());
+    }
+
+    #[test]
+    fn test_transliterate_with_stats() {
+        let mut dict = tokyo_fixture();
+        let (reading, stats) = transliterate_with_stats("東京X都", &mut dict);
+        assert_eq!(reading, transliterate("東京X都", &mut dict));
+        assert_eq!(stats.tokens, 3);
+        assert!(stats.nodes > stats.tokens, "{:?}", stats);
+
+        let (reading, stats) = transliterate_with_stats("", &mut dict);
+        assert_eq!(reading, "");
+        assert_eq!(stats, TransliterateStats::default());
+    }
+
+    #[test]
+    fn test_lattice_chunks_end_at_sentences() {
+        assert_eq!(lattice_chunks("東京都。京都", 6), ["東京都。京都"]);
+        assert_eq!(lattice_chunks("東京都。京都", 4), ["東京都。", "京都"]);
+        assert_eq!(lattice_chunks("東京都。京都", 3), ["東京都", "。京都"]);
+        assert_eq!(
+            lattice_chunks("あ。い\nう！え", 5),
+            ["あ。い", "\n", "う！え"]
+        );
+        assert_eq!(
+            lattice_chunks(" 東京 \n\t京都", 6),
+            [" ", "東京", " \n\t", "京都"]
+        );
+        assert!(lattice_chunks("", 4).is_empty());
+    }
+
+    #[test]
+    fn test_long_texts_are_analysed_in_pieces() {
+        let mut dict = tokyo_fixture();
+        let text = "東京都。京都X\n東京";
+        let whole = transliterate(text, &mut dict);
+        let whole_tokens = tokenize(text, &mut dict).len();
+
+        dict.set_max_lattice_chars(text.chars().count());
+        assert_eq!(transliterate(text, &mut dict), whole);
+
+        dict.set_max_lattice_chars(5);
+        let pieces = ["東京都。", "京都X\n", "東京"];
+        let expected: String = pieces.iter().map(|p| transliterate(p, &mut dict)).collect();
+        assert_eq!(transliterate(text, &mut dict), expected);
+        assert_eq!(transliterate_with_stats(text, &mut dict).0, expected);
+        assert_eq!(transliterate_detailed(text, &mut dict).output, expected);
+        let tokens = tokenize(text, &mut dict);
+        assert_eq!(tokens.len(), whole_tokens);
+        assert_eq!(tokens.iter().map(|t| t.surface).collect::<String>(), text);
+
+        assert_eq!(transliterate_nbest(text, &mut dict, 3)[0].0, expected);
+        assert_eq!(tokenize_nbest(text, &mut dict, 3)[0].0, tokens);
+        let analysis = analyze(text, &mut dict);
+        let analysed: Vec<Token> = analysis.tokens.iter().map(|t| t.token.as_token()).collect();
+        assert_eq!(analysed, tokens);
+        let chars: Vec<char> = text.chars().collect();
+        for t in &analysis.tokens {
+            assert_eq!(
+                chars[t.start..t.end].iter().collect::<String>(),
+                t.token.surface
+            );
+        }
+
+        // The cuts split the middle 東京, for nbest as for `transliterate`.
+        dict.set_max_lattice_chars(3);
+        let best = transliterate("東京東京東京", &mut dict);
+        assert_eq!(best, "トーキョーヒガシケイトーキョー");
+        let nbest = transliterate_nbest("東京東京東京", &mut dict, 3);
+        assert_eq!(nbest[0].0, best);
+        assert!(nbest.windows(2).all(|pair| pair[0].1 <= pair[1].1));
+    }
+
+    #[test]
+    fn test_expensive_paths_keep_dictionary_readings() {
+        let mut dict = tokyo_fixture();
+        let text = "東京都京都東京".repeat(40);
+        let analysis = analyze(&text, &mut dict);
+        assert!(analysis.total_cost > UNKNOWN_COST);
+        assert!(analysis.tokens.iter().all(|t| !t.token.is_unknown));
+        let readings: Vec<&str> = analysis
+            .tokens
+            .iter()
+            .map(|t| t.token.reading.as_str())
+            .collect();
+        assert_eq!(
+            readings,
+            ["トーキョー", "ト", "キョート", "トーキョー"].repeat(40)
+        );
+        assert_eq!(
+            transliterate(&text, &mut dict),
+            "トーキョートキョートトーキョー".repeat(40)
+        );
+    }
+
+    #[test]
+    fn test_whitespace_is_copied_between_pieces() {
+        let mut dict = fixture(&[("漢字", "カンジ", 0, 100), ("です", "デス", 0, 100)], 1);
+        assert_eq!(transliterate("漢字\nです", &mut dict), "カンジ\nデス");
+        assert_eq!(
+            transliterate(" 漢字 　です\r\n\n", &mut dict),
+            " カンジ 　デス\r\n\n"
+        );
+
+        let details = transliterate_detailed("漢字\n\nです", &mut dict);
+        assert_eq!(details.output, "カンジ\n\nデス");
+        assert_eq!((details.tokens, details.unknown_chars), (2, 0));
+
+        let tokens: Vec<(&str, bool)> = tokenize("漢字\n\nです", &mut dict)
+            .iter()
+            .map(|t| (t.surface, t.is_unknown))
+            .collect();
+        assert_eq!(tokens, [("漢字", false), ("\n\n", true), ("です", false)]);
+    }
+
+    #[test]
+    fn test_multi_megabyte_text_stays_small() {
+        let mut dict = tokyo_fixture();
+        let sentence = "東京都に京都。";
+        let text = sentence.repeat((3 << 20) / sentence.len() + 1);
+        let (reading, stats) = transliterate_with_stats(&text, &mut dict);
+        assert_eq!(
+            reading,
+            transliterate(sentence, &mut dict).repeat(text.len() / sentence.len())
+        );
+        // No lattice spans more than the limit, whatever the length of the text.
+        assert!(stats.largest_lattice <= DEFAULT_MAX_LATTICE_CHARS);
+        assert!(stats.largest_lattice > DEFAULT_MAX_LATTICE_CHARS / 2);
+        dict.set_max_lattice_chars(100);
+        let (_, stats) = transliterate_with_stats(&sentence.repeat(1000), &mut dict);
+        assert!(stats.largest_lattice <= 100 && stats.largest_lattice > 50);
+    }
+
+    #[test]
+    fn test_missing_readings_keep_the_surface() {
+        let mut dict = fixture(
+            &[
+                ("東京", "トーキョー", 0, 100),
+                ("〒", "*", 0, 100),
+                ("々", "", 0, 100),
+            ],
+            1,
+        );
+        assert_eq!(transliterate("東京〒々", &mut dict), "トーキョー〒々");
+        let tokens = tokenize("〒々", &mut dict);
+        assert_eq!(tokens[0].reading, "〒");
+        assert_eq!(tokens[1].reading, "々");
+        assert!(tokens.iter().all(|t| !t.is_unknown));
+
+        dict.set_missing_reading(MissingReading::Stored);
+        assert_eq!(transliterate("東京〒々", &mut dict), "トーキョー*");
+    }
+
+    #[test]
+    fn test_transliterate_detailed() {
+        let mut dict = tokyo_fixture();
+        let details = transliterate_detailed("東京XY都", &mut dict);
+        assert_eq!(details.output, transliterate("東京XY都", &mut dict));
+        assert_eq!(
+            details.cost,
+            transliterate_nbest("東京XY都", &mut dict, 1)[0].1
+        );
+        assert_eq!(details.tokens, tokenize("東京XY都", &mut dict).len());
+        assert_eq!(details.unknown_chars, 2);
+        assert_eq!(details.unknown_ratio("東京XY都"), 0.4);
+
+        let details = transliterate_detailed("東京都", &mut dict);
+        assert_eq!((details.tokens, details.unknown_chars), (2, 0));
+        assert_eq!(details.cost, 200);
+        assert_eq!(
+            transliterate_detailed("", &mut dict),
+            TransliterateDetails::default()
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_entries_and_details_json() {
+        let mut dict = tokyo_fixture();
+        let entry = dict.lookup_exact("東京").remove(0);
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(
+            json,
+            r#"{"surface":"東京","char_len":2,"pos_id":0,"right_id":0,"word_cost":100,"reading":{"stored":{"offset":27,"len":15}}}"#
+        );
+        let back: DictEntry = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.reading, entry.reading);
+
+        dict.add_entry("東", "アズマ", 0, 0);
+        let added = dict
+            .lookup_exact("東")
+            .into_iter()
+            .find(|e| matches!(e.reading, EntryReading::Inline(_)))
+            .unwrap();
+        assert!(serde_json::to_string(&added)
+            .unwrap()
+            .ends_with(r#""reading":{"inline":"アズマ"}}"#));
+
+        let details = transliterate_detailed("東京都", &mut tokyo_fixture());
+        assert_eq!(
+            serde_json::to_string(&details).unwrap(),
+            r#"{"output":"トーキョート","cost":200,"tokens":2,"unknown_chars":0}"#
+        );
+    }
+
+    #[test]
+    fn test_tokenize_round_trips_through_formats() {
+        let mut dict = tokyo_fixture();
+        let tokens = tokenize("東京X都", &mut dict);
+        let tsv = format::format_tsv(&tokens);
+        let parsed = format::parse_tsv(&tsv).unwrap();
+        assert_eq!(parsed.len(), tokens.len());
+        assert!(parsed.iter().zip(&tokens).all(|(p, t)| p == t));
+        let mecab = format::format_mecab(&tokens);
+        let from_mecab = format::parse_mecab(&mecab).unwrap();
+        assert_eq!(format::format_mecab(&from_mecab), mecab);
+    }
+
+    fn char_def_builder() -> DictionaryBuilder {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("東京", "トーキョー", 0, 100);
+        builder.add_entry("年", "ネン", 0, 100);
+        builder.set_matrix(vec![0; 4], 2);
+        builder.set_char_definitions(crate::unknown::tests::sample());
+        builder
+    }
+
+    #[test]
+    fn test_unknown_runs_are_grouped_by_category() {
+        let mut dict = load_built(char_def_builder());
+        let tokens = tokenize("コーヒー2024年東京", &mut dict);
+        let surfaces: Vec<&str> = tokens.iter().map(|t| t.surface).collect();
+        assert_eq!(surfaces, ["コーヒー", "2024", "年", "東京"]);
+        assert!(tokens[0].is_unknown && tokens[1].is_unknown);
+        assert_eq!((tokens[0].pos_id, tokens[0].word_cost), (1, 2500));
+        assert_eq!((tokens[1].pos_id, tokens[1].word_cost), (1, 1000));
+        assert!(!tokens[2].is_unknown);
+        assert_eq!(
+            transliterate("コーヒー2024年東京", &mut dict),
+            "コーヒー2024ネントーキョー"
+        );
+    }
+
+    #[test]
+    fn test_unmatched_runs_become_one_token() {
+        let mut dict = fixture(&[("使う", "ツカウ", 0, 100), ("を", "ヲ", 0, 100)], 1);
+        let analysis = analyze("HTML5を使う", &mut dict);
+        let surfaces: Vec<&str> = analysis
+            .tokens
+            .iter()
+            .map(|t| t.token.surface.as_str())
+            .collect();
+        assert_eq!(surfaces, ["HTML5", "を", "使う"]);
+        assert!(analysis.tokens[0].token.is_unknown);
+        assert_eq!(analysis.total_cost, UNKNOWN_COST + 200);
+
+        let ascii = "abc1-".repeat(200);
+        let text = format!("を{ascii}を使う");
+        // One node covers the whole run: the lattice stays a handful of nodes, not one or more
+        // per character of the run.
+        let (nodes, _) = viterbi(&text, &mut dict);
+        assert_eq!(nodes.items(), 7);
+        let tokens = tokenize(&text, &mut dict);
+        assert_eq!(tokens.len(), 4);
+        assert_eq!(tokens[1].surface, ascii);
+        assert_eq!(
+            transliterate(&text, &mut dict),
+            format!("ヲ{ascii}ヲツカウ")
+        );
+    }
+
+    #[test]
+    fn test_unknown_pos_id_connects_unknown_nodes() {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("を", "ヲ", 1, 100);
+        // Right id 0 into left id 1 costs 5000, right id 1 into left id 1 costs 50.
+        builder.set_matrix(vec![0, 5000, 0, 50], 2);
+        let mut dict = load_built(builder);
+        let cost_and_pos = |dict: &mut Dictionary| {
+            let analysis = analyze("XMLを", dict);
+            (analysis.total_cost, analysis.tokens[0].token.pos_id)
+        };
+        assert_eq!(cost_and_pos(&mut dict), (UNKNOWN_COST + 5000 + 100, 0));
+
+        for (pos_id, expected) in [
+            // BOS into left id 1 costs 5000 as well.
+            (Some(1), (5000 + UNKNOWN_COST + 50 + 100, 1)),
+            (None, (UNKNOWN_COST + 100, 0)),
+        ] {
+            dict.set_viterbi_config(ViterbiConfig {
+                unknown_pos_id: pos_id,
+                ..ViterbiConfig::default()
+            });
+            assert_eq!(cost_and_pos(&mut dict), expected);
+        }
+    }
+
+    #[test]
+    fn test_unknown_pos_id_connects_into_unknown_nodes() {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("東京", "トーキョー", 1, 90);
+        builder.add_entry("東", "ヒガシ", 2, 50);
+        builder.add_entry("京", "ケイ", 2, 50);
+        // Right id 1 into left id 0 costs 5000; every other connection is free.
+        let mut matrix = vec![0; 9];
+        matrix[3] = 5000;
+        builder.set_matrix(matrix, 3);
+        let mut dict = load_built(builder);
+        let surfaces = |dict: &mut Dictionary| {
+            let tokens = tokenize("東京X", dict);
+            tokens
+                .iter()
+                .map(|t| t.surface.to_string())
+                .collect::<Vec<_>>()
+        };
+        // 東京 is cheaper on its own, but connects into the unknown node at 5000.
+        assert_eq!(surfaces(&mut dict), ["東", "京", "X"]);
+
+        dict.set_viterbi_config(ViterbiConfig {
+            unknown_pos_id: None,
+            ..ViterbiConfig::default()
+        });
+        assert_eq!(surfaces(&mut dict), ["東京", "X"]);
+    }
+
+    #[test]
+    fn test_unknown_kanji_falls_back_to_short_nodes() {
+        let mut dict = load_built(char_def_builder());
+        // KANJI is not grouped: unknown kanji get nodes of at most two characters, and none are
+        // added where a dictionary entry starts.
+        let tokens = tokenize("都市東京", &mut dict);
+        let surfaces: Vec<&str> = tokens.iter().map(|t| t.surface).collect();
+        assert_eq!(surfaces, ["都市", "東京"]);
+        assert_eq!(tokens[0].word_cost, 8000);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_version_2_keeps_flat_unknown_cost() {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("東京", "トーキョー", 0, 100);
+        builder.set_matrix(vec![0], 1);
+        builder.format_version(2);
+        let mut dict = load_built(builder);
+        let tokens = tokenize("東京XY", &mut dict);
+        let surfaces: Vec<&str> = tokens.iter().map(|t| t.surface).collect();
+        assert_eq!(surfaces, ["東京", "XY"]);
+        dict.set_compat(AnalysisCompat::V2);
+        let tokens = tokenize("東京XY", &mut dict);
+        let surfaces: Vec<&str> = tokens.iter().map(|t| t.surface).collect();
+        assert_eq!(surfaces, ["東京", "X", "Y"]);
+
+        let mut builder = char_def_builder();
+        builder.format_version(2);
+        assert!(builder.write(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_flat_unknowns_keep_one_node_per_position() {
+        let mut dict = fixture(&[("東", "トウ", 0, 100), ("東", "ヒガシ", 0, 200)], 1);
+        dict.set_compat(AnalysisCompat::V2);
+        let text = format!("東{}", "X".repeat(100_000));
+        let (nodes, _) = viterbi(&text, &mut dict);
+        assert_eq!(nodes[1].len(), 2);
+        assert!(nodes.iter().skip(2).all(|column| column.len() == 1));
+
+        let analysis = analyze(&text, &mut dict);
+        assert_eq!(analysis.tokens.len(), 100_001);
+        assert_eq!(analysis.total_cost, 100 + 100_000 * UNKNOWN_COST);
+
+        // The alternative reading is still reachable through the collapsed node.
+        let readings: Vec<String> = transliterate_nbest("東XX", &mut dict, 2)
+            .into_iter()
+            .map(|(reading, _)| reading)
+            .collect();
+        assert_eq!(readings, ["トウXX", "ヒガシXX"]);
+    }
+
+    #[test]
+    fn test_lookup_exact_and_readings_of() {
+        let mut dict = fixture(
+            &[
+                ("東", "ヒガシ", 0, 300),
+                ("東京", "トーキョー", 0, 100),
+                ("東京", "トウキョウ", 0, 200),
+                ("東京都", "トーキョート", 0, 100),
+            ],
+            1,
+        );
+        let costs: Vec<i16> = dict
+            .lookup_exact("東京")
+            .iter()
+            .map(|e| e.word_cost)
+            .collect();
+        assert_eq!(costs.len(), 2);
+        assert!(costs.contains(&100) && costs.contains(&200));
+        let mut readings = dict.readings_of("東京");
+        readings.sort();
+        assert_eq!(readings, ["トウキョウ", "トーキョー"]);
+        assert_eq!(dict.readings_of("東"), ["ヒガシ"]);
+
+        assert!(dict.lookup_exact("東京タワー").is_empty());
+        assert!(dict.lookup_exact("大阪").is_empty());
+        assert!(dict.lookup_exact("").is_empty());
+
+        dict.add_entry("大阪", "オーサカ", 0, 100);
+        assert_eq!(dict.readings_of("大阪"), ["オーサカ"]);
+    }
+
+    #[test]
+    fn test_iter_entries_follows_index_keys() {
+        let mut dict = fixture(
+            &[
+                ("東京", "トーキョー", 0, 100),
+                ("東", "ヒガシ", 0, 300),
+                ("大阪", "オーサカ", 0, 100),
+            ],
+            1,
+        );
+        dict.set_cache_limit(CacheLimit::Blocks(1));
+        dict.add_entry("京都", "キョート", 0, 100);
+        let keys = dict.index_keys();
+        assert_eq!(
+            keys.iter().map(|(&c, &n)| (c, n)).collect::<Vec<_>>(),
+            [('京', 1), ('大', 1), ('東', 2)]
+        );
+        let entries: Vec<(char, String)> =
+            dict.iter_entries().map(|(c, e)| (c, e.surface)).collect();
+        assert_eq!(
+            entries,
+            [
+                ('京', "京都".to_string()),
+                ('大', "大阪".to_string()),
+                ('東', "東".to_string()),
+                ('東', "東京".to_string()),
+            ]
+        );
+        assert_eq!(entries.len(), dict.num_entries() + 1);
+    }
+
+    #[test]
+    fn test_connection_matrix_accessors() {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("東京", "トーキョー", 0, 100);
+        // Two right ids by three left ids.
+        builder.set_connection_matrix(vec![0, 5, 0, -3, 0, 7], 2, 3);
+        let dict = load_built(builder);
+        let data = dict.data();
+        assert_eq!((data.matrix_size(), data.pos_count()), (6, 3));
+        assert_eq!(data.connection_cost(0, 1), Some(5));
+        assert_eq!(data.connection_cost(1, 2), Some(7));
+        assert_eq!(data.connection_cost(1, 1), Some(0));
+        assert_eq!(data.connection_cost(2, 0), None);
+        assert_eq!(data.connection_cost(0, 3), None);
+        let cells: Vec<_> = data.connection_costs().collect();
+        assert_eq!(cells, [(0, 1, 5), (1, 0, -3), (1, 2, 7)]);
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_cost_overrides_apply_to_cached_and_new_entries() {
+        let mut dict = tokyo_fixture();
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+        dict.adjust_cost("東京都", "トーキョート", 100);
+        dict.adjust_cost("東", "ヒガシ", -250);
+        assert_eq!(transliterate("東京都", &mut dict), "ヒガシキョート");
+        assert_eq!(dict.cost_overrides().len(), 2);
+
+        dict.adjust_cost("東", "ヒガシ", 250);
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+        assert_eq!(dict.cost_overrides().len(), 1);
+
+        let path = crate::testutil::temp_path("overrides.csv");
+        std::fs::write(&path, "# surface,reading,delta\n東,ヒガシ,-250\n").unwrap();
+        let mut fresh = tokyo_fixture();
+        assert_eq!(fresh.load_overrides(path.to_str().unwrap()).unwrap(), 1);
+        std::fs::remove_file(&path).ok();
+        assert_eq!(transliterate("東京都", &mut fresh), "ヒガシキョート");
+    }
+
+    #[test]
+    fn test_surface_overrides_and_boosts() {
+        let mut dict = tokyo_fixture();
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+        // 東 and 京都 lost to 東京 and 都 at 400 against 200; boosted, they win at 150.
+        dict.boost("東", -250);
+        assert_eq!(transliterate("東京都", &mut dict), "ヒガシキョート");
+        assert_eq!(dict.lookup_exact("東")[0].word_cost, 50);
+        dict.override_cost("京都", 500);
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+        dict.override_cost("京都", 100);
+        dict.adjust_cost("京都", "キョート", 10);
+        assert_eq!(dict.lookup_exact("京都")[0].word_cost, 110);
+        assert_eq!(
+            dict.list_overrides(),
+            [
+                SurfaceOverride {
+                    surface: "京都".to_string(),
+                    cost: Some(100),
+                    delta: 0,
+                },
+                SurfaceOverride {
+                    surface: "東".to_string(),
+                    cost: None,
+                    delta: -250,
+                },
+            ]
+        );
+
+        // Boosts that cancel out leave no override behind.
+        dict.boost("東", 250);
+        assert_eq!(dict.list_overrides().len(), 1);
+        dict.boost("東", -250);
+        dict.clear_overrides();
+        assert!(dict.list_overrides().is_empty() && dict.cost_overrides().is_empty());
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+        assert_eq!(dict.lookup_exact("京都")[0].word_cost, 100);
+    }
+
+    #[test]
+    fn test_analyze_snapshot_round_trip() {
+        let mut dict = tokyo_fixture();
+        let analysis = analyze("東京X都", &mut dict);
+        let surfaces: Vec<&str> = analysis
+            .tokens
+            .iter()
+            .map(|t| t.token.surface.as_str())
+            .collect();
+        assert_eq!(surfaces, ["東京", "X", "都"]);
+        assert_eq!(
+            analysis
+                .tokens
+                .iter()
+                .map(|t| (t.start, t.end))
+                .collect::<Vec<_>>(),
+            [(0, 2), (2, 3), (3, 4)]
+        );
+        assert_eq!(analysis.total_cost, 100 + UNKNOWN_COST + 100);
+        assert!(analysis
+            .tokens
+            .iter()
+            .zip(tokenize("東京X都", &mut dict))
+            .all(|(a, t)| a.token == t));
+        let back = Analysis::from_bytes(&analysis.to_bytes()).unwrap();
+        assert_eq!(back, analysis);
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_shared_data_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<DictionaryData>();
+
+        let mut builder = DictionaryBuilder::new();
+        for (surface, reading) in [
+            ("東京", "トーキョー"),
+            ("都", "ト"),
+            ("京都", "キョート"),
+            ("大阪", "オーサカ"),
+            ("府", "フ"),
+        ] {
+            builder.add_entry(surface, reading, 0, 100);
+        }
+        builder.set_matrix(vec![0], 1);
+        let path = crate::testutil::temp_path("shared.bin");
+        builder.write_to_file(&path).unwrap();
+        let data = Arc::new(DictionaryData::load(path.to_str().unwrap()).unwrap());
+
+        let texts = [
+            "東京都",
+            "京都府",
+            "大阪府",
+            "東京",
+            "大阪都",
+            "京都",
+            "都府",
+            "東京大阪",
+        ];
+        let expected: Vec<String> = {
+            let mut dict = Dictionary::with_data(data.clone()).unwrap();
+            texts.iter().map(|t| transliterate(t, &mut dict)).collect()
+        };
+        assert_eq!(expected[0], "トーキョート");
+
+        let handles: Vec<_> = texts
+            .iter()
+            .map(|&text| {
+                let data = Arc::clone(&data);
+                std::thread::spawn(move || {
+                    let mut dict = Dictionary::with_data(data).unwrap();
+                    (0..20)
+                        .map(|_| transliterate(text, &mut dict))
+                        .last()
+                        .unwrap()
+                })
+            })
+            .collect();
+        let results: Vec<String> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(results, expected);
+        assert_eq!(data.cache_stats().resident_blocks, 5);
+    }
+
+    #[test]
+    fn test_overrides_are_per_handle() {
+        let mut dict = tokyo_fixture();
+        dict.adjust_cost("東", "ヒガシ", -250);
+        assert_eq!(transliterate("東京都", &mut dict), "ヒガシキョート");
+        // The shared block keeps the original cost.
+        let shared = dict
+            .data()
+            .entry_cache
+            .lock()
+            .unwrap()
+            .peek('東')
+            .cloned()
+            .unwrap();
+        assert!(shared
+            .iter()
+            .any(|e| e.surface == "東" && e.word_cost == 300));
+    }
+
+    /// Entries sharing a first char (so their block spans many small frames) with readings long
+    /// enough to cross frame boundaries themselves.
+    fn frame_entries() -> Vec<(String, String)> {
+        let kana: Vec<char> = ('ア'..='ン').collect();
+        (0..80u32)
+            .map(|i| {
+                let second = char::from_u32('一' as u32 + i).unwrap();
+                let first = if i % 3 == 0 { '東' } else { '京' };
+                let reading: String = (0..1 + i as usize % 23)
+                    .map(|j| kana[(i as usize * 7 + j) % kana.len()])
+                    .collect();
+                (format!("{}{}", first, second), reading)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_tiny_frames_are_transparent() {
+        let entries = frame_entries();
+        for frame_size in [1, 7, 16, 64, 4096] {
+            let mut builder = DictionaryBuilder::new();
+            for (surface, reading) in &entries {
+                builder.add_entry(surface, reading, 0, 100);
+            }
+            builder.set_matrix(vec![0], 1);
+            builder.frame_size(frame_size);
+            let mut dict = load_built(builder);
+
+            // Alternate between blocks and readings so each read starts from wherever the
+            // previous one left the decoder (and its offset limit).
+            for (surface, reading) in entries.iter().rev() {
+                assert_eq!(
+                    transliterate(surface, &mut dict),
+                    *reading,
+                    "frame size {}",
+                    frame_size
+                );
             }
+            let text: String = entries.iter().map(|(s, _)| s.as_str()).collect();
+            let expected: String = entries.iter().map(|(_, r)| r.as_str()).collect();
+            assert_eq!(transliterate(&text, &mut dict), expected);
+        }
+    }
 
-            if let Some(prev_idx) = node.prev_node {
-                current_pos = node.start_pos;
-                current_node_idx = prev_idx;
-            } else {
-                break;
+    #[test]
+    #[cfg(all(feature = "fs", feature = "zstd"))]
+    fn test_frame_cache_is_transparent() {
+        let entries = frame_entries();
+        let text: String = entries.iter().map(|(s, _)| s.as_str()).collect();
+        let expected: String = entries.iter().map(|(_, r)| r.as_str()).collect();
+        let mut builder = frame_builder(&entries);
+        builder.frame_size(64);
+        let path = crate::testutil::temp_path("frames.bin");
+        builder.write_to_file(&path).unwrap();
+
+        let mut stats = Vec::new();
+        for frame_cache in [0, 1, DEFAULT_FRAME_CACHE] {
+            let options = LoadOptions {
+                frame_cache,
+                ..LoadOptions::default()
+            };
+            let mut dict = Dictionary::load_with_options(&path, options).unwrap();
+            for (surface, reading) in entries.iter().rev() {
+                assert_eq!(transliterate(surface, &mut dict), *reading);
             }
+            assert_eq!(transliterate(&text, &mut dict), expected);
+            assert_eq!(transliterate(&text, &mut dict), expected);
+            let CacheStats {
+                frame_hits,
+                frame_misses,
+                resident_frames,
+                ..
+            } = dict.cache_stats();
+            assert!(resident_frames <= frame_cache);
+            stats.push((frame_hits, frame_misses));
         }
+        std::fs::remove_file(&path).ok();
+        assert_eq!(stats[0], (0, 0));
+        assert!(stats[1].1 > 0 && stats[2].0 > stats[1].0);
     }
 
-    result.reverse();
-    result.join("")
-}
+    #[cfg(feature = "zstd")]
+    fn frame_builder(entries: &[(String, String)]) -> DictionaryBuilder {
+        let mut builder = DictionaryBuilder::new();
+        for (surface, reading) in entries {
+            builder.add_entry(surface, reading, 0, 100);
+        }
+        builder.set_matrix(vec![0], 1);
+        builder
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_uncompressed_region() {
+        let entries = frame_entries();
+        let text: String = entries.iter().map(|(s, _)| s.as_str()).collect();
+        let expected: String = entries.iter().map(|(_, r)| r.as_str()).collect();
+
+        let mut compressed = Vec::new();
+        let mut builder = frame_builder(&entries);
+        builder.compression_level(19);
+        let compressed_stats = builder.write(&mut compressed).unwrap();
+        let mut raw = Vec::new();
+        let mut builder = frame_builder(&entries);
+        builder.compress(false);
+        let raw_stats = builder.write(&mut raw).unwrap();
+        assert_eq!(
+            raw_stats.compressed_bytes,
+            raw_stats.entries_bytes + raw_stats.strings_bytes
+        );
+        assert!(compressed_stats.compressed_bytes < raw_stats.compressed_bytes);
+        assert_eq!(
+            raw.len() - compressed.len(),
+            raw_stats.compressed_bytes - compressed_stats.compressed_bytes
+        );
+
+        let mut builder = frame_builder(&entries);
+        builder.compress(false);
+        let mut dict = load_built(builder);
+        assert!(!dict.data().compressed);
+        for (surface, reading) in entries.iter().rev() {
+            assert_eq!(transliterate(surface, &mut dict), *reading);
+        }
+        assert_eq!(transliterate(&text, &mut dict), expected);
+
+        let mut builder = frame_builder(&entries);
+        builder.compress(false);
+        let mut dict = load_built(builder);
+        dict.preload_all().unwrap();
+        assert_eq!(transliterate(&text, &mut dict), expected);
+
+        let mut builder = frame_builder(&entries);
+        builder.compress(false);
+        builder.format_version(5);
+        let err = builder.write(Vec::new()).err().unwrap();
+        assert!(err.to_string().contains("uncompressed"));
+    }
 
     #[test]
-    fn test_nihon() {
-        let mut dict = Dictionary::load("out/mucab.bin").expect("Failed to load dictionary");
-        let result = transliterate("日本", &mut dict);
-        assert_eq!(result, "ニッポン");
+    fn test_readings_stored_in_hiragana() {
+        let builder = |form| {
+            let mut builder = DictionaryBuilder::new();
+            builder.add_entry("東京", "トーキョー", 0, 100);
+            builder.add_entry("ヴ", "ヴ", 0, 100);
+            // The two readings only share their bytes once converted.
+            builder.add_entry("今日", "キョウ", 0, 100);
+            builder.add_entry("きょう", "きょう", 0, 100);
+            builder.extend_entries([Entry {
+                surface: "は".to_string(),
+                pos_id: 0,
+                right_id: 0,
+                cost: 100,
+                reading: "ハ".to_string(),
+                lemma: None,
+                pronunciation: Some("ワ".to_string()),
+                accent: None,
+            }]);
+            builder.set_matrix(vec![0], 1);
+            builder.reading_kana(form);
+            builder
+        };
+        let mut bytes = Vec::new();
+        let katakana = builder(KanaForm::Katakana).write(&mut bytes).unwrap();
+        let mut dict = Dictionary::from_bytes(bytes).unwrap();
+        assert_eq!(dict.reading_kana(), KanaForm::Katakana);
+        assert_eq!(transliterate("東京はヴ", &mut dict), "トーキョーハヴ");
+
+        let mut bytes = Vec::new();
+        let hiragana = builder(KanaForm::Hiragana).write(&mut bytes).unwrap();
+        assert_eq!(hiragana.strings_bytes, katakana.other_kana_strings_bytes);
+        assert_eq!(hiragana.other_kana_strings_bytes, katakana.strings_bytes);
+        assert!(hiragana.strings_bytes < katakana.strings_bytes);
+        let mut dict = Dictionary::from_bytes(bytes).unwrap();
+        assert_eq!(dict.reading_kana(), KanaForm::Hiragana);
+        assert_eq!(dict.info().unwrap().reading_kana, KanaForm::Hiragana);
+        assert_eq!(transliterate("東京はヴ", &mut dict), "とーきょーはゔ");
+        dict.add_entry("駅", "エキ", 0, 100);
+        assert_eq!(transliterate("駅", &mut dict), "えき");
+
+        // A tokenizer over it starts out asking for what is stored, and converts nothing.
+        let mut tokenizer = Tokenizer::new(dict);
+        assert_eq!(tokenizer.options().kana, KanaForm::Hiragana);
+        assert_eq!(tokenizer.transliterate("東京は"), "とーきょーは");
+        let mut tokenizer = tokenizer.with_options(Options {
+            pronunciation: true,
+            kana: KanaForm::HiraganaKeepVu,
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.transliterate("はヴ"), "わゔ");
+
+        let mut bytes = Vec::new();
+        builder(KanaForm::HiraganaKeepVu).write(&mut bytes).unwrap();
+        let dict = Dictionary::from_bytes(bytes).unwrap();
+        assert_eq!(dict.reading_kana(), KanaForm::HiraganaKeepVu);
+        let mut tokenizer = Tokenizer::new(dict).with_options(Options {
+            kana: KanaForm::Hiragana,
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.transliterate("東京ヴ"), "とーきょーゔ");
+
+        // User dictionaries have to store theirs the same way.
+        let mut dict = load_built(builder(KanaForm::Hiragana));
+        let err = dict
+            .set_user_dictionary(user_fixture(&[("京都", "キョート", 100)]))
+            .err()
+            .unwrap();
+        assert!(
+            err.to_string().contains("user dictionary readings"),
+            "{}",
+            err
+        );
+
+        #[cfg(feature = "zstd")]
+        {
+            let mut older = builder(KanaForm::Hiragana);
+            older.format_version(5);
+            let err = older.write(Vec::new()).err().unwrap();
+            assert!(err.to_string().contains("Hiragana"), "{}", err);
+        }
     }
 
     #[test]
-    fn test_nihongo() {
-        let mut dict = Dictionary::load("out/mucab.bin").expect("Failed to load dictionary");
-        let result = transliterate("日本語", &mut dict);
-        assert_eq!(result, "ニホンゴ");
+    fn test_lookup_matches_naive_prefix_scan() {
+        let surfaces = [
+            "東",
+            "東京",
+            "東京",
+            "東京都",
+            "東京都庁",
+            "東京湾",
+            "東北",
+            "東口",
+            "東𠀋",
+            "東𠀋京",
+            "京",
+            "京都",
+            "京都府",
+            "都",
+            "都庁",
+        ];
+        let mut builder = DictionaryBuilder::new();
+        for (i, surface) in surfaces.iter().enumerate() {
+            builder.add_entry(surface, &format!("R{}", i), 0, 100);
+        }
+        builder.set_matrix(vec![0], 1);
+        let mut dict = load_built(builder);
+
+        for text in [
+            "東京都庁舎",
+            "東京湾岸",
+            "東𠀋京都府",
+            "京都府東北",
+            "東",
+            "西東京",
+        ] {
+            let chars: Vec<char> = text.chars().collect();
+            for start in 0..chars.len() {
+                let rest: String = chars[start..].iter().collect();
+                let mut matches = Vec::new();
+                dict.lookup(&chars, start, &mut matches);
+                let mut found: Vec<String> = matches
+                    .into_iter()
+                    .map(|(c, i)| dict.get_entry(c, i).unwrap().surface.to_string())
+                    .collect();
+                let mut expected: Vec<String> = surfaces
+                    .iter()
+                    .filter(|s| rest.starts_with(*s))
+                    .map(|s| s.to_string())
+                    .collect();
+                found.sort();
+                expected.sort();
+                assert_eq!(found, expected, "{} at {}", text, start);
+            }
+        }
     }
 
     #[test]
-    fn test_tokyo() {
-        let mut dict = Dictionary::load("out/mucab.bin").expect("Failed to load dictionary");
-        let result = transliterate("東京", &mut dict);
-        assert_eq!(result, "トーキョー");
+    fn test_preload_all_matches_lazy_reads() {
+        let entries = frame_entries();
+        let build = || {
+            let mut builder = DictionaryBuilder::new();
+            for (surface, reading) in &entries {
+                builder.add_entry(surface, reading, 0, 100);
+            }
+            builder.set_matrix(vec![0], 1);
+            builder.frame_size(64);
+            load_built(builder)
+        };
+        let mut lazy = build();
+        let mut eager = build();
+        eager.preload_all().unwrap();
+        assert!(eager.data().strings.get().is_some());
+        assert_eq!(eager.data().cache_stats().resident_blocks, 2);
+
+        let text: String = entries.iter().map(|(s, _)| s.as_str()).collect();
+        assert_eq!(
+            transliterate(&text, &mut eager),
+            transliterate(&text, &mut lazy)
+        );
+        // Preloading twice is a no-op.
+        eager.preload_all().unwrap();
+    }
+
+    /// One line per analysis: `surface/reading/pos/word_cost@path_cost` tokens, then the total.
+    fn golden(dict: &mut Dictionary, texts: &[&str]) -> Vec<String> {
+        texts
+            .iter()
+            .map(|text| {
+                let analysis = analyze(text, dict);
+                let mut line: Vec<String> = analysis
+                    .tokens
+                    .iter()
+                    .map(|t| {
+                        format!(
+                            "{}/{}/{}/{}@{}",
+                            t.token.surface,
+                            t.token.reading,
+                            t.token.pos_id,
+                            t.token.word_cost,
+                            t.path_cost
+                        )
+                    })
+                    .collect();
+                line.push(format!("= {}", analysis.total_cost));
+                line.join(" ")
+            })
+            .collect()
+    }
+
+    fn compat_fixture(compat: AnalysisCompat) -> Dictionary<'static> {
+        let mut builder = char_def_builder();
+        // Ending on right id 1 (the unknown templates) costs 300.
+        builder.set_matrix(vec![0, 0, 300, 0], 2);
+        let mut dict = load_built(builder);
+        dict.set_compat(compat);
+        dict
+    }
+
+    const COMPAT_TEXTS: &[&str] = &["東京コーヒー", "2024年", "東京"];
+
+    #[test]
+    fn test_compat_v1_golden() {
+        // Flat per-character unknowns despite the char.def data, and no EOS cost.
+        let mut dict = compat_fixture(AnalysisCompat::V1);
+        assert_eq!(
+            golden(&mut dict, COMPAT_TEXTS),
+            [
+                "東京/トーキョー/0/100@100 コ/コ/0/0@10100 ー/ー/0/0@20100 ヒ/ヒ/0/0@30100 \
+                 ー/ー/0/0@40100 = 40100",
+                "2/2/0/0@10000 0/0/0/0@20000 2/2/0/0@30000 4/4/0/0@40000 年/ネン/0/100@40100 \
+                 = 40100",
+                "東京/トーキョー/0/100@100 = 100",
+            ]
+        );
+        assert_eq!(
+            transliterate_nbest("東京コーヒー", &mut dict, 1)[0].1,
+            40100
+        );
+    }
+
+    #[test]
+    fn test_compat_v2_golden() {
+        let mut dict = compat_fixture(AnalysisCompat::V2);
+        assert_eq!(
+            golden(&mut dict, COMPAT_TEXTS),
+            [
+                "東京/トーキョー/0/100@100 コーヒー/コーヒー/1/2500@2600 = 2900",
+                "2024/2024/1/1000@1000 年/ネン/0/100@1400 = 1400",
+                "東京/トーキョー/0/100@100 = 100",
+            ]
+        );
+        assert_eq!(transliterate_nbest("東京コーヒー", &mut dict, 1)[0].1, 2900);
+    }
+
+    #[test]
+    fn test_long_vowel_stays_in_unknown_katakana_run() {
+        // ー starts an entry of its own, which used to cut the unmatched run at it.
+        let mut dict = fixture(&[("ー", "ー", 0, 100), ("を", "ヲ", 0, 100)], 1);
+        let surfaces = |dict: &mut Dictionary| -> Vec<String> {
+            tokenize("コーヒーを", dict)
+                .into_iter()
+                .map(|t| t.surface.to_string())
+                .collect()
+        };
+        assert_eq!(surfaces(&mut dict), ["コーヒー", "を"]);
+        assert_eq!(transliterate("コーヒーを", &mut dict), "コーヒーヲ");
+
+        dict.set_compat(AnalysisCompat::V3);
+        assert_eq!(surfaces(&mut dict), ["コ", "ー", "ヒ", "ー", "を"]);
+    }
+
+    #[test]
+    fn test_iteration_mark_repeats_previous_reading() {
+        let mut dict = fixture(&[("人", "ヒト", 0, 100), ("時", "トキ", 0, 100)], 1);
+        assert_eq!(transliterate("人々", &mut dict), "ヒトヒト");
+        assert_eq!(transliterate("時々、人", &mut dict), "トキトキ、ヒト");
+
+        let tokens = tokenize("人々", &mut dict);
+        assert_eq!(tokens[1].surface, "々");
+        assert_eq!(tokens[1].reading, "ヒト");
+        assert!(tokens[1].is_unknown);
+
+        // With nothing known before it, 々 is copied through.
+        assert_eq!(transliterate("々", &mut dict), "々");
+    }
+
+    #[test]
+    fn test_read_readings_keeps_span_order() {
+        let mut builder = DictionaryBuilder::new();
+        for (surface, reading) in frame_entries() {
+            builder.add_entry(&surface, &reading, 0, 100);
+        }
+        builder.set_matrix(vec![0], 1);
+        builder.frame_size(7);
+        let mut dict = load_built(builder);
+
+        let mut spans: Vec<(u32, u16)> = ['京', '東']
+            .into_iter()
+            .flat_map(|c| {
+                dict.load_block(c)
+                    .iter()
+                    .map(|e| match e.reading {
+                        ReadingRef::Stored { offset, len } => (offset, len),
+                        ReadingRef::Inline(_) => unreachable!(),
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        spans.reverse();
+        spans.push(spans[3]);
+        let expected: Vec<String> = spans
+            .iter()
+            .map(|&(offset, len)| dict.read_reading_at(offset, len).unwrap())
+            .collect();
+        assert_eq!(dict.read_readings(&spans), expected);
+        assert!(dict.read_readings(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_cache_limit_evicts_without_changing_results() {
+        let mut unbounded = tokyo_fixture();
+        let mut bounded = tokyo_fixture();
+        bounded.set_cache_limit(CacheLimit::Blocks(1));
+        let text = "東京都と京都";
+        assert_eq!(
+            transliterate(text, &mut bounded),
+            transliterate(text, &mut unbounded)
+        );
+        assert_eq!(tokenize(text, &mut bounded), tokenize(text, &mut unbounded));
+
+        let stats = bounded.cache_stats();
+        assert_eq!(stats.resident_blocks, 1);
+        assert!(stats.misses > 3 && stats.hits > 0);
+        assert!(stats.approx_bytes > 0);
+        assert_eq!(bounded.data().cache_stats().resident_blocks, 1);
+        assert_eq!(unbounded.cache_stats().resident_blocks, 3);
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_mmap_matches_file_loading() {
+        let mut builder = char_def_builder();
+        builder.frame_size(16);
+        let path = crate::testutil::temp_path("mmap.bin");
+        builder.write_to_file(&path).unwrap();
+        let mut file = Dictionary::load(&path).unwrap();
+        let mut mapped = Dictionary::load_mmap(&path).unwrap();
+        let mut second = Dictionary::with_data(mapped.data().clone()).unwrap();
+
+        let text = "コーヒー2024年東京";
+        let expected = tokenize(text, &mut file);
+        assert_eq!(tokenize(text, &mut mapped), expected);
+        assert_eq!(tokenize(text, &mut second), expected);
+        drop((mapped, second));
+        std::fs::remove_file(&path).ok();
+
+        let mut builder = char_def_builder();
+        builder.compress(false);
+        let path = crate::testutil::temp_path("mmap-raw.bin");
+        builder.write_to_file(&path).unwrap();
+        let mut mapped = Dictionary::load_mmap(&path).unwrap();
+        assert_eq!(tokenize(text, &mut mapped), expected);
+        drop(mapped);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_from_bytes_matches_file_loading() {
+        let text = "コーヒー2024年東京";
+        let expected = tokenize(text, &mut load_built(char_def_builder()));
+        for &compress in compress_modes() {
+            let mut builder = char_def_builder();
+            builder.compress(compress);
+            let mut bytes = Vec::new();
+            builder.write(&mut bytes).unwrap();
+            let len = bytes.len() as u64;
+
+            let mut dict = Dictionary::from_bytes(bytes).unwrap();
+            let mut second = Dictionary::with_data(dict.data().clone()).unwrap();
+            assert_eq!(tokenize(text, &mut dict), expected);
+            assert_eq!(tokenize(text, &mut second), expected);
+            dict.data().verify_checksum().unwrap();
+            let info = dict.info().unwrap();
+            assert_eq!(info.compressed, compress);
+            assert!(info.region_bytes > 0 && info.region_bytes < len);
+        }
+
+        // Cuts into the seek table at the end of the compressed region.
+        #[cfg(feature = "zstd")]
+        {
+            let mut bytes = Vec::new();
+            char_def_builder().write(&mut bytes).unwrap();
+            bytes.truncate(bytes.len() - 4);
+            let err = Dictionary::from_bytes(bytes).err().unwrap();
+            assert!(matches!(err, MucabError::Corrupt(_)), "{}", err);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "fs")]
+    fn test_load_reports_progress_by_phase() {
+        // A matrix and an index of a little over one progress step each.
+        let mut builder = DictionaryBuilder::new();
+        for i in 0..7000 {
+            let surface = char::from_u32('一' as u32 + i).unwrap().to_string();
+            builder.add_entry(&surface, "ア", 0, 100);
+        }
+        builder.set_matrix(vec![0; 200 * 200], 200);
+        let path = crate::testutil::temp_path("progress.bin");
+        builder.write_to_file(&path).unwrap();
+
+        let mut reports = Vec::new();
+        let mut dict = Dictionary::load_with_progress(&path, |phase, done| {
+            reports.push((phase, done));
+        })
+        .unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(transliterate("一", &mut dict), "ア");
+
+        let of = |phase| -> Vec<f32> {
+            reports
+                .iter()
+                .filter(|&&(p, _)| p == phase)
+                .map(|&(_, done)| done)
+                .collect()
+        };
+        assert_eq!(of(LoadPhase::Header), [0.0, 1.0]);
+        let matrix = of(LoadPhase::Matrix);
+        assert_eq!(matrix.len(), 3);
+        assert!(matrix[1] > 0.0 && matrix[1] < 1.0);
+        assert_eq!(of(LoadPhase::Index).len(), 3);
+        assert_eq!(reports.last(), Some(&(LoadPhase::Ready, 1.0)));
+        let phases: Vec<LoadPhase> = reports.iter().map(|&(phase, _)| phase).collect();
+        assert!(phases.is_sorted_by_key(|&phase| phase as u8));
+    }
+
+    fn user_fixture(entries: &[(&str, &str, i16)]) -> Dictionary<'static> {
+        let text: String = entries
+            .iter()
+            .map(|(surface, reading, cost)| format!("{},{},{}\n", surface, reading, cost))
+            .collect();
+        let entries = user::parse_user_entries(&text).unwrap();
+        load_built(user::user_dictionary_builder(entries))
+    }
+
+    #[test]
+    fn test_user_dictionary_entries_join_the_lattice() {
+        let mut dict = tokyo_fixture();
+        dict.set_user_dictionary(user_fixture(&[
+            ("タワー", "タワー", 50),
+            ("東京", "トウキョウ", 50),
+        ]))
+        .unwrap();
+        let tokens = tokenize("東京タワー", &mut dict);
+        let readings: Vec<&str> = tokens.iter().map(|t| t.reading.as_ref()).collect();
+        assert_eq!(readings, ["トウキョウ", "タワー"]);
+        assert!(tokens.iter().all(|t| !t.is_unknown));
+
+        // Both entries for 東京 stay candidates; the costs decide.
+        let nbest: Vec<String> = transliterate_nbest("東京", &mut dict, 5)
+            .into_iter()
+            .map(|(reading, _)| reading)
+            .collect();
+        assert_eq!(nbest[..2], ["トウキョウ", "トーキョー"]);
+        dict.user_dictionary_mut()
+            .unwrap()
+            .adjust_cost("東京", "トウキョウ", 100);
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+    }
+
+    #[test]
+    fn test_user_entries_outside_the_matrix_are_rejected() {
+        let mut dict = tokyo_fixture();
+        let entries = user::parse_user_entries("タワー,タワー,50,7\n").unwrap();
+        let err = dict
+            .set_user_dictionary(load_built(user::user_dictionary_builder(entries)))
+            .unwrap_err();
+        assert!(matches!(err, MucabError::Corrupt(_)), "{}", err);
+        assert!(dict.user_dictionary().is_none());
+    }
+
+    #[test]
+    fn test_saturated_paths_are_still_converted() {
+        let mut dict = fixture(&[("東", "ヒガシ", 0, i16::MAX)], 1);
+        dict.set_max_lattice_chars(usize::MAX);
+        // 70000 entries at i16::MAX cost more than i32::MAX along the only path.
+        let text = "東".repeat(70_000);
+        assert_eq!(transliterate(&text, &mut dict), "ヒガシ".repeat(70_000));
+        assert_eq!(tokenize(&text, &mut dict).len(), 70_000);
+
+        // V1 charges every unknown char 10000, so 250000 of them pass i32::MAX as well.
+        dict.set_compat(AnalysisCompat::V1);
+        let unknown: String = "ÅΩ".chars().cycle().take(250_000).collect();
+        let text = format!("東{unknown}東");
+        assert_eq!(
+            transliterate(&text, &mut dict),
+            format!("ヒガシ{unknown}ヒガシ")
+        );
+        let tokens = tokenize(&text, &mut dict);
+        assert_eq!(tokens.len(), 250_002);
+        assert!(tokens[1..250_001].iter().all(|t| t.is_unknown));
+        assert_eq!(tokens[250_001].reading, "ヒガシ");
+    }
+
+    #[test]
+    fn test_added_entries_survive_eviction() {
+        let mut dict = tokyo_fixture();
+        dict.set_cache_limit(CacheLimit::Blocks(1));
+        dict.add_entry("東京湾", "トーキョーワン", 0, 50);
+        dict.add_entry("ムカブ", "ミューカブ", 0, 50);
+        assert_eq!(
+            transliterate("東京湾とムカブと京都", &mut dict),
+            "トーキョーワンとミューカブとキョート"
+        );
+        assert_eq!(
+            transliterate("ムカブ東京湾", &mut dict),
+            "ミューカブトーキョーワン"
+        );
+        let tokens = tokenize("東京湾", &mut dict);
+        assert_eq!(tokens.len(), 1);
+        assert!(!tokens[0].is_unknown);
+
+        // Added entries take overrides like stored ones, and stay on this handle.
+        dict.adjust_cost("東京湾", "トーキョーワン", 20000);
+        assert_eq!(transliterate("東京湾", &mut dict), "トーキョー湾");
+        assert_eq!(transliterate("ムカブ", &mut tokyo_fixture()), "ムカブ");
+    }
+
+    #[test]
+    fn test_transliterate_romaji() {
+        let mut dict = fixture(
+            &[
+                ("東京", "トウキョウ", 0, 100),
+                ("学校", "ガッコウ", 0, 100),
+                ("新聞", "シンブン", 0, 100),
+                ("行っ", "イッ", 0, 100),
+                ("た", "タ", 0, 100),
+            ],
+            1,
+        );
+        let style = romaji::RomajiStyle::default();
+        assert_eq!(transliterate_romaji("東京", &mut dict, style), "tōkyō");
+        assert_eq!(transliterate_romaji("学校", &mut dict, style), "gakkō");
+        assert_eq!(transliterate_romaji("新聞", &mut dict, style), "shinbun");
+        assert_eq!(transliterate_romaji("行った", &mut dict, style), "itta");
+        assert_eq!(transliterate_romaji("東京X", &mut dict, style), "tōkyōX");
+    }
+
+    #[test]
+    fn test_transliterate_furigana() {
+        let mut dict = fixture(
+            &[
+                ("漢字", "カンジ", 0, 100),
+                ("食べる", "タベル", 0, 100),
+                ("を", "ヲ", 0, 100),
+            ],
+            1,
+        );
+        let format = furigana::RubyFormat::Parenthesized;
+        assert_eq!(
+            transliterate_furigana("漢字を食べるX", &mut dict, format),
+            "漢字(かんじ)を食(た)べるX"
+        );
+        assert_eq!(
+            transliterate_furigana("漢字", &mut dict, furigana::RubyFormat::Html),
+            "<ruby>漢字<rt>かんじ</rt></ruby>"
+        );
+    }
+
+    #[test]
+    fn test_transliterate_map_sees_every_token() {
+        let mut dict = tokyo_fixture();
+        let text = "東京都 X京都";
+        let plain = transliterate_map(text, &mut dict, |token| token.reading.to_string());
+        assert_eq!(plain, transliterate(text, &mut dict));
+
+        let marked = transliterate_map(text, &mut dict, |token| match token.is_unknown {
+            true => format!("<{}>", token.surface),
+            false => format!("{}/{}", token.reading, token.pos_id),
+        });
+        assert_eq!(marked, "トーキョー/0ト/0< ><X>キョート/0");
+    }
+
+    #[test]
+    fn test_transliterate_to_writes_what_transliterate_returns() {
+        let mut dict = tokyo_fixture();
+        dict.set_max_lattice_chars(4);
+        let text = "東京都。京都 X東京。\n東京都";
+        let mut out = Vec::new();
+        transliterate_to(text, &mut dict, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            transliterate(text, &mut dict)
+        );
+
+        // A writer that fills up keeps what fit.
+        let mut short = [0u8; 10];
+        let err = transliterate_to(text, &mut dict, &mut short[..]).unwrap_err();
+        assert!(matches!(err, MucabError::Io(ref e) if e.kind() == std::io::ErrorKind::WriteZero));
+        assert_eq!(&short[..9], "トーキ".as_bytes());
+    }
+
+    #[test]
+    fn test_conversions_give_up_at_a_deadline_or_flag() {
+        let mut dict = tokyo_fixture();
+        let text = "東京都。京都".repeat(200);
+        assert_eq!(
+            transliterate_with_deadline(&text, &mut dict, Duration::from_secs(60)).unwrap(),
+            transliterate(&text, &mut dict)
+        );
+        let err = transliterate_with_deadline(&text, &mut dict, Duration::ZERO).unwrap_err();
+        assert!(
+            matches!(&err, MucabError::Timeout { partial } if partial.is_empty()),
+            "{:?}",
+            err
+        );
+
+        // Whitespace is copied through before the first lattice is built.
+        let cancel = Arc::new(AtomicBool::new(false));
+        let text = format!("  {}", text);
+        assert!(transliterate_cancellable(&text, &mut dict, &cancel).is_ok());
+        cancel.store(true, std::sync::atomic::Ordering::Relaxed);
+        let err = transliterate_cancellable(&text, &mut dict, &cancel).unwrap_err();
+        assert!(
+            matches!(&err, MucabError::Cancelled { partial } if partial == "  "),
+            "{:?}",
+            err
+        );
+        // The handle converts as before afterwards.
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
     }
 }
@@ -0,0 +1,109 @@
+//! Unicode normalization of the input before lookup.
+//!
+//! Dictionary surfaces are in the usual forms: full-width kana, ASCII-width Latin letters and
+//! digits, precomposed voiced kana. NFKC folds text written otherwise onto them: ＡＢＣ becomes
+//! ABC, halfwidth ｶﾞ becomes ガ, か followed by a combining dakuten becomes が.
+//!
+//! Each character is normalized together with the combining marks that follow it, so every
+//! normalized character can be traced back to the original bytes it came from. A character
+//! that expands into several (㍿ into 株式会社) maps every one of them to its own bytes.
+
+use std::ops::Range;
+
+use unicode_normalization::char::{canonical_combining_class, decompose_compatible};
+use unicode_normalization::{is_nfkc_quick, IsNormalized, UnicodeNormalization};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Normalization {
+    /// Look the text up as it is.
+    #[default]
+    None,
+    /// Look the text up in Unicode normalization form KC.
+    Nfkc,
+}
+
+/// A text after normalization, with where each of its chars came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Normalized {
+    pub text: String,
+    /// Byte range in the original text of each char of `text`, in order.
+    pub spans: Vec<Range<usize>>,
+}
+
+/// Whether `c` composes with what comes before it: its decomposition starts with a combining
+/// mark (as halfwidth ﾞ and ﾟ do), or it is a Hangul vowel or final jamo.
+fn attaches_to_previous(c: char) -> bool {
+    let mut first = None;
+    decompose_compatible(c, |d| {
+        first.get_or_insert(d);
+    });
+    let first = first.unwrap_or(c);
+    canonical_combining_class(first) != 0 || ('\u{1161}'..='\u{11C2}').contains(&first)
+}
+
+impl Normalization {
+    /// `text` in this form, or `None` when that leaves it unchanged.
+    pub(crate) fn apply(&self, text: &str) -> Option<Normalized> {
+        if *self == Normalization::None || is_nfkc_quick(text.chars()) == IsNormalized::Yes {
+            return None;
+        }
+        let mut normalized = Normalized {
+            text: String::with_capacity(text.len()),
+            spans: Vec::with_capacity(text.len()),
+        };
+        let mut rest = text.char_indices().peekable();
+        while let Some((start, c)) = rest.next() {
+            let mut end = start + c.len_utf8();
+            while let Some(&(i, next)) = rest.peek() {
+                if !attaches_to_previous(next) {
+                    break;
+                }
+                end = i + next.len_utf8();
+                rest.next();
+            }
+            for folded in text[start..end].nfkc() {
+                normalized.text.push(folded);
+                normalized.spans.push(start..end);
+            }
+        }
+        if normalized.text == text {
+            return None;
+        }
+        Some(normalized)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nfkc(text: &str) -> Option<(String, Vec<Range<usize>>)> {
+        Normalization::Nfkc
+            .apply(text)
+            .map(|normalized| (normalized.text, normalized.spans))
+    }
+
+    #[test]
+    fn test_halfwidth_dakuten_folds_onto_the_kana() {
+        let (text, spans) = nfkc("ｶﾞｽ").unwrap();
+        assert_eq!(text, "ガス");
+        assert_eq!(spans, [0..6, 6..9]);
+        assert_eq!(nfkc("か\u{3099}").unwrap().0, "が");
+        assert_eq!(nfkc("ﾊﾟ").unwrap().0, "パ");
+    }
+
+    #[test]
+    fn test_spans_point_at_the_original() {
+        let text = "ＡＢ東京㍿";
+        let (normalized, spans) = nfkc(text).unwrap();
+        assert_eq!(normalized, "AB東京株式会社");
+        let sources: Vec<&str> = spans.iter().map(|span| &text[span.clone()]).collect();
+        assert_eq!(sources, ["Ａ", "Ｂ", "東", "京", "㍿", "㍿", "㍿", "㍿"]);
+    }
+
+    #[test]
+    fn test_unchanged_text_is_not_copied() {
+        assert_eq!(nfkc("東京のガス、ABC"), None);
+        assert_eq!(Normalization::None.apply("ＡＢＣ"), None);
+    }
+}
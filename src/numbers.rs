@@ -0,0 +1,414 @@
+//! Japanese readings of numbers written in digits.
+//!
+//! A run of ASCII or full-width digits is read as a number up to the 京 (10^16) unit: 2024 is
+//! ニセンニジューヨン. Runs with a leading zero or of more than 20 digits are codes rather than
+//! quantities and are read digit by digit. The counters 年, 月, 日, 人 and 円 right after a
+//! number are read with it, since many combinations are irregular: 4年 is ヨネン, 1日 ツイタチ,
+//! 2人 フタリ. Readings are in the katakana and long vowel marks of dictionary pronunciations.
+
+use std::borrow::Cow;
+
+use crate::Token;
+
+const DIGITS: [&str; 10] = [
+    "ゼロ",
+    "イチ",
+    "ニ",
+    "サン",
+    "ヨン",
+    "ゴ",
+    "ロク",
+    "ナナ",
+    "ハチ",
+    "キュー",
+];
+
+/// Units of four digits each, from 10^4 up.
+const UNITS: [&str; 4] = ["マン", "オク", "チョー", "ケイ"];
+
+const MAX_DIGITS: usize = 20;
+
+/// The counters read together with the number before them.
+pub const COUNTERS: [char; 5] = ['年', '月', '日', '人', '円'];
+
+fn digit_value(c: char) -> Option<u32> {
+    match c {
+        '0'..='9' => Some(c as u32 - '0' as u32),
+        '０'..='９' => Some(c as u32 - '０' as u32),
+        _ => None,
+    }
+}
+
+/// Whether `text` is a non-empty run of ASCII or full-width digits.
+pub fn is_digits(text: &str) -> bool {
+    !text.is_empty() && text.chars().all(|c| digit_value(c).is_some())
+}
+
+/// The value of a run of digits, or `None` when it is to be read digit by digit.
+fn value(digits: &str) -> Option<u128> {
+    let count = digits.chars().count();
+    if count > MAX_DIGITS || (count > 1 && digits.starts_with(['0', '０'])) {
+        return None;
+    }
+    digits.chars().try_fold(0u128, |n, c| {
+        n.checked_mul(10)?.checked_add(digit_value(c)? as u128)
+    })
+}
+
+/// Reading of 1..=9999; `before_unit` gives 1000 as イッセン, as in イッセンマン.
+fn group_reading(n: u128, before_unit: bool, out: &mut String) {
+    let (thousands, hundreds, tens, ones) = (n / 1000, n / 100 % 10, n / 10 % 10, n % 10);
+    match thousands {
+        0 => {}
+        1 if before_unit => out.push_str("イッセン"),
+        1 => out.push_str("セン"),
+        3 => out.push_str("サンゼン"),
+        8 => out.push_str("ハッセン"),
+        d => {
+            out.push_str(DIGITS[d as usize]);
+            out.push_str("セン");
+        }
+    }
+    match hundreds {
+        0 => {}
+        1 => out.push_str("ヒャク"),
+        3 => out.push_str("サンビャク"),
+        6 => out.push_str("ロッピャク"),
+        8 => out.push_str("ハッピャク"),
+        d => {
+            out.push_str(DIGITS[d as usize]);
+            out.push_str("ヒャク");
+        }
+    }
+    if tens > 1 {
+        out.push_str(DIGITS[tens as usize]);
+    }
+    if tens > 0 {
+        out.push_str("ジュー");
+    }
+    if ones > 0 {
+        out.push_str(DIGITS[ones as usize]);
+    }
+}
+
+fn number_reading(n: u128) -> String {
+    if n == 0 {
+        return DIGITS[0].to_string();
+    }
+    let mut groups = Vec::new();
+    let mut rest = n;
+    while rest > 0 {
+        groups.push(rest % 10_000);
+        rest /= 10_000;
+    }
+    let mut out = String::new();
+    for (unit, &group) in groups.iter().enumerate().rev() {
+        if group == 0 {
+            continue;
+        }
+        group_reading(group, unit > 0, &mut out);
+        if unit == 0 {
+            continue;
+        }
+        let unit = UNITS[unit - 1];
+        // イチ, ハチ and ジュー are shortened before チョー and ケイ.
+        if unit == "チョー" || unit == "ケイ" {
+            for (long, short) in [("イチ", "イッ"), ("ハチ", "ハッ"), ("ジュー", "ジュッ")]
+            {
+                if out.ends_with(long) {
+                    out.truncate(out.len() - long.len());
+                    out.push_str(short);
+                    break;
+                }
+            }
+        }
+        out.push_str(unit);
+    }
+    out
+}
+
+/// The reading of a run of digits, or `None` if `digits` has anything else in it.
+pub fn reading(digits: &str) -> Option<String> {
+    if !is_digits(digits) {
+        return None;
+    }
+    Some(match value(digits) {
+        Some(n) => number_reading(n),
+        None => digits
+            .chars()
+            .filter_map(digit_value)
+            .map(|d| DIGITS[d as usize])
+            .collect(),
+    })
+}
+
+/// The reading of a run of digits followed by `counter`, one of [`COUNTERS`].
+pub fn counter_reading(digits: &str, counter: char) -> Option<String> {
+    let mut number = reading(digits)?;
+    let n = value(digits);
+    // ヨン becomes ヨ before ネン, ニン and エン: 4年 ヨネン, 14人 ジューヨニン.
+    let shorten_yon = |number: &mut String| {
+        if number.ends_with("ヨン") {
+            number.truncate(number.len() - "ン".len());
+        }
+    };
+    let reading = match (counter, n) {
+        ('人', Some(1)) => "ヒトリ".to_string(),
+        ('人', Some(2)) => "フタリ".to_string(),
+        ('年', _) | ('人', _) | ('円', _) => {
+            shorten_yon(&mut number);
+            number + counter_suffix(counter)
+        }
+        ('月', Some(4)) => "シガツ".to_string(),
+        ('月', Some(7)) => "シチガツ".to_string(),
+        ('月', Some(9)) => "クガツ".to_string(),
+        ('日', Some(n)) => match day_reading(n) {
+            Some(day) => day.to_string(),
+            None => number + "ニチ",
+        },
+        _ => number + counter_suffix(counter),
+    };
+    Some(reading)
+}
+
+fn counter_suffix(counter: char) -> &'static str {
+    match counter {
+        '年' => "ネン",
+        '月' => "ガツ",
+        '日' => "ニチ",
+        '人' => "ニン",
+        '円' => "エン",
+        _ => "",
+    }
+}
+
+/// The days of the month read with native numerals.
+fn day_reading(n: u128) -> Option<&'static str> {
+    Some(match n {
+        1 => "ツイタチ",
+        2 => "フツカ",
+        3 => "ミッカ",
+        4 => "ヨッカ",
+        5 => "イツカ",
+        6 => "ムイカ",
+        7 => "ナノカ",
+        8 => "ヨーカ",
+        9 => "ココノカ",
+        10 => "トーカ",
+        14 => "ジューヨッカ",
+        20 => "ハツカ",
+        24 => "ニジューヨッカ",
+        _ => return None,
+    })
+}
+
+/// Byte offset of `part`, a slice of `text`, within it.
+fn offset_in(text: &str, part: &str) -> usize {
+    part.as_ptr() as usize - text.as_ptr() as usize
+}
+
+/// Cuts an unknown token at the edges of its digit runs, and after a counter right after one,
+/// so that numbers inside a run of unknown text stand on their own.
+fn split_unknown<'t>(token: Token<'t>, out: &mut Vec<Token<'t>>) {
+    if !token.is_unknown || !token.surface.chars().any(|c| digit_value(c).is_some()) {
+        out.push(token);
+        return;
+    }
+    let surface = token.surface;
+    let mut piece_start = 0;
+    let mut prev: Option<char> = None;
+    for (i, c) in surface.char_indices() {
+        let cut = match prev {
+            None => false,
+            Some(p) => {
+                let (p_digit, c_digit) = (digit_value(p).is_some(), digit_value(c).is_some());
+                p_digit != c_digit || (COUNTERS.contains(&p) && i - p.len_utf8() == piece_start)
+            }
+        };
+        if cut {
+            let piece = &surface[piece_start..i];
+            out.push(Token {
+                surface: piece,
+                reading: Cow::Borrowed(piece),
+                start_byte: token.start_byte + piece_start,
+                end_byte: token.start_byte + i,
+                ..token.clone()
+            });
+            piece_start = i;
+        }
+        prev = Some(c);
+    }
+    let piece = &surface[piece_start..];
+    out.push(Token {
+        surface: piece,
+        reading: Cow::Borrowed(piece),
+        start_byte: token.start_byte + piece_start,
+        ..token
+    });
+}
+
+/// Replaces each run of digits in `tokens`, all slices of `text`, by one token carrying the
+/// number's reading; a counter right after the run joins it. The merged tokens are known
+/// words, so readings get the configured kana form.
+pub(crate) fn read_numbers<'t>(text: &'t str, tokens: Vec<Token<'t>>) -> Vec<Token<'t>> {
+    let mut pieces = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        split_unknown(token, &mut pieces);
+    }
+    let mut out = Vec::with_capacity(pieces.len());
+    let mut tokens = pieces.into_iter().peekable();
+    while let Some(first) = tokens.next() {
+        if !is_digits(first.surface) {
+            out.push(first);
+            continue;
+        }
+        let start = offset_in(text, first.surface);
+        let mut end = start + first.surface.len();
+        let mut digits = first.surface.to_string();
+        let mut end_byte = first.end_byte;
+        while let Some(next) = tokens.next_if(|t| is_digits(t.surface)) {
+            end = offset_in(text, next.surface) + next.surface.len();
+            end_byte = next.end_byte;
+            digits.push_str(next.surface);
+        }
+        let mut counter_char = None;
+        let start_byte = first.start_byte;
+        let mut source = first;
+        if let Some(counter) = tokens.next_if(|t| {
+            let mut chars = t.surface.chars();
+            matches!((chars.next(), chars.next()), (Some(c), None) if COUNTERS.contains(&c))
+        }) {
+            end = offset_in(text, counter.surface) + counter.surface.len();
+            end_byte = counter.end_byte;
+            counter_char = counter.surface.chars().next();
+            source = counter;
+        }
+        let reading = match counter_char {
+            Some(counter) => counter_reading(&digits, counter),
+            None => reading(&digits),
+        };
+        out.push(Token {
+            surface: &text[start..end],
+            reading: Cow::Owned(reading.unwrap_or(digits)),
+            pos_id: source.pos_id,
+            pos: source.pos,
+            word_cost: source.word_cost,
+            is_unknown: false,
+            is_punctuation: false,
+            start_byte,
+            end_byte,
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        });
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(digits: &str) -> String {
+        reading(digits).unwrap()
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(read("0"), "ゼロ");
+        assert_eq!(read("7"), "ナナ");
+        assert_eq!(read("10"), "ジュー");
+        assert_eq!(read("24"), "ニジューヨン");
+        assert_eq!(read("300"), "サンビャク");
+        assert_eq!(read("608"), "ロッピャクハチ");
+        assert_eq!(read("3800"), "サンゼンハッピャク");
+        assert_eq!(read("2024"), "ニセンニジューヨン");
+        assert_eq!(read("２０２４"), "ニセンニジューヨン");
+        assert_eq!(read("10000"), "イチマン");
+        assert_eq!(read("10000000"), "イッセンマン");
+        assert_eq!(read("120000305"), "イチオクニセンマンサンビャクゴ");
+        assert_eq!(read("1000000000000"), "イッチョー");
+        assert_eq!(read("80000000000000"), "ハチジュッチョー");
+        assert_eq!(reading("12a"), None);
+        assert_eq!(reading(""), None);
+    }
+
+    #[test]
+    fn test_codes_are_read_digit_by_digit() {
+        assert_eq!(read("007"), "ゼロゼロナナ");
+        assert_eq!(read(&"1".repeat(21)), "イチ".repeat(21));
+        assert_eq!(
+            read(&"9".repeat(20)),
+            number_reading(99_999_999_999_999_999_999)
+        );
+    }
+
+    #[test]
+    fn test_counters() {
+        assert_eq!(
+            counter_reading("2024", '年').unwrap(),
+            "ニセンニジューヨネン"
+        );
+        assert_eq!(counter_reading("4", '年').unwrap(), "ヨネン");
+        assert_eq!(counter_reading("4", '月').unwrap(), "シガツ");
+        assert_eq!(counter_reading("11", '月').unwrap(), "ジューイチガツ");
+        assert_eq!(counter_reading("1", '日').unwrap(), "ツイタチ");
+        assert_eq!(counter_reading("20", '日').unwrap(), "ハツカ");
+        assert_eq!(counter_reading("15", '日').unwrap(), "ジューゴニチ");
+        assert_eq!(counter_reading("1", '人').unwrap(), "ヒトリ");
+        assert_eq!(counter_reading("2", '人').unwrap(), "フタリ");
+        assert_eq!(counter_reading("4", '人').unwrap(), "ヨニン");
+        assert_eq!(counter_reading("14", '人').unwrap(), "ジューヨニン");
+        assert_eq!(counter_reading("11", '人').unwrap(), "ジューイチニン");
+        assert_eq!(counter_reading("104", '円').unwrap(), "ヒャクヨエン");
+    }
+
+    fn unknown(surface: &str) -> Token<'_> {
+        Token {
+            surface,
+            reading: Cow::Borrowed(surface),
+            pos_id: 0,
+            pos: None,
+            word_cost: 0,
+            is_unknown: true,
+            is_punctuation: false,
+            start_byte: 0,
+            end_byte: surface.len(),
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        }
+    }
+
+    #[test]
+    fn test_digit_tokens_merge_with_their_counter() {
+        let text = "2024年3月に1人";
+        let tokens = ["2", "0", "2", "4", "年", "3", "月", "に", "1", "人"];
+        let mut offset = 0;
+        let tokens = tokens
+            .iter()
+            .map(|t| {
+                let token = unknown(&text[offset..offset + t.len()]).offset_by(offset);
+                offset += t.len();
+                token
+            })
+            .collect();
+        let merged = read_numbers(text, tokens);
+        assert!(merged
+            .iter()
+            .all(|t| &text[t.start_byte..t.end_byte] == t.surface));
+        let merged: Vec<(&str, String, bool)> = merged
+            .into_iter()
+            .map(|t| (t.surface, t.reading.into_owned(), t.is_unknown))
+            .collect();
+        assert_eq!(
+            merged,
+            [
+                ("2024年", "ニセンニジューヨネン".to_string(), false),
+                ("3月", "サンガツ".to_string(), false),
+                ("に", "に".to_string(), true),
+                ("1人", "ヒトリ".to_string(), false),
+            ]
+        );
+    }
+}
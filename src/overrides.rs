@@ -0,0 +1,97 @@
+//! Word cost adjustments applied on top of a built dictionary, stored as `surface,reading,delta`
+//! CSV lines. Blank lines and lines starting with `#` are ignored.
+
+/// Adds `delta` to the word cost of every entry with this surface and reading.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CostOverride {
+    pub surface: String,
+    pub reading: String,
+    pub delta: i16,
+}
+
+/// Sets or shifts the word cost of every entry with this surface, whatever its reading: the
+/// cost becomes `cost` if set, the stored one otherwise, plus `delta`. See
+/// [`crate::Dictionary::override_cost`] and [`crate::Dictionary::boost`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SurfaceOverride {
+    pub surface: String,
+    pub cost: Option<i16>,
+    pub delta: i16,
+}
+
+impl SurfaceOverride {
+    /// The word cost of an entry stored with `cost`.
+    pub fn apply(&self, cost: i16) -> i16 {
+        self.cost.unwrap_or(cost).saturating_add(self.delta)
+    }
+}
+
+pub fn parse_overrides(text: &str) -> Result<Vec<CostOverride>, String> {
+    let mut overrides = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split(',').collect();
+        if parts.len() != 3 {
+            return Err(format!(
+                "line {}: expected surface,reading,delta",
+                line_no + 1
+            ));
+        }
+        if parts[0].is_empty() {
+            return Err(format!("line {}: empty surface", line_no + 1));
+        }
+        let delta = parts[2]
+            .trim()
+            .parse()
+            .map_err(|_| format!("line {}: bad delta {}", line_no + 1, parts[2]))?;
+        overrides.push(CostOverride {
+            surface: parts[0].to_string(),
+            reading: parts[1].to_string(),
+            delta,
+        });
+    }
+    Ok(overrides)
+}
+
+pub fn format_overrides(overrides: &[CostOverride]) -> String {
+    let mut out = String::from("# surface,reading,delta\n");
+    for o in overrides {
+        out.push_str(&format!("{},{},{}\n", o.surface, o.reading, o.delta));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let overrides = vec![
+            CostOverride {
+                surface: "東京".to_string(),
+                reading: "トーキョー".to_string(),
+                delta: -120,
+            },
+            CostOverride {
+                surface: "京".to_string(),
+                reading: "ケイ".to_string(),
+                delta: 500,
+            },
+        ];
+        let csv = format_overrides(&overrides);
+        assert_eq!(parse_overrides(&csv).unwrap(), overrides);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_overrides("東京,トーキョー\n").is_err());
+        assert!(parse_overrides("東京,トーキョー,lots\n").is_err());
+        assert!(parse_overrides(",トーキョー,1\n").is_err());
+        assert!(parse_overrides("東京,トーキョー,40000\n").is_err());
+        assert!(parse_overrides("# comment\n\n").unwrap().is_empty());
+    }
+}
@@ -0,0 +1,108 @@
+//! Part-of-speech names: the feature columns of the source CSV that describe an entry's part of
+//! speech (for IPADIC, the four POS levels plus conjugation type and form), joined with `,` and
+//! kept per left context id. Dictionaries store them from format version 4 on.
+//!
+//! On disk the names are deduplicated: a u16 count of distinct names, each a u16 length and
+//! UTF-8 bytes, then a u16 count of pos ids, each the u16 index of its name or `u16::MAX`.
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+const NO_NAME: u16 = u16::MAX;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct PosNames {
+    names: Vec<Box<str>>,
+    /// Index into `names` for each pos id, [`NO_NAME`] for ids without one.
+    by_id: Vec<u16>,
+}
+
+impl PosNames {
+    /// Deduplicates `names`, indexed by pos id.
+    pub(crate) fn from_ids(names: &[Option<String>]) -> Self {
+        let mut pos_names = PosNames::default();
+        let mut seen: HashMap<&str, u16> = HashMap::new();
+        for name in names {
+            let idx = match name {
+                None => NO_NAME,
+                Some(name) => *seen.entry(name).or_insert_with(|| {
+                    pos_names.names.push(name.as_str().into());
+                    (pos_names.names.len() - 1) as u16
+                }),
+            };
+            pos_names.by_id.push(idx);
+        }
+        pos_names
+    }
+
+    pub(crate) fn get(&self, pos_id: u16) -> Option<&str> {
+        let &idx = self.by_id.get(pos_id as usize)?;
+        self.names.get(idx as usize).map(|name| &**name)
+    }
+
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&(self.names.len() as u16).to_le_bytes())?;
+        for name in &self.names {
+            writer.write_all(&(name.len() as u16).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+        }
+        writer.write_all(&(self.by_id.len() as u16).to_le_bytes())?;
+        for idx in &self.by_id {
+            writer.write_all(&idx.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut u16_buf = [0u8; 2];
+        let mut read_u16 = |reader: &mut R| -> std::io::Result<u16> {
+            reader.read_exact(&mut u16_buf)?;
+            Ok(u16::from_le_bytes(u16_buf))
+        };
+
+        let num_names = read_u16(reader)? as usize;
+        let mut names = Vec::with_capacity(num_names);
+        for _ in 0..num_names {
+            let mut name = vec![0u8; read_u16(reader)? as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| {
+                std::io::Error::new(std::io::ErrorKind::InvalidData, "POS name not UTF-8")
+            })?;
+            names.push(name.into_boxed_str());
+        }
+        let num_ids = read_u16(reader)? as usize;
+        let mut by_id = Vec::with_capacity(num_ids);
+        for _ in 0..num_ids {
+            let idx = read_u16(reader)?;
+            if idx != NO_NAME && idx as usize >= names.len() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "POS name index out of range",
+                ));
+            }
+            by_id.push(idx);
+        }
+        Ok(PosNames { names, by_id })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip_deduplicates() {
+        let noun = Some("名詞,一般,*,*,*,*".to_string());
+        let names = PosNames::from_ids(&[noun.clone(), None, noun, Some("助詞".to_string())]);
+        assert_eq!(names.names.len(), 2);
+
+        let mut bytes = Vec::new();
+        names.write_to(&mut bytes).unwrap();
+        let back = PosNames::read_from(&mut bytes.as_slice()).unwrap();
+        assert_eq!(back, names);
+        assert_eq!(back.get(2), Some("名詞,一般,*,*,*,*"));
+        assert_eq!(back.get(1), None);
+        assert_eq!(back.get(3), Some("助詞"));
+        assert_eq!(back.get(4), None);
+    }
+}
@@ -0,0 +1,87 @@
+//! What to do with punctuation when assembling a reading.
+//!
+//! Only unknown tokens made up entirely of punctuation count: the built-in pause marks below,
+//! plus any character given a replacement in [`PunctuationPolicy::Replace`].
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum PunctuationPolicy {
+    /// Echo punctuation into the reading unchanged.
+    #[default]
+    Keep,
+    /// Leave punctuation out of the reading.
+    Drop,
+    /// Substitute each mapped character; punctuation without an entry is kept.
+    Replace(HashMap<char, String>),
+}
+
+/// The built-in sentence and clause marks.
+pub fn is_punctuation_char(c: char) -> bool {
+    matches!(
+        c,
+        '。' | '、' | '！' | '？' | '．' | '，' | '・' | '…' | '!' | '?' | '.' | ',' | ';' | ':'
+    )
+}
+
+/// Whether every char of `surface` is built-in punctuation. False for the empty string.
+pub fn is_punctuation(surface: &str) -> bool {
+    !surface.is_empty() && surface.chars().all(is_punctuation_char)
+}
+
+impl PunctuationPolicy {
+    /// Like [`is_punctuation`], also counting the characters this policy replaces.
+    pub fn classifies(&self, surface: &str) -> bool {
+        match self {
+            PunctuationPolicy::Replace(map) => {
+                !surface.is_empty()
+                    && surface
+                        .chars()
+                        .all(|c| is_punctuation_char(c) || map.contains_key(&c))
+            }
+            _ => is_punctuation(surface),
+        }
+    }
+
+    /// Appends the reading of a punctuation token with this surface to `out`.
+    pub fn render(&self, surface: &str, out: &mut String) {
+        match self {
+            PunctuationPolicy::Keep => out.push_str(surface),
+            PunctuationPolicy::Drop => {}
+            PunctuationPolicy::Replace(map) => {
+                for c in surface.chars() {
+                    match map.get(&c) {
+                        Some(replacement) => out.push_str(replacement),
+                        None => out.push(c),
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        assert!(is_punctuation("。"));
+        assert!(is_punctuation("！？"));
+        assert!(!is_punctuation("X。"));
+        assert!(!is_punctuation(""));
+        let policy = PunctuationPolicy::Replace(HashMap::from([('♪', " ".to_string())]));
+        assert!(policy.classifies("♪。"));
+        assert!(!PunctuationPolicy::Keep.classifies("♪"));
+    }
+
+    #[test]
+    fn test_render() {
+        let mut out = String::new();
+        PunctuationPolicy::Keep.render("、", &mut out);
+        PunctuationPolicy::Drop.render("。", &mut out);
+        let policy = PunctuationPolicy::Replace(HashMap::from([('。', "|".to_string())]));
+        policy.render("。、", &mut out);
+        assert_eq!(out, "、|、");
+    }
+}
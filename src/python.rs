@@ -0,0 +1,100 @@
+//! Python bindings, behind the `python` feature, built into a `mucab` module by maturin (see
+//! `pyproject.toml`).
+//!
+//! A `Mucab` object can be shared between Python threads. It holds one [`DictionaryData`] and a
+//! pool of [`Dictionary`] handles on it: each call takes an idle handle, or opens a new one when
+//! every handle is busy, and runs with the GIL released.
+
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use crate::{Dictionary, DictionaryData, MucabError, OwnedToken, Token};
+
+#[pyclass(module = "mucab", name = "Mucab", frozen)]
+struct Mucab {
+    data: Arc<DictionaryData>,
+    idle: Mutex<Vec<Dictionary<'static>>>,
+}
+
+fn to_py_err(e: MucabError) -> PyErr {
+    match e {
+        MucabError::Io(e) => e.into(),
+        invalid => PyValueError::new_err(invalid.to_string()),
+    }
+}
+
+impl Mucab {
+    /// Runs `f` on a handle from the pool, putting it back afterwards.
+    fn with_handle<T>(&self, f: impl FnOnce(&mut Dictionary<'static>) -> T) -> PyResult<T> {
+        let idle = self.idle.lock().unwrap().pop();
+        let mut dict = match idle {
+            Some(dict) => dict,
+            None => Dictionary::with_data(self.data.clone())?,
+        };
+        let result = f(&mut dict);
+        self.idle.lock().unwrap().push(dict);
+        Ok(result)
+    }
+}
+
+fn token_dict(py: Python<'_>, token: OwnedToken) -> PyResult<Bound<'_, PyDict>> {
+    let dict = PyDict::new(py);
+    dict.set_item("surface", token.surface)?;
+    dict.set_item("reading", token.reading)?;
+    dict.set_item("pos_id", token.pos_id)?;
+    dict.set_item("pos", token.pos)?;
+    dict.set_item("word_cost", token.word_cost)?;
+    dict.set_item("is_unknown", token.is_unknown)?;
+    dict.set_item("is_punctuation", token.is_punctuation)?;
+    dict.set_item("start_byte", token.start_byte)?;
+    dict.set_item("end_byte", token.end_byte)?;
+    dict.set_item("pronunciation", token.pronunciation)?;
+    dict.set_item("accent", token.accent)?;
+    dict.set_item("lemma", token.lemma)?;
+    Ok(dict)
+}
+
+#[pymethods]
+impl Mucab {
+    /// Loads the dictionary at `dict_path`. A missing or unreadable file raises `OSError`, a
+    /// damaged one `ValueError`.
+    #[new]
+    fn new(dict_path: PathBuf) -> PyResult<Self> {
+        let data = Arc::new(DictionaryData::load(&dict_path).map_err(to_py_err)?);
+        let first = Dictionary::with_data(data.clone())?;
+        Ok(Mucab {
+            data,
+            idle: Mutex::new(vec![first]),
+        })
+    }
+
+    /// `text` with every word the dictionary knows replaced by its reading.
+    fn transliterate(&self, py: Python<'_>, text: &str) -> PyResult<String> {
+        py.allow_threads(|| self.with_handle(|dict| crate::transliterate(text, dict)))
+    }
+
+    /// The segmentation of `text`, one dict per token with the fields of `mucab::Token`.
+    fn tokenize<'py>(&self, py: Python<'py>, text: &str) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let tokens: Vec<OwnedToken> = py.allow_threads(|| {
+            self.with_handle(|dict| {
+                crate::tokenize(text, dict)
+                    .into_iter()
+                    .map(Token::into_owned)
+                    .collect()
+            })
+        })?;
+        tokens
+            .into_iter()
+            .map(|token| token_dict(py, token))
+            .collect()
+    }
+}
+
+#[pymodule]
+fn mucab(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Mucab>()
+}
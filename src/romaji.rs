@@ -0,0 +1,293 @@
+//! Hepburn romanization of kana readings.
+//!
+//! The input is split into syllables first, so that digraphs (しゃ sha, ティ ti) are read as one
+//! unit; っ and ん are then resolved against the syllable that follows, and long vowels against
+//! the one before. Readings are converted token by token: vowels are only merged into long
+//! vowels within a token, while っ and ん look across token boundaries (行っ|た → itta).
+//! Anything that is not kana is copied unchanged.
+
+use crate::kana::katakana_to_hiragana;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LongVowels {
+    /// とうきょう → tōkyō.
+    #[default]
+    Macron,
+    /// とうきょう → tookyoo.
+    Doubled,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RomajiStyle {
+    pub long_vowels: LongVowels,
+    /// Traditional Hepburn: ん before b, m and p becomes m (shimbun rather than shinbun).
+    pub m_before_labials: bool,
+}
+
+/// Romanization of one kana, or of a kana followed by a small kana, in hiragana.
+#[rustfmt::skip]
+fn syllable(s: &str) -> Option<&'static str> {
+    Some(match s {
+        "あ" => "a", "い" => "i", "う" => "u", "え" => "e", "お" => "o",
+        "か" => "ka", "き" => "ki", "く" => "ku", "け" => "ke", "こ" => "ko",
+        "が" => "ga", "ぎ" => "gi", "ぐ" => "gu", "げ" => "ge", "ご" => "go",
+        "さ" => "sa", "し" => "shi", "す" => "su", "せ" => "se", "そ" => "so",
+        "ざ" => "za", "じ" => "ji", "ず" => "zu", "ぜ" => "ze", "ぞ" => "zo",
+        "た" => "ta", "ち" => "chi", "つ" => "tsu", "て" => "te", "と" => "to",
+        "だ" => "da", "ぢ" => "ji", "づ" => "zu", "で" => "de", "ど" => "do",
+        "な" => "na", "に" => "ni", "ぬ" => "nu", "ね" => "ne", "の" => "no",
+        "は" => "ha", "ひ" => "hi", "ふ" => "fu", "へ" => "he", "ほ" => "ho",
+        "ば" => "ba", "び" => "bi", "ぶ" => "bu", "べ" => "be", "ぼ" => "bo",
+        "ぱ" => "pa", "ぴ" => "pi", "ぷ" => "pu", "ぺ" => "pe", "ぽ" => "po",
+        "ま" => "ma", "み" => "mi", "む" => "mu", "め" => "me", "も" => "mo",
+        "や" => "ya", "ゆ" => "yu", "よ" => "yo",
+        "ら" => "ra", "り" => "ri", "る" => "ru", "れ" => "re", "ろ" => "ro",
+        "わ" => "wa", "ゐ" => "i", "ゑ" => "e", "を" => "o", "ゔ" => "vu",
+        "ぁ" => "a", "ぃ" => "i", "ぅ" => "u", "ぇ" => "e", "ぉ" => "o",
+        "ゃ" => "ya", "ゅ" => "yu", "ょ" => "yo", "ゎ" => "wa", "ゕ" => "ka", "ゖ" => "ke",
+        "きゃ" => "kya", "きゅ" => "kyu", "きょ" => "kyo",
+        "ぎゃ" => "gya", "ぎゅ" => "gyu", "ぎょ" => "gyo",
+        "しゃ" => "sha", "しゅ" => "shu", "しょ" => "sho", "しぇ" => "she",
+        "じゃ" => "ja", "じゅ" => "ju", "じょ" => "jo", "じぇ" => "je",
+        "ちゃ" => "cha", "ちゅ" => "chu", "ちょ" => "cho", "ちぇ" => "che",
+        "ぢゃ" => "ja", "ぢゅ" => "ju", "ぢょ" => "jo",
+        "にゃ" => "nya", "にゅ" => "nyu", "にょ" => "nyo",
+        "ひゃ" => "hya", "ひゅ" => "hyu", "ひょ" => "hyo",
+        "びゃ" => "bya", "びゅ" => "byu", "びょ" => "byo",
+        "ぴゃ" => "pya", "ぴゅ" => "pyu", "ぴょ" => "pyo",
+        "みゃ" => "mya", "みゅ" => "myu", "みょ" => "myo",
+        "りゃ" => "rya", "りゅ" => "ryu", "りょ" => "ryo",
+        // Combinations used for loanwords.
+        "いぇ" => "ye", "うぃ" => "wi", "うぇ" => "we", "うぉ" => "wo",
+        "ゔぁ" => "va", "ゔぃ" => "vi", "ゔぇ" => "ve", "ゔぉ" => "vo",
+        "ふぁ" => "fa", "ふぃ" => "fi", "ふぇ" => "fe", "ふぉ" => "fo", "ふゅ" => "fyu",
+        "てぃ" => "ti", "てゅ" => "tyu", "でぃ" => "di", "でゅ" => "dyu",
+        "とぅ" => "tu", "どぅ" => "du",
+        "つぁ" => "tsa", "つぃ" => "tsi", "つぇ" => "tse", "つぉ" => "tso",
+        _ => return None,
+    })
+}
+
+fn is_small(c: char) -> bool {
+    matches!(
+        c,
+        'ぁ' | 'ぃ' | 'ぅ' | 'ぇ' | 'ぉ' | 'ゃ' | 'ゅ' | 'ょ' | 'ゎ'
+    )
+}
+
+enum Unit {
+    Syllable(&'static str),
+    Sokuon,
+    Moraic,
+    LongMark,
+    Other(char),
+}
+
+impl Unit {
+    /// The romanization this unit starts with, for resolving a preceding っ or ん.
+    fn head(&self) -> Option<&'static str> {
+        match self {
+            Unit::Syllable(r) => Some(r),
+            _ => None,
+        }
+    }
+}
+
+/// Splits hiragana (and ー) into units, digraphs first. Each char and unit carries whether it
+/// starts a token; digraphs never span two tokens.
+fn units(chars: &[(char, bool)]) -> Vec<(Unit, bool)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let (c, boundary) = chars[i];
+        if let Some(&(next, false)) = chars.get(i + 1) {
+            if is_small(next) {
+                let pair: String = [c, next].into_iter().collect();
+                if let Some(r) = syllable(&pair) {
+                    out.push((Unit::Syllable(r), boundary));
+                    i += 2;
+                    continue;
+                }
+            }
+        }
+        let unit = match c {
+            'っ' => Unit::Sokuon,
+            'ん' => Unit::Moraic,
+            'ー' => Unit::LongMark,
+            _ => {
+                let mut buf = [0u8; 4];
+                match syllable(c.encode_utf8(&mut buf)) {
+                    Some(r) => Unit::Syllable(r),
+                    None => Unit::Other(c),
+                }
+            }
+        };
+        out.push((unit, boundary));
+        i += 1;
+    }
+    out
+}
+
+fn macron(v: char) -> Option<char> {
+    Some(match v {
+        'a' => 'ā',
+        'i' => 'ī',
+        'u' => 'ū',
+        'e' => 'ē',
+        'o' => 'ō',
+        _ => return None,
+    })
+}
+
+/// Lengthens the vowel `out` ends with, if it ends with a plain vowel.
+fn lengthen(out: &mut String, style: RomajiStyle) -> bool {
+    let Some(last) = out.chars().last() else {
+        return false;
+    };
+    let Some(long) = macron(last) else {
+        return false;
+    };
+    match style.long_vowels {
+        LongVowels::Macron => {
+            out.pop();
+            out.push(long);
+        }
+        LongVowels::Doubled => out.push(last),
+    }
+    true
+}
+
+/// Whether the plain vowel `next` after a syllable ending in `last` makes a long vowel:
+/// aa, uu, ee, oo and ou. ii and ei are written out.
+fn extends(last: char, next: &str) -> bool {
+    matches!(
+        (last, next),
+        ('a', "a") | ('u', "u") | ('e', "e") | ('o', "o") | ('o', "u")
+    )
+}
+
+/// Romanizes readings given token by token.
+pub fn readings_to_romaji<'r>(
+    readings: impl IntoIterator<Item = &'r str>,
+    style: RomajiStyle,
+) -> String {
+    let mut chars = Vec::new();
+    for reading in readings {
+        for (i, c) in reading.chars().enumerate() {
+            chars.push((katakana_to_hiragana(c), i == 0));
+        }
+    }
+    let units = units(&chars);
+
+    let mut out = String::new();
+    // Whether `out` ends with a syllable of the current token that a vowel may lengthen.
+    let mut can_lengthen = false;
+    for (i, (unit, boundary)) in units.iter().enumerate() {
+        let next = units.get(i + 1).and_then(|(u, _)| u.head());
+        match unit {
+            Unit::Syllable(r) => {
+                let last = out.chars().last();
+                if can_lengthen && !boundary && last.is_some_and(|l| extends(l, r)) {
+                    lengthen(&mut out, style);
+                    can_lengthen = false;
+                    continue;
+                }
+                out.push_str(r);
+                can_lengthen = true;
+            }
+            Unit::Sokuon => {
+                match next {
+                    Some(r) if r.starts_with("ch") => out.push('t'),
+                    Some(r) if !r.starts_with(['a', 'i', 'u', 'e', 'o']) => {
+                        out.push(r.chars().next().unwrap())
+                    }
+                    _ => {}
+                }
+                can_lengthen = false;
+            }
+            Unit::Moraic => {
+                match next {
+                    Some(r) if r.starts_with(['a', 'i', 'u', 'e', 'o', 'y']) => out.push_str("n'"),
+                    Some(r) if style.m_before_labials && r.starts_with(['b', 'm', 'p']) => {
+                        out.push('m')
+                    }
+                    _ => out.push('n'),
+                }
+                can_lengthen = false;
+            }
+            Unit::LongMark => {
+                if !lengthen(&mut out, style) {
+                    out.push('ー');
+                }
+                can_lengthen = false;
+            }
+            Unit::Other(c) => {
+                out.push(*c);
+                can_lengthen = false;
+            }
+        }
+    }
+    out
+}
+
+/// Romanizes a single reading.
+pub fn to_romaji(reading: &str, style: RomajiStyle) -> String {
+    readings_to_romaji([reading], style)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hepburn(reading: &str) -> String {
+        to_romaji(reading, RomajiStyle::default())
+    }
+
+    #[test]
+    fn test_digraphs_and_long_vowels() {
+        assert_eq!(hepburn("トウキョウ"), "tōkyō");
+        assert_eq!(hepburn("ガッコウ"), "gakkō");
+        assert_eq!(hepburn("シャシン"), "shashin");
+        assert_eq!(hepburn("おかあさん"), "okāsan");
+        assert_eq!(hepburn("コーヒー"), "kōhī");
+        assert_eq!(hepburn("せんせい"), "sensei");
+        assert_eq!(hepburn("おおきい"), "ōkii");
+        assert_eq!(hepburn("パーティー"), "pātī");
+        let doubled = RomajiStyle {
+            long_vowels: LongVowels::Doubled,
+            ..RomajiStyle::default()
+        };
+        assert_eq!(to_romaji("トウキョウ", doubled), "tookyoo");
+        assert_eq!(to_romaji("コーヒー", doubled), "koohii");
+    }
+
+    #[test]
+    fn test_sokuon_and_moraic_n() {
+        assert_eq!(hepburn("マッチャ"), "matcha");
+        assert_eq!(hepburn("ざっし"), "zasshi");
+        assert_eq!(hepburn("あっ"), "a");
+        assert_eq!(hepburn("シンブン"), "shinbun");
+        assert_eq!(hepburn("しんあい"), "shin'ai");
+        assert_eq!(hepburn("こんや"), "kon'ya");
+        let traditional = RomajiStyle {
+            m_before_labials: true,
+            ..RomajiStyle::default()
+        };
+        assert_eq!(to_romaji("シンブン", traditional), "shimbun");
+        assert_eq!(to_romaji("さんぽ", traditional), "sampo");
+    }
+
+    #[test]
+    fn test_token_boundaries() {
+        let style = RomajiStyle::default();
+        assert_eq!(readings_to_romaji(["イッ", "タ"], style), "itta");
+        assert_eq!(readings_to_romaji(["コ", "ウマ"], style), "kouma");
+        assert_eq!(readings_to_romaji(["ホン", "ヤ"], style), "hon'ya");
+    }
+
+    #[test]
+    fn test_other_chars_pass_through() {
+        assert_eq!(hepburn("東京2024ー"), "東京2024ー");
+        assert_eq!(hepburn("ABCです。"), "ABCdesu。");
+    }
+}
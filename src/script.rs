@@ -0,0 +1,103 @@
+//! The script of a character, by its Unicode Script property, and what readings of unknown
+//! tokens do with the kanji in them ([`UnknownKanjiPolicy`]).
+
+use std::borrow::Cow;
+
+use unicode_script::{Script, UnicodeScript};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharScript {
+    /// Script Hiragana or Katakana, halfwidth katakana included.
+    Kana,
+    /// Script Han: kanji, and marks such as 々 and 〆.
+    Han,
+    /// Anything else, including the prolonged sound mark ー, whose script is Common.
+    Other,
+}
+
+pub fn char_script(c: char) -> CharScript {
+    match c.script() {
+        Script::Hiragana | Script::Katakana => CharScript::Kana,
+        Script::Han => CharScript::Han,
+        _ => CharScript::Other,
+    }
+}
+
+/// What the reading of an unknown token does with its kanji, which no dictionary entry gave a
+/// reading. Kana and other characters go through as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum UnknownKanjiPolicy {
+    /// Keep the kanji in the reading.
+    #[default]
+    Passthrough,
+    /// Replace each kanji with this text, such as 〓.
+    Placeholder(String),
+    /// Put `open` and `close` around each run of kanji, as in ⟦漢⟧, for later stages to find.
+    Wrap { open: String, close: String },
+}
+
+impl UnknownKanjiPolicy {
+    /// `reading` with its kanji handled by this policy, borrowed if nothing changed.
+    pub fn apply<'r>(&self, reading: &'r str) -> Cow<'r, str> {
+        let is_han = |c: char| char_script(c) == CharScript::Han;
+        if !reading.chars().any(is_han) {
+            return Cow::Borrowed(reading);
+        }
+        let mut out = String::with_capacity(reading.len());
+        match self {
+            UnknownKanjiPolicy::Passthrough => return Cow::Borrowed(reading),
+            UnknownKanjiPolicy::Placeholder(placeholder) => {
+                for c in reading.chars() {
+                    if is_han(c) {
+                        out.push_str(placeholder);
+                    } else {
+                        out.push(c);
+                    }
+                }
+            }
+            UnknownKanjiPolicy::Wrap { open, close } => {
+                let mut in_run = false;
+                for c in reading.chars() {
+                    if is_han(c) != in_run {
+                        in_run = !in_run;
+                        out.push_str(if in_run { open } else { close });
+                    }
+                    out.push(c);
+                }
+                if in_run {
+                    out.push_str(close);
+                }
+            }
+        }
+        Cow::Owned(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_char_script() {
+        let scripts: Vec<CharScript> = "あア々漢ｱーa".chars().map(char_script).collect();
+        use CharScript::*;
+        assert_eq!(scripts, [Kana, Kana, Han, Han, Kana, Other, Other]);
+    }
+
+    #[test]
+    fn test_policies() {
+        let reading = "ヒ鬱々とX龘";
+        assert!(matches!(
+            UnknownKanjiPolicy::Passthrough.apply(reading),
+            Cow::Borrowed("ヒ鬱々とX龘")
+        ));
+        let placeholder = UnknownKanjiPolicy::Placeholder("〓".to_string());
+        assert_eq!(placeholder.apply(reading), "ヒ〓〓とX〓");
+        assert!(matches!(placeholder.apply("カナ"), Cow::Borrowed(_)));
+        let wrap = UnknownKanjiPolicy::Wrap {
+            open: "⟦".to_string(),
+            close: "⟧".to_string(),
+        };
+        assert_eq!(wrap.apply(reading), "ヒ⟦鬱々⟧とX⟦龘⟧");
+    }
+}
@@ -0,0 +1,58 @@
+#[cfg(feature = "fs")]
+use std::path::PathBuf;
+#[cfg(feature = "fs")]
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::builder::DictionaryBuilder;
+use crate::Dictionary;
+
+#[cfg(feature = "fs")]
+static FIXTURE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Unique path under the system temp dir for a fixture file.
+#[cfg(feature = "fs")]
+pub fn temp_path(name: &str) -> PathBuf {
+    let n = FIXTURE_COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("mucab-{}-{}-{}", std::process::id(), n, name))
+}
+
+/// Writes `builder` to a temp file and loads it back, or loads its bytes without the `fs`
+/// feature.
+#[cfg(feature = "fs")]
+pub fn load_built(mut builder: DictionaryBuilder) -> Dictionary<'static> {
+    let path = temp_path("fixture.bin");
+    builder
+        .write_to_file(&path)
+        .expect("Failed to write fixture");
+    let dict = Dictionary::load(&path).expect("Failed to load fixture");
+    std::fs::remove_file(&path).ok();
+    dict
+}
+
+#[cfg(not(feature = "fs"))]
+pub fn load_built(mut builder: DictionaryBuilder) -> Dictionary<'static> {
+    let mut bytes = Vec::new();
+    builder.write(&mut bytes).expect("Failed to write fixture");
+    Dictionary::from_bytes(bytes).expect("Failed to load fixture")
+}
+
+/// Builds a dictionary from `(surface, reading, pos_id, cost)` entries with an all-zero matrix
+/// covering `pos_count` ids.
+pub fn fixture(entries: &[(&str, &str, u16, i16)], pos_count: u16) -> Dictionary<'static> {
+    let mut builder = DictionaryBuilder::new();
+    for &(surface, reading, pos_id, cost) in entries {
+        builder.add_entry(surface, reading, pos_id, cost);
+    }
+    builder.set_matrix(vec![0; pos_count as usize * pos_count as usize], pos_count);
+    load_built(builder)
+}
+
+/// The settings of [`DictionaryBuilder::compress`] this build can write: raw, and compressed with
+/// the `zstd` feature.
+pub fn compress_modes() -> &'static [bool] {
+    if cfg!(feature = "zstd") {
+        &[false, true]
+    } else {
+        &[false]
+    }
+}
@@ -0,0 +1,252 @@
+use std::borrow::Cow;
+
+/// One segment of an analysed text, borrowing from the input text (and, where possible, from the
+/// dictionary). Cheap to produce; convert with [`Token::into_owned`] to keep it past the borrow.
+///
+/// For unknown tokens (text not covered by the dictionary) `reading` is the surface itself and
+/// `pos_id`/`word_cost` come from the unknown-word template that produced them, or are 0 for
+/// dictionaries without character definitions.
+///
+/// `start_byte..end_byte` is where `surface` lies in the text given to [`crate::tokenize`] or
+/// [`crate::Tokenizer::tokens`], always on char boundaries of that text, normalized or not.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Token<'a> {
+    pub surface: &'a str,
+    pub reading: Cow<'a, str>,
+    pub pos_id: u16,
+    /// The dictionary's name for `pos_id` (see [`crate::pos`]), if it has one.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub pos: Option<Cow<'a, str>>,
+    pub word_cost: i16,
+    pub is_unknown: bool,
+    /// An unknown token made up entirely of punctuation (see [`crate::punctuation`]).
+    pub is_punctuation: bool,
+    pub start_byte: usize,
+    pub end_byte: usize,
+    /// The entry's pronunciation if it differs from the reading, and its accent type (see
+    /// [`crate::DictEntry::pronunciation`] and [`crate::DictEntry::accent`]). Only dictionary
+    /// words have them, and only in dictionaries built with them.
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub pronunciation: Option<Cow<'a, str>>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub accent: Option<u8>,
+    /// The dictionary form of a dictionary word, 食べる for 食べた, in dictionaries that store
+    /// lemmas (see [`crate::DictEntry::lemma`]).
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub lemma: Option<Cow<'a, str>>,
+}
+
+/// The owned counterpart of [`Token`], free of any borrow so it can be stored or sent to other
+/// threads. Field for field identical to [`Token`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OwnedToken {
+    pub surface: String,
+    pub reading: String,
+    pub pos_id: u16,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub pos: Option<String>,
+    pub word_cost: i16,
+    pub is_unknown: bool,
+    /// An unknown token made up entirely of punctuation (see [`crate::punctuation`]).
+    pub is_punctuation: bool,
+    /// Absent from JSON written before spans were tracked, and then 0.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub start_byte: usize,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub end_byte: usize,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub pronunciation: Option<String>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub accent: Option<u8>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub lemma: Option<String>,
+}
+
+impl Token<'_> {
+    pub fn into_owned(self) -> OwnedToken {
+        OwnedToken {
+            surface: self.surface.to_string(),
+            reading: self.reading.into_owned(),
+            pos_id: self.pos_id,
+            pos: self.pos.map(Cow::into_owned),
+            word_cost: self.word_cost,
+            is_unknown: self.is_unknown,
+            is_punctuation: self.is_punctuation,
+            start_byte: self.start_byte,
+            end_byte: self.end_byte,
+            pronunciation: self.pronunciation.map(Cow::into_owned),
+            accent: self.accent,
+            lemma: self.lemma.map(Cow::into_owned),
+        }
+    }
+
+    pub fn to_owned_token(&self) -> OwnedToken {
+        self.clone().into_owned()
+    }
+
+    /// This token with its span moved `offset` bytes further into the text, for tokens of a
+    /// piece starting `offset` bytes in.
+    pub(crate) fn offset_by(mut self, offset: usize) -> Self {
+        self.start_byte += offset;
+        self.end_byte += offset;
+        self
+    }
+}
+
+impl OwnedToken {
+    /// Borrows this token as a [`Token`] without copying.
+    pub fn as_token(&self) -> Token<'_> {
+        Token {
+            surface: &self.surface,
+            reading: Cow::Borrowed(&self.reading),
+            pos_id: self.pos_id,
+            pos: self.pos.as_deref().map(Cow::Borrowed),
+            word_cost: self.word_cost,
+            is_unknown: self.is_unknown,
+            is_punctuation: self.is_punctuation,
+            start_byte: self.start_byte,
+            end_byte: self.end_byte,
+            pronunciation: self.pronunciation.as_deref().map(Cow::Borrowed),
+            accent: self.accent,
+            lemma: self.lemma.as_deref().map(Cow::Borrowed),
+        }
+    }
+}
+
+impl From<Token<'_>> for OwnedToken {
+    fn from(token: Token<'_>) -> Self {
+        token.into_owned()
+    }
+}
+
+impl From<&Token<'_>> for OwnedToken {
+    fn from(token: &Token<'_>) -> Self {
+        token.to_owned_token()
+    }
+}
+
+impl<'t> From<&'t OwnedToken> for Token<'t> {
+    fn from(token: &'t OwnedToken) -> Self {
+        token.as_token()
+    }
+}
+
+impl<'t, 'a: 't> From<&'t Token<'a>> for Token<'t> {
+    fn from(token: &'t Token<'a>) -> Self {
+        Token {
+            surface: token.surface,
+            reading: Cow::Borrowed(&token.reading),
+            pos_id: token.pos_id,
+            pos: token.pos.as_deref().map(Cow::Borrowed),
+            word_cost: token.word_cost,
+            is_unknown: token.is_unknown,
+            is_punctuation: token.is_punctuation,
+            start_byte: token.start_byte,
+            end_byte: token.end_byte,
+            pronunciation: token.pronunciation.as_deref().map(Cow::Borrowed),
+            accent: token.accent,
+            lemma: token.lemma.as_deref().map(Cow::Borrowed),
+        }
+    }
+}
+
+impl PartialEq<OwnedToken> for Token<'_> {
+    fn eq(&self, other: &OwnedToken) -> bool {
+        *self == other.as_token()
+    }
+}
+
+impl PartialEq<Token<'_>> for OwnedToken {
+    fn eq(&self, other: &Token<'_>) -> bool {
+        self.as_token() == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Token<'static> {
+        Token {
+            surface: "東京",
+            reading: Cow::Borrowed("トーキョー"),
+            pos_id: 3,
+            pos: None,
+            word_cost: -40,
+            is_unknown: false,
+            is_punctuation: false,
+            start_byte: 0,
+            end_byte: "東京".len(),
+            pronunciation: None,
+            accent: None,
+            lemma: None,
+        }
+    }
+
+    #[test]
+    fn test_owned_round_trip() {
+        let token = sample();
+        let owned = token.to_owned_token();
+        assert_eq!(owned.surface, "東京");
+        assert_eq!(owned.reading, "トーキョー");
+        assert_eq!(token, owned);
+        assert_eq!(owned, token);
+        assert_eq!(owned.as_token(), token);
+        assert_eq!(OwnedToken::from(&token), owned);
+        assert_eq!(token.into_owned(), owned);
+    }
+
+    #[test]
+    fn test_pos_round_trip() {
+        let token = Token {
+            pos: Some(Cow::Borrowed("名詞,固有名詞,地域,一般,*,*")),
+            ..sample()
+        };
+        let owned = token.to_owned_token();
+        assert_eq!(owned.pos.as_deref(), Some("名詞,固有名詞,地域,一般,*,*"));
+        assert_eq!(owned.as_token(), token);
+        assert_ne!(owned, sample());
+    }
+
+    #[test]
+    fn test_inequality_across_types() {
+        let mut owned = sample().into_owned();
+        owned.word_cost += 1;
+        assert_ne!(sample(), owned);
+    }
+
+    #[test]
+    fn test_owned_token_is_send() {
+        let owned = sample().into_owned();
+        let handle = std::thread::spawn(move || owned.reading.len());
+        assert_eq!(handle.join().unwrap(), "トーキョー".len());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_owned_token_json() {
+        let owned = sample().into_owned();
+        let json = serde_json::to_string(&owned).unwrap();
+        assert_eq!(
+            json,
+            r#"{"surface":"東京","reading":"トーキョー","pos_id":3,"word_cost":-40,"is_unknown":false,"is_punctuation":false,"start_byte":0,"end_byte":6}"#
+        );
+        let back: OwnedToken = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, owned);
+        assert_eq!(serde_json::to_string(&sample()).unwrap(), json);
+    }
+}
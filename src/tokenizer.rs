@@ -0,0 +1,756 @@
+//! Streaming tokenization over long inputs.
+//!
+//! The text is cut into chunks right after sentence and clause punctuation (and line breaks),
+//! and each chunk is analysed on its own, the way MeCab treats one sentence at a time. Only one
+//! chunk's lattice and tokens are held at once, so memory depends on the longest chunk rather
+//! than on the whole input. Runs without any split character are cut after
+//! [`MAX_CHUNK_CHARS`] characters.
+//!
+//! With [`Normalization::Nfkc`] each chunk is normalized before it is analysed (see
+//! [`crate::normalize`]), and the tokens are mapped back: surfaces are slices of the original
+//! text, and unknown tokens keep it as their reading.
+
+use std::borrow::Cow;
+use std::collections::{HashSet, VecDeque};
+
+use crate::furigana::{self, RubyFormat};
+use crate::greedy;
+use crate::kana::KanaForm;
+use crate::normalize::{Normalization, Normalized};
+use crate::numbers;
+use crate::punctuation::PunctuationPolicy;
+use crate::script::UnknownKanjiPolicy;
+use crate::{
+    tokenize, tokenize_nbest, AnalysisCompat, Dictionary, MissingReading, MucabError, Token,
+    ViterbiConfig,
+};
+
+/// Upper bound on the characters analysed in one go when no split character turns up.
+pub const MAX_CHUNK_CHARS: usize = 4096;
+
+fn is_split_char(c: char) -> bool {
+    matches!(
+        c,
+        '。' | '、' | '！' | '？' | '．' | '，' | '!' | '?' | '\n' | '\u{3000}'
+    )
+}
+
+/// Byte length of the next chunk of `text`: up to and including the first split character (plus
+/// any split characters right after it), or [`MAX_CHUNK_CHARS`] characters.
+fn next_chunk_len(text: &str) -> usize {
+    let mut chars = text.char_indices().peekable();
+    let mut count = 0;
+    while let Some((i, c)) = chars.next() {
+        count += 1;
+        if is_split_char(c) {
+            let mut end = i + c.len_utf8();
+            while let Some(&(j, next)) = chars.peek() {
+                if !is_split_char(next) {
+                    break;
+                }
+                end = j + next.len_utf8();
+                chars.next();
+            }
+            return end;
+        }
+        if count == MAX_CHUNK_CHARS {
+            return i + c.len_utf8();
+        }
+    }
+    text.len()
+}
+
+/// How a [`Tokenizer`] segments each chunk.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Mode {
+    /// The cheapest path through the lattice of every match, connection costs included.
+    #[default]
+    Viterbi,
+    /// The longest match at each position, without a lattice or the connection matrix:
+    /// faster, but worse at segmenting; see [`crate::greedy`].
+    Greedy,
+}
+
+/// Settings applied by a [`Tokenizer`] on top of the dictionary.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub punctuation: PunctuationPolicy,
+    /// Analysis behavior level, set on the dictionary by [`Tokenizer::with_options`].
+    pub compat: AnalysisCompat,
+    /// Script of dictionary readings. Unknown tokens keep their surface as is. Nothing is
+    /// converted when the dictionary already stores its readings in this form (see
+    /// [`Dictionary::reading_kana`]); readings stored in hiragana stay hiragana whatever it is.
+    pub kana: KanaForm,
+    /// What the readings of unknown tokens do with kanji; kept, by default.
+    pub unknown_kanji: UnknownKanjiPolicy,
+    /// Read the words that have a pronunciation (see [`crate::DictEntry::pronunciation`]) as
+    /// it, ワ rather than ハ for the particle は, instead of their reading.
+    pub pronunciation: bool,
+    /// What entries without a reading contribute, set on the dictionary like `compat`.
+    pub missing_reading: MissingReading,
+    /// Cost and connection of the flat unknown-word fallback, set on the dictionary like
+    /// `compat`.
+    pub viterbi: ViterbiConfig,
+    /// Normalization of the text before lookup.
+    pub normalize: Normalization,
+    /// Read runs of digits as Japanese numbers, together with a counter after them (see
+    /// [`crate::numbers`]). Off by default, keeping digits as they are.
+    pub read_numbers: bool,
+    /// Segmentation of the chunks. [`Tokenizer::nbest`] searches the lattice whatever it is.
+    pub mode: Mode,
+    /// POS name prefixes (see [`crate::pos`]) of tokens to leave as written: a token whose POS
+    /// name starts with one of them is read as its surface, and gets no furigana from
+    /// [`Tokenizer::furigana`].
+    pub keep_pos: Vec<String>,
+    /// POS name prefixes of tokens [`Tokenizer::tokens`] leaves out, such as `助詞` for
+    /// particles. Conversions keep them.
+    pub drop_pos: Vec<String>,
+    /// Leave unknown tokens, punctuation included, out of [`Tokenizer::tokens`] too.
+    pub drop_unknown: bool,
+}
+
+/// Whether the POS name of `token` starts with one of `prefixes`.
+fn has_pos(token: &Token, prefixes: &[String]) -> bool {
+    token.pos.as_deref().is_some_and(|pos| {
+        prefixes
+            .iter()
+            .any(|prefix| pos.starts_with(prefix.as_str()))
+    })
+}
+
+impl Options {
+    /// The tokens of `text`, segmented according to the mode.
+    fn segment<'t>(&self, text: &'t str, dict: &mut Dictionary) -> Vec<Token<'t>> {
+        match self.mode {
+            Mode::Viterbi => tokenize(text, dict),
+            Mode::Greedy => greedy::tokenize(text, dict),
+        }
+    }
+
+    /// The tokens analysed from `text`, with numbers read if asked.
+    fn group<'t>(&self, text: &'t str, tokens: Vec<Token<'t>>) -> Vec<Token<'t>> {
+        if self.read_numbers {
+            numbers::read_numbers(text, tokens)
+        } else {
+            tokens
+        }
+    }
+
+    /// Whether `drop_pos` or `drop_unknown` leave `token` out.
+    fn drops(&self, token: &Token) -> bool {
+        (self.drop_unknown && token.is_unknown) || has_pos(token, &self.drop_pos)
+    }
+
+    /// Tags the punctuation the policy adds and puts dictionary readings, or pronunciations if
+    /// asked, in the kana form, or reads the tokens `keep_pos` keeps as their surface. `stored`
+    /// is the form the dictionary readings are already in.
+    fn finish<'t>(&self, mut token: Token<'t>, stored: KanaForm) -> Token<'t> {
+        if token.is_unknown && !token.is_punctuation {
+            token.is_punctuation = self.punctuation.classifies(token.surface);
+        }
+        if has_pos(&token, &self.keep_pos) {
+            token.reading = Cow::Borrowed(token.surface);
+            return token;
+        }
+        if let Some(pronunciation) = token.pronunciation.as_ref().filter(|_| self.pronunciation) {
+            token.reading = pronunciation.clone();
+        }
+        if self.kana != stored && (!token.is_unknown || token.reading != token.surface) {
+            if let Cow::Owned(reading) = self.kana.apply(&token.reading) {
+                token.reading = Cow::Owned(reading);
+            }
+        }
+        if token.is_unknown {
+            if let Cow::Owned(reading) = self.unknown_kanji.apply(&token.reading) {
+                token.reading = Cow::Owned(reading);
+            }
+        }
+        token
+    }
+
+    /// Appends what `token` turns into, punctuation rendered according to the policy.
+    fn render(&self, token: &Token, out: &mut String) {
+        if token.is_punctuation {
+            self.punctuation.render(token.surface, out);
+        } else {
+            out.push_str(&token.reading);
+        }
+    }
+}
+
+/// Owns a [`Dictionary`] and the [`Options`] applied on top of it: the main entry point for
+/// converting text. The free functions such as [`crate::transliterate`] take the dictionary as
+/// it is.
+pub struct Tokenizer<'a> {
+    dict: Dictionary<'a>,
+    options: Options,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Wraps `dict` with default options, keeping the compat level, missing reading handling and
+    /// unknown-word fallback already set on it, and the kana form its readings are stored in.
+    pub fn new(dict: Dictionary<'a>) -> Self {
+        let options = Options {
+            kana: dict.reading_kana(),
+            compat: dict.compat(),
+            missing_reading: dict.missing_reading(),
+            viterbi: dict.viterbi_config(),
+            ..Options::default()
+        };
+        Self { dict, options }
+    }
+
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.dict.set_compat(options.compat);
+        self.dict.set_missing_reading(options.missing_reading);
+        self.dict.set_viterbi_config(options.viterbi);
+        self.options = options;
+        self
+    }
+
+    /// Overlays a user dictionary; see [`Dictionary::set_user_dictionary`].
+    pub fn with_user_dict(mut self, user: Dictionary<'a>) -> Result<Self, MucabError> {
+        self.dict.set_user_dictionary(user)?;
+        Ok(self)
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn dictionary(&self) -> &Dictionary<'a> {
+        &self.dict
+    }
+
+    pub fn dictionary_mut(&mut self) -> &mut Dictionary<'a> {
+        &mut self.dict
+    }
+
+    pub fn into_dictionary(self) -> Dictionary<'a> {
+        self.dict
+    }
+
+    /// Tokenizes `text` lazily, one chunk at a time. The tokens are the same as [`tokenize`]
+    /// (or [`greedy::tokenize`], in [`Mode::Greedy`]) would give for each chunk separately,
+    /// except that their spans are into `text`, `is_punctuation` also covers the characters a
+    /// [`PunctuationPolicy::Replace`] map adds and readings are in the configured [`KanaForm`].
+    /// Punctuation is tagged, never dropped, whatever the policy; the tokens `drop_pos` and
+    /// `drop_unknown` select are left out, after the segmentation is chosen.
+    pub fn tokens<'t>(&mut self, text: &'t str) -> TokenIterator<'_, 'a, 't> {
+        self.stream(text, true)
+    }
+
+    /// [`Self::tokens`], leaving out the dropped tokens if `filtered`.
+    fn stream<'t>(&mut self, text: &'t str, filtered: bool) -> TokenIterator<'_, 'a, 't> {
+        TokenIterator {
+            dict: &mut self.dict,
+            options: &self.options,
+            rest: text,
+            offset: 0,
+            pending: VecDeque::new(),
+            filtered,
+        }
+    }
+
+    /// Joins the readings of [`Self::tokens`], rendering punctuation tokens according to the
+    /// punctuation policy. No token is dropped.
+    pub fn transliterate(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut tokens = self.stream(text, false);
+        while let Some(token) = tokens.next() {
+            tokens.options.render(&token, &mut out);
+        }
+        out
+    }
+
+    /// Collects [`Self::tokens`].
+    pub fn tokenize<'t>(&mut self, text: &'t str) -> Vec<Token<'t>> {
+        self.tokens(text).collect()
+    }
+
+    /// The surfaces of [`Self::tokens`], which put together give `text` back unless some are
+    /// dropped.
+    pub fn segment<'t>(&mut self, text: &'t str) -> Vec<&'t str> {
+        self.tokens(text).map(|token| token.surface).collect()
+    }
+
+    /// [`Self::tokens`] with each dictionary word annotated with its reading in hiragana, as
+    /// [`crate::transliterate_furigana`] annotates them. No token is dropped.
+    pub fn furigana(&mut self, text: &str, format: RubyFormat) -> String {
+        let tokens: Vec<Token> = self.stream(text, false).collect();
+        furigana::format_ruby(
+            &furigana::ruby_segments(&tokens, KanaForm::Hiragana),
+            format,
+        )
+    }
+
+    /// Up to `n` conversions of `text`, cheapest first, with their path costs: the paths of
+    /// [`tokenize_nbest`] over the whole text, not chunk by chunk, rendered the way
+    /// [`Self::transliterate`] renders tokens. Conversions that render the same are reported
+    /// once.
+    pub fn nbest(&mut self, text: &str, n: usize) -> Vec<(String, i32)> {
+        let paths = match self.options.normalize.apply(text) {
+            Some(normalized) => tokenize_nbest(&normalized.text, &mut self.dict, n)
+                .into_iter()
+                .map(|(tokens, cost)| (denormalize(text, &normalized, tokens), cost))
+                .collect(),
+            None => tokenize_nbest(text, &mut self.dict, n),
+        };
+        let stored = self.dict.reading_kana();
+        let mut seen = HashSet::new();
+        paths
+            .into_iter()
+            .filter_map(|(tokens, cost)| {
+                let mut out = String::with_capacity(text.len());
+                for token in self.options.group(text, tokens) {
+                    self.options
+                        .render(&self.options.finish(token, stored), &mut out);
+                }
+                seen.insert(out.clone()).then_some((out, cost))
+            })
+            .collect()
+    }
+}
+
+/// The tokens of `normalized` as `options` segment it, with each token's surface the part of
+/// `text` its chars came from.
+fn tokenize_normalized<'t>(
+    options: &Options,
+    text: &'t str,
+    normalized: &Normalized,
+    dict: &mut Dictionary,
+) -> Vec<Token<'t>> {
+    denormalize(text, normalized, options.segment(&normalized.text, dict))
+}
+
+/// `tokens` of `normalized`, with each token's surface the part of `text` its chars came from.
+/// Tokens splitting the expansion of one character share its surface.
+fn denormalize<'t>(text: &'t str, normalized: &Normalized, tokens: Vec<Token>) -> Vec<Token<'t>> {
+    let mut pos = 0;
+    tokens
+        .into_iter()
+        .map(|token| {
+            let len = token.surface.chars().count();
+            let start = normalized.spans[pos].start;
+            let end = normalized.spans[pos + len - 1].end;
+            pos += len;
+            let surface = &text[start..end];
+            Token {
+                surface,
+                reading: if token.is_unknown && token.reading == token.surface {
+                    Cow::Borrowed(surface)
+                } else {
+                    Cow::Owned(token.reading.into_owned())
+                },
+                pos_id: token.pos_id,
+                pos: token.pos.map(|pos| Cow::Owned(pos.into_owned())),
+                word_cost: token.word_cost,
+                is_unknown: token.is_unknown,
+                is_punctuation: token.is_punctuation,
+                start_byte: start,
+                end_byte: end,
+                pronunciation: token.pronunciation.map(|p| Cow::Owned(p.into_owned())),
+                accent: token.accent,
+                lemma: token.lemma.map(|l| Cow::Owned(l.into_owned())),
+            }
+        })
+        .collect()
+}
+
+/// Iterator returned by [`Tokenizer::tokens`].
+pub struct TokenIterator<'s, 'a, 't> {
+    dict: &'s mut Dictionary<'a>,
+    options: &'s Options,
+    rest: &'t str,
+    /// Byte offset of `rest` in the text.
+    offset: usize,
+    pending: VecDeque<Token<'t>>,
+    /// Leave out the tokens the options drop.
+    filtered: bool,
+}
+
+impl<'t> Iterator for TokenIterator<'_, '_, 't> {
+    type Item = Token<'t>;
+
+    fn next(&mut self) -> Option<Token<'t>> {
+        while self.pending.is_empty() && !self.rest.is_empty() {
+            let (chunk, rest) = self.rest.split_at(next_chunk_len(self.rest));
+            self.rest = rest;
+            let tokens = match self.options.normalize.apply(chunk) {
+                Some(normalized) => {
+                    tokenize_normalized(self.options, chunk, &normalized, self.dict)
+                }
+                None => self.options.segment(chunk, self.dict),
+            };
+            let offset = self.offset;
+            self.offset += chunk.len();
+            self.pending = self
+                .options
+                .group(chunk, tokens)
+                .into_iter()
+                .filter(|token| !(self.filtered && self.options.drops(token)))
+                .map(|token| token.offset_by(offset))
+                .collect();
+        }
+        let token = self.pending.pop_front()?;
+        Some(self.options.finish(token, self.dict.reading_kana()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::{DictionaryBuilder, Entry};
+    use crate::testutil::{fixture, load_built};
+    use std::collections::HashMap;
+
+    fn tokenizer() -> Tokenizer<'static> {
+        Tokenizer::new(fixture(
+            &[
+                ("東京", "トーキョー", 0, 100),
+                ("京都", "キョート", 0, 100),
+                ("都", "ト", 0, 100),
+            ],
+            1,
+        ))
+    }
+
+    #[test]
+    fn test_chunks_end_after_punctuation() {
+        assert_eq!(next_chunk_len("東京。京都"), "東京。".len());
+        assert_eq!(next_chunk_len("東京、。\n京都"), "東京、。\n".len());
+        assert_eq!(next_chunk_len("東京"), "東京".len());
+        let long = "あ".repeat(MAX_CHUNK_CHARS + 10);
+        assert_eq!(next_chunk_len(&long), MAX_CHUNK_CHARS * "あ".len());
+    }
+
+    #[test]
+    fn test_tokens_match_per_chunk_tokenize() {
+        let mut tokenizer = tokenizer();
+        let text = "東京都。京都、東京\n都";
+        let streamed: Vec<Token> = tokenizer.tokens(text).collect();
+
+        let mut expected = Vec::new();
+        let mut offset = 0;
+        for chunk in ["東京都。", "京都、", "東京\n", "都"] {
+            let tokens = tokenize(chunk, tokenizer.dictionary_mut());
+            expected.extend(tokens.into_iter().map(|t| t.offset_by(offset)));
+            offset += chunk.len();
+        }
+        assert_eq!(streamed, expected);
+        let surfaces: String = streamed.iter().map(|t| t.surface).collect();
+        assert_eq!(surfaces, text);
+    }
+
+    #[test]
+    fn test_options_set_the_compat_level() {
+        let tokenizer = tokenizer().with_options(Options {
+            compat: AnalysisCompat::V1,
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.dictionary().compat(), AnalysisCompat::V1);
+        let again = Tokenizer::new(tokenizer.into_dictionary());
+        assert_eq!(again.options().compat, AnalysisCompat::V1);
+    }
+
+    #[test]
+    fn test_unknown_cost_flips_a_borderline_segmentation() {
+        let dict = fixture(
+            &[("東京", "トーキョー", 0, 3000), ("東", "ヒガシ", 0, 100)],
+            1,
+        );
+        let mut tokenizer = Tokenizer::new(dict);
+        let surfaces = |tokenizer: &mut Tokenizer| -> Vec<String> {
+            tokenizer
+                .tokens("東京")
+                .map(|t| t.surface.to_string())
+                .collect()
+        };
+        // 東京 at 3000 beats 東 at 100 followed by an unknown 京.
+        assert_eq!(surfaces(&mut tokenizer), ["東京"]);
+
+        let mut tokenizer = tokenizer.with_options(Options {
+            viterbi: ViterbiConfig {
+                unknown_cost: 1000,
+                ..ViterbiConfig::default()
+            },
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.dictionary().viterbi_config().unknown_cost, 1000);
+        assert_eq!(surfaces(&mut tokenizer), ["東", "京"]);
+    }
+
+    #[test]
+    fn test_hiragana_readings() {
+        let mut tokenizer = tokenizer().with_options(Options {
+            kana: KanaForm::Hiragana,
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.transliterate("東京都カ"), "とーきょーとカ");
+        assert_eq!(
+            Tokenizer::new(tokenizer.into_dictionary()).transliterate("京都"),
+            "キョート"
+        );
+    }
+
+    #[test]
+    fn test_unknown_kanji_policies() {
+        let mut tokenizer = tokenizer();
+        assert_eq!(tokenizer.transliterate("東京鬱カ"), "トーキョー鬱カ");
+        let mut tokenizer = tokenizer.with_options(Options {
+            unknown_kanji: UnknownKanjiPolicy::Placeholder("〓".to_string()),
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.transliterate("東京鬱カ"), "トーキョー〓カ");
+        let tokens: Vec<Token> = tokenizer.tokens("鬱").collect();
+        assert_eq!((tokens[0].surface, &*tokens[0].reading), ("鬱", "〓"));
+
+        let mut tokenizer = tokenizer.with_options(Options {
+            unknown_kanji: UnknownKanjiPolicy::Wrap {
+                open: "⟦".to_string(),
+                close: "⟧".to_string(),
+            },
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.transliterate("東京鬱々カ"), "トーキョー⟦鬱々⟧カ");
+    }
+
+    #[test]
+    fn test_nfkc_matches_halfwidth_and_fullwidth_text() {
+        let dict = fixture(
+            &[("ガス", "ガス", 0, 100), ("ABC", "エービーシー", 0, 100)],
+            1,
+        );
+        let text = "ＡＢＣのｶﾞｽ";
+        let mut plain = Tokenizer::new(dict);
+        assert!(plain.tokens(text).all(|t| t.is_unknown));
+
+        let mut tokenizer = Tokenizer::new(plain.into_dictionary()).with_options(Options {
+            normalize: Normalization::Nfkc,
+            ..Options::default()
+        });
+        let tokens: Vec<(&str, String, bool)> = tokenizer
+            .tokens(text)
+            .map(|t| (t.surface, t.reading.into_owned(), t.is_unknown))
+            .collect();
+        assert_eq!(
+            tokens,
+            [
+                ("ＡＢＣ", "エービーシー".to_string(), false),
+                ("の", "の".to_string(), true),
+                ("ｶﾞｽ", "ガス".to_string(), false),
+            ]
+        );
+        assert_eq!(tokenizer.transliterate(text), "エービーシーのガス");
+
+        // Pronunciations, accents and lemmas survive the mapping back to the original text.
+        let mut builder = DictionaryBuilder::new();
+        builder.extend_entries([
+            Entry {
+                surface: "東京".to_string(),
+                pos_id: 0,
+                right_id: 0,
+                cost: 100,
+                reading: "トーキョー".to_string(),
+                lemma: Some("東京".to_string()),
+                pronunciation: None,
+                accent: Some(0),
+            },
+            Entry {
+                surface: "は".to_string(),
+                pos_id: 0,
+                right_id: 0,
+                cost: 100,
+                reading: "ハ".to_string(),
+                lemma: None,
+                pronunciation: Some("ワ".to_string()),
+                accent: None,
+            },
+        ]);
+        builder.set_matrix(vec![0], 1);
+        let mut tokenizer = Tokenizer::new(load_built(builder)).with_options(Options {
+            pronunciation: true,
+            normalize: Normalization::Nfkc,
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.transliterate("東京はＡ"), "トーキョーワＡ");
+        let accents: Vec<Option<u8>> = tokenizer.tokens("東京はＡ").map(|t| t.accent).collect();
+        assert_eq!(accents, [Some(0), None, None]);
+        let lemmas: Vec<Option<String>> = tokenizer
+            .tokens("東京はＡ")
+            .map(|t| t.lemma.map(Cow::into_owned))
+            .collect();
+        assert_eq!(lemmas, [Some("東京".to_string()), None, None]);
+    }
+
+    #[test]
+    fn test_spans_slice_the_original_text() {
+        let dict = fixture(
+            &[
+                ("ガス", "ガス", 0, 100),
+                ("ABC", "エービーシー", 0, 100),
+                ("東京", "トーキョー", 0, 100),
+            ],
+            1,
+        );
+        let text = "東京 😀ＡＢＣ、ｶﾞｽ😀\n東京";
+        let mut tokenizer = Tokenizer::new(dict).with_options(Options {
+            normalize: Normalization::Nfkc,
+            ..Options::default()
+        });
+        let tokens = tokenizer.tokenize(text);
+        assert_eq!(tokens.iter().filter(|t| !t.is_unknown).count(), 4);
+        for token in &tokens {
+            assert_eq!(&text[token.start_byte..token.end_byte], token.surface);
+        }
+        assert_eq!(tokens.last().map(|t| t.end_byte), Some(text.len()));
+        for token in tokenize(text, tokenizer.dictionary_mut()) {
+            assert_eq!(&text[token.start_byte..token.end_byte], token.surface);
+        }
+    }
+
+    #[test]
+    fn test_numbers_are_read_when_asked() {
+        let text = "2024年に１０人";
+        assert_eq!(tokenizer().transliterate(text), text);
+
+        let mut tokenizer = tokenizer().with_options(Options {
+            read_numbers: true,
+            kana: KanaForm::Hiragana,
+            ..Options::default()
+        });
+        assert_eq!(
+            tokenizer.transliterate(text),
+            "にせんにじゅーよねんにじゅーにん"
+        );
+        let surfaces: Vec<&str> = tokenizer.tokens(text).map(|t| t.surface).collect();
+        assert_eq!(surfaces, ["2024年", "に", "１０人"]);
+    }
+
+    #[test]
+    fn test_segment_tokenize_and_nbest_share_the_options() {
+        let mut tokenizer = tokenizer().with_options(Options {
+            kana: KanaForm::Hiragana,
+            ..Options::default()
+        });
+        assert_eq!(
+            tokenizer.segment("東京都。京都"),
+            ["東京", "都", "。", "京都"]
+        );
+        let readings: Vec<String> = tokenizer
+            .tokenize("東京都")
+            .into_iter()
+            .map(|t| t.reading.into_owned())
+            .collect();
+        assert_eq!(readings, ["とーきょー", "と"]);
+
+        let nbest = tokenizer.nbest("東京都", 2);
+        let conversions: Vec<&str> = nbest.iter().map(|(s, _)| s.as_str()).collect();
+        assert_eq!(conversions, ["とーきょーと", "東きょーと"]);
+        assert_eq!(nbest[0].1, 200);
+        assert_eq!(conversions[0], tokenizer.transliterate("東京都"));
+        assert!(tokenizer.nbest("東京都", 0).is_empty());
+    }
+
+    #[test]
+    fn test_tokens_on_empty_input() {
+        let mut tokenizer = tokenizer();
+        assert_eq!(tokenizer.tokens("").count(), 0);
+    }
+
+    fn punctuation_tokenizer(policy: PunctuationPolicy) -> Tokenizer<'static> {
+        tokenizer().with_options(Options {
+            punctuation: policy,
+            ..Options::default()
+        })
+    }
+
+    #[test]
+    fn test_kept_parts_of_speech_stay_as_written() {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("東京", "トーキョー", 1, 100);
+        builder.add_entry("京都", "キョート", 1, 100);
+        builder.add_entry("都", "ト", 0, 100);
+        builder.add_entry("行く", "イク", 0, 100);
+        builder.set_pos_name(0, "名詞,一般");
+        builder.set_pos_name(1, "名詞,固有名詞,地名");
+        builder.set_matrix(vec![0; 4], 2);
+        let mut tokenizer = Tokenizer::new(load_built(builder)).with_options(Options {
+            keep_pos: vec!["名詞,固有名詞".to_string()],
+            kana: KanaForm::Hiragana,
+            ..Options::default()
+        });
+        assert_eq!(tokenizer.transliterate("東京都に行く"), "東京とにいく");
+        assert_eq!(
+            tokenizer.furigana("東京都に行く", RubyFormat::Parenthesized),
+            "東京都(と)に行(い)く"
+        );
+    }
+
+    #[test]
+    fn test_dropped_tokens_leave_the_segmentation_alone() {
+        let mut builder = DictionaryBuilder::new();
+        builder.add_entry("私", "ワタシ", 0, 100);
+        builder.add_entry("は", "ハ", 1, 100);
+        builder.add_entry("学生", "ガクセイ", 2, 100);
+        builder.add_entry("学", "ガク", 2, 50);
+        builder.add_entry("生です", "ナマデス", 2, 100);
+        builder.add_entry("です", "デス", 3, 100);
+        builder.set_pos_name(0, "名詞,代名詞");
+        builder.set_pos_name(1, "助詞,係助詞");
+        builder.set_pos_name(2, "名詞,一般");
+        builder.set_pos_name(3, "助動詞");
+        let mut matrix = vec![0; 16];
+        // 学 before 生です costs more than 学生 before です.
+        matrix[2 * 4 + 2] = 1000;
+        builder.set_matrix(matrix, 4);
+        let mut tokenizer = Tokenizer::new(load_built(builder)).with_options(Options {
+            drop_pos: vec!["助詞".to_string(), "助動詞".to_string()],
+            drop_unknown: true,
+            ..Options::default()
+        });
+        let text = "私は、学生です";
+        assert_eq!(tokenizer.segment(text), ["私", "学生"]);
+        let spans: Vec<&str> = tokenizer
+            .tokens(text)
+            .map(|t| &text[t.start_byte..t.end_byte])
+            .collect();
+        assert_eq!(spans, ["私", "学生"]);
+        assert_eq!(tokenizer.transliterate(text), "ワタシハ、ガクセイデス");
+    }
+
+    #[test]
+    fn test_punctuation_policies() {
+        let text = "東京、京都。♪";
+        let mut keep = punctuation_tokenizer(PunctuationPolicy::Keep);
+        assert_eq!(keep.transliterate(text), "トーキョー、キョート。♪");
+
+        let mut drop = punctuation_tokenizer(PunctuationPolicy::Drop);
+        assert_eq!(drop.transliterate(text), "トーキョーキョート♪");
+
+        let map = HashMap::from([('、', "<pause>".to_string()), ('♪', String::new())]);
+        let mut replace = punctuation_tokenizer(PunctuationPolicy::Replace(map));
+        assert_eq!(replace.transliterate(text), "トーキョー<pause>キョート。");
+    }
+
+    #[test]
+    fn test_dropped_punctuation_keeps_token_spans() {
+        let text = "東京、京都。";
+        let mut drop = punctuation_tokenizer(PunctuationPolicy::Drop);
+        let tokens: Vec<Token> = drop.tokens(text).collect();
+        let flags: Vec<(&str, bool)> = tokens
+            .iter()
+            .map(|t| (t.surface, t.is_punctuation))
+            .collect();
+        assert_eq!(
+            flags,
+            [("東京", false), ("、", true), ("京都", false), ("。", true)]
+        );
+        let mut offset = 0;
+        for token in &tokens {
+            assert_eq!(&text[offset..offset + token.surface.len()], token.surface);
+            offset += token.surface.len();
+        }
+        assert_eq!(offset, text.len());
+    }
+}
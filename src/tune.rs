@@ -0,0 +1,250 @@
+//! Greedy word cost tuning against a corpus of sentences with gold readings.
+//!
+//! For every sentence whose best reading differs from the gold one, the n-best list is searched
+//! for a path that produces the gold reading. Tokens that only appear on one of the two paths are
+//! the spans where the paths disagree; the tuner tries to make one of those entries cheaper (gold
+//! side) or more expensive (chosen side) by just enough to flip the decision. A change is kept
+//! only if the sentence then converts correctly and no sentence that was already correct breaks.
+
+use std::collections::HashMap;
+
+use crate::overrides::CostOverride;
+use crate::{tokenize_nbest, transliterate, Dictionary, Token};
+
+pub struct TuneOptions {
+    /// Largest total adjustment, in either direction, made to any one entry.
+    pub max_delta: i16,
+    /// How many candidate paths to search for the gold reading.
+    pub nbest: usize,
+}
+
+impl Default for TuneOptions {
+    fn default() -> Self {
+        Self {
+            max_delta: 500,
+            nbest: 16,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TuneReport {
+    pub sentences: usize,
+    pub correct_before: usize,
+    pub correct_after: usize,
+    /// Every adjustment in effect afterwards, including any the dictionary had beforehand.
+    pub overrides: Vec<CostOverride>,
+}
+
+/// Parses `text<TAB>gold reading` lines, skipping blank lines and `#` comments.
+pub fn parse_corpus(text: &str) -> Result<Vec<(String, String)>, String> {
+    let mut corpus = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((sentence, gold)) = line.split_once('\t') else {
+            return Err(format!("line {}: expected text<TAB>reading", line_no + 1));
+        };
+        corpus.push((sentence.to_string(), gold.to_string()));
+    }
+    Ok(corpus)
+}
+
+/// `(surface, reading)` of each dictionary token on a path, keyed by its char span.
+fn path_entries(tokens: &[Token]) -> Vec<((usize, usize), (String, String))> {
+    let mut start = 0;
+    let mut entries = Vec::new();
+    for token in tokens {
+        let end = start + token.surface.chars().count();
+        if !token.is_unknown {
+            entries.push((
+                (start, end),
+                (token.surface.to_string(), token.reading.to_string()),
+            ));
+        }
+        start = end;
+    }
+    entries
+}
+
+/// Candidate `(surface, reading, delta)` adjustments that would make the gold path cheaper than
+/// the chosen one, gold-side entries first.
+fn proposals(
+    chosen: &[Token],
+    chosen_cost: i32,
+    gold: &[Token],
+    gold_cost: i32,
+) -> Vec<(String, String, i32)> {
+    let chosen = path_entries(chosen);
+    let gold = path_entries(gold);
+    let need = gold_cost.saturating_sub(chosen_cost).saturating_add(1);
+
+    // Net number of times an entry's cost is paid on the gold path compared to the chosen one.
+    let mut net: HashMap<&(String, String), i32> = HashMap::new();
+    for (_, key) in &gold {
+        *net.entry(key).or_insert(0) += 1;
+    }
+    for (_, key) in &chosen {
+        *net.entry(key).or_insert(0) -= 1;
+    }
+
+    let mut out = Vec::new();
+    let gold_only = gold.iter().filter(|e| !chosen.contains(e));
+    let chosen_only = chosen.iter().filter(|e| !gold.contains(e));
+    for (_, key) in gold_only.chain(chosen_only) {
+        let k = net[key];
+        if k == 0 || out.iter().any(|(s, r, _)| (s, r) == (&key.0, &key.1)) {
+            continue;
+        }
+        // Lower gold-side entries, raise chosen-side ones; ceil(need / |k|) per occurrence.
+        let step = (need + k.abs() - 1) / k.abs();
+        out.push((key.0.clone(), key.1.clone(), -k.signum() * step));
+    }
+    out
+}
+
+/// Runs one greedy pass over `corpus`, leaving the accepted adjustments applied to `dict`.
+pub fn tune(
+    dict: &mut Dictionary,
+    corpus: &[(String, String)],
+    options: &TuneOptions,
+) -> TuneReport {
+    let mut correct: Vec<bool> = corpus
+        .iter()
+        .map(|(text, gold)| transliterate(text, dict) == *gold)
+        .collect();
+    let correct_before = correct.iter().filter(|&&c| c).count();
+    let mut applied: HashMap<(String, String), i32> = HashMap::new();
+
+    for i in 0..corpus.len() {
+        if correct[i] {
+            continue;
+        }
+        let (text, gold) = &corpus[i];
+        let paths = tokenize_nbest(text, dict, options.nbest);
+        let Some((gold_tokens, gold_cost)) = paths.iter().find(|(tokens, _)| {
+            tokens
+                .iter()
+                .map(|t| t.reading.as_ref())
+                .collect::<String>()
+                == *gold
+        }) else {
+            continue;
+        };
+        let (chosen_tokens, chosen_cost) = &paths[0];
+
+        for (surface, reading, delta) in
+            proposals(chosen_tokens, *chosen_cost, gold_tokens, *gold_cost)
+        {
+            let key = (surface, reading);
+            let total = applied.get(&key).copied().unwrap_or(0) + delta;
+            if total.abs() > options.max_delta as i32 {
+                continue;
+            }
+            let delta = delta as i16;
+            dict.adjust_cost(&key.0, &key.1, delta);
+
+            let fixed = transliterate(text, dict) == *gold;
+            let breaks = fixed
+                && corpus
+                    .iter()
+                    .zip(&correct)
+                    .any(|((other, other_gold), &ok)| {
+                        ok && other.contains(key.0.as_str())
+                            && transliterate(other, dict) != *other_gold
+                    });
+            if fixed && !breaks {
+                applied.insert(key, total);
+                correct[i] = true;
+                break;
+            }
+            dict.adjust_cost(&key.0, &key.1, -delta);
+        }
+    }
+
+    let correct_after = corpus
+        .iter()
+        .filter(|(text, gold)| transliterate(text, dict) == *gold)
+        .count();
+
+    TuneReport {
+        sentences: corpus.len(),
+        correct_before,
+        correct_after,
+        overrides: dict.cost_overrides(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::fixture;
+
+    fn dict() -> Dictionary<'static> {
+        fixture(
+            &[
+                ("東京", "トーキョー", 0, 100),
+                ("東", "ヒガシ", 0, 60),
+                ("京", "ケイ", 0, 60),
+                ("京都", "キョート", 0, 100),
+                ("都", "ト", 0, 200),
+            ],
+            1,
+        )
+    }
+
+    fn corpus(lines: &[(&str, &str)]) -> Vec<(String, String)> {
+        lines
+            .iter()
+            .map(|&(t, g)| (t.to_string(), g.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_fixes_sentence_with_smallest_gold_side_change() {
+        let mut dict = dict();
+        let corpus = corpus(&[("東京", "ヒガシケイ"), ("京都", "キョート")]);
+        let report = tune(&mut dict, &corpus, &TuneOptions::default());
+        assert_eq!((report.correct_before, report.correct_after), (1, 2));
+        assert_eq!(
+            report.overrides,
+            [CostOverride {
+                surface: "東".to_string(),
+                reading: "ヒガシ".to_string(),
+                delta: -21,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_rejects_changes_that_break_correct_sentences() {
+        let mut dict = dict();
+        let corpus = corpus(&[("東京", "トーキョー"), ("東京", "ヒガシケイ")]);
+        let report = tune(&mut dict, &corpus, &TuneOptions::default());
+        assert_eq!((report.correct_before, report.correct_after), (1, 1));
+        assert!(report.overrides.is_empty());
+        assert_eq!(transliterate("東京", &mut dict), "トーキョー");
+    }
+
+    #[test]
+    fn test_respects_max_delta() {
+        let mut dict = dict();
+        let corpus = corpus(&[("東京", "ヒガシケイ")]);
+        let options = TuneOptions {
+            max_delta: 20,
+            ..TuneOptions::default()
+        };
+        let report = tune(&mut dict, &corpus, &options);
+        assert_eq!(report.correct_after, 0);
+        assert!(report.overrides.is_empty());
+    }
+
+    #[test]
+    fn test_parse_corpus() {
+        let corpus = parse_corpus("# gold\n東京\tトーキョー\n\n").unwrap();
+        assert_eq!(corpus, [("東京".to_string(), "トーキョー".to_string())]);
+        assert!(parse_corpus("東京 トーキョー\n").is_err());
+    }
+}
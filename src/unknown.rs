@@ -0,0 +1,468 @@
+//! MeCab-style unknown word definitions (char.def / unk.def).
+//!
+//! Characters are classified into categories; when no dictionary entry covers a position (or the
+//! category is marked `invoke`), unknown nodes are created from the category's templates, either
+//! spanning the whole run of same-category characters (`group`) or 1..=`length` characters.
+
+use std::io::{Read, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnknownTemplate {
+    pub left_id: u16,
+    pub right_id: u16,
+    pub cost: i16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharCategory {
+    pub name: String,
+    /// Create unknown nodes even where dictionary entries start.
+    pub invoke: bool,
+    /// Create one node spanning the full run of characters of this category.
+    pub group: bool,
+    /// Additionally create nodes of 1..=length characters.
+    pub length: u8,
+    pub templates: Vec<UnknownTemplate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct CharRange {
+    start: u32,
+    end: u32,
+    category: u8,
+    /// Bit `i` set when the range also belongs to category `i`.
+    mask: u32,
+}
+
+/// Character categories and their code point ranges. Ranges never overlap and are kept sorted.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CharDefinitions {
+    pub categories: Vec<CharCategory>,
+    ranges: Vec<CharRange>,
+    default_category: u8,
+}
+
+fn parse_code_point(s: &str) -> Option<u32> {
+    u32::from_str_radix(s.strip_prefix("0x").or_else(|| s.strip_prefix("0X"))?, 16).ok()
+}
+
+impl CharDefinitions {
+    /// Parses the contents of a MeCab char.def file. As in MeCab, later range lines override
+    /// earlier ones, and characters outside every range belong to `DEFAULT`.
+    pub fn parse_char_def(text: &str) -> Result<Self, String> {
+        let mut defs = CharDefinitions::default();
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields[0].starts_with("0x") || fields[0].starts_with("0X") {
+                let (start, end) = match fields[0].split_once("..") {
+                    Some((a, b)) => (parse_code_point(a), parse_code_point(b)),
+                    None => (parse_code_point(fields[0]), parse_code_point(fields[0])),
+                };
+                let (Some(start), Some(end)) = (start, end) else {
+                    return Err(format!("line {}: bad code point range", line_no + 1));
+                };
+                if fields.len() < 2 {
+                    return Err(format!("line {}: range without category", line_no + 1));
+                }
+                let mut mask = 0u32;
+                let mut primary = None;
+                for name in &fields[1..] {
+                    let idx = defs.category_index(name).ok_or_else(|| {
+                        format!("line {}: undefined category {}", line_no + 1, name)
+                    })?;
+                    primary.get_or_insert(idx);
+                    mask |= 1 << idx;
+                }
+                defs.insert_range(CharRange {
+                    start,
+                    end,
+                    category: primary.unwrap(),
+                    mask,
+                });
+            } else {
+                if fields.len() != 4 {
+                    return Err(format!(
+                        "line {}: expected NAME INVOKE GROUP LENGTH",
+                        line_no + 1
+                    ));
+                }
+                if defs.categories.len() == 32 {
+                    return Err(format!("line {}: more than 32 categories", line_no + 1));
+                }
+                let flag = |s: &str| match s {
+                    "0" => Ok(false),
+                    "1" => Ok(true),
+                    _ => Err(format!(
+                        "line {}: expected 0 or 1, found {}",
+                        line_no + 1,
+                        s
+                    )),
+                };
+                let length = fields[3]
+                    .parse()
+                    .map_err(|_| format!("line {}: bad length {}", line_no + 1, fields[3]))?;
+                defs.categories.push(CharCategory {
+                    name: fields[0].to_string(),
+                    invoke: flag(fields[1])?,
+                    group: flag(fields[2])?,
+                    length,
+                    templates: Vec::new(),
+                });
+            }
+        }
+        if defs.categories.is_empty() {
+            return Err("no categories defined".to_string());
+        }
+        defs.default_category = defs.category_index("DEFAULT").unwrap_or(0);
+        Ok(defs)
+    }
+
+    /// Parses the contents of a MeCab unk.def file into
+    /// `(category, left_id, right_id, cost)` rows, with the raw ids as written in the file.
+    pub fn parse_unk_def(text: &str) -> Result<Vec<(String, i16, i16, i16)>, String> {
+        let mut rows = Vec::new();
+        for (line_no, line) in text.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let parts: Vec<&str> = line.split(',').collect();
+            if parts.len() < 4 {
+                return Err(format!("line {}: expected at least 4 fields", line_no + 1));
+            }
+            let number = |s: &str| {
+                s.trim()
+                    .parse::<i16>()
+                    .map_err(|_| format!("line {}: bad number {}", line_no + 1, s))
+            };
+            rows.push((
+                parts[0].to_string(),
+                number(parts[1])?,
+                number(parts[2])?,
+                number(parts[3])?,
+            ));
+        }
+        Ok(rows)
+    }
+
+    pub fn category_index(&self, name: &str) -> Option<u8> {
+        self.categories
+            .iter()
+            .position(|c| c.name == name)
+            .map(|i| i as u8)
+    }
+
+    pub fn add_template(
+        &mut self,
+        category: &str,
+        template: UnknownTemplate,
+    ) -> Result<(), String> {
+        let idx = self
+            .category_index(category)
+            .ok_or_else(|| format!("undefined category {}", category))?;
+        self.categories[idx as usize].templates.push(template);
+        Ok(())
+    }
+
+    fn insert_range(&mut self, new: CharRange) {
+        let mut ranges = Vec::with_capacity(self.ranges.len() + 2);
+        for r in self.ranges.drain(..) {
+            if r.end < new.start || r.start > new.end {
+                ranges.push(r);
+                continue;
+            }
+            if r.start < new.start {
+                ranges.push(CharRange {
+                    end: new.start - 1,
+                    ..r
+                });
+            }
+            if r.end > new.end {
+                ranges.push(CharRange {
+                    start: new.end + 1,
+                    ..r
+                });
+            }
+        }
+        ranges.push(new);
+        ranges.sort_by_key(|r| r.start);
+        self.ranges = ranges;
+    }
+
+    /// Returns the primary category index of `c` and the mask of every category it belongs to.
+    pub fn classify(&self, c: char) -> (u8, u32) {
+        let cp = c as u32;
+        let idx = self.ranges.partition_point(|r| r.end < cp);
+        match self.ranges.get(idx) {
+            Some(r) if r.start <= cp => (r.category, r.mask),
+            _ => (self.default_category, 1 << self.default_category),
+        }
+    }
+
+    /// Renders the categories and ranges as char.def text that [`Self::parse_char_def`] reads
+    /// back into equal definitions. Templates are left to [`Self::to_unk_def`].
+    pub fn to_char_def(&self) -> String {
+        let mut out = String::new();
+        for c in &self.categories {
+            out.push_str(&format!(
+                "{} {} {} {}\n",
+                c.name, c.invoke as u8, c.group as u8, c.length
+            ));
+        }
+        for r in &self.ranges {
+            out.push_str(&format!("0x{:04X}..0x{:04X}", r.start, r.end));
+            let primary = r.category as usize;
+            out.push(' ');
+            out.push_str(&self.categories[primary].name);
+            for (i, c) in self.categories.iter().enumerate() {
+                if i != primary && r.mask & (1 << i) != 0 {
+                    out.push(' ');
+                    out.push_str(&c.name);
+                }
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Renders the templates as `CATEGORY,left_id,right_id,cost` unk.def rows, in category and
+    /// template order, with the ids as stored. MeCab's feature columns are not kept.
+    pub fn to_unk_def(&self) -> String {
+        let mut out = String::new();
+        for c in &self.categories {
+            for t in &c.templates {
+                out.push_str(&format!(
+                    "{},{},{},{}\n",
+                    c.name, t.left_id, t.right_id, t.cost
+                ));
+            }
+        }
+        out
+    }
+
+    pub(crate) fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&(self.categories.len() as u16).to_le_bytes())?;
+        for category in &self.categories {
+            writer.write_all(&[category.name.len() as u8])?;
+            writer.write_all(category.name.as_bytes())?;
+            writer.write_all(&[category.invoke as u8, category.group as u8, category.length])?;
+            writer.write_all(&(category.templates.len() as u16).to_le_bytes())?;
+            for t in &category.templates {
+                writer.write_all(&t.left_id.to_le_bytes())?;
+                writer.write_all(&t.right_id.to_le_bytes())?;
+                writer.write_all(&t.cost.to_le_bytes())?;
+            }
+        }
+        writer.write_all(&(self.ranges.len() as u32).to_le_bytes())?;
+        for r in &self.ranges {
+            writer.write_all(&r.start.to_le_bytes())?;
+            writer.write_all(&r.end.to_le_bytes())?;
+            writer.write_all(&[r.category])?;
+            writer.write_all(&r.mask.to_le_bytes())?;
+        }
+        writer.write_all(&[self.default_category])?;
+        Ok(())
+    }
+
+    /// Reads a section written by [`Self::write_to`]; `None` when it holds no categories.
+    pub(crate) fn read_from<R: Read>(reader: &mut R) -> std::io::Result<Option<Self>> {
+        fn invalid(msg: &str) -> std::io::Error {
+            std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+        }
+        let mut u16_buf = [0u8; 2];
+        let mut u32_buf = [0u8; 4];
+        let mut u8_buf = [0u8; 1];
+
+        reader.read_exact(&mut u16_buf)?;
+        let num_categories = u16::from_le_bytes(u16_buf) as usize;
+        if num_categories == 0 {
+            return Ok(None);
+        }
+        if num_categories > 32 {
+            return Err(invalid("too many character categories"));
+        }
+
+        let mut categories = Vec::with_capacity(num_categories);
+        for _ in 0..num_categories {
+            reader.read_exact(&mut u8_buf)?;
+            let mut name = vec![0u8; u8_buf[0] as usize];
+            reader.read_exact(&mut name)?;
+            let name = String::from_utf8(name).map_err(|_| invalid("category name not UTF-8"))?;
+            let mut flags = [0u8; 3];
+            reader.read_exact(&mut flags)?;
+            reader.read_exact(&mut u16_buf)?;
+            let num_templates = u16::from_le_bytes(u16_buf) as usize;
+            let mut templates = Vec::with_capacity(num_templates);
+            for _ in 0..num_templates {
+                let mut t = [0u8; 6];
+                reader.read_exact(&mut t)?;
+                templates.push(UnknownTemplate {
+                    left_id: u16::from_le_bytes([t[0], t[1]]),
+                    right_id: u16::from_le_bytes([t[2], t[3]]),
+                    cost: i16::from_le_bytes([t[4], t[5]]),
+                });
+            }
+            categories.push(CharCategory {
+                name,
+                invoke: flags[0] != 0,
+                group: flags[1] != 0,
+                length: flags[2],
+                templates,
+            });
+        }
+
+        reader.read_exact(&mut u32_buf)?;
+        let num_ranges = u32::from_le_bytes(u32_buf) as usize;
+        let mut ranges = Vec::with_capacity(num_ranges.min(1 << 16));
+        for _ in 0..num_ranges {
+            let mut r = [0u8; 13];
+            reader.read_exact(&mut r)?;
+            let category = r[8];
+            if category as usize >= num_categories {
+                return Err(invalid("character range refers to unknown category"));
+            }
+            ranges.push(CharRange {
+                start: u32::from_le_bytes([r[0], r[1], r[2], r[3]]),
+                end: u32::from_le_bytes([r[4], r[5], r[6], r[7]]),
+                category,
+                mask: u32::from_le_bytes([r[9], r[10], r[11], r[12]]),
+            });
+        }
+
+        reader.read_exact(&mut u8_buf)?;
+        if u8_buf[0] as usize >= num_categories {
+            return Err(invalid("default category out of range"));
+        }
+
+        Ok(Some(CharDefinitions {
+            categories,
+            ranges,
+            default_category: u8_buf[0],
+        }))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) const CHAR_DEF: &str = "\
+# comment line
+DEFAULT 0 1 0
+SPACE 0 1 0
+KANJI 0 0 2
+NUMERIC 1 1 0
+ALPHA 1 1 0
+KATAKANA 1 1 2
+KANJINUMERIC 1 1 0
+
+0x0020 SPACE
+0x0030..0x0039 NUMERIC
+0x0041..0x005A ALPHA
+0x0061..0x007A ALPHA
+0x30A1..0x30FF KATAKANA
+0x4E00..0x9FFF KANJI
+0x4E00 KANJINUMERIC KANJI # 一
+";
+
+    pub(crate) const UNK_DEF: &str = "\
+DEFAULT,0,0,3000,記号,一般,*,*,*,*,*
+SPACE,0,0,100,記号,空白,*,*,*,*,*
+KANJI,1,1,8000,名詞,一般,*,*,*,*,*
+NUMERIC,1,1,1000,名詞,数,*,*,*,*,*
+ALPHA,1,1,2000,名詞,固有名詞,*,*,*,*,*
+KATAKANA,1,1,2500,名詞,一般,*,*,*,*,*
+KANJINUMERIC,1,1,1000,名詞,数,*,*,*,*,*
+";
+
+    pub(crate) fn sample() -> CharDefinitions {
+        let mut defs = CharDefinitions::parse_char_def(CHAR_DEF).unwrap();
+        for (cat, left, right, cost) in CharDefinitions::parse_unk_def(UNK_DEF).unwrap() {
+            defs.add_template(
+                &cat,
+                UnknownTemplate {
+                    left_id: left as u16,
+                    right_id: right as u16,
+                    cost,
+                },
+            )
+            .unwrap();
+        }
+        defs
+    }
+
+    #[test]
+    fn test_classify() {
+        let defs = sample();
+        let name = |c| defs.categories[defs.classify(c).0 as usize].name.as_str();
+        assert_eq!(name('5'), "NUMERIC");
+        assert_eq!(name('カ'), "KATAKANA");
+        assert_eq!(name('ー'), "KATAKANA");
+        assert_eq!(name('漢'), "KANJI");
+        assert_eq!(name('あ'), "DEFAULT");
+        assert_eq!(name('一'), "KANJINUMERIC");
+        let kanji = defs.category_index("KANJI").unwrap();
+        assert_ne!(defs.classify('一').1 & (1 << kanji), 0);
+    }
+
+    #[test]
+    fn test_later_ranges_override_earlier_ones() {
+        let defs = CharDefinitions::parse_char_def(
+            "DEFAULT 0 1 0\nA 0 1 0\nB 0 1 0\n0x0041..0x005A A\n0x0045..0x0046 B\n",
+        )
+        .unwrap();
+        let b = defs.category_index("B").unwrap();
+        let a = defs.category_index("A").unwrap();
+        assert_eq!(defs.classify('D').0, a);
+        assert_eq!(defs.classify('E').0, b);
+        assert_eq!(defs.classify('F').0, b);
+        assert_eq!(defs.classify('G').0, a);
+        assert_eq!(
+            defs.classify('a').0,
+            defs.category_index("DEFAULT").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(CharDefinitions::parse_char_def("").is_err());
+        assert!(CharDefinitions::parse_char_def("DEFAULT 0 1\n").is_err());
+        assert!(CharDefinitions::parse_char_def("DEFAULT 0 1 0\n0x0041 NOPE\n").is_err());
+        assert!(CharDefinitions::parse_unk_def("KANJI,1,x,3\n").is_err());
+    }
+
+    #[test]
+    fn test_text_round_trip() {
+        let defs = sample();
+        let mut back = CharDefinitions::parse_char_def(&defs.to_char_def()).unwrap();
+        for (cat, left, right, cost) in CharDefinitions::parse_unk_def(&defs.to_unk_def()).unwrap()
+        {
+            let template = UnknownTemplate {
+                left_id: left as u16,
+                right_id: right as u16,
+                cost,
+            };
+            back.add_template(&cat, template).unwrap();
+        }
+        assert_eq!(back, defs);
+    }
+
+    #[test]
+    fn test_section_round_trip() {
+        let defs = sample();
+        let mut bytes = Vec::new();
+        defs.write_to(&mut bytes).unwrap();
+        let back = CharDefinitions::read_from(&mut bytes.as_slice())
+            .unwrap()
+            .unwrap();
+        assert_eq!(back, defs);
+
+        let empty = 0u16.to_le_bytes();
+        assert!(CharDefinitions::read_from(&mut empty.as_slice())
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,107 @@
+//! User dictionaries: small mucab.bin files built from `surface,reading[,cost[,left_id[,right_id]]]`
+//! CSV lines and consulted alongside a system dictionary (see
+//! [`crate::Dictionary::set_user_dictionary`]). Blank lines and lines starting with `#` are
+//! ignored.
+//!
+//! User entries join the same lattice as system entries, so their context ids are taken to be in
+//! the system dictionary's id space and connect through its matrix. Without ids they use 0; the
+//! right id defaults to the left one.
+
+use crate::builder::{DictionaryBuilder, Entry};
+
+/// Word cost of user entries that do not give one.
+pub const DEFAULT_USER_COST: i16 = 0;
+
+pub fn parse_user_entries(text: &str) -> Result<Vec<Entry>, String> {
+    let mut entries = Vec::new();
+    for (line_no, line) in text.lines().enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let parts: Vec<&str> = line.split(',').collect();
+        if parts.len() < 2 || parts.len() > 5 {
+            return Err(format!(
+                "line {}: expected surface,reading[,cost[,left_id[,right_id]]]",
+                line_no + 1
+            ));
+        }
+        if parts[0].is_empty() {
+            return Err(format!("line {}: empty surface", line_no + 1));
+        }
+        if parts[0].len() > u16::MAX as usize || parts[1].len() > u16::MAX as usize {
+            return Err(format!("line {}: surface or reading too long", line_no + 1));
+        }
+        let field = |i: usize, name: &str| -> Result<Option<i64>, String> {
+            match parts.get(i).map(|p| p.trim()) {
+                None | Some("") => Ok(None),
+                Some(p) => p
+                    .parse()
+                    .map(Some)
+                    .map_err(|_| format!("line {}: bad {} {}", line_no + 1, name, p)),
+            }
+        };
+        let cost = field(2, "cost")?.unwrap_or(DEFAULT_USER_COST as i64);
+        let left_id = field(3, "left id")?.unwrap_or(0);
+        let right_id = field(4, "right id")?.unwrap_or(left_id);
+        let cost = i16::try_from(cost)
+            .map_err(|_| format!("line {}: cost {} out of range", line_no + 1, cost))?;
+        let (Ok(pos_id), Ok(right_id)) = (u16::try_from(left_id), u16::try_from(right_id)) else {
+            return Err(format!("line {}: context id out of range", line_no + 1));
+        };
+        entries.push(Entry {
+            surface: parts[0].to_string(),
+            pos_id,
+            right_id,
+            cost,
+            reading: parts[1].to_string(),
+            lemma: None,
+            pronunciation: None,
+            accent: None,
+        });
+    }
+    Ok(entries)
+}
+
+/// A builder holding `entries` and the 1x1 matrix user dictionaries carry; the system matrix is
+/// used for every connection.
+pub fn user_dictionary_builder(entries: Vec<Entry>) -> DictionaryBuilder {
+    let mut builder = DictionaryBuilder::new();
+    builder.extend_entries(entries);
+    builder.set_matrix(vec![0], 1);
+    builder
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_user_entries() {
+        let entries =
+            parse_user_entries("# terms\n東京タワー,トーキョータワー\nミュカブ,ミューカブ,-500,3\n\n甲,コウ,10,1,2\r\n")
+                .unwrap();
+        let fields: Vec<_> = entries
+            .iter()
+            .map(|e| (e.surface.as_str(), e.cost, e.pos_id, e.right_id))
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                ("東京タワー", DEFAULT_USER_COST, 0, 0),
+                ("ミュカブ", -500, 3, 3),
+                ("甲", 10, 1, 2)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rejects_bad_lines() {
+        assert!(parse_user_entries("東京").is_err());
+        assert!(parse_user_entries(",トーキョー").is_err());
+        assert!(parse_user_entries("東京,トーキョー,abc").is_err());
+        assert!(parse_user_entries("東京,トーキョー,40000").is_err());
+        assert!(parse_user_entries("東京,トーキョー,0,-1").is_err());
+        assert!(parse_user_entries("a,b,1,2,3,4").is_err());
+    }
+}
@@ -0,0 +1,53 @@
+/* Exercises include/mucab.h against a dictionary whose path is the first argument; run by
+ * tests/ffi.rs. */
+#include <stdio.h>
+#include <string.h>
+
+#include "mucab.h"
+
+static int fail(const char *what) {
+    const char *error = mucab_last_error();
+    fprintf(stderr, "%s: %s\n", what, error ? error : "(no error)");
+    return 1;
+}
+
+int main(int argc, char **argv) {
+    if (argc != 2) {
+        fprintf(stderr, "usage: %s <mucab.bin>\n", argv[0]);
+        return 2;
+    }
+
+    if (mucab_dict_load("/nonexistent/mucab.bin") != NULL) {
+        fprintf(stderr, "loading a missing file succeeded\n");
+        return 1;
+    }
+    if (mucab_last_error() == NULL || strstr(mucab_last_error(), "nonexistent") == NULL) {
+        return fail("missing file error");
+    }
+
+    MucabDict *dict = mucab_dict_load(argv[1]);
+    if (dict == NULL) {
+        return fail("mucab_dict_load");
+    }
+    char *output = mucab_transliterate(dict, "東京都へ行く");
+    if (output == NULL) {
+        return fail("mucab_transliterate");
+    }
+    printf("%s\n", output);
+    mucab_string_free(output);
+
+    if (mucab_transliterate(dict, NULL) != NULL || mucab_transliterate(NULL, "東京") != NULL) {
+        fprintf(stderr, "NULL arguments were accepted\n");
+        return 1;
+    }
+    if (mucab_transliterate(dict, "\xff") != NULL) {
+        fprintf(stderr, "invalid UTF-8 was accepted\n");
+        return 1;
+    }
+    printf("%s\n", mucab_last_error());
+
+    mucab_dict_free(dict);
+    mucab_dict_free(NULL);
+    mucab_string_free(NULL);
+    return 0;
+}
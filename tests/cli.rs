@@ -0,0 +1,97 @@
+//! Runs the `mucab` binary to check how it finds its dictionary and what it exits with when
+//! there is none.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+
+use mucab::builder::DictionaryBuilder;
+
+/// `mucab` with every dictionary search path pointed into `data_home`.
+fn mucab(data_home: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_mucab"));
+    command
+        .env_remove("MUCAB_DICT")
+        .env_remove("APPDATA")
+        .env("XDG_DATA_HOME", data_home)
+        .env("HOME", data_home);
+    command
+}
+
+/// Runs `command` with `input` on stdin.
+fn run_with_input(command: &mut Command, input: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn install_dictionary(dict_path: &Path) {
+    std::fs::create_dir_all(dict_path.parent().unwrap()).unwrap();
+    let mut builder = DictionaryBuilder::new();
+    builder.add_entry("東京", "トーキョー", 0, 100);
+    builder.set_matrix(vec![0], 1);
+    builder.write_to_file(dict_path).unwrap();
+}
+
+#[test]
+fn test_which_dict_without_a_dictionary_is_a_usage_error() {
+    let data_home = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("cli-data-home");
+    std::fs::create_dir_all(&data_home).unwrap();
+    let dict_path = data_home.join("mucab").join("mucab.bin");
+    std::fs::remove_file(&dict_path).ok();
+
+    let output = mucab(&data_home).arg("--which-dict").output().unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(&dict_path.display().to_string()),
+        "{}",
+        stderr
+    );
+
+    install_dictionary(&dict_path);
+    let output = mucab(&data_home).arg("--which-dict").output().unwrap();
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim_end(),
+        dict_path.display().to_string()
+    );
+}
+
+#[test]
+fn test_analyze_uses_the_default_dictionary() {
+    let data_home = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("cli-analyze-home");
+    install_dictionary(&data_home.join("mucab").join("mucab.bin"));
+
+    let output = run_with_input(mucab(&data_home).arg("analyze"), "東京\n");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "東京\tトーキョー\t0\t100\nEOS\n"
+    );
+
+    let snapshots = data_home.join("snapshots");
+    std::fs::remove_dir_all(&snapshots).ok();
+    let snapshot_dir = snapshots.to_str().unwrap();
+    let output = run_with_input(
+        mucab(&data_home).args(["analyze", "--snapshot-out", snapshot_dir]),
+        "東京\n",
+    );
+    assert_eq!(output.status.code(), Some(0));
+    assert!(snapshots.join("1.snap").is_file());
+    let output = run_with_input(
+        mucab(&data_home).args(["analyze", "--snapshot-compare", snapshot_dir]),
+        "東京\n",
+    );
+    assert_eq!(output.status.code(), Some(0));
+}
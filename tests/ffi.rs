@@ -0,0 +1,64 @@
+//! Builds tests/c/smoke.c against include/mucab.h and the cdylib, and runs it.
+#![cfg(unix)]
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use mucab::builder::DictionaryBuilder;
+use mucab::Dictionary;
+
+#[test]
+fn test_c_program_transliterates() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let tmp = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    // `cargo test` links the tests against the rlib only; build the cdylib next to it, in
+    // target/<profile>.
+    let exe = std::env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap().parent().unwrap();
+    let mut cargo = Command::new(env!("CARGO"));
+    cargo.args(["build", "--lib", "--features", "ffi"]);
+    if lib_dir.ends_with("release") {
+        cargo.arg("--release");
+    }
+    let status = cargo.current_dir(&manifest_dir).status().unwrap();
+    assert!(status.success(), "building the cdylib failed");
+
+    let dict_path = tmp.join("ffi-fixture.bin");
+    let mut builder = DictionaryBuilder::new();
+    builder.add_entry("東京", "トーキョー", 0, 100);
+    builder.add_entry("都", "ト", 0, 100);
+    builder.add_entry("行", "イ", 0, 100);
+    builder.set_matrix(vec![0], 1);
+    builder.write_to_file(&dict_path).unwrap();
+    let mut dict = Dictionary::load(&dict_path).unwrap();
+    let expected = mucab::transliterate("東京都へ行く", &mut dict);
+
+    let program = tmp.join("ffi-smoke");
+    let cc = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(cc)
+        .arg(manifest_dir.join("tests/c/smoke.c"))
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg("-L")
+        .arg(lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lmucab")
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .expect("a C compiler is needed to run this test; set CC");
+    assert!(status.success(), "compiling smoke.c failed");
+
+    let output = Command::new(&program).arg(&dict_path).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        output.status.success(),
+        "{}{}",
+        stdout,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], expected);
+    assert!(lines[1].starts_with("text is not UTF-8"), "{}", lines[1]);
+    std::fs::remove_file(&dict_path).ok();
+}
@@ -0,0 +1,44 @@
+//! Converts a corpus on rayon's pool with one cloned handle per worker, and checks it against
+//! converting it on one handle.
+
+use rayon::prelude::*;
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{transliterate, Dictionary};
+
+#[test]
+fn test_cloned_handles_convert_a_corpus_in_parallel() {
+    let path = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("parallel-fixture.bin");
+    let mut builder = DictionaryBuilder::new();
+    builder.add_entry("東京", "トーキョー", 0, 100);
+    builder.add_entry("東", "ヒガシ", 0, 300);
+    builder.add_entry("京都", "キョート", 0, 100);
+    builder.add_entry("都", "ト", 0, 100);
+    builder.add_entry("大阪", "オーサカ", 0, 100);
+    builder.add_entry("府", "フ", 0, 100);
+    builder.set_matrix(vec![0], 1);
+    builder.write_to_file(&path).unwrap();
+
+    let mut dict = Dictionary::load(&path).unwrap();
+    // Clones carry the overrides of the handle they come from.
+    dict.adjust_cost("東", "ヒガシ", -250);
+    let words = ["東京都", "京都府", "大阪府", "東京", "大阪都", "京都"];
+    let corpus: Vec<String> = (0..2000)
+        .map(|i| format!("{}{}", words[i % words.len()], words[i * 7 % words.len()]))
+        .collect();
+    let expected: Vec<String> = corpus
+        .iter()
+        .map(|line| transliterate(line, &mut dict))
+        .collect();
+    assert_eq!(expected[0], "ヒガシキョートヒガシキョート");
+
+    let converted: Vec<String> = corpus
+        .par_iter()
+        .map_init(
+            || dict.try_clone().unwrap(),
+            |handle, line| transliterate(line, handle),
+        )
+        .collect();
+    std::fs::remove_file(&path).ok();
+    assert_eq!(converted, expected);
+}
@@ -0,0 +1,37 @@
+//! Converts a multi-megabyte document and checks the peak resident memory of the process. It is a
+//! test binary of its own so no other test's allocations count towards the peak.
+
+#![cfg(target_os = "linux")]
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{transliterate, Dictionary};
+
+/// Peak resident memory of this process, in kB.
+fn peak_rss_kb() -> u64 {
+    let status = std::fs::read_to_string("/proc/self/status").unwrap();
+    let line = status.lines().find(|l| l.starts_with("VmHWM:")).unwrap();
+    line.split_whitespace().nth(1).unwrap().parse().unwrap()
+}
+
+#[test]
+fn test_multi_megabyte_text_stays_small() {
+    let mut builder = DictionaryBuilder::new();
+    builder.add_entry("東京", "トーキョー", 0, 100);
+    builder.add_entry("都", "ト", 0, 100);
+    builder.add_entry("東京都", "トーキョート", 0, 250);
+    builder.add_entry("京都", "キョート", 0, 100);
+    builder.set_matrix(vec![0], 1);
+    let mut bytes = Vec::new();
+    builder.write(&mut bytes).unwrap();
+    let mut dict = Dictionary::from_bytes(bytes).unwrap();
+
+    let sentence = "東京都に京都。";
+    let text = sentence.repeat((3 << 20) / sentence.len() + 1);
+    let reading = transliterate(&text, &mut dict);
+    assert_eq!(
+        reading,
+        transliterate(sentence, &mut dict).repeat(text.len() / sentence.len())
+    );
+    // Input and output alone take ~7 MB; a whole-text lattice would take hundreds.
+    assert!(peak_rss_kb() < 100 << 10, "peak RSS {} kB", peak_rss_kb());
+}
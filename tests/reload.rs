@@ -0,0 +1,122 @@
+//! Swaps the dictionary of worker threads mid-run, each picking up newly published data between
+//! conversions, reloads a handle from files, a corrupt one among them, and opens handles on data
+//! whose file has been replaced on disk.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{transliterate, Dictionary, DictionaryData};
+
+fn write_fixture(path: &Path, tokyo: &str) {
+    let mut builder = DictionaryBuilder::new();
+    builder.add_entry("東京", tokyo, 0, 100);
+    builder.add_entry("都", "ト", 0, 100);
+    builder.set_matrix(vec![0], 1);
+    builder.write_to_file(path).unwrap();
+}
+
+#[test]
+fn test_workers_pick_up_swapped_data() {
+    let tmp = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let (old_path, new_path) = (tmp.join("reload-old.bin"), tmp.join("reload-new.bin"));
+    write_fixture(&old_path, "トーキョー");
+    write_fixture(&new_path, "トウキョウ");
+
+    let current = Arc::new(Mutex::new(Arc::new(
+        DictionaryData::load(&old_path).unwrap(),
+    )));
+    let conversions = Arc::new(AtomicUsize::new(0));
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let (current, conversions) = (current.clone(), conversions.clone());
+            std::thread::spawn(move || {
+                let mut dict = Dictionary::with_data(current.lock().unwrap().clone()).unwrap();
+                let mut outputs = Vec::new();
+                let mut after_swap = 0;
+                while after_swap < 50 {
+                    let latest = current.lock().unwrap().clone();
+                    if !Arc::ptr_eq(&latest, dict.data()) {
+                        dict.replace_data(latest).unwrap();
+                    }
+                    let output = transliterate("東京都", &mut dict);
+                    if output == "トウキョウト" {
+                        after_swap += 1;
+                    }
+                    outputs.push(output);
+                    conversions.fetch_add(1, Ordering::Relaxed);
+                }
+                outputs
+            })
+        })
+        .collect();
+
+    while conversions.load(Ordering::Relaxed) < 100 {
+        std::thread::yield_now();
+    }
+    *current.lock().unwrap() = Arc::new(DictionaryData::load(&new_path).unwrap());
+
+    for worker in workers {
+        let outputs = worker.join().unwrap();
+        // Old readings up to the swap, new ones after it, and nothing else.
+        let swap = outputs.iter().position(|o| o == "トウキョウト").unwrap();
+        assert!(outputs[..swap].iter().all(|o| o == "トーキョート"));
+        assert!(outputs[swap..].iter().all(|o| o == "トウキョウト"));
+    }
+    std::fs::remove_file(&old_path).ok();
+    std::fs::remove_file(&new_path).ok();
+}
+
+#[test]
+fn test_failed_reload_keeps_the_old_dictionary() {
+    let tmp = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let (old_path, new_path, corrupt_path) = (
+        tmp.join("reload-keep-old.bin"),
+        tmp.join("reload-keep-new.bin"),
+        tmp.join("reload-keep-corrupt.bin"),
+    );
+    write_fixture(&old_path, "トーキョー");
+    write_fixture(&new_path, "トウキョウ");
+    let mut bytes = std::fs::read(&new_path).unwrap();
+    let last = bytes.len() - 1;
+    bytes[last] ^= 0xff;
+    std::fs::write(&corrupt_path, bytes).unwrap();
+
+    let mut dict = Dictionary::load(&old_path).unwrap();
+    assert!(dict.reload(tmp.join("reload-missing.bin")).is_err());
+    assert!(dict.reload(&corrupt_path).is_err());
+    assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+
+    dict.reload(&new_path).unwrap();
+    assert_eq!(transliterate("東京都", &mut dict), "トウキョウト");
+    for path in [old_path, new_path, corrupt_path] {
+        std::fs::remove_file(path).ok();
+    }
+}
+
+#[test]
+fn test_old_data_outlives_a_file_published_over_it() {
+    let tmp = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let (path, next_path) = (
+        tmp.join("reload-published.bin"),
+        tmp.join("reload-next.bin"),
+    );
+    write_fixture(&path, "トーキョー");
+    let old = Arc::new(DictionaryData::load(&path).unwrap());
+
+    write_fixture(&next_path, "トウキョウ");
+    std::fs::rename(&next_path, &path).unwrap();
+
+    // Handles opened on the old data after the publish still read the old file.
+    old.verify_checksum().unwrap();
+    let mut dict = Dictionary::with_data(old.clone()).unwrap();
+    assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+    assert!(dict.take_error().is_none());
+
+    dict.reload(&path).unwrap();
+    assert_eq!(transliterate("東京都", &mut dict), "トウキョウト");
+    let mut still_old = Dictionary::with_data(old).unwrap();
+    assert_eq!(transliterate("東京都", &mut still_old), "トーキョート");
+    std::fs::remove_file(&path).ok();
+}
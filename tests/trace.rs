@@ -0,0 +1,95 @@
+//! Loads and converts with a subscriber that records span names and cache-miss events, and
+//! checks the instrumentation of the `trace` feature reports them.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+use mucab::builder::DictionaryBuilder;
+use mucab::{transliterate, Dictionary};
+
+#[derive(Default)]
+struct Log {
+    next_id: AtomicU64,
+    spans: Mutex<Vec<String>>,
+    events: Mutex<Vec<String>>,
+}
+
+struct Recorder(Arc<Log>);
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        self.0
+            .spans
+            .lock()
+            .unwrap()
+            .push(span.metadata().name().to_string());
+        Id::from_u64(self.0.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        struct Message(String);
+        impl tracing::field::Visit for Message {
+            fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+                if field.name() == "message" {
+                    self.0 = format!("{:?}", value);
+                }
+            }
+        }
+        let mut message = Message(String::new());
+        event.record(&mut message);
+        self.0.events.lock().unwrap().push(message.0);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[test]
+fn test_spans_and_cache_misses_are_reported() {
+    let path = std::path::PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("trace-fixture.bin");
+    let mut builder = DictionaryBuilder::new();
+    builder.add_entry("東京", "トーキョー", 0, 100);
+    builder.add_entry("都", "ト", 0, 100);
+    builder.set_matrix(vec![0], 1);
+    builder.write_to_file(&path).unwrap();
+
+    let log = Arc::new(Log::default());
+    tracing::subscriber::with_default(Recorder(log.clone()), || {
+        let mut dict = Dictionary::load(&path).unwrap();
+        assert_eq!(transliterate("東京都", &mut dict), "トーキョート");
+    });
+    std::fs::remove_file(&path).ok();
+
+    let spans = log.spans.lock().unwrap();
+    for name in [
+        "load_file",
+        "build_lattice",
+        "bulk_read_entries",
+        "search_lattice",
+    ] {
+        assert!(
+            spans.iter().any(|span| span == name),
+            "{} in {:?}",
+            name,
+            spans
+        );
+    }
+    let events = log.events.lock().unwrap();
+    assert!(
+        events.iter().any(|event| event == "entry cache miss"),
+        "{:?}",
+        events
+    );
+}